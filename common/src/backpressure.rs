@@ -0,0 +1,126 @@
+//! Pauses consumption from the broker whilst a component's send buffer is full.
+use crate::metrics::names::{CONSUMER_PAUSED, SEND_BUFFER_DEPTH};
+use clap::{Args, ValueEnum};
+use metrics::gauge;
+use rdkafka::consumer::Consumer;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Determines what a component does when its buffer of messages awaiting delivery to the broker is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SendBufferFullPolicy {
+    /// Consumption is paused until the buffer has drained to half its size.
+    #[default]
+    Backpressure,
+    /// The component exits.
+    Exit,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct BackpressureOpts {
+    /// Determines what happens when the send buffer is full.
+    #[clap(long, default_value = "backpressure")]
+    pub send_buffer_full_policy: SendBufferFullPolicy,
+
+    /// Whilst consumption is paused, the send buffer is checked at this interval (in milliseconds) to see whether it has drained.
+    #[clap(long, default_value = "100")]
+    pub backpressure_poll_ms: u64,
+}
+
+impl BackpressureOpts {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.backpressure_poll_ms)
+    }
+}
+
+/// Pauses and resumes a consumer according to the number of messages in a send buffer.
+///
+/// Consumption is paused when the buffer is full, and resumed once it is no more than half full,
+/// so that the consumer is not toggled on every message.
+pub struct Backpressure {
+    policy: SendBufferFullPolicy,
+    capacity: usize,
+    paused: bool,
+}
+
+impl Backpressure {
+    /// Creates a new [Backpressure].
+    /// # Parameters
+    /// - policy: if [SendBufferFullPolicy::Exit], the consumer is never paused, and only the buffer depth is recorded.
+    /// - capacity: the size of the send buffer.
+    pub fn new(policy: SendBufferFullPolicy, capacity: usize) -> Self {
+        gauge!(CONSUMER_PAUSED).set(0);
+        Self {
+            policy,
+            capacity,
+            paused: false,
+        }
+    }
+
+    pub fn policy(&self) -> SendBufferFullPolicy {
+        self.policy
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Records the depth of the send buffer, and pauses or resumes the consumer's assigned partitions as required.
+    ///
+    /// A full buffer pauses the current assignment even if consumption is already paused,
+    /// so that partitions assigned by a rebalance whilst paused are also paused.
+    /// # Parameters
+    /// - consumer: the consumer whose partitions are paused or resumed.
+    /// - depth: the number of messages in the send buffer.
+    pub fn update<C: Consumer>(&mut self, consumer: &C, depth: usize) {
+        gauge!(SEND_BUFFER_DEPTH).set(depth as f64);
+
+        if self.policy == SendBufferFullPolicy::Exit {
+            return;
+        }
+
+        if depth >= self.capacity {
+            match consumer
+                .assignment()
+                .and_then(|assignment| consumer.pause(&assignment))
+            {
+                Ok(()) => {
+                    if !self.paused {
+                        warn!("Send buffer full, pausing consumption");
+                        self.paused = true;
+                    }
+                }
+                Err(e) => warn!("Failed to pause consumption: {e}"),
+            }
+        } else if self.paused && self.should_resume(depth) {
+            match consumer
+                .assignment()
+                .and_then(|assignment| consumer.resume(&assignment))
+            {
+                Ok(()) => {
+                    info!("Send buffer drained, resuming consumption");
+                    self.paused = false;
+                }
+                Err(e) => warn!("Failed to resume consumption: {e}"),
+            }
+        }
+        gauge!(CONSUMER_PAUSED).set(if self.paused { 1 } else { 0 });
+    }
+
+    fn should_resume(&self, depth: usize) -> bool {
+        depth <= self.capacity / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_once_half_drained() {
+        let backpressure = Backpressure::new(SendBufferFullPolicy::Backpressure, 10);
+        assert!(!backpressure.should_resume(6));
+        assert!(backpressure.should_resume(5));
+        assert!(backpressure.should_resume(0));
+    }
+}
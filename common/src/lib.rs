@@ -1,3 +1,4 @@
+pub mod backpressure;
 pub mod metrics;
 pub mod spanned;
 pub mod tracer;
@@ -5,10 +6,14 @@ mod version;
 
 use clap::Args;
 use rdkafka::{
+    Message, Offset, TopicPartitionList,
     config::ClientConfig,
-    consumer::{Consumer, StreamConsumer},
+    consumer::{Consumer, ConsumerContext, DefaultConsumerContext, StreamConsumer},
     error::KafkaError,
+    message::{BorrowedMessage, OwnedMessage},
 };
+use std::time::Duration;
+use tracing::warn;
 
 pub type DigitizerId = u8;
 pub type Time = u32;
@@ -20,6 +25,9 @@ pub type SampleRate = u64;
 
 pub const CHANNELS_PER_DIGITIZER: usize = 8;
 
+/// How long to wait for the broker when loading the latest message of a topic on startup.
+const LATEST_MESSAGE_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub fn channel_index(digitizer_index: usize, channel_index: usize) -> usize {
     (digitizer_index * CHANNELS_PER_DIGITIZER) + channel_index
 }
@@ -76,13 +84,34 @@ pub fn create_default_consumer(
     consumer_group: &String,
     topics_to_subscribe: Option<&[&str]>,
 ) -> Result<StreamConsumer, KafkaError> {
+    create_default_consumer_with_context(
+        broker_address,
+        username,
+        password,
+        consumer_group,
+        topics_to_subscribe,
+        DefaultConsumerContext,
+    )
+}
+
+/// As [create_default_consumer], but with a [ConsumerContext] whose callbacks are run by the consumer,
+/// for instance on a rebalance.
+pub fn create_default_consumer_with_context<C: ConsumerContext + 'static>(
+    broker_address: &String,
+    username: &Option<String>,
+    password: &Option<String>,
+    consumer_group: &String,
+    topics_to_subscribe: Option<&[&str]>,
+    context: C,
+) -> Result<StreamConsumer<C>, KafkaError> {
     // Setup consumer with arguments and default parameters.
-    let consumer: StreamConsumer = generate_kafka_client_config(broker_address, username, password)
-        .set("group.id", consumer_group)
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "false")
-        .create()?;
+    let consumer: StreamConsumer<C> =
+        generate_kafka_client_config(broker_address, username, password)
+            .set("group.id", consumer_group)
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .create_with_context(context)?;
 
     // Subscribe to if topics are provided.
     if let Some(topics_to_subscribe) = topics_to_subscribe {
@@ -92,3 +121,89 @@ pub fn create_default_consumer(
 
     Ok(consumer)
 }
+
+/// Creates a consumer for a topic, such as a control topic, positioned at the last message of each partition.
+///
+/// Every instance of a component must receive every message of such a topic, so the partitions
+/// are assigned directly, rather than shared via the consumer group.
+/// Only the most recent message is of use, so earlier messages are skipped.
+/// # Parameters
+/// - kafka_opts: the options of the Kafka broker.
+/// - consumer_group: the consumer group of the consumer, which should be distinct from that of the component's other consumers.
+/// - topic: the topic to consume.
+/// # Return
+/// The consumer, and the number of partitions with a last message to be read by [load_latest_message].
+pub fn create_latest_message_consumer(
+    kafka_opts: &CommonKafkaOpts,
+    consumer_group: &str,
+    topic: &str,
+) -> Result<(StreamConsumer, usize), KafkaError> {
+    let consumer: StreamConsumer = generate_kafka_client_config(
+        &kafka_opts.broker,
+        &kafka_opts.username,
+        &kafka_opts.password,
+    )
+    .set("group.id", consumer_group)
+    .set("enable.partition.eof", "false")
+    .set("session.timeout.ms", "6000")
+    .set("enable.auto.commit", "false")
+    .create()?;
+
+    let metadata = consumer.fetch_metadata(Some(topic), LATEST_MESSAGE_STARTUP_TIMEOUT)?;
+    let mut assignment = TopicPartitionList::new();
+    let mut num_last_messages = 0;
+    for partition in metadata
+        .topics()
+        .iter()
+        .flat_map(|topic| topic.partitions())
+    {
+        let (low, high) =
+            consumer.fetch_watermarks(topic, partition.id(), LATEST_MESSAGE_STARTUP_TIMEOUT)?;
+        let offset = if high > low {
+            num_last_messages += 1;
+            Offset::Offset(high - 1)
+        } else {
+            Offset::End
+        };
+        assignment.add_partition_offset(topic, partition.id(), offset)?;
+    }
+    consumer.assign(&assignment)?;
+    Ok((consumer, num_last_messages))
+}
+
+/// Reads the last message of each partition of the topic, and returns the most recent.
+/// This should be called before any other messages are consumed, so they are never processed against stale settings.
+/// # Parameters
+/// - consumer: the consumer created by [create_latest_message_consumer].
+/// - num_last_messages: the number of partitions with a last message to read.
+pub async fn load_latest_message(
+    consumer: &StreamConsumer,
+    num_last_messages: usize,
+) -> Result<Option<OwnedMessage>, KafkaError> {
+    let mut latest: Option<OwnedMessage> = None;
+    for _ in 0..num_last_messages {
+        let Ok(msg) = tokio::time::timeout(LATEST_MESSAGE_STARTUP_TIMEOUT, consumer.recv()).await
+        else {
+            warn!("Timed out reading the latest message");
+            break;
+        };
+        let msg = msg?.detach();
+        if latest
+            .as_ref()
+            .is_none_or(|latest| latest.timestamp().to_millis() <= msg.timestamp().to_millis())
+        {
+            latest = Some(msg);
+        }
+    }
+    Ok(latest)
+}
+
+/// Awaits the next message from an optional consumer, or waits forever if there is no such consumer.
+pub async fn recv_if_present(
+    consumer: Option<&StreamConsumer>,
+) -> Result<BorrowedMessage<'_>, KafkaError> {
+    match consumer {
+        Some(consumer) => consumer.recv().await,
+        None => std::future::pending().await,
+    }
+}
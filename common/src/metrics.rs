@@ -22,6 +22,8 @@ pub mod names {
         concatcp!(METRIC_NAME_PREFIX, "last_message_timestamp");
     pub const LAST_MESSAGE_FRAME_NUMBER: &str =
         concatcp!(METRIC_NAME_PREFIX, "last_message_frame_number");
+    pub const SEND_BUFFER_DEPTH: &str = concatcp!(METRIC_NAME_PREFIX, "send_buffer_depth");
+    pub const CONSUMER_PAUSED: &str = concatcp!(METRIC_NAME_PREFIX, "consumer_paused");
 }
 
 pub mod messages_received {
@@ -0,0 +1,53 @@
+//! Defines the messages on the control topic, which change the configuration of the aggregator whilst it is running.
+use serde::{Deserialize, Deserializer, de::Error};
+use supermusr_common::DigitizerId;
+
+/// A JSON message on the control topic.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ControlMessage {
+    /// If present, replaces the digitisers from which each frame must receive a message to be complete.
+    /// This must not be empty, as every frame would then be complete.
+    #[serde(default, deserialize_with = "deserialize_expected_digitisers")]
+    pub(crate) expected_digitisers: Option<Vec<DigitizerId>>,
+}
+
+fn deserialize_expected_digitisers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<DigitizerId>>, D::Error> {
+    let expected_digitisers = Vec::<DigitizerId>::deserialize(deserializer)?;
+    if expected_digitisers.is_empty() {
+        return Err(D::Error::custom("expected-digitisers must not be empty"));
+    }
+    Ok(Some(expected_digitisers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_expected_digitisers() {
+        let message: ControlMessage =
+            serde_json::from_str(r#"{ "expected-digitisers": [0, 1, 3] }"#).unwrap();
+        assert_eq!(message.expected_digitisers, Some(vec![0, 1, 3]));
+    }
+
+    #[test]
+    fn expected_digitisers_are_optional() {
+        let message: ControlMessage = serde_json::from_str("{}").unwrap();
+        assert_eq!(message.expected_digitisers, None);
+    }
+
+    #[test]
+    fn reject_empty_expected_digitisers() {
+        assert!(
+            serde_json::from_str::<ControlMessage>(r#"{ "expected-digitisers": [] }"#).is_err()
+        );
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        assert!(serde_json::from_str::<ControlMessage>(r#"{ "digitisers": [0] }"#).is_err());
+    }
+}
@@ -1,19 +1,62 @@
 //! Defines the event list type, used for both digitiser messages and frame messages.
 use super::{Accumulate, DigitiserData};
 use crate::frame::AggregatedFrame;
+use clap::{Args, ValueEnum};
 use supermusr_common::{Channel, DigitizerId, Intensity, Time};
 use supermusr_streaming_types::{
     aev2_frame_assembled_event_v2_generated::{
         FrameAssembledEventListMessage, FrameAssembledEventListMessageArgs,
         finish_frame_assembled_event_list_message_buffer,
     },
+    aev3_frame_assembled_event_v3_generated as aev3,
+    aev4_frame_assembled_event_v4_generated as aev4,
     dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
-    flatbuffers::FlatBufferBuilder,
+    dev3_digitizer_event_v3_generated as dev3,
+    flatbuffers::{FlatBufferBuilder, Vector, WIPOffset},
     frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
+    time_deltas::encode_time_deltas,
 };
 
+/// The order of the events in each dispatched frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum EventOrder {
+    /// The events of each digitiser, in the order in which the digitisers' messages were received.
+    #[default]
+    Received,
+    /// Events sorted by time.
+    Time,
+    /// Events grouped by channel, in increasing order, and sorted by time within each group.
+    Channel,
+}
+
+impl From<EventOrder> for aev4::EventOrder {
+    fn from(order: EventOrder) -> Self {
+        match order {
+            EventOrder::Received => aev4::EventOrder::Received,
+            EventOrder::Time => aev4::EventOrder::Time,
+            EventOrder::Channel => aev4::EventOrder::Channel,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Args)]
+pub(crate) struct FrameEncodingOptions {
+    /// The order of the events in each dispatched frame. If `channel`, frames include an index of the first event
+    /// of each channel. See README.md.
+    #[clap(long, default_value = "received")]
+    pub(crate) event_order: EventOrder,
+
+    /// If set, frames are dispatched as `aev4` messages, in which the time of each event is encoded as the
+    /// difference from that of the previous event. See README.md.
+    #[clap(long)]
+    pub(crate) delta_encode_times: bool,
+}
+
 /// Event list, either for a digitiser message, or frame message.
-#[derive(Debug, PartialEq, Eq)]
+///
+/// The pulse shape fields, [Self::width], [Self::area] and [Self::rise_time], are optional.
+/// Each is either empty, or has one value per event, with NaN for events for which it was not measured.
+#[derive(Debug, PartialEq)]
 pub(crate) struct EventData {
     /// Time at which event occurred, relative to frame metadata timestamp (ns).
     time: Vec<Time>,
@@ -21,6 +64,12 @@ pub(crate) struct EventData {
     intensity: Vec<Intensity>,
     /// Id of the detector which registered the event.
     channel: Vec<Channel>,
+    /// Time from the start to the end of the event's pulse (ns).
+    width: Vec<f32>,
+    /// Integral of the event's pulse over its width.
+    area: Vec<f32>,
+    /// Time from the start to the peak of the event's pulse (ns).
+    rise_time: Vec<f32>,
 }
 
 impl EventData {
@@ -30,6 +79,9 @@ impl EventData {
             time,
             intensity,
             channel,
+            width: Vec::new(),
+            area: Vec::new(),
+            rise_time: Vec::new(),
         }
     }
 
@@ -59,6 +111,9 @@ impl EventData {
             time,
             intensity,
             channel,
+            width: Vec::new(),
+            area: Vec::new(),
+            rise_time: Vec::new(),
         }
     }
 
@@ -72,9 +127,22 @@ impl EventData {
             time: Vec::with_capacity(capacity),
             intensity: Vec::with_capacity(capacity),
             channel: Vec::with_capacity(capacity),
+            width: Vec::new(),
+            area: Vec::new(),
+            rise_time: Vec::new(),
         }
     }
 
+    /// Returns the time of each event in the list, relative to the frame metadata timestamp (ns).
+    pub(crate) fn times(&self) -> &[Time] {
+        &self.time
+    }
+
+    /// Returns the channel of each event in the list.
+    pub(crate) fn channels(&self) -> &[Channel] {
+        &self.channel
+    }
+
     /// Returns the number of events in the list.
     ///
     /// This assumes all fields are of equal length.
@@ -82,6 +150,58 @@ impl EventData {
     pub(crate) fn event_count(&self) -> usize {
         self.time.len()
     }
+
+    /// Returns `true` if any of the optional pulse shape fields are present.
+    pub(crate) fn has_pulse_shape(&self) -> bool {
+        !(self.width.is_empty() && self.area.is_empty() && self.rise_time.is_empty())
+    }
+
+    /// Reorders the events in the list.
+    ///
+    /// The sort is stable, so events with equal keys remain in the order in which they were received.
+    /// # Parameters
+    /// - order: the order in which to place the events.
+    pub(crate) fn sort(&mut self, order: EventOrder) {
+        let mut permutation = (0..self.event_count()).collect::<Vec<_>>();
+        match order {
+            EventOrder::Received => return,
+            EventOrder::Time => permutation.sort_by_key(|&index| self.time.get(index)),
+            EventOrder::Channel => {
+                permutation.sort_by_key(|&index| (self.channel.get(index), self.time.get(index)))
+            }
+        }
+        self.time = permute(&self.time, &permutation);
+        self.intensity = permute(&self.intensity, &permutation);
+        self.channel = permute(&self.channel, &permutation);
+        self.width = permute(&self.width, &permutation);
+        self.area = permute(&self.area, &permutation);
+        self.rise_time = permute(&self.rise_time, &permutation);
+    }
+
+    /// Returns the channel of each run of consecutive events with the same channel, and the index of its first event.
+    ///
+    /// If the list has been sorted by [EventOrder::Channel], each channel has exactly one run.
+    pub(crate) fn channel_groups(&self) -> (Vec<Channel>, Vec<u32>) {
+        let mut channels = Vec::new();
+        let mut starts = Vec::new();
+        for (index, &channel) in self.channel.iter().enumerate() {
+            if channels.last() != Some(&channel) {
+                channels.push(channel);
+                starts.push(index as u32);
+            }
+        }
+        (channels, starts)
+    }
+}
+
+/// Returns the values reordered so that the value at each index of the permutation is taken from the index it holds.
+///
+/// An empty list, such as an absent pulse shape field, remains empty.
+fn permute<T: Copy>(values: &[T], permutation: &[usize]) -> Vec<T> {
+    permutation
+        .iter()
+        .filter_map(|&index| values.get(index).copied())
+        .collect()
 }
 
 impl<'a> From<DigitizerEventListMessage<'a>> for EventData {
@@ -105,57 +225,225 @@ impl<'a> From<DigitizerEventListMessage<'a>> for EventData {
             time,
             intensity,
             channel,
+            width: Vec::new(),
+            area: Vec::new(),
+            rise_time: Vec::new(),
         }
     }
 }
 
+impl<'a> From<dev3::DigitizerEventListMessage<'a>> for EventData {
+    fn from(msg: dev3::DigitizerEventListMessage<'a>) -> Self {
+        let time = msg.time().expect("data should have times").iter().collect();
+        let intensity = msg
+            .voltage()
+            .expect("data should have intensities")
+            .iter()
+            .collect();
+        let channel = msg
+            .channel()
+            .expect("data should have channel numbers")
+            .iter()
+            .collect();
+
+        // As above, the pulse shape fields, if present, are guaranteed by the
+        // `trace-to-events` unit to be of the same length as the other fields.
+        Self {
+            time,
+            intensity,
+            channel,
+            width: msg.width().map(|v| v.iter().collect()).unwrap_or_default(),
+            area: msg.area().map(|v| v.iter().collect()).unwrap_or_default(),
+            rise_time: msg
+                .rise_time()
+                .map(|v| v.iter().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Accumulates one of the optional pulse shape fields.
+///
+/// If the field is present in any of the inputs, then the result contains one value per event,
+/// with NaN for each event from an input in which the field is absent. Otherwise the result is empty.
+fn accumulate_pulse_shape_field(
+    data: &mut DigitiserData<EventData>,
+    field: impl Fn(&mut EventData) -> &mut Vec<f32>,
+) -> Vec<f32> {
+    if data.iter_mut().all(|(_, value)| field(value).is_empty()) {
+        return Vec::new();
+    }
+    let total_len = data.iter().map(|(_, v)| v.event_count()).sum();
+
+    data.iter_mut()
+        .fold(Vec::with_capacity(total_len), |mut acc, (_, value)| {
+            let event_count = value.event_count();
+            let values = field(value);
+            if values.is_empty() {
+                acc.extend(std::iter::repeat_n(f32::NAN, event_count));
+            } else {
+                acc.append(values);
+            }
+            acc
+        })
+}
+
 impl Accumulate<EventData> for DigitiserData<EventData> {
     fn accumulate(data: &mut DigitiserData<EventData>) -> EventData {
         // The guarantee that all fields are of equal length depends on all
         // inputs in the collection having fields of equal length.
         let total_len = data.iter().map(|(_, v)| v.event_count()).sum();
 
-        data.iter_mut()
-            .fold(EventData::with_capacity(total_len), |mut acc, value| {
-                acc.time.append(&mut value.1.time);
-                acc.intensity.append(&mut value.1.intensity);
-                acc.channel.append(&mut value.1.channel);
-                acc
-            })
+        // The pulse shape fields are accumulated first, as this depends on the event counts of the inputs.
+        let width = accumulate_pulse_shape_field(data, |value| &mut value.width);
+        let area = accumulate_pulse_shape_field(data, |value| &mut value.area);
+        let rise_time = accumulate_pulse_shape_field(data, |value| &mut value.rise_time);
+
+        let mut result =
+            data.iter_mut()
+                .fold(EventData::with_capacity(total_len), |mut acc, value| {
+                    acc.time.append(&mut value.1.time);
+                    acc.intensity.append(&mut value.1.intensity);
+                    acc.channel.append(&mut value.1.channel);
+                    acc
+                });
+        result.width = width;
+        result.area = area;
+        result.rise_time = rise_time;
+        result
     }
 }
 
 impl From<AggregatedFrame<EventData>> for Vec<u8> {
     fn from(frame: AggregatedFrame<EventData>) -> Self {
+        frame.encode(FrameEncodingOptions::default())
+    }
+}
+
+impl AggregatedFrame<EventData> {
+    /// Orders the events of the frame and serialises it as a frame assembled event list message.
+    ///
+    /// Frames are emitted as `aev4` messages if times are delta encoded. Otherwise, they are only emitted as `aev3`
+    /// messages if they carry pulse shape fields, are corrections, their digitisers disagreed on the frame metadata,
+    /// or their events are grouped by channel, and as `aev2` messages if not.
+    /// # Parameters
+    /// - options: the order of the events, and whether their times are delta encoded.
+    pub(crate) fn encode(mut self, options: FrameEncodingOptions) -> Vec<u8> {
+        self.digitiser_data.sort(options.event_order);
+        let groups = (options.event_order == EventOrder::Channel)
+            .then(|| self.digitiser_data.channel_groups());
+
         let mut fbb = FlatBufferBuilder::new();
 
-        let timestamp = frame.metadata.timestamp.into();
+        let timestamp = self.metadata.timestamp.into();
         let metadata = FrameMetadataV2Args {
             timestamp: Some(&timestamp),
-            period_number: frame.metadata.period_number,
-            protons_per_pulse: frame.metadata.protons_per_pulse,
-            running: frame.metadata.running,
-            frame_number: frame.metadata.frame_number,
-            veto_flags: frame.metadata.veto_flags,
+            period_number: self.metadata.period_number,
+            protons_per_pulse: self.metadata.protons_per_pulse,
+            running: self.metadata.running,
+            frame_number: self.metadata.frame_number,
+            veto_flags: self.metadata.veto_flags,
         };
         let metadata = FrameMetadataV2::create(&mut fbb, &metadata);
 
-        let message = FrameAssembledEventListMessageArgs {
-            metadata: Some(metadata),
-            time: Some(fbb.create_vector::<Time>(&frame.digitiser_data.time)),
-            voltage: Some(fbb.create_vector::<Intensity>(&frame.digitiser_data.intensity)),
-            channel: Some(fbb.create_vector::<Channel>(&frame.digitiser_data.channel)),
-            complete: frame.complete,
-            digitizers_present: Some(fbb.create_vector::<DigitizerId>(&frame.digitiser_ids)),
-        };
-        let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+        let voltage = Some(fbb.create_vector::<Intensity>(&self.digitiser_data.intensity));
+        let digitizers_present = Some(fbb.create_vector::<DigitizerId>(&self.digitiser_ids));
+        let (group_channel, group_start) = groups
+            .as_ref()
+            .map(|(channels, starts)| {
+                (
+                    Some(fbb.create_vector::<Channel>(channels)),
+                    Some(fbb.create_vector::<u32>(starts)),
+                )
+            })
+            .unwrap_or_default();
+
+        if options.delta_encode_times {
+            let group_starts = groups.as_ref().map(|(_, starts)| starts.as_slice());
+            let time_delta =
+                encode_time_deltas(&self.digitiser_data.time, group_starts.unwrap_or_default());
+            let time_delta = Some(fbb.create_vector::<u8>(&time_delta));
+            // When grouped, the channel of each event is given by its group.
+            let channel = groups
+                .is_none()
+                .then(|| fbb.create_vector::<Channel>(&self.digitiser_data.channel));
+            let inconsistent_digitizers = (!self.inconsistent_digitiser_ids.is_empty())
+                .then(|| fbb.create_vector::<DigitizerId>(&self.inconsistent_digitiser_ids));
+            let message = aev4::FrameAssembledEventListMessageArgs {
+                metadata: Some(metadata),
+                order: options.event_order.into(),
+                time_delta,
+                voltage,
+                channel,
+                group_channel,
+                group_start,
+                complete: self.complete,
+                digitizers_present,
+                width: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.width),
+                area: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.area),
+                rise_time: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.rise_time),
+                correction: self.correction,
+                inconsistent_digitizers,
+                metadata_disagreements: self.metadata_disagreements,
+            };
+            let message = aev4::FrameAssembledEventListMessage::create(&mut fbb, &message);
+            aev4::finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+            return fbb.finished_data().to_vec();
+        }
 
-        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        let time = Some(fbb.create_vector::<Time>(&self.digitiser_data.time));
+        let channel = Some(fbb.create_vector::<Channel>(&self.digitiser_data.channel));
+
+        if self.digitiser_data.has_pulse_shape()
+            || self.correction
+            || self.metadata_disagreements != 0
+            || groups.is_some()
+        {
+            let inconsistent_digitizers = (!self.inconsistent_digitiser_ids.is_empty())
+                .then(|| fbb.create_vector::<DigitizerId>(&self.inconsistent_digitiser_ids));
+            let message = aev3::FrameAssembledEventListMessageArgs {
+                metadata: Some(metadata),
+                time,
+                voltage,
+                channel,
+                complete: self.complete,
+                digitizers_present,
+                width: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.width),
+                area: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.area),
+                rise_time: create_pulse_shape_vector(&mut fbb, &self.digitiser_data.rise_time),
+                correction: self.correction,
+                inconsistent_digitizers,
+                metadata_disagreements: self.metadata_disagreements,
+                group_channel,
+                group_start,
+            };
+            let message = aev3::FrameAssembledEventListMessage::create(&mut fbb, &message);
+            aev3::finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        } else {
+            let message = FrameAssembledEventListMessageArgs {
+                metadata: Some(metadata),
+                time,
+                voltage,
+                channel,
+                complete: self.complete,
+                digitizers_present,
+            };
+            let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+            finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        }
 
         fbb.finished_data().to_vec()
     }
 }
 
+/// Creates a vector of one of the optional pulse shape fields, or [None] if the field is absent.
+fn create_pulse_shape_vector<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    values: &[f32],
+) -> Option<WIPOffset<Vector<'a, f32>>> {
+    (!values.is_empty()).then(|| fbb.create_vector(values))
+}
+
 #[cfg(test)]
 mod test {
     use chrono::Utc;
@@ -222,15 +510,266 @@ mod test {
                 },
                 true,
                 vec![0, 1],
-                EventData {
-                    time: vec![1, 2, 8, 9, 7],
-                    intensity: vec![2, 8, 8, 2, 7],
-                    channel: vec![1, 3, 1, 0, 4],
-                },
+                EventData::new(
+                    vec![1, 2, 8, 9, 7],
+                    vec![2, 8, 8, 2, 7],
+                    vec![1, 3, 1, 0, 4],
+                ),
             );
             frame.into()
         };
 
         assert_eq!(test, reference);
     }
+
+    #[test]
+    fn accumulate_pulse_shape() {
+        let mut with_shape = EventData::new(vec![1, 2], vec![5, 6], vec![0, 0]);
+        with_shape.width = vec![3.0, 4.0];
+        let without_shape = EventData::new(vec![3], vec![7], vec![1]);
+
+        let mut data: DigitiserData<EventData> = vec![(1, without_shape), (0, with_shape)];
+        let result = DigitiserData::<EventData>::accumulate(&mut data);
+
+        assert_eq!(result.time, [3, 1, 2]);
+        assert_eq!(result.width.len(), 3);
+        assert!(result.width.first().unwrap().is_nan());
+        assert_eq!(result.width.get(1..), Some([3.0, 4.0].as_slice()));
+        assert!(result.area.is_empty());
+        assert!(result.rise_time.is_empty());
+        assert!(result.has_pulse_shape());
+    }
+
+    #[test]
+    fn accumulate_without_pulse_shape() {
+        let mut data: DigitiserData<EventData> = vec![
+            (0, EventData::dummy_data(0, 2, &[0])),
+            (1, EventData::dummy_data(0, 2, &[1])),
+        ];
+        let result = DigitiserData::<EventData>::accumulate(&mut data);
+
+        assert_eq!(result.event_count(), 4);
+        assert!(!result.has_pulse_shape());
+    }
+
+    #[test]
+    fn aggregate_frame_with_pulse_shape_to_aev3() {
+        let mut data = EventData::new(vec![1, 2], vec![2, 8], vec![1, 3]);
+        data.rise_time = vec![4.0, f32::NAN];
+        let frame = AggregatedFrame::new(
+            FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+            true,
+            vec![0],
+            data,
+        );
+        let bytes: Vec<u8> = frame.into();
+
+        assert!(aev3::frame_assembled_event_list_message_buffer_has_identifier(&bytes));
+        let message = aev3::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert_eq!(message.time().unwrap().iter().collect::<Vec<_>>(), [1, 2]);
+        assert!(message.width().is_none());
+        assert!(message.area().is_none());
+        let rise_time = message.rise_time().unwrap();
+        assert_eq!(rise_time.get(0), 4.0);
+        assert!(rise_time.get(1).is_nan());
+    }
+
+    #[test]
+    fn correction_frame_to_aev3() {
+        let mut frame = AggregatedFrame::new(
+            FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+            false,
+            vec![3],
+            EventData::new(vec![1, 2], vec![2, 8], vec![1, 3]),
+        );
+        frame.correction = true;
+        let bytes: Vec<u8> = frame.into();
+
+        assert!(aev3::frame_assembled_event_list_message_buffer_has_identifier(&bytes));
+        let message = aev3::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert!(message.correction());
+        assert!(!message.complete());
+        assert!(message.width().is_none());
+        assert_eq!(
+            message
+                .digitizers_present()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            [3]
+        );
+    }
+
+    #[test]
+    fn inconsistent_frame_to_aev3() {
+        let mut frame = AggregatedFrame::new(
+            FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+            true,
+            vec![0, 1, 2],
+            EventData::new(vec![1, 2], vec![2, 8], vec![1, 3]),
+        );
+        frame.inconsistent_digitiser_ids = vec![2];
+        frame.metadata_disagreements = 1;
+        let bytes: Vec<u8> = frame.into();
+
+        assert!(aev3::frame_assembled_event_list_message_buffer_has_identifier(&bytes));
+        let message = aev3::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert!(!message.correction());
+        assert_eq!(message.metadata_disagreements(), 1);
+        assert_eq!(
+            message
+                .inconsistent_digitizers()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            [2]
+        );
+    }
+
+    fn frame_of(data: EventData) -> AggregatedFrame<EventData> {
+        AggregatedFrame::new(
+            FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+            true,
+            vec![0, 1],
+            data,
+        )
+    }
+
+    #[test]
+    fn sort_by_time_reorders_every_field() {
+        let mut data = EventData::new(vec![5, 1, 3, 1], vec![50, 10, 30, 11], vec![0, 1, 2, 3]);
+        data.width = vec![5.0, 1.0, 3.0, 1.5];
+        data.sort(EventOrder::Time);
+
+        assert_eq!(data.time, [1, 1, 3, 5]);
+        assert_eq!(data.intensity, [10, 11, 30, 50]);
+        assert_eq!(data.channel, [1, 3, 2, 0]);
+        assert_eq!(data.width, [1.0, 1.5, 3.0, 5.0]);
+        assert!(data.area.is_empty());
+    }
+
+    #[test]
+    fn sort_by_channel_groups_events() {
+        let mut data = EventData::new(vec![5, 1, 3, 2, 4], vec![0; 5], vec![2, 0, 2, 2, 0]);
+        data.sort(EventOrder::Channel);
+
+        assert_eq!(data.channel, [0, 0, 2, 2, 2]);
+        assert_eq!(data.time, [1, 4, 2, 3, 5]);
+        assert_eq!(data.channel_groups(), (vec![0, 2], vec![0, 2]));
+    }
+
+    #[test]
+    fn channel_grouped_frame_to_aev3() {
+        let frame = frame_of(EventData::new(
+            vec![1, 2, 8, 9, 7],
+            vec![2, 8, 8, 2, 7],
+            vec![1, 3, 1, 0, 4],
+        ));
+        let bytes = frame.encode(FrameEncodingOptions {
+            event_order: EventOrder::Channel,
+            delta_encode_times: false,
+        });
+
+        assert!(aev3::frame_assembled_event_list_message_buffer_has_identifier(&bytes));
+        let message = aev3::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert_eq!(
+            message.channel().unwrap().iter().collect::<Vec<_>>(),
+            [0, 1, 1, 3, 4]
+        );
+        assert_eq!(
+            message.time().unwrap().iter().collect::<Vec<_>>(),
+            [9, 1, 8, 2, 7]
+        );
+        assert_eq!(
+            message.group_channel().unwrap().iter().collect::<Vec<_>>(),
+            [0, 1, 3, 4]
+        );
+        assert_eq!(
+            message.group_start().unwrap().iter().collect::<Vec<_>>(),
+            [0, 1, 3, 4]
+        );
+    }
+
+    #[test]
+    fn delta_encoded_frame_to_aev4() {
+        let times = vec![1000, 2000, 8000, 9000, 7000];
+        let frame = frame_of(EventData::new(
+            times.clone(),
+            vec![2, 8, 8, 2, 7],
+            vec![1, 3, 1, 0, 4],
+        ));
+        let bytes = frame.encode(FrameEncodingOptions {
+            event_order: EventOrder::Received,
+            delta_encode_times: true,
+        });
+
+        assert!(aev4::frame_assembled_event_list_message_buffer_has_identifier(&bytes));
+        let message = aev4::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert_eq!(message.order(), aev4::EventOrder::Received);
+        assert!(message.group_start().is_none());
+        assert_eq!(
+            message.channel().unwrap().iter().collect::<Vec<_>>(),
+            [1, 3, 1, 0, 4]
+        );
+        let time_delta = message.time_delta().unwrap().bytes();
+        assert_eq!(time_delta.len(), 10);
+        assert_eq!(
+            supermusr_streaming_types::time_deltas::decode_time_deltas(time_delta, &[]),
+            Ok(times)
+        );
+    }
+
+    #[test]
+    fn channel_grouped_frame_to_aev4_omits_channels() {
+        let frame = frame_of(EventData::new(
+            vec![1, 2, 8, 9, 7],
+            vec![2, 8, 8, 2, 7],
+            vec![1, 3, 1, 0, 4],
+        ));
+        let bytes = frame.encode(FrameEncodingOptions {
+            event_order: EventOrder::Channel,
+            delta_encode_times: true,
+        });
+
+        let message = aev4::root_as_frame_assembled_event_list_message(&bytes).unwrap();
+        assert_eq!(message.order(), aev4::EventOrder::Channel);
+        assert!(message.channel().is_none());
+        let group_start = message.group_start().unwrap().iter().collect::<Vec<_>>();
+        assert_eq!(group_start, [0, 1, 3, 4]);
+        assert_eq!(
+            supermusr_streaming_types::time_deltas::decode_time_deltas(
+                message.time_delta().unwrap().bytes(),
+                &group_start
+            ),
+            Ok(vec![9, 1, 8, 2, 7])
+        );
+    }
 }
@@ -2,7 +2,7 @@
 //!
 //! [FrameCache]: crate::frame::FrameCache
 mod event;
-pub(crate) use event::EventData;
+pub(crate) use event::{EventData, FrameEncodingOptions};
 
 use supermusr_common::DigitizerId;
 
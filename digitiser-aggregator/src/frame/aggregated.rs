@@ -1,6 +1,9 @@
 //! Defines the struct for a frame which is ready to be dispatched.
 use super::partial::PartialFrame;
-use crate::data::{Accumulate, DigitiserData};
+use crate::{
+    data::{Accumulate, DigitiserData},
+    offsets::MessageOffset,
+};
 use supermusr_common::{
     DigitizerId,
     spanned::{SpanOnce, Spanned, SpannedMut},
@@ -13,7 +16,7 @@ pub(crate) struct AggregatedFrame<D> {
     ///
     /// [SpannedAggregator]: supermusr_common::spanned::SpannedAggregator
     span: SpanOnce,
-    /// The uniquely identifying metadata of the frame, common to all digitiser messages related to this frame (except possibly for [FrameMetadata::veto_flags]).
+    /// The metadata of the frame, merged from that of each digitiser message related to this frame.
     pub(crate) metadata: FrameMetadata,
     /// Is `true` if and only if the frame has received data frame all expected digitisers.
     pub(crate) complete: bool,
@@ -21,6 +24,19 @@ pub(crate) struct AggregatedFrame<D> {
     pub(crate) digitiser_ids: Vec<DigitizerId>,
     /// The frame's event data.
     pub(crate) digitiser_data: D,
+    /// The offsets of the digitiser messages from which the frame was built,
+    /// which can be committed once the frame has been delivered.
+    pub(crate) offsets: Vec<MessageOffset>,
+    /// Is `true` if the frame contains late data for a frame which has already been dispatched.
+    pub(crate) correction: bool,
+    /// The partition of the output topic to which the frame is dispatched, or [None] if it is chosen by the producer.
+    pub(crate) partition: Option<i32>,
+    /// The digitisers whose frame metadata differs from [Self::metadata], in increasing order.
+    pub(crate) inconsistent_digitiser_ids: Vec<DigitizerId>,
+    /// Bitmask of the metadata fields on which the digitisers disagree, see [disagreement].
+    ///
+    /// [disagreement]: super::validation::disagreement
+    pub(crate) metadata_disagreements: u8,
 }
 
 #[cfg(test)]
@@ -37,6 +53,11 @@ impl<D> AggregatedFrame<D> {
             complete,
             digitiser_ids,
             digitiser_data,
+            offsets: Vec::new(),
+            correction: false,
+            partition: None,
+            inconsistent_digitiser_ids: Vec::new(),
+            metadata_disagreements: 0,
         }
     }
 }
@@ -57,6 +78,11 @@ where
             digitiser_data: <DigitiserData<D> as Accumulate<D>>::accumulate(
                 &mut partial.digitiser_data,
             ),
+            offsets: std::mem::take(&mut partial.offsets),
+            correction: false,
+            partition: None,
+            inconsistent_digitiser_ids: Vec::new(),
+            metadata_disagreements: 0,
         }
     }
 }
@@ -1,23 +1,56 @@
 //! Defines the cache stores frames as they are assembled from digitiser messages.
-use super::{AggregatedFrame, RejectMessageError, partial::PartialFrame};
-use crate::data::{Accumulate, DigitiserData};
+use super::{
+    AggregatedFrame, ExpectedDigitisers, FrameTtl, RejectMessageError, partial::PartialFrame,
+    timestamp::TimestampWindow, validation::FrameValidationOptions,
+};
+use crate::{
+    data::{Accumulate, DigitiserData},
+    offsets::MessageOffset,
+};
 use chrono::{DateTime, Utc};
-use std::{collections::VecDeque, fmt::Debug, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    time::Duration,
+};
 use supermusr_common::{DigitizerId, record_metadata_fields_to_span, spanned::SpannedAggregator};
 use supermusr_streaming_types::FrameMetadata;
 use tracing::{info_span, warn};
 
+/// A recently dispatched frame, so that late messages belonging to it can be identified.
+struct DispatchedFrame {
+    /// The metadata of the frame.
+    metadata: FrameMetadata,
+    /// The digitisers whose data has been dispatched for the frame, including in correction frames.
+    digitiser_ids: Vec<DigitizerId>,
+}
+
 /// Contains all the partial frames as well as handling the frame lifetime and completeness.
 pub(crate) struct FrameCache<D: Debug> {
     /// Specifies the maximum time that a partial frame should live
     /// in the cache before being dispatched event if it is missing some digitisers.
-    ttl: Duration,
+    ttl: FrameTtl,
     /// Specifies the complete set of digitisers
     /// a partial frame should have before being complete.
-    expected_digitisers: Vec<DigitizerId>,
-    /// The metadata timestamp of the last frame to be dispatched,
-    /// value is [None] if no frame has been dispatched yet.
-    latest_timestamp_dispatched: Option<DateTime<Utc>>,
+    expected_digitisers: ExpectedDigitisers,
+    /// The metadata timestamp of the last frame to be dispatched from each shard,
+    /// a shard has no entry if no frame has been dispatched from it yet.
+    latest_timestamp_dispatched: HashMap<Option<i32>, DateTime<Utc>>,
+    /// If `true`, the input topic is partitioned by frame, and each partition is a shard.
+    /// Otherwise, every message belongs to the single shard [None].
+    sharded: bool,
+    /// Quarantines messages whose timestamps are far from the broker time or the recent median.
+    timestamp_window: TimestampWindow,
+    /// The most recently dispatched frames, so that late messages belonging to them can be identified.
+    recently_dispatched: VecDeque<DispatchedFrame>,
+    /// The maximum length of `recently_dispatched`.
+    dispatched_history: usize,
+    /// If `true`, late messages for recently dispatched frames are dispatched as correction frames.
+    correction_frames: bool,
+    /// Correction frames awaiting dispatch.
+    corrections: VecDeque<AggregatedFrame<D>>,
+    /// Determines how messages are assigned to frames, and how their metadata is checked and merged.
+    validation: FrameValidationOptions,
     /// The partial frames currently in the cache.
     frames: VecDeque<PartialFrame<D>>,
 }
@@ -28,15 +61,20 @@ where
 {
     /// Creates and returns a new [FrameCache] instance.
     /// # Parameters
-    /// - ttl: time-to-live duration
-    /// - expected_digitisers: list of digitisers that form a complete frame.
-    ///
-    /// Note that `expected_digitisers` should be increasing and without duplicates, this is not checked.
-    pub(crate) fn new(ttl: Duration, expected_digitisers: Vec<DigitizerId>) -> Self {
+    /// - ttl: time-to-live of partial frames, which may adapt to the lateness of digitiser messages.
+    /// - expected_digitisers: the digitisers that form a complete frame.
+    pub(crate) fn new(ttl: FrameTtl, expected_digitisers: ExpectedDigitisers) -> Self {
         Self {
             ttl,
             expected_digitisers,
-            latest_timestamp_dispatched: None,
+            latest_timestamp_dispatched: Default::default(),
+            sharded: false,
+            timestamp_window: TimestampWindow::new(None),
+            recently_dispatched: Default::default(),
+            dispatched_history: 0,
+            correction_frames: false,
+            corrections: Default::default(),
+            validation: Default::default(),
             frames: Default::default(),
         }
     }
@@ -45,14 +83,72 @@ where
     /// If a partial frame with the same `metadata` already exists, and is yet
     /// to receive a message with the same `digitiser_id`, then `data` is added
     /// to the partial frame, otherwise a new [PartialFrame] is created.
+    ///
+    /// The message's `offset` is kept with the frame, so that it is only committed once the frame has been delivered.
+    ///
+    /// The message is rejected if its timestamp lies outside the window of the `broker_timestamp`
+    /// or the recent median, if it belongs to a frame which has already been dispatched, or if its
+    /// metadata disagrees with that of its frame and the veto merge policy is `reject`.
+    /// If correction frames are enabled, a message belonging to a recently dispatched frame which lacked
+    /// its digitiser is instead made into a correction frame, which is returned by the next call to [Self::poll].
     #[tracing::instrument(skip_all, level = "trace")]
     pub(crate) fn push<'a>(
         &'a mut self,
         digitiser_id: DigitizerId,
         metadata: &FrameMetadata,
         data: D,
+        offset: MessageOffset,
+        broker_timestamp: Option<DateTime<Utc>>,
     ) -> Result<(), RejectMessageError> {
-        if let Some(latest_timestamp_dispatched) = self.latest_timestamp_dispatched {
+        // A message from a digitiser marked absent shows it has returned, even if the message is rejected.
+        if self.expected_digitisers.record_message(digitiser_id) {
+            self.update_completion_status();
+        }
+
+        if !self
+            .timestamp_window
+            .accept(metadata.timestamp, broker_timestamp)
+        {
+            warn!(
+                "Frame's timestamp {0} is outside the window of the reference time, quarantining",
+                metadata.timestamp
+            );
+            return Err(RejectMessageError::TimestampQuarantined);
+        }
+
+        if let Some(dispatched) = self
+            .recently_dispatched
+            .iter_mut()
+            .find(|dispatched| self.validation.same_frame(&dispatched.metadata, metadata))
+        {
+            if dispatched.digitiser_ids.contains(&digitiser_id) {
+                warn!("Dispatched frame already has digitiser id: {digitiser_id}");
+                return Err(RejectMessageError::IdAlreadyPresent);
+            }
+            warn!(
+                "Message from digitiser {digitiser_id} arrived after its frame was dispatched: {0}",
+                metadata.timestamp
+            );
+            if !self.correction_frames {
+                return Err(RejectMessageError::LateForDispatchedFrame);
+            }
+            dispatched.digitiser_ids.push(digitiser_id);
+
+            let mut frame = PartialFrame::<D>::new(Duration::ZERO, metadata.clone());
+            if let Err(e) = frame.span_init() {
+                warn!("Frame span initiation failed {e}")
+            }
+            frame.push(digitiser_id, metadata, data, offset);
+            let mut correction = AggregatedFrame::from(frame);
+            correction.correction = true;
+            correction.partition = self.shard(offset);
+            self.corrections.push_back(correction);
+            return Ok(());
+        }
+
+        if let Some(&latest_timestamp_dispatched) =
+            self.latest_timestamp_dispatched.get(&self.shard(offset))
+        {
             if metadata.timestamp <= latest_timestamp_dispatched {
                 warn!(
                     "Frame's timestamp earlier than or equal to the latest frame dispatched: {0} <= {1}",
@@ -65,27 +161,36 @@ where
             match self
                 .frames
                 .iter_mut()
-                .find(|frame| frame.metadata.equals_ignoring_veto_flags(metadata))
+                .find(|frame| self.validation.same_frame(&frame.metadata, metadata))
             {
                 Some(frame) => {
                     if frame.has_digitiser_id(digitiser_id) {
                         warn!("Frame already has digitiser id: {digitiser_id}");
                         return Err(RejectMessageError::IdAlreadyPresent);
                     }
-                    frame.push(digitiser_id, data);
-                    frame.push_veto_flags(metadata.veto_flags);
-                    frame.set_completion_status(&self.expected_digitisers);
+                    if !self.validation.accepts(&frame.metadata, metadata) {
+                        warn!(
+                            "Metadata from digitiser {digitiser_id} disagrees with that of its frame: {0}",
+                            metadata.timestamp
+                        );
+                        return Err(RejectMessageError::InconsistentMetadata);
+                    }
+                    self.ttl.record(digitiser_id, frame.age());
+                    frame.push(digitiser_id, metadata, data, offset);
+                    frame.set_completion_status(self.expected_digitisers.current());
                     frame
                 }
                 None => {
-                    let mut frame = PartialFrame::<D>::new(self.ttl, metadata.clone());
+                    let mut frame = PartialFrame::<D>::new(self.ttl.get(), metadata.clone());
 
                     // Initialise the span field
                     if let Err(e) = frame.span_init() {
                         warn!("Frame span initiation failed {e}")
                     }
 
-                    frame.push(digitiser_id, data);
+                    self.ttl.record(digitiser_id, Duration::ZERO);
+                    frame.push(digitiser_id, metadata, data, offset);
+                    frame.set_completion_status(self.expected_digitisers.current());
                     self.frames.push_back(frame);
                     self.frames
                         .back()
@@ -119,13 +224,17 @@ where
     /// has a complete complement of digitisers, or has been in the cache past its expiry time.
     /// If one is found it is removed from the cache and returned as an [AggregatedFrame].
     pub(crate) fn poll(&mut self) -> Option<AggregatedFrame<D>> {
+        if let Some(correction) = self.corrections.pop_front() {
+            return Some(correction);
+        }
+
         // Find a frame which is completed
         if self
             .frames
             .front()
             .is_some_and(|frame| frame.is_complete() || frame.is_expired())
         {
-            let frame = self
+            let mut frame = self
                 .frames
                 .pop_front()
                 .expect("self.frames should be non-empty, this should never fail");
@@ -133,29 +242,178 @@ where
                 warn!("Frame span drop failed {e}")
             }
 
-            // This frame is the next to be set to latest timestamp dispatched
-            self.latest_timestamp_dispatched = Some(frame.metadata.timestamp);
-            Some(frame.into())
+            if !frame.is_complete() {
+                self.ttl.record_missing(
+                    self.expected_digitisers
+                        .current()
+                        .iter()
+                        .copied()
+                        .filter(|&id| !frame.has_digitiser_id(id)),
+                );
+            }
+
+            if self
+                .expected_digitisers
+                .record_frame(&frame.digitiser_ids())
+            {
+                self.update_completion_status();
+            }
+
+            if self.dispatched_history > 0 {
+                if self.recently_dispatched.len() >= self.dispatched_history {
+                    self.recently_dispatched.pop_front();
+                }
+                self.recently_dispatched.push_back(DispatchedFrame {
+                    metadata: frame.metadata.clone(),
+                    digitiser_ids: frame.digitiser_ids(),
+                });
+            }
+
+            // This frame is the next to be set to latest timestamp dispatched from its shard
+            let shard = frame.offsets.first().and_then(|offset| self.shard(*offset));
+            self.latest_timestamp_dispatched
+                .insert(shard, frame.metadata.timestamp);
+            let digitiser_metadata = std::mem::take(&mut frame.digitiser_metadata);
+            let mut frame = AggregatedFrame::from(frame);
+            frame.partition = shard;
+            if let Some(merged) = self.validation.merge(&digitiser_metadata) {
+                frame.metadata = merged.metadata;
+                frame.inconsistent_digitiser_ids = merged.inconsistent_digitisers;
+                frame.metadata_disagreements = merged.disagreements;
+            }
+            Some(frame)
         } else {
             None
         }
     }
 
+    /// Sets the window within which message timestamps must lie of the broker time, or of the recent median
+    /// if the message has no broker time. Messages outside the window are quarantined.
+    pub(crate) fn set_timestamp_window(&mut self, window: Duration) {
+        self.timestamp_window = TimestampWindow::new(Some(window));
+    }
+
+    /// Sets the number of dispatched frames which are remembered, so that messages arriving
+    /// after their frame has been dispatched are identified as late.
+    pub(crate) fn set_dispatched_history(&mut self, dispatched_history: usize) {
+        self.dispatched_history = dispatched_history;
+        while self.recently_dispatched.len() > dispatched_history {
+            self.recently_dispatched.pop_front();
+        }
+    }
+
+    /// Sets whether late messages for recently dispatched frames are dispatched as correction frames.
+    pub(crate) fn set_correction_frames(&mut self, correction_frames: bool) {
+        self.correction_frames = correction_frames;
+    }
+
+    /// Sets how messages are assigned to frames, and how the metadata of the messages of each frame is checked and merged.
+    pub(crate) fn set_validation(&mut self, validation: FrameValidationOptions) {
+        self.validation = validation;
+    }
+
+    /// Replaces the digitisers that form a complete frame.
+    ///
+    /// Partial frames already in the cache which are complete with respect to the new digitisers are marked as such.
+    pub(crate) fn set_expected_digitisers(&mut self, expected_digitisers: Vec<DigitizerId>) {
+        self.expected_digitisers.set(expected_digitisers);
+        self.update_completion_status();
+    }
+
+    /// Updates the completion status of every partial frame, after a change to the expected digitisers.
+    fn update_completion_status(&mut self) {
+        for frame in &mut self.frames {
+            frame.set_completion_status(self.expected_digitisers.current());
+        }
+    }
+
     /// Returns the number of partial frames currently in the cache.
     pub(crate) fn get_num_partial_frames(&self) -> usize {
         self.frames.len()
     }
+
+    /// Sets the timestamp of the last frame dispatched from a shard before the component was restarted,
+    /// or before the shard was assigned to it, so that messages consumed again which belong to that frame,
+    /// or earlier ones, are not dispatched twice.
+    ///
+    /// The timestamp is ignored if a later frame has already been dispatched from the shard.
+    /// # Parameters
+    /// - shard: the partition of the input topic if sharded, otherwise [None].
+    /// - timestamp: the timestamp of the last frame dispatched.
+    pub(crate) fn resume_after(&mut self, shard: Option<i32>, timestamp: DateTime<Utc>) {
+        self.latest_timestamp_dispatched
+            .entry(shard)
+            .and_modify(|latest| *latest = timestamp.max(*latest))
+            .or_insert(timestamp);
+    }
+
+    /// Removes, without dispatching them, the partial frames and correction frames containing
+    /// data from any of the given partitions, which have been revoked by a rebalance.
+    ///
+    /// The messages of these frames are consumed again by the instance the partitions are assigned to.
+    /// If sharded, the timestamp of the last frame dispatched from each revoked partition is also forgotten.
+    /// # Return
+    /// The offsets of the messages of the removed frames.
+    pub(crate) fn evict_partitions(&mut self, partitions: &[i32]) -> Vec<MessageOffset> {
+        let revoked = |offsets: &[MessageOffset]| {
+            offsets
+                .iter()
+                .any(|offset| partitions.contains(&offset.partition))
+        };
+
+        let (removed, kept) = std::mem::take(&mut self.frames)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|frame| revoked(&frame.offsets));
+        self.frames = kept;
+        let mut evicted = removed
+            .into_iter()
+            .flat_map(|frame| frame.offsets)
+            .collect::<Vec<_>>();
+
+        let (removed, kept) = std::mem::take(&mut self.corrections)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|frame| revoked(&frame.offsets));
+        self.corrections = kept;
+        evicted.extend(removed.into_iter().flat_map(|frame| frame.offsets));
+
+        if self.sharded {
+            for partition in partitions {
+                self.latest_timestamp_dispatched.remove(&Some(*partition));
+            }
+        }
+        evicted
+    }
+
+    /// Sets whether the input topic is partitioned by frame.
+    ///
+    /// If so, the timestamp of the last frame dispatched is tracked separately for each partition,
+    /// so that messages from a partition which lags behind the others are not rejected, and each frame
+    /// is dispatched to the partition of the output topic with the same number as that of its messages.
+    pub(crate) fn set_sharded(&mut self, sharded: bool) {
+        self.sharded = sharded;
+    }
+
+    /// Returns the shard to which a message belongs.
+    fn shard(&self, offset: MessageOffset) -> Option<i32> {
+        self.sharded.then_some(offset.partition)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::data::EventData;
+    use crate::{
+        data::EventData,
+        frame::validation::{VetoMergePolicy, disagreement},
+    };
     use chrono::Utc;
 
     #[test]
     fn one_frame_in_one_frame_out() {
-        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 4, 8]);
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1, 4, 8], None),
+        );
 
         let frame_1 = FrameMetadata {
             timestamp: Utc::now(),
@@ -171,7 +429,13 @@ mod test {
         assert_eq!(cache.get_num_partial_frames(), 0);
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0, 1, 2]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
         assert_eq!(cache.get_num_partial_frames(), 1);
@@ -180,7 +444,13 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[3, 4, 5]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -188,7 +458,13 @@ mod test {
 
         assert!(
             cache
-                .push(4, &frame_1, EventData::dummy_data(0, 5, &[6, 7, 8]))
+                .push(
+                    4,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[6, 7, 8]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -196,7 +472,13 @@ mod test {
 
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[9, 10, 11]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -233,7 +515,10 @@ mod test {
 
     #[tokio::test]
     async fn one_frame_in_one_frame_out_missing_digitiser_timeout() {
-        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 4, 8]);
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1, 4, 8], None),
+        );
 
         let frame_1 = FrameMetadata {
             timestamp: Utc::now(),
@@ -248,7 +533,13 @@ mod test {
 
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0, 1, 2]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -256,7 +547,13 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[3, 4, 5]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -264,7 +561,13 @@ mod test {
 
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[9, 10, 11]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -302,7 +605,10 @@ mod test {
 
     #[tokio::test]
     async fn one_frame_in_one_frame_out_missing_digitiser_and_late_message_timeout() {
-        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1, 4, 8]);
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1, 4, 8], None),
+        );
 
         let frame_1 = FrameMetadata {
             timestamp: Utc::now(),
@@ -314,17 +620,35 @@ mod test {
         };
         assert!(
             cache
-                .push(0, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0, 1, 2]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[3, 4, 5]))
+                .push(
+                    1,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[3, 4, 5]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
         assert!(
             cache
-                .push(8, &frame_1, EventData::dummy_data(0, 5, &[9, 10, 11]))
+                .push(
+                    8,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[9, 10, 11]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
 
@@ -335,14 +659,23 @@ mod test {
         //  This call to push should return an error
         assert!(
             cache
-                .push(4, &frame_1, EventData::dummy_data(0, 5, &[6, 7, 8]))
+                .push(
+                    4,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[6, 7, 8]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_err()
         );
     }
 
     #[test]
     fn test_metadata_equality() {
-        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![1, 2]);
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![1, 2], None),
+        );
 
         let timestamp = Utc::now();
         let frame_1 = FrameMetadata {
@@ -370,7 +703,13 @@ mod test {
 
         assert!(
             cache
-                .push(1, &frame_1, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    1,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0, 1, 2]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
         assert_eq!(cache.frames.len(), 1);
@@ -378,10 +717,583 @@ mod test {
 
         assert!(
             cache
-                .push(2, &frame_2, EventData::dummy_data(0, 5, &[0, 1, 2]))
+                .push(
+                    2,
+                    &frame_2,
+                    EventData::dummy_data(0, 5, &[0, 1, 2]),
+                    MessageOffset::default(),
+                    None
+                )
                 .is_ok()
         );
         assert_eq!(cache.frames.len(), 1);
         assert!(cache.poll().is_some());
     }
+
+    #[test]
+    fn aggregated_frame_keeps_offsets() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], None),
+        );
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let offsets = [0, 1].map(|offset| MessageOffset {
+            partition: 2,
+            offset,
+        });
+
+        for (digitiser_id, offset) in offsets.iter().enumerate() {
+            assert!(
+                cache
+                    .push(
+                        digitiser_id as DigitizerId,
+                        &frame_1,
+                        EventData::dummy_data(0, 5, &[0]),
+                        *offset,
+                        None
+                    )
+                    .is_ok()
+            );
+        }
+
+        let frame = cache.poll().unwrap();
+        assert_eq!(frame.offsets, offsets);
+    }
+
+    #[test]
+    fn resumed_cache_rejects_dispatched_frames() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], None),
+        );
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        cache.resume_after(None, frame_1.timestamp);
+
+        assert!(matches!(
+            cache.push(
+                0,
+                &frame_1,
+                EventData::dummy_data(0, 5, &[0]),
+                MessageOffset::default(),
+                None
+            ),
+            Err(RejectMessageError::TimestampTooEarly)
+        ));
+        assert_eq!(cache.get_num_partial_frames(), 0);
+    }
+
+    #[tokio::test]
+    async fn frame_completes_without_absent_digitiser() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], Some(1)),
+        );
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_2 = FrameMetadata {
+            frame_number: 1729,
+            timestamp: frame_1.timestamp + Duration::from_millis(20),
+            ..frame_1.clone()
+        };
+
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    None
+                )
+                .is_ok()
+        );
+        tokio::time::sleep(Duration::from_millis(105)).await;
+        assert!(!cache.poll().unwrap().complete);
+
+        // Digitiser 1 is now absent, so the next frame is complete without it.
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_2,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    None
+                )
+                .is_ok()
+        );
+        assert!(cache.poll().unwrap().complete);
+    }
+
+    #[test]
+    fn late_message_for_dispatched_frame() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0], None),
+        );
+        cache.set_dispatched_history(1);
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_2 = FrameMetadata {
+            frame_number: 1729,
+            timestamp: frame_1.timestamp + Duration::from_millis(20),
+            ..frame_1.clone()
+        };
+
+        for frame in [&frame_1, &frame_2] {
+            assert!(
+                cache
+                    .push(
+                        0,
+                        frame,
+                        EventData::dummy_data(0, 5, &[0]),
+                        MessageOffset::default(),
+                        None
+                    )
+                    .is_ok()
+            );
+            assert!(cache.poll().is_some());
+        }
+
+        assert!(matches!(
+            cache.push(
+                1,
+                &frame_2,
+                EventData::dummy_data(0, 5, &[0]),
+                MessageOffset::default(),
+                None
+            ),
+            Err(RejectMessageError::LateForDispatchedFrame)
+        ));
+        // Only the most recent frame is remembered.
+        assert!(matches!(
+            cache.push(
+                1,
+                &frame_1,
+                EventData::dummy_data(0, 5, &[0]),
+                MessageOffset::default(),
+                None
+            ),
+            Err(RejectMessageError::TimestampTooEarly)
+        ));
+    }
+
+    #[test]
+    fn future_timestamp_is_quarantined() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0], None),
+        );
+        cache.set_timestamp_window(Duration::from_secs(1));
+
+        let now = Utc::now();
+        let future_frame = FrameMetadata {
+            timestamp: now + Duration::from_secs(3600),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_1 = FrameMetadata {
+            timestamp: now,
+            frame_number: 1729,
+            ..future_frame.clone()
+        };
+
+        assert!(matches!(
+            cache.push(
+                0,
+                &future_frame,
+                EventData::dummy_data(0, 5, &[0]),
+                MessageOffset::default(),
+                Some(now)
+            ),
+            Err(RejectMessageError::TimestampQuarantined)
+        ));
+        assert_eq!(cache.get_num_partial_frames(), 0);
+
+        // Correctly stamped messages are still accepted.
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    Some(now)
+                )
+                .is_ok()
+        );
+        assert!(cache.poll().is_some());
+    }
+
+    #[test]
+    fn late_message_made_into_correction_frame() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0], None),
+        );
+        cache.set_dispatched_history(1);
+        cache.set_correction_frames(true);
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let offset = MessageOffset {
+            partition: 0,
+            offset: 7,
+        };
+
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    None
+                )
+                .is_ok()
+        );
+        assert!(!cache.poll().unwrap().correction);
+
+        assert!(
+            cache
+                .push(1, &frame_1, EventData::dummy_data(0, 5, &[1]), offset, None)
+                .is_ok()
+        );
+        let correction = cache.poll().unwrap();
+        assert!(correction.correction);
+        assert_eq!(correction.metadata, frame_1);
+        assert_eq!(correction.digitiser_ids, [1]);
+        assert_eq!(correction.offsets, [offset]);
+        assert!(cache.poll().is_none());
+
+        // Each digitiser's data is only dispatched once.
+        assert!(matches!(
+            cache.push(
+                1,
+                &frame_1,
+                EventData::dummy_data(0, 5, &[1]),
+                MessageOffset::default(),
+                None
+            ),
+            Err(RejectMessageError::IdAlreadyPresent)
+        ));
+    }
+
+    #[test]
+    fn sharded_cache_tracks_partitions_separately() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0], None),
+        );
+        cache.set_sharded(true);
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_2 = FrameMetadata {
+            frame_number: 1729,
+            timestamp: frame_1.timestamp + Duration::from_millis(20),
+            ..frame_1.clone()
+        };
+        let [offset_1, offset_2] = [1, 0].map(|partition| MessageOffset {
+            partition,
+            offset: 0,
+        });
+
+        // Frame 2 is dispatched from partition 0 before frame 1 arrives on partition 1.
+        for (frame, offset) in [(&frame_2, offset_2), (&frame_1, offset_1)] {
+            assert!(
+                cache
+                    .push(0, frame, EventData::dummy_data(0, 5, &[0]), offset, None)
+                    .is_ok()
+            );
+            let dispatched = cache.poll().unwrap();
+            assert_eq!(dispatched.metadata, *frame);
+            assert_eq!(dispatched.partition, Some(offset.partition));
+        }
+
+        // Each partition still rejects its own earlier frames.
+        assert!(matches!(
+            cache.push(
+                0,
+                &frame_1,
+                EventData::dummy_data(0, 5, &[0]),
+                offset_2,
+                None
+            ),
+            Err(RejectMessageError::TimestampTooEarly)
+        ));
+    }
+
+    #[tokio::test]
+    async fn revoked_partitions_evicted_without_dispatch() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], None),
+        );
+        cache.set_sharded(true);
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let frame_2 = FrameMetadata {
+            frame_number: 1729,
+            timestamp: frame_1.timestamp + Duration::from_millis(20),
+            ..frame_1.clone()
+        };
+        let frame_3 = FrameMetadata {
+            frame_number: 1730,
+            timestamp: frame_1.timestamp + Duration::from_millis(40),
+            ..frame_1.clone()
+        };
+        let offset = |partition, offset| MessageOffset { partition, offset };
+
+        // Frame 1 is dispatched from partition 0, then frames 2 and 3 are partially assembled.
+        for id in [0, 1] {
+            assert!(
+                cache
+                    .push(
+                        id,
+                        &frame_1,
+                        EventData::dummy_data(0, 5, &[0]),
+                        offset(0, id.into()),
+                        None
+                    )
+                    .is_ok()
+            );
+        }
+        assert_eq!(cache.poll().unwrap().metadata, frame_1);
+        for (frame, offset) in [(&frame_2, offset(0, 2)), (&frame_3, offset(1, 0))] {
+            assert!(
+                cache
+                    .push(0, frame, EventData::dummy_data(0, 5, &[0]), offset, None)
+                    .is_ok()
+            );
+        }
+
+        assert_eq!(cache.evict_partitions(&[0]), [offset(0, 2)]);
+        assert_eq!(cache.get_num_partial_frames(), 1);
+
+        // The last frame dispatched from the revoked partition is forgotten, until found when it is assigned again.
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0]),
+                    offset(0, 0),
+                    None
+                )
+                .is_ok()
+        );
+        cache.evict_partitions(&[0]);
+        cache.resume_after(Some(0), frame_1.timestamp);
+        assert!(matches!(
+            cache.push(
+                0,
+                &frame_1,
+                EventData::dummy_data(0, 5, &[0]),
+                offset(0, 0),
+                None
+            ),
+            Err(RejectMessageError::TimestampTooEarly)
+        ));
+
+        tokio::time::sleep(Duration::from_millis(105)).await;
+        let frame = cache.poll().unwrap();
+        assert_eq!(frame.metadata, frame_3);
+        assert!(cache.poll().is_none());
+    }
+
+    #[test]
+    fn veto_flags_of_every_digitiser_kept_by_default() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], None),
+        );
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 0,
+        };
+        let vetoed = FrameMetadata {
+            veto_flags: 4,
+            ..frame_1.clone()
+        };
+
+        // The veto is raised by the second digitiser only
+        for (id, metadata) in [(0, &frame_1), (1, &vetoed)] {
+            assert!(
+                cache
+                    .push(
+                        id,
+                        metadata,
+                        EventData::dummy_data(0, 5, &[0]),
+                        MessageOffset::default(),
+                        None
+                    )
+                    .is_ok()
+            );
+        }
+        assert_eq!(cache.get_num_partial_frames(), 1);
+
+        let frame = cache.poll().unwrap();
+        assert!(frame.complete);
+        assert_eq!(frame.metadata, frame_1);
+        assert_eq!(frame.metadata.veto_flags, 4);
+        assert!(frame.inconsistent_digitiser_ids.is_empty());
+        assert_eq!(frame.metadata_disagreements, 0);
+    }
+
+    #[test]
+    fn inconsistent_digitisers_recorded_in_merged_frame() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1, 2], None),
+        );
+        cache.set_validation(FrameValidationOptions {
+            veto_merge_policy: Some(VetoMergePolicy::And),
+            check_frame_consistency: true,
+        });
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let other_period = FrameMetadata {
+            period_number: 2,
+            veto_flags: 6,
+            ..frame_1.clone()
+        };
+
+        for (id, metadata) in [(0, &other_period), (1, &frame_1), (2, &frame_1)] {
+            assert!(
+                cache
+                    .push(
+                        id,
+                        metadata,
+                        EventData::dummy_data(0, 5, &[0]),
+                        MessageOffset::default(),
+                        None
+                    )
+                    .is_ok()
+            );
+        }
+        assert_eq!(cache.get_num_partial_frames(), 1);
+
+        let frame = cache.poll().unwrap();
+        assert!(frame.complete);
+        assert_eq!(frame.metadata.period_number, 1);
+        assert_eq!(frame.metadata.veto_flags, 4);
+        assert_eq!(frame.inconsistent_digitiser_ids, [0]);
+        assert_eq!(
+            frame.metadata_disagreements,
+            disagreement::PERIOD_NUMBER | disagreement::VETO_FLAGS
+        );
+    }
+
+    #[test]
+    fn reject_policy_rejects_inconsistent_message() {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0, 1], None),
+        );
+        cache.set_validation(FrameValidationOptions {
+            veto_merge_policy: Some(VetoMergePolicy::Reject),
+            check_frame_consistency: false,
+        });
+
+        let frame_1 = FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags: 4,
+        };
+        let other_veto_flags = FrameMetadata {
+            veto_flags: 5,
+            ..frame_1.clone()
+        };
+
+        assert!(
+            cache
+                .push(
+                    0,
+                    &frame_1,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    None
+                )
+                .is_ok()
+        );
+        assert!(matches!(
+            cache.push(
+                1,
+                &other_veto_flags,
+                EventData::dummy_data(0, 5, &[1]),
+                MessageOffset::default(),
+                None
+            ),
+            Err(RejectMessageError::InconsistentMetadata)
+        ));
+        assert!(cache.poll().is_none());
+    }
 }
@@ -0,0 +1,158 @@
+//! Defines the set of digitisers from which a frame must receive messages to be complete.
+use const_format::concatcp;
+use metrics::gauge;
+use std::collections::HashMap;
+use supermusr_common::{DigitizerId, metrics::names::METRIC_NAME_PREFIX};
+use tracing::{info, warn};
+
+/// The number of digitisers which a frame currently requires to be complete.
+pub(crate) const EXPECTED_DIGITISERS: &str = concatcp!(METRIC_NAME_PREFIX, "expected_digitisers");
+
+/// The digitisers expected to contribute to each frame.
+///
+/// The configured set can be replaced whilst running. Optionally, a configured digitiser which is missing from
+/// a number of consecutive frames is marked absent, and is no longer expected until it sends another message.
+pub(crate) struct ExpectedDigitisers {
+    /// The digitisers configured to be expected.
+    configured: Vec<DigitizerId>,
+    /// If [Some], a digitiser missing from this many consecutive frames is marked absent.
+    absent_after_frames: Option<usize>,
+    /// The number of consecutive dispatched frames from which each configured digitiser has been missing.
+    missed_frames: HashMap<DigitizerId, usize>,
+    /// The configured digitisers which are not marked absent, in increasing order.
+    current: Vec<DigitizerId>,
+}
+
+impl ExpectedDigitisers {
+    /// Creates and returns a new [ExpectedDigitisers] instance.
+    /// # Parameters
+    /// - configured: the digitisers expected to contribute to each frame.
+    /// - absent_after_frames: if [Some], a digitiser missing from this many consecutive frames is marked absent.
+    pub(crate) fn new(configured: Vec<DigitizerId>, absent_after_frames: Option<usize>) -> Self {
+        let mut expected = Self {
+            configured: Vec::new(),
+            absent_after_frames,
+            missed_frames: Default::default(),
+            current: Vec::new(),
+        };
+        expected.set(configured);
+        expected
+    }
+
+    /// Replaces the configured digitisers, clearing any which were marked absent.
+    pub(crate) fn set(&mut self, mut configured: Vec<DigitizerId>) {
+        configured.sort();
+        configured.dedup();
+        info!("Expected digitisers set to {configured:?}");
+        self.configured = configured;
+        self.missed_frames.clear();
+        self.update_current();
+    }
+
+    /// Returns the digitisers currently expected, in increasing order.
+    pub(crate) fn current(&self) -> &[DigitizerId] {
+        &self.current
+    }
+
+    /// Records that a message has been received from a digitiser.
+    /// # Return
+    /// `true` if the digitiser had been marked absent, and is now expected again.
+    pub(crate) fn record_message(&mut self, digitiser_id: DigitizerId) -> bool {
+        let was_absent = self.is_absent(digitiser_id);
+        self.missed_frames.remove(&digitiser_id);
+        if was_absent {
+            info!("Digitiser {digitiser_id} has returned, and is expected again");
+            self.update_current();
+        }
+        was_absent
+    }
+
+    /// Records the digitisers which contributed to a dispatched frame.
+    /// # Return
+    /// `true` if any digitiser has been newly marked absent.
+    pub(crate) fn record_frame(&mut self, digitiser_ids: &[DigitizerId]) -> bool {
+        let Some(absent_after_frames) = self.absent_after_frames else {
+            return false;
+        };
+        let mut changed = false;
+        for &id in &self.configured {
+            if digitiser_ids.contains(&id) {
+                continue;
+            }
+            let missed = self.missed_frames.entry(id).or_default();
+            *missed += 1;
+            if *missed == absent_after_frames {
+                warn!(
+                    "Digitiser {id} has been missing from {missed} consecutive frames, marking absent"
+                );
+                changed = true;
+            }
+        }
+        if changed {
+            self.update_current();
+        }
+        changed
+    }
+
+    fn is_absent(&self, digitiser_id: DigitizerId) -> bool {
+        self.absent_after_frames.is_some_and(|absent_after_frames| {
+            self.missed_frames
+                .get(&digitiser_id)
+                .is_some_and(|&missed| missed >= absent_after_frames)
+        })
+    }
+
+    fn update_current(&mut self) {
+        self.current = self
+            .configured
+            .iter()
+            .copied()
+            .filter(|&id| !self.is_absent(id))
+            .collect();
+        gauge!(EXPECTED_DIGITISERS).set(self.current.len() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_digitisers_are_sorted() {
+        let expected = ExpectedDigitisers::new(vec![4, 0, 1, 4], None);
+        assert_eq!(expected.current(), [0, 1, 4]);
+    }
+
+    #[test]
+    fn silent_digitiser_is_marked_absent_and_returns() {
+        let mut expected = ExpectedDigitisers::new(vec![0, 1, 2], Some(2));
+
+        assert!(!expected.record_frame(&[0, 1]));
+        assert_eq!(expected.current(), [0, 1, 2]);
+        assert!(expected.record_frame(&[0, 1]));
+        assert_eq!(expected.current(), [0, 1]);
+
+        assert!(!expected.record_message(1));
+        assert!(expected.record_message(2));
+        assert_eq!(expected.current(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn digitisers_are_not_marked_absent_unless_enabled() {
+        let mut expected = ExpectedDigitisers::new(vec![0, 1], None);
+        for _ in 0..10 {
+            assert!(!expected.record_frame(&[0]));
+        }
+        assert_eq!(expected.current(), [0, 1]);
+    }
+
+    #[test]
+    fn setting_digitisers_clears_absence() {
+        let mut expected = ExpectedDigitisers::new(vec![0, 1], Some(1));
+        expected.record_frame(&[0]);
+        assert_eq!(expected.current(), [0]);
+
+        expected.set(vec![0, 1, 2]);
+        assert_eq!(expected.current(), [0, 1, 2]);
+    }
+}
@@ -4,17 +4,41 @@
 //! defined in the [crate::data] module.
 mod aggregated;
 mod cache;
+mod expected;
 mod partial;
+mod timestamp;
+mod ttl;
+mod validation;
+
+use const_format::concatcp;
+use supermusr_common::metrics::names::METRIC_NAME_PREFIX;
 
 pub(crate) use aggregated::AggregatedFrame;
 pub(crate) use cache::FrameCache;
+pub(crate) use expected::{EXPECTED_DIGITISERS, ExpectedDigitisers};
+pub(crate) use ttl::{AdaptiveTtlOptions, DIGITISER_LATENESS, FRAME_TTL, FrameTtl};
+pub(crate) use validation::{FRAME_METADATA_DISAGREEMENTS, FrameValidationOptions};
+
+/// Number of digitiser messages rejected by the cache, labelled by the reason for rejection.
+pub(crate) const REJECTED_MESSAGES: &str = concatcp!(METRIC_NAME_PREFIX, "rejected_messages");
+
+/// Number of correction frames, containing late data for frames already dispatched, sent by the aggregator.
+pub(crate) const CORRECTION_FRAMES_SENT: &str =
+    concatcp!(METRIC_NAME_PREFIX, "correction_frames_sent");
 
 /// Represents the reason why a digitiser event list message is rejected
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum RejectMessageError {
     /// The frame has already encountered an event list from this digitiser.
     IdAlreadyPresent,
     /// The event list's timestamp occurs before [FrameCache::latest_timestamp_dispatched].
     TimestampTooEarly,
+    /// The event list's timestamp lies outside the window of the broker time or the recent median.
+    TimestampQuarantined,
+    /// The event list belongs to a frame which has recently been dispatched.
+    LateForDispatchedFrame,
+    /// The event list's metadata disagrees with that of its frame, and the veto merge policy rejects disagreements.
+    InconsistentMetadata,
 }
 
 impl From<RejectMessageError> for &'static str {
@@ -22,6 +46,9 @@ impl From<RejectMessageError> for &'static str {
         match value {
             RejectMessageError::IdAlreadyPresent => "id_already_present",
             RejectMessageError::TimestampTooEarly => "timestamp_too_early",
+            RejectMessageError::TimestampQuarantined => "timestamp_quarantined",
+            RejectMessageError::LateForDispatchedFrame => "late_for_dispatched_frame",
+            RejectMessageError::InconsistentMetadata => "inconsistent_metadata",
         }
     }
 }
@@ -1,5 +1,5 @@
 //! Defines the struct for a frame which is awaiting data from digitiser messages.
-use crate::data::DigitiserData;
+use crate::{data::DigitiserData, offsets::MessageOffset};
 use std::time::Duration;
 use supermusr_common::{
     DigitizerId,
@@ -17,25 +17,34 @@ pub(crate) struct PartialFrame<D> {
     span: SpanOnce,
     /// IS `true` if and only if all expected digitiser messages have been collected.
     complete: bool,
+    /// Time at which the partial frame was created, on receipt of its first digitiser message.
+    created: Instant,
     /// Time at which the partial frame should be considered expired, and can be dispatched
     /// from the cache even if incomplete.
     expiry: Instant,
-    /// The uniquely identifying metadata of the frame, common to all digitiser messages related to this frame (except possibly for [FrameMetadata::veto_flags]).
+    /// The metadata of the frame's first digitiser message, which identifies the frame.
     pub(super) metadata: FrameMetadata,
+    /// The metadata of each digitiser message from which the frame is built, in order of arrival.
+    pub(super) digitiser_metadata: Vec<(DigitizerId, FrameMetadata)>,
     /// The frame's event data.
     pub(super) digitiser_data: DigitiserData<D>,
+    /// The offsets of the digitiser messages from which the frame is built.
+    pub(super) offsets: Vec<MessageOffset>,
 }
 
 impl<D> PartialFrame<D> {
     pub(super) fn new(ttl: Duration, metadata: FrameMetadata) -> Self {
-        let expiry = Instant::now() + ttl;
+        let created = Instant::now();
 
         Self {
             span: SpanOnce::default(),
             complete: false,
-            expiry,
+            created,
+            expiry: created + ttl,
             metadata,
+            digitiser_metadata: Default::default(),
             digitiser_data: Default::default(),
+            offsets: Default::default(),
         }
     }
 
@@ -67,18 +76,20 @@ impl<D> PartialFrame<D> {
     /// Pushes the given data from a digitser to the frame.
     /// # Parameters
     /// - digitiser_id: the id of the digitiser sending the data.
+    /// - metadata: the metadata of the message, which is merged with that of the other messages when the frame is dispatched.
     /// - data: the data in the message.
-    pub(super) fn push(&mut self, digitiser_id: DigitizerId, data: D) {
+    /// - offset: the offset of the message.
+    pub(super) fn push(
+        &mut self,
+        digitiser_id: DigitizerId,
+        metadata: &FrameMetadata,
+        data: D,
+        offset: MessageOffset,
+    ) {
+        self.digitiser_metadata
+            .push((digitiser_id, metadata.clone()));
         self.digitiser_data.push((digitiser_id, data));
-    }
-
-    /// Ammends the metadata [veto_flags] field with `veto_flags` from a new digitiser message.
-    /// This is necessary until it is determined whether [veto_flags] should be identical accross
-    /// all digitisers during a frame.
-    ///
-    /// [veto_flags]: FrameMetadata::veto_flags
-    pub(super) fn push_veto_flags(&mut self, veto_flags: u16) {
-        self.metadata.veto_flags |= veto_flags;
+        self.offsets.push(offset);
     }
 
     /// Returns value of [self.complete].
@@ -88,6 +99,11 @@ impl<D> PartialFrame<D> {
         self.complete
     }
 
+    /// Returns the time since the partial frame was created.
+    pub(super) fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
     /// Returns `true` if and only if the current time instant is greater than `self.expiry`
     pub(super) fn is_expired(&self) -> bool {
         Instant::now() > self.expiry
@@ -0,0 +1,156 @@
+//! Defines the window within which the timestamps of digitiser messages are trusted.
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{collections::VecDeque, time::Duration};
+
+/// The number of recently accepted timestamps from which the median is taken.
+const NUM_RECENT_TIMESTAMPS: usize = 64;
+
+/// The number of consecutive timestamps rejected against the median after which they replace the recent timestamps.
+const NUM_REJECTED_BEFORE_RESET: usize = 16;
+
+/// Checks that the timestamp of each digitiser message is close to a reference time.
+///
+/// The reference is the broker timestamp of the message if it has one,
+/// and otherwise the median timestamp of recently accepted messages.
+///
+/// Should the timestamps jump, for instance after a gap in the data longer than the window,
+/// every timestamp would be rejected against the median of the earlier ones, so once
+/// [NUM_REJECTED_BEFORE_RESET] consecutive timestamps are rejected, they become the recent timestamps.
+pub(crate) struct TimestampWindow {
+    /// The maximum difference between a timestamp and the reference, or [None] if every timestamp is accepted.
+    window: Option<TimeDelta>,
+    /// Recently accepted timestamps.
+    recent: VecDeque<DateTime<Utc>>,
+    /// Timestamps rejected against the median since one was last accepted.
+    rejected: Vec<DateTime<Utc>>,
+}
+
+impl TimestampWindow {
+    /// Creates and returns a new [TimestampWindow] instance.
+    /// # Parameters
+    /// - window: the maximum difference between a timestamp and the reference, or [None] if every timestamp is accepted.
+    pub(crate) fn new(window: Option<Duration>) -> Self {
+        Self {
+            window: window.map(|window| TimeDelta::from_std(window).unwrap_or(TimeDelta::MAX)),
+            recent: VecDeque::with_capacity(NUM_RECENT_TIMESTAMPS),
+            rejected: Vec::with_capacity(NUM_REJECTED_BEFORE_RESET),
+        }
+    }
+
+    /// Returns `true` if the timestamp lies within the window of the reference, in which case it is also
+    /// used in the median of recent timestamps.
+    /// # Parameters
+    /// - timestamp: the metadata timestamp of a digitiser message.
+    /// - broker_timestamp: the timestamp of the Kafka message, if it has one.
+    pub(crate) fn accept(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        broker_timestamp: Option<DateTime<Utc>>,
+    ) -> bool {
+        let Some(window) = self.window else {
+            return true;
+        };
+        if let Some(reference) = broker_timestamp {
+            let accepted = (timestamp - reference).abs() <= window;
+            if accepted {
+                self.push(timestamp);
+            }
+            return accepted;
+        }
+
+        let accepted = self
+            .median()
+            .is_none_or(|reference| (timestamp - reference).abs() <= window);
+        if accepted {
+            self.push(timestamp);
+        } else {
+            self.rejected.push(timestamp);
+            if self.rejected.len() >= NUM_REJECTED_BEFORE_RESET {
+                self.recent = self.rejected.drain(..).collect();
+            }
+        }
+        accepted
+    }
+
+    /// Adds an accepted timestamp to the recent timestamps.
+    fn push(&mut self, timestamp: DateTime<Utc>) {
+        if self.recent.len() >= NUM_RECENT_TIMESTAMPS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(timestamp);
+        self.rejected.clear();
+    }
+
+    /// Returns the median of the recently accepted timestamps, or [None] if there are none.
+    fn median(&self) -> Option<DateTime<Utc>> {
+        let mut recent = self.recent.iter().copied().collect::<Vec<_>>();
+        recent.sort_unstable();
+        recent.get(recent.len() / 2).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_timestamp_accepted_without_window() {
+        let mut window = TimestampWindow::new(None);
+        let now = Utc::now();
+        assert!(window.accept(now + Duration::from_secs(3600), Some(now)));
+    }
+
+    #[test]
+    fn timestamp_compared_with_broker_time() {
+        let mut window = TimestampWindow::new(Some(Duration::from_secs(1)));
+        let now = Utc::now();
+        assert!(window.accept(now + Duration::from_millis(500), Some(now)));
+        assert!(!window.accept(now + Duration::from_secs(60), Some(now)));
+        assert!(!window.accept(now - Duration::from_secs(60), Some(now)));
+    }
+
+    #[test]
+    fn timestamp_compared_with_recent_median() {
+        let mut window = TimestampWindow::new(Some(Duration::from_secs(1)));
+        let now = Utc::now();
+
+        // The first timestamp cannot be checked.
+        assert!(window.accept(now, None));
+        for n in 1..5 {
+            assert!(window.accept(now + Duration::from_millis(100 * n), None));
+        }
+        assert!(!window.accept(now + Duration::from_secs(60), None));
+        assert!(window.accept(now + Duration::from_millis(600), None));
+    }
+
+    #[test]
+    fn median_follows_gap_longer_than_window() {
+        let mut window = TimestampWindow::new(Some(Duration::from_secs(1)));
+        let now = Utc::now();
+        for n in 0..5 {
+            assert!(window.accept(now + Duration::from_millis(100 * n), None));
+        }
+
+        // After the gap, timestamps are rejected until enough consecutive ones agree.
+        let later = now + Duration::from_secs(600);
+        for n in 0..NUM_REJECTED_BEFORE_RESET as u64 {
+            assert!(!window.accept(later + Duration::from_millis(100 * n), None));
+        }
+        assert!(window.accept(later + Duration::from_millis(1600), None));
+        assert!(!window.accept(now, None));
+    }
+
+    #[test]
+    fn accepted_timestamp_clears_rejections() {
+        let mut window = TimestampWindow::new(Some(Duration::from_secs(1)));
+        let now = Utc::now();
+        assert!(window.accept(now, None));
+
+        // Isolated outliers are never enough to move the median.
+        for _ in 0..NUM_REJECTED_BEFORE_RESET {
+            assert!(!window.accept(now + Duration::from_secs(60), None));
+            assert!(window.accept(now, None));
+        }
+        assert!(!window.accept(now + Duration::from_secs(60), None));
+    }
+}
@@ -0,0 +1,266 @@
+//! Defines the time-to-live of partial frames, which may adapt to how late digitiser messages arrive.
+use clap::Args;
+use const_format::concatcp;
+use metrics::{gauge, histogram};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use supermusr_common::{DigitizerId, metrics::names::METRIC_NAME_PREFIX};
+use tracing::debug;
+
+/// Time from the first message of a frame to the arrival of each digitiser's message, labelled by digitiser id.
+pub(crate) const DIGITISER_LATENESS: &str = concatcp!(METRIC_NAME_PREFIX, "digitiser_lateness");
+/// The TTL given to new partial frames.
+pub(crate) const FRAME_TTL: &str = concatcp!(METRIC_NAME_PREFIX, "frame_ttl");
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct AdaptiveTtlOptions {
+    /// If set, the frame TTL adapts to the lateness of recent digitiser messages, starting from `--frame-ttl-ms`. See README.md.
+    #[clap(long)]
+    pub(crate) adaptive_frame_ttl: bool,
+
+    /// Percentile of the lateness of recent digitiser messages on which the adaptive frame TTL is based, from 0 to 100.
+    #[clap(long, default_value = "99", value_parser = parse_percentile)]
+    pub(crate) frame_ttl_percentile: f64,
+
+    /// The adaptive frame TTL is this multiple of the percentile of lateness.
+    #[clap(long, default_value = "1.5")]
+    pub(crate) frame_ttl_scale: f64,
+
+    /// Number of recent digitiser messages whose lateness is used for the adaptive frame TTL.
+    #[clap(long, default_value = "1000")]
+    pub(crate) frame_ttl_window: usize,
+
+    /// Lower bound of the adaptive frame TTL in milliseconds.
+    #[clap(long, default_value = "50")]
+    pub(crate) frame_ttl_min_ms: u64,
+
+    /// Upper bound of the adaptive frame TTL in milliseconds.
+    #[clap(long, default_value = "5000")]
+    pub(crate) frame_ttl_max_ms: u64,
+
+    /// A digitiser missing from more than this many consecutive expired frames is taken to be absent,
+    /// and no longer counts towards the adaptive frame TTL until its messages arrive again.
+    #[clap(long, default_value = "10")]
+    pub(crate) frame_ttl_absent_frames: usize,
+}
+
+/// Parses a percentile, which must be between 0 and 100 inclusive.
+fn parse_percentile(value: &str) -> Result<f64, String> {
+    let percentile: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=100.0).contains(&percentile) {
+        Ok(percentile)
+    } else {
+        Err(format!("{percentile} is not between 0 and 100"))
+    }
+}
+
+impl AdaptiveTtlOptions {
+    fn min(&self) -> Duration {
+        Duration::from_millis(self.frame_ttl_min_ms)
+    }
+
+    fn max(&self) -> Duration {
+        Duration::from_millis(self.frame_ttl_max_ms)
+    }
+}
+
+/// Records the lateness of digitiser messages, and provides the TTL of new partial frames.
+pub(crate) struct FrameTtl {
+    /// The current TTL.
+    ttl: Duration,
+    /// If [Some], the TTL adapts to the recorded lateness.
+    adaptive: Option<AdaptiveTtl>,
+}
+
+/// The lateness of recent digitiser messages, from which the TTL is derived.
+struct AdaptiveTtl {
+    options: AdaptiveTtlOptions,
+    /// The samples in the order they were recorded.
+    samples: VecDeque<Duration>,
+    /// The same samples in ascending order, so the percentile is found without sorting.
+    sorted: Vec<Duration>,
+    /// The number of consecutive expired frames from which each digitiser has been missing.
+    missing_frames: HashMap<DigitizerId, usize>,
+}
+
+impl AdaptiveTtl {
+    fn push(&mut self, lateness: Duration) {
+        if self.samples.len() >= self.options.frame_ttl_window {
+            if let Some(oldest) = self.samples.pop_front() {
+                if let Ok(index) = self.sorted.binary_search(&oldest) {
+                    self.sorted.remove(index);
+                }
+            }
+        }
+        self.samples.push_back(lateness);
+        let index = self.sorted.partition_point(|&sample| sample <= lateness);
+        self.sorted.insert(index, lateness);
+    }
+
+    /// Returns the configured percentile of the samples, scaled and clamped to the bounds,
+    /// or [None] if there are no samples.
+    fn ttl(&self) -> Option<Duration> {
+        let rank = (self.options.frame_ttl_percentile / 100.0 * self.sorted.len() as f64).ceil();
+        let percentile = self
+            .sorted
+            .get((rank as usize).saturating_sub(1))
+            .copied()?;
+        let ttl = Duration::from_nanos(
+            (percentile.as_nanos() as f64 * self.options.frame_ttl_scale).round() as u64,
+        );
+        Some(ttl.max(self.options.min()).min(self.options.max()))
+    }
+}
+
+impl FrameTtl {
+    /// Creates a [FrameTtl] which does not change.
+    pub(crate) fn fixed(ttl: Duration) -> Self {
+        gauge!(FRAME_TTL).set(ttl);
+        Self {
+            ttl,
+            adaptive: None,
+        }
+    }
+
+    /// Creates a [FrameTtl] which adapts to the lateness of digitiser messages.
+    /// # Parameters
+    /// - initial: the TTL used until the lateness of any message has been recorded.
+    /// - options: determines how the TTL is derived from the lateness.
+    pub(crate) fn adaptive(initial: Duration, options: AdaptiveTtlOptions) -> Self {
+        gauge!(FRAME_TTL).set(initial);
+        Self {
+            ttl: initial,
+            adaptive: Some(AdaptiveTtl {
+                samples: VecDeque::with_capacity(options.frame_ttl_window),
+                sorted: Vec::with_capacity(options.frame_ttl_window),
+                missing_frames: Default::default(),
+                options,
+            }),
+        }
+    }
+
+    /// Returns the TTL for a new partial frame, updating it from the recorded lateness if adaptive.
+    pub(crate) fn get(&mut self) -> Duration {
+        if let Some(ttl) = self.adaptive.as_ref().and_then(AdaptiveTtl::ttl) {
+            if ttl != self.ttl {
+                debug!("Frame TTL changed to {ttl:?}");
+                gauge!(FRAME_TTL).set(ttl);
+            }
+            self.ttl = ttl;
+        }
+        self.ttl
+    }
+
+    /// Records the time between the first message of a frame and the arrival of a digitiser's message.
+    pub(crate) fn record(&mut self, digitiser_id: DigitizerId, lateness: Duration) {
+        histogram!(
+            DIGITISER_LATENESS,
+            &[("digitizer_id", digitiser_id.to_string())]
+        )
+        .record(lateness);
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.missing_frames.remove(&digitiser_id);
+            adaptive.push(lateness);
+        }
+    }
+
+    /// Records that a frame expired without messages from some expected digitisers.
+    ///
+    /// As the lateness of these messages is unknown, it is taken to be the upper bound of the TTL,
+    /// so that a TTL which is too short for a lagging digitiser grows.
+    /// A digitiser which has been missing for more than `--frame-ttl-absent-frames` consecutive frames
+    /// is taken to be absent rather than lagging, so is not counted, lest it hold the TTL at its upper bound.
+    pub(crate) fn record_missing(&mut self, missing: impl IntoIterator<Item = DigitizerId>) {
+        if let Some(adaptive) = &mut self.adaptive {
+            for digitiser_id in missing {
+                let missing_frames = adaptive.missing_frames.entry(digitiser_id).or_default();
+                *missing_frames += 1;
+                if *missing_frames <= adaptive.options.frame_ttl_absent_frames {
+                    adaptive.push(adaptive.options.max());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> AdaptiveTtlOptions {
+        AdaptiveTtlOptions {
+            adaptive_frame_ttl: true,
+            frame_ttl_percentile: 90.0,
+            frame_ttl_scale: 2.0,
+            frame_ttl_window: 10,
+            frame_ttl_min_ms: 5,
+            frame_ttl_max_ms: 100,
+            frame_ttl_absent_frames: 2,
+        }
+    }
+
+    #[test]
+    fn fixed_ttl_does_not_adapt() {
+        let mut ttl = FrameTtl::fixed(Duration::from_millis(500));
+        ttl.record(0, Duration::from_millis(1));
+        ttl.record_missing([1, 2, 3]);
+        assert_eq!(ttl.get(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn ttl_follows_percentile_of_recent_lateness() {
+        let mut ttl = FrameTtl::adaptive(Duration::from_millis(500), options());
+        assert_eq!(ttl.get(), Duration::from_millis(500));
+
+        for lateness in 1..=10 {
+            ttl.record(0, Duration::from_millis(lateness));
+        }
+        assert_eq!(ttl.get(), Duration::from_millis(18));
+
+        // Earlier samples leave the window.
+        for _ in 0..10 {
+            ttl.record(0, Duration::from_millis(1));
+        }
+        assert_eq!(ttl.get(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn missing_digitisers_increase_ttl_to_bound() {
+        let mut ttl = FrameTtl::adaptive(Duration::from_millis(500), options());
+        for _ in 0..8 {
+            ttl.record(0, Duration::from_millis(10));
+        }
+        ttl.record_missing([1, 2]);
+        assert_eq!(ttl.get(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn absent_digitiser_stops_counting() {
+        let mut ttl = FrameTtl::adaptive(Duration::from_millis(500), options());
+        for _ in 0..3 {
+            ttl.record_missing([1]);
+            for _ in 0..4 {
+                ttl.record(0, Duration::from_millis(10));
+            }
+        }
+        // Only the first two frames missing digitiser 1 are counted, so the TTL follows digitiser 0.
+        assert_eq!(ttl.get(), Duration::from_millis(20));
+
+        // Once its messages arrive again, the digitiser counts again if it is missing.
+        ttl.record(1, Duration::from_millis(10));
+        ttl.record_missing([1]);
+        ttl.record_missing([1]);
+        assert_eq!(ttl.get(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn percentile_must_be_between_0_and_100() {
+        assert_eq!(parse_percentile("0"), Ok(0.0));
+        assert_eq!(parse_percentile("100"), Ok(100.0));
+        assert!(parse_percentile("100.5").is_err());
+        assert!(parse_percentile("-1").is_err());
+        assert!(parse_percentile("high").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Checks that the digitisers contributing to a frame agree on its metadata, and merges their metadata.
+use clap::{Args, ValueEnum};
+use const_format::concatcp;
+use metrics::counter;
+use supermusr_common::{DigitizerId, metrics::names::METRIC_NAME_PREFIX};
+use supermusr_streaming_types::FrameMetadata;
+
+/// Number of dispatched frames whose digitisers disagreed on a metadata field, labelled by the field.
+pub(crate) const FRAME_METADATA_DISAGREEMENTS: &str =
+    concatcp!(METRIC_NAME_PREFIX, "frame_metadata_disagreements");
+
+/// Bits of [MergedMetadata::disagreements], one for each field of the metadata.
+pub(crate) mod disagreement {
+    pub(crate) const PERIOD_NUMBER: u8 = 1;
+    pub(crate) const PROTONS_PER_PULSE: u8 = 2;
+    pub(crate) const RUNNING: u8 = 4;
+    pub(crate) const VETO_FLAGS: u8 = 8;
+}
+
+/// Determines how the veto flags of the digitisers contributing to a frame are merged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum VetoMergePolicy {
+    /// A flag is set if it is set by any digitiser. This is used if no policy is given.
+    #[default]
+    Or,
+    /// A flag is set if it is set by every digitiser.
+    And,
+    /// A flag is set if it is set by more than half the digitisers.
+    Majority,
+    /// A message whose veto flags differ from those of the frame's first message is rejected.
+    Reject,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+pub(crate) struct FrameValidationOptions {
+    /// If set, the veto flags of the digitisers contributing to a frame are merged by this policy,
+    /// and their disagreements recorded, otherwise the veto flags are OR-ed together. See README.md.
+    #[clap(long)]
+    pub(crate) veto_merge_policy: Option<VetoMergePolicy>,
+
+    /// If set, digitiser messages are assigned to frames by timestamp and frame number alone, and digitisers
+    /// which disagree on the period number, protons per pulse or running flag are recorded. See README.md.
+    #[clap(long)]
+    pub(crate) check_frame_consistency: bool,
+}
+
+/// The metadata of a frame merged from that of each contributing digitiser.
+pub(crate) struct MergedMetadata {
+    /// The merged metadata.
+    pub(crate) metadata: FrameMetadata,
+    /// The digitisers whose metadata differs from that of the majority, in increasing order.
+    pub(crate) inconsistent_digitisers: Vec<DigitizerId>,
+    /// Bitmask of the [disagreement] fields on which the digitisers disagree.
+    pub(crate) disagreements: u8,
+}
+
+impl FrameValidationOptions {
+    /// Returns `true` if the metadata of the digitisers contributing to a frame is checked for disagreements
+    /// and merged by field when it is dispatched.
+    fn merges(&self) -> bool {
+        self.check_frame_consistency || self.veto_merge_policy.is_some()
+    }
+
+    /// Returns `true` if messages with the given metadata belong to the same frame.
+    pub(crate) fn same_frame(&self, frame: &FrameMetadata, message: &FrameMetadata) -> bool {
+        if self.check_frame_consistency {
+            frame.timestamp == message.timestamp && frame.frame_number == message.frame_number
+        } else {
+            frame.equals_ignoring_veto_flags(message)
+        }
+    }
+
+    /// Returns `true` if a message belonging to a frame may be added to it.
+    ///
+    /// Under [VetoMergePolicy::Reject], the message is rejected if any checked field differs from that of the frame's first message.
+    /// # Parameters
+    /// - frame: the metadata of the frame's first message.
+    /// - message: the metadata of the new message.
+    pub(crate) fn accepts(&self, frame: &FrameMetadata, message: &FrameMetadata) -> bool {
+        self.veto_merge_policy != Some(VetoMergePolicy::Reject) || differences(frame, message) == 0
+    }
+
+    /// Merges the metadata of each digitiser contributing to a frame.
+    ///
+    /// The period number, protons per pulse and running flag are those of the majority of digitisers, or of the
+    /// earliest message if tied, and the veto flags are merged according to [Self::veto_merge_policy].
+    /// # Parameters
+    /// - digitiser_metadata: the metadata of each message of the frame, in order of arrival, which must not be empty.
+    /// # Return
+    /// The merged metadata, or [None] if `digitiser_metadata` is empty.
+    /// If neither `--check-frame-consistency` nor `--veto-merge-policy` is given, the frame takes the metadata
+    /// of its first message, with the veto flags of every message OR-ed together, and no disagreements are recorded.
+    pub(crate) fn merge(
+        &self,
+        digitiser_metadata: &[(DigitizerId, FrameMetadata)],
+    ) -> Option<MergedMetadata> {
+        let (_, first) = digitiser_metadata.first()?;
+        let all = || digitiser_metadata.iter().map(|(_, metadata)| metadata);
+        let any_veto_flags = || all().fold(0, |flags, metadata| flags | metadata.veto_flags);
+
+        if !self.merges() {
+            return Some(MergedMetadata {
+                metadata: FrameMetadata {
+                    veto_flags: any_veto_flags(),
+                    ..first.clone()
+                },
+                inconsistent_digitisers: Vec::new(),
+                disagreements: 0,
+            });
+        }
+
+        let veto_flags = match self.veto_merge_policy.unwrap_or_default() {
+            VetoMergePolicy::Or => any_veto_flags(),
+            VetoMergePolicy::And => {
+                all().fold(u16::MAX, |flags, metadata| flags & metadata.veto_flags)
+            }
+            VetoMergePolicy::Majority => (0..u16::BITS)
+                .map(|bit| 1 << bit)
+                .filter(|flag| {
+                    2 * all().filter(|m| m.veto_flags & flag != 0).count()
+                        > digitiser_metadata.len()
+                })
+                .fold(0, |flags, flag| flags | flag),
+            VetoMergePolicy::Reject => first.veto_flags,
+        };
+        let metadata = FrameMetadata {
+            period_number: majority(all().map(|metadata| metadata.period_number))
+                .unwrap_or(first.period_number),
+            protons_per_pulse: majority(all().map(|metadata| metadata.protons_per_pulse))
+                .unwrap_or(first.protons_per_pulse),
+            running: majority(all().map(|metadata| metadata.running)).unwrap_or(first.running),
+            veto_flags,
+            ..first.clone()
+        };
+
+        // A digitiser is inconsistent if its veto flags differ from those of the majority,
+        // as under some policies the merged veto flags may differ from those of every digitiser.
+        let consensus = FrameMetadata {
+            veto_flags: majority(all().map(|metadata| metadata.veto_flags))
+                .unwrap_or(first.veto_flags),
+            ..metadata.clone()
+        };
+        let mut inconsistent_digitisers = digitiser_metadata
+            .iter()
+            .filter(|(_, digitiser_metadata)| differences(&consensus, digitiser_metadata) != 0)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        inconsistent_digitisers.sort();
+
+        // The digitisers disagree on a field if any of them differs from the first.
+        let disagreements = all().fold(0, |fields, metadata| fields | differences(first, metadata));
+        record_disagreements(disagreements);
+
+        Some(MergedMetadata {
+            metadata,
+            inconsistent_digitisers,
+            disagreements,
+        })
+    }
+}
+
+/// Returns the bitmask of the [disagreement] fields on which the metadata differ.
+fn differences(a: &FrameMetadata, b: &FrameMetadata) -> u8 {
+    [
+        (
+            a.period_number != b.period_number,
+            disagreement::PERIOD_NUMBER,
+        ),
+        (
+            a.protons_per_pulse != b.protons_per_pulse,
+            disagreement::PROTONS_PER_PULSE,
+        ),
+        (a.running != b.running, disagreement::RUNNING),
+        (a.veto_flags != b.veto_flags, disagreement::VETO_FLAGS),
+    ]
+    .into_iter()
+    .filter(|(differs, _)| *differs)
+    .fold(0, |fields, (_, field)| fields | field)
+}
+
+/// Increments the disagreement counter of each field in the bitmask.
+fn record_disagreements(disagreements: u8) {
+    for (field, label) in [
+        (disagreement::PERIOD_NUMBER, "period_number"),
+        (disagreement::PROTONS_PER_PULSE, "protons_per_pulse"),
+        (disagreement::RUNNING, "running"),
+        (disagreement::VETO_FLAGS, "veto_flags"),
+    ] {
+        if disagreements & field != 0 {
+            counter!(FRAME_METADATA_DISAGREEMENTS, &[("field", label)]).increment(1);
+        }
+    }
+}
+
+/// Returns the most common value, or the earliest of the most common values if tied, or [None] if there are no values.
+fn majority<T: Copy + PartialEq>(values: impl Iterator<Item = T> + Clone) -> Option<T> {
+    let mut best: Option<(T, usize)> = None;
+    for value in values.clone() {
+        let count = values.clone().filter(|other| *other == value).count();
+        if best.is_none_or(|(_, best_count)| count > best_count) {
+            best = Some((value, count));
+        }
+    }
+    best.map(|(value, _)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn metadata(veto_flags: u16) -> FrameMetadata {
+        FrameMetadata {
+            timestamp: Utc::now(),
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 1728,
+            veto_flags,
+        }
+    }
+
+    fn options(veto_merge_policy: VetoMergePolicy) -> FrameValidationOptions {
+        FrameValidationOptions {
+            veto_merge_policy: Some(veto_merge_policy),
+            check_frame_consistency: true,
+        }
+    }
+
+    #[test]
+    fn veto_flags_or_merged_by_default() {
+        let first = metadata(1);
+        let digitiser_metadata = [(0, first.clone()), (1, metadata(2))];
+        let merged = FrameValidationOptions::default()
+            .merge(&digitiser_metadata)
+            .unwrap();
+        assert_eq!(merged.metadata.veto_flags, 3);
+        assert!(merged.inconsistent_digitisers.is_empty());
+        assert_eq!(merged.disagreements, 0);
+
+        let merged = FrameValidationOptions {
+            veto_merge_policy: None,
+            check_frame_consistency: true,
+        }
+        .merge(&digitiser_metadata)
+        .unwrap();
+        assert_eq!(merged.metadata.veto_flags, 3);
+    }
+
+    #[test]
+    fn veto_flags_merged_by_policy() {
+        let first = metadata(0b011);
+        let digitiser_metadata = [
+            (0, first.clone()),
+            (
+                1,
+                FrameMetadata {
+                    veto_flags: 0b110,
+                    ..first.clone()
+                },
+            ),
+            (
+                2,
+                FrameMetadata {
+                    veto_flags: 0b010,
+                    ..first.clone()
+                },
+            ),
+        ];
+        for (policy, veto_flags) in [
+            (VetoMergePolicy::Or, 0b111),
+            (VetoMergePolicy::And, 0b010),
+            (VetoMergePolicy::Majority, 0b010),
+        ] {
+            let merged = options(policy).merge(&digitiser_metadata).unwrap();
+            assert_eq!(merged.metadata.veto_flags, veto_flags);
+            assert_eq!(merged.disagreements, disagreement::VETO_FLAGS);
+            // With no majority, the first digitiser's veto flags are taken as the consensus.
+            assert_eq!(merged.inconsistent_digitisers, [1, 2]);
+        }
+    }
+
+    #[test]
+    fn majority_period_number_used_and_dissenter_recorded() {
+        let first = metadata(0);
+        let digitiser_metadata = [
+            (
+                3,
+                FrameMetadata {
+                    period_number: 2,
+                    ..first.clone()
+                },
+            ),
+            (0, first.clone()),
+            (1, first.clone()),
+        ];
+        let merged = options(VetoMergePolicy::Or)
+            .merge(&digitiser_metadata)
+            .unwrap();
+        assert_eq!(merged.metadata.period_number, 1);
+        assert_eq!(merged.inconsistent_digitisers, [3]);
+        assert_eq!(merged.disagreements, disagreement::PERIOD_NUMBER);
+    }
+
+    #[test]
+    fn consistent_metadata_has_no_disagreements() {
+        let first = metadata(4);
+        let merged = options(VetoMergePolicy::Majority)
+            .merge(&[(0, first.clone()), (1, first.clone())])
+            .unwrap();
+        assert_eq!(merged.metadata.veto_flags, 4);
+        assert!(merged.inconsistent_digitisers.is_empty());
+        assert_eq!(merged.disagreements, 0);
+    }
+
+    #[test]
+    fn reject_policy_rejects_disagreeing_message() {
+        let first = metadata(4);
+        let options = options(VetoMergePolicy::Reject);
+        assert!(options.accepts(&first, &first));
+        assert!(!options.accepts(
+            &first,
+            &FrameMetadata {
+                veto_flags: 5,
+                ..first.clone()
+            }
+        ));
+        assert!(!options.accepts(
+            &first,
+            &FrameMetadata {
+                running: false,
+                ..first.clone()
+            }
+        ));
+    }
+
+    #[test]
+    fn frames_matched_by_timestamp_and_number_when_checking_consistency() {
+        let first = metadata(0);
+        let other_period = FrameMetadata {
+            period_number: 2,
+            ..first.clone()
+        };
+        assert!(options(VetoMergePolicy::Or).same_frame(&first, &other_period));
+        assert!(!FrameValidationOptions::default().same_frame(&first, &other_period));
+    }
+}
@@ -0,0 +1,460 @@
+//! Accumulates time-of-flight histograms of the events of each channel and period from dispatched frames,
+//! and publishes snapshots of them as `ahs1` messages.
+use crate::{data::EventData, frame::AggregatedFrame};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use const_format::concatcp;
+use metrics::counter;
+use rdkafka::{
+    message::Message,
+    producer::{FutureProducer, FutureRecord},
+};
+use std::{collections::BTreeMap, num::NonZeroUsize, time::Duration};
+use supermusr_common::{
+    Channel, Time,
+    metrics::{
+        failures::{self, FailureKind},
+        names::{FAILURES, METRIC_NAME_PREFIX},
+    },
+};
+use supermusr_streaming_types::{
+    ahs1_aggregated_histogram_v1_generated::{
+        AggregatedHistogramMessage, AggregatedHistogramMessageArgs, PeriodHistogram,
+        PeriodHistogramArgs, finish_aggregated_histogram_message_buffer,
+    },
+    ecs_pl72_run_start_generated::{root_as_run_start, run_start_buffer_has_identifier},
+    flatbuffers::FlatBufferBuilder,
+    frame_metadata_v2_generated::GpsTime,
+};
+use tracing::{debug, info, warn};
+
+/// Number of histogram snapshots sent by the aggregator.
+pub(crate) const HISTOGRAMS_SENT: &str = concatcp!(METRIC_NAME_PREFIX, "histograms_sent");
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct HistogramOptions {
+    /// If set, time-of-flight histograms of the events of each channel and period are accumulated from dispatched frames,
+    /// and snapshots of them are sent to this Kafka topic. See README.md.
+    #[clap(long)]
+    pub(crate) histogram_topic: Option<String>,
+
+    /// Interval in milliseconds at which histogram snapshots are sent.
+    #[clap(long, default_value = "5000")]
+    pub(crate) histogram_interval_ms: u64,
+
+    /// Start of the first time-of-flight bin, in nanoseconds since the start of the frame.
+    #[clap(long, default_value = "0")]
+    pub(crate) histogram_tof_min_ns: Time,
+
+    /// End of the last time-of-flight bin, in nanoseconds since the start of the frame.
+    #[clap(long, default_value = "20000000")]
+    pub(crate) histogram_tof_max_ns: Time,
+
+    /// Number of time-of-flight bins of equal width.
+    #[clap(long, default_value = "2000")]
+    pub(crate) histogram_bins: NonZeroUsize,
+
+    /// If set, `RunStart` messages are consumed from this Kafka topic, and each resets the histograms,
+    /// after a final snapshot of the previous run's histograms is sent.
+    #[clap(long)]
+    pub(crate) run_control_topic: Option<String>,
+}
+
+impl HistogramOptions {
+    /// Returns the interval at which histogram snapshots are sent.
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_millis(self.histogram_interval_ms)
+    }
+
+    /// Returns the index of the bin containing the given time, or [None] if it lies outside every bin.
+    fn bin(&self, time: Time) -> Option<usize> {
+        if time < self.histogram_tof_min_ns || time >= self.histogram_tof_max_ns {
+            return None;
+        }
+        let bins = self.histogram_bins.get() as u64;
+        let offset = u64::from(time - self.histogram_tof_min_ns);
+        let range = u64::from(self.histogram_tof_max_ns - self.histogram_tof_min_ns);
+        usize::try_from(offset * bins / range).ok()
+    }
+
+    /// Returns the edges of the bins, one more than the number of bins.
+    fn bin_edges(&self) -> Vec<Time> {
+        let bins = self.histogram_bins.get() as u64;
+        let min = u64::from(self.histogram_tof_min_ns);
+        let range = u64::from(
+            self.histogram_tof_max_ns
+                .saturating_sub(self.histogram_tof_min_ns),
+        );
+        (0..=bins)
+            .map(|edge| Time::try_from(min + edge * range / bins).unwrap_or(Time::MAX))
+            .collect()
+    }
+}
+
+/// The histograms of the events of each channel over the frames of one period.
+#[derive(Default)]
+struct PeriodHistograms {
+    /// Number of frames accumulated, excluding correction frames.
+    frames: u64,
+    /// The bin counts of each channel which has registered an event.
+    channels: BTreeMap<Channel, Vec<u32>>,
+}
+
+/// Accumulates the histograms of the current run.
+pub(crate) struct Histogrammer {
+    options: HistogramOptions,
+    /// The name of the current run, if a `RunStart` message has been received.
+    run_name: Option<String>,
+    /// The start time of the current run, if a `RunStart` message has been received.
+    /// Frames before this belong to the previous run, so are not accumulated.
+    run_start: Option<DateTime<Utc>>,
+    /// The histograms of each period.
+    periods: BTreeMap<u64, PeriodHistograms>,
+}
+
+impl Histogrammer {
+    /// Creates and returns a new [Histogrammer] instance.
+    pub(crate) fn new(options: HistogramOptions) -> Self {
+        Self {
+            options,
+            run_name: None,
+            run_start: None,
+            periods: Default::default(),
+        }
+    }
+
+    /// Returns `true` if histograms are accumulated.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.options.histogram_topic.is_some()
+    }
+
+    /// Adds the events of a dispatched frame to the histograms of its period,
+    /// unless the frame is from before the start of the current run.
+    pub(crate) fn accumulate(&mut self, frame: &AggregatedFrame<EventData>) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self
+            .run_start
+            .is_some_and(|run_start| frame.metadata.timestamp < run_start)
+        {
+            return;
+        }
+        let bins = self.options.histogram_bins.get();
+        let period = self
+            .periods
+            .entry(frame.metadata.period_number)
+            .or_default();
+        if !frame.correction {
+            period.frames += 1;
+        }
+        let events = frame
+            .digitiser_data
+            .times()
+            .iter()
+            .zip(frame.digitiser_data.channels());
+        for (&time, &channel) in events {
+            let Some(bin) = self.options.bin(time) else {
+                continue;
+            };
+            let counts = period
+                .channels
+                .entry(channel)
+                .or_insert_with(|| vec![0; bins]);
+            if let Some(count) = counts.get_mut(bin) {
+                *count = count.saturating_add(1);
+            }
+        }
+    }
+
+    /// Clears the histograms, at the start of a new run.
+    /// # Parameters
+    /// - run_name: the name of the new run.
+    /// - run_start: the start time of the new run, before which frames are not accumulated.
+    pub(crate) fn reset(&mut self, run_name: Option<String>, run_start: Option<DateTime<Utc>>) {
+        info!("Resetting histograms for run {run_name:?} starting at {run_start:?}");
+        self.run_name = run_name;
+        self.run_start = run_start;
+        self.periods.clear();
+    }
+
+    /// Returns a snapshot of the histograms as an `ahs1` message.
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let periods = self
+            .periods
+            .iter()
+            .map(|(&period_number, histograms)| {
+                let channel = histograms.channels.keys().copied().collect::<Vec<_>>();
+                let counts = histograms
+                    .channels
+                    .values()
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>();
+                let args = PeriodHistogramArgs {
+                    period_number,
+                    frames: histograms.frames,
+                    channel: Some(fbb.create_vector(&channel)),
+                    counts: Some(fbb.create_vector(&counts)),
+                };
+                PeriodHistogram::create(&mut fbb, &args)
+            })
+            .collect::<Vec<_>>();
+
+        let timestamp: GpsTime = Utc::now().into();
+        let args = AggregatedHistogramMessageArgs {
+            timestamp: Some(&timestamp),
+            run_name: self.run_name.as_deref().map(|name| fbb.create_string(name)),
+            bin_edges: Some(fbb.create_vector(&self.options.bin_edges())),
+            periods: Some(fbb.create_vector(&periods)),
+        };
+        let message = AggregatedHistogramMessage::create(&mut fbb, &args);
+        finish_aggregated_histogram_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+
+    /// Sends a snapshot of the histograms to the histogram topic.
+    pub(crate) fn publish(&self, producer: &FutureProducer) {
+        let Some(topic) = &self.options.histogram_topic else {
+            return;
+        };
+        let payload = self.snapshot();
+        match producer.send_result(FutureRecord::<(), _>::to(topic).payload(&payload)) {
+            Ok(delivery) => {
+                tokio::spawn(async move {
+                    match delivery.await {
+                        Ok(Ok(delivery)) => {
+                            debug!("Histogram delivery: {delivery:?}");
+                            counter!(HISTOGRAMS_SENT).increment(1);
+                        }
+                        Ok(Err((e, _))) => report_publish_failure(&e),
+                        Err(e) => report_publish_failure(&e),
+                    }
+                });
+            }
+            Err((e, _)) => report_publish_failure(&e),
+        }
+    }
+}
+
+/// Records the failure to send a histogram snapshot.
+fn report_publish_failure(e: &dyn std::error::Error) {
+    warn!("Failed to send histograms: {e}");
+    counter!(
+        FAILURES,
+        &[failures::get_label(FailureKind::KafkaPublishFailed)]
+    )
+    .increment(1);
+}
+
+/// Resets the histograms if the message is a `RunStart`, after sending a final snapshot of those of the previous run.
+/// Other messages on the run control topic are ignored.
+/// # Parameters
+/// - histogrammer: the histograms of the current run.
+/// - producer: the Kafka producer object.
+/// - msg: the message.
+pub(crate) fn process_run_control_message(
+    histogrammer: &mut Histogrammer,
+    producer: &FutureProducer,
+    msg: &impl Message,
+) {
+    if let Some((run_name, run_start)) = parse_run_start(msg) {
+        histogrammer.publish(producer);
+        histogrammer.reset(run_name, run_start);
+    }
+}
+
+/// Resets the histograms if the latest message on the run control topic is a `RunStart`, so those of a run
+/// in progress when the component starts carry its name. There are no histograms of a previous run to send.
+/// # Parameters
+/// - histogrammer: the histograms, which have not yet accumulated any frames.
+/// - msg: the latest message on the run control topic.
+pub(crate) fn load_latest_run_control_message(histogrammer: &mut Histogrammer, msg: &impl Message) {
+    if let Some((run_name, run_start)) = parse_run_start(msg) {
+        histogrammer.reset(run_name, run_start);
+    }
+}
+
+/// Parses a message on the run control topic.
+/// # Return
+/// The name and start time of the run, if the message is a `RunStart`.
+fn parse_run_start(msg: &impl Message) -> Option<(Option<String>, Option<DateTime<Utc>>)> {
+    let payload = msg.payload()?;
+    if !run_start_buffer_has_identifier(payload) {
+        return None;
+    }
+    match root_as_run_start(payload) {
+        Ok(run_start) => Some((
+            run_start.run_name().map(ToOwned::to_owned),
+            i64::try_from(run_start.start_time())
+                .ok()
+                .and_then(DateTime::from_timestamp_millis),
+        )),
+        Err(e) => {
+            warn!("Failed to parse RunStart message: {e}");
+            counter!(
+                FAILURES,
+                &[failures::get_label(FailureKind::UnableToDecodeMessage)]
+            )
+            .increment(1);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::{OwnedMessage, Timestamp};
+    use supermusr_streaming_types::{
+        FrameMetadata,
+        ahs1_aggregated_histogram_v1_generated::root_as_aggregated_histogram_message,
+        ecs_pl72_run_start_generated::{RunStart, RunStartArgs, finish_run_start_buffer},
+    };
+
+    fn histogrammer() -> Histogrammer {
+        Histogrammer::new(HistogramOptions {
+            histogram_topic: Some("histograms".to_owned()),
+            histogram_interval_ms: 1000,
+            histogram_tof_min_ns: 100,
+            histogram_tof_max_ns: 500,
+            histogram_bins: NonZeroUsize::new(4).unwrap(),
+            run_control_topic: None,
+        })
+    }
+
+    fn frame(
+        period_number: u64,
+        time: Vec<Time>,
+        channel: Vec<Channel>,
+    ) -> AggregatedFrame<EventData> {
+        let intensity = vec![0; time.len()];
+        AggregatedFrame::new(
+            FrameMetadata {
+                timestamp: Utc::now(),
+                period_number,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1,
+                veto_flags: 0,
+            },
+            true,
+            vec![0],
+            EventData::new(time, intensity, channel),
+        )
+    }
+
+    #[test]
+    fn events_binned_by_channel_and_period() {
+        let mut histogrammer = histogrammer();
+        histogrammer.accumulate(&frame(
+            0,
+            vec![50, 100, 199, 250, 499, 500],
+            vec![3, 3, 3, 1, 1, 1],
+        ));
+        histogrammer.accumulate(&frame(1, vec![450], vec![3]));
+
+        let period = histogrammer.periods.get(&0).unwrap();
+        assert_eq!(period.frames, 1);
+        assert_eq!(period.channels.get(&3).unwrap(), &[2, 0, 0, 0]);
+        assert_eq!(period.channels.get(&1).unwrap(), &[0, 1, 0, 1]);
+        let period = histogrammer.periods.get(&1).unwrap();
+        assert_eq!(period.channels.get(&3).unwrap(), &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn frames_before_run_start_not_accumulated() {
+        let mut histogrammer = histogrammer();
+        let previous_run = frame(0, vec![150], vec![1]);
+        histogrammer.reset(
+            Some("run".to_owned()),
+            Some(previous_run.metadata.timestamp + Duration::from_millis(1)),
+        );
+        histogrammer.accumulate(&previous_run);
+        assert!(histogrammer.periods.is_empty());
+
+        let mut this_run = frame(0, vec![150], vec![1]);
+        this_run.metadata.timestamp = previous_run.metadata.timestamp + Duration::from_millis(1);
+        histogrammer.accumulate(&this_run);
+        assert_eq!(histogrammer.periods.get(&0).unwrap().frames, 1);
+    }
+
+    #[test]
+    fn snapshot_to_ahs1() {
+        let mut histogrammer = histogrammer();
+        histogrammer.reset(Some("run".to_owned()), None);
+        histogrammer.accumulate(&frame(2, vec![150, 350], vec![4, 1]));
+        let mut correction = frame(2, vec![150], vec![4]);
+        correction.correction = true;
+        histogrammer.accumulate(&correction);
+
+        let bytes = histogrammer.snapshot();
+        let message = root_as_aggregated_histogram_message(&bytes).unwrap();
+        assert_eq!(message.run_name(), Some("run"));
+        assert_eq!(
+            message.bin_edges().unwrap().iter().collect::<Vec<_>>(),
+            [100, 200, 300, 400, 500]
+        );
+        let periods = message.periods().unwrap();
+        assert_eq!(periods.len(), 1);
+        let period = periods.get(0);
+        assert_eq!(period.period_number(), 2);
+        assert_eq!(period.frames(), 1);
+        assert_eq!(period.channel().unwrap().iter().collect::<Vec<_>>(), [1, 4]);
+        assert_eq!(
+            period.counts().unwrap().iter().collect::<Vec<_>>(),
+            [0, 0, 1, 0, 2, 0, 0, 0]
+        );
+
+        histogrammer.reset(None, None);
+        let bytes = histogrammer.snapshot();
+        let message = root_as_aggregated_histogram_message(&bytes).unwrap();
+        assert!(message.run_name().is_none());
+        assert!(message.periods().unwrap().is_empty());
+    }
+
+    fn run_control_message(payload: Vec<u8>) -> OwnedMessage {
+        OwnedMessage::new(
+            Some(payload),
+            None,
+            "run-control".to_owned(),
+            Timestamp::NotAvailable,
+            0,
+            0,
+            None,
+        )
+    }
+
+    #[test]
+    fn latest_run_start_loaded() {
+        let mut fbb = FlatBufferBuilder::new();
+        let args = RunStartArgs {
+            start_time: 1_000,
+            run_name: Some(fbb.create_string("run")),
+            ..Default::default()
+        };
+        let run_start = RunStart::create(&mut fbb, &args);
+        finish_run_start_buffer(&mut fbb, run_start);
+
+        let mut histogrammer = histogrammer();
+        load_latest_run_control_message(
+            &mut histogrammer,
+            &run_control_message(fbb.finished_data().to_vec()),
+        );
+        assert_eq!(histogrammer.run_name.as_deref(), Some("run"));
+        assert_eq!(
+            histogrammer.run_start,
+            DateTime::from_timestamp_millis(1_000)
+        );
+    }
+
+    #[test]
+    fn latest_run_control_message_ignored_if_not_run_start() {
+        let mut histogrammer = histogrammer();
+        load_latest_run_control_message(&mut histogrammer, &run_control_message(vec![0; 16]));
+        assert_eq!(histogrammer.run_name, None);
+        assert_eq!(histogrammer.run_start, None);
+    }
+}
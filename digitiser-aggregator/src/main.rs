@@ -12,18 +12,30 @@
 //! * Records completion status of a frame event list message as well as all digitiser ids that contributed to it.
 //! * Ignores any digitiser message whose timestamp is before the that of last frame event list to be dispatched.
 //! * Ignores any digitiser message whose [id] and [metadata] have already been seen.
+//! * Optionally quarantines any digitiser message whose timestamp is far from the broker time, or the recent median.
+//! * Optionally forwards digitiser messages which arrive after their frame was dispatched, and those quarantined, to separate topics.
+//! * Optionally dispatches digitiser messages which arrive after their frame was dispatched as correction frames.
+//! * Merges the frame metadata of the digitisers contributing to a frame, and records any digitisers which disagree.
+//! * Optionally monitors the event rate of each channel and digitiser, and flags channels which are silent or hot.
+//! * Optionally sorts the events of each frame by time, or groups them by channel, and delta encodes their times.
+//! * Optionally accumulates time-of-flight histograms of each channel and period, and sends snapshots of them.
+//! * Optionally runs as one of several instances, each aggregating the frames of the input partitions assigned to it.
+//! * Only commits the offset of a digitiser message once the frames containing it, and all earlier messages, have been delivered.
+//!   On restart, messages of frames which were not delivered are consumed again, and those of frames which were are ignored.
 //!
 //! ## Assumptions
 //! * That each [DigitizerEventListMessage] has equally sized event fields (i.e. [time], [channel], and [voltage] are
 //!   present and are all of equal length). This is guaranteed by the `trace-to-events` component.
+//! * That the optional pulse shape fields of `dev3` digitiser messages are, when present, of the same length
+//!   as the other event fields. Frames containing such fields are dispatched as `aev3` messages.
 //! * That the time stamps of [DigitizerEventListMessage] are correct.
 //!
 //! ## Error Conditions
 //! * Missing fields of the [DigitizerEventListMessage] will cause it to be ignored.
-//! * If a single digitser message has metadata timestamp set to a future time,
+//! * Unless `--timestamp-window-ms` is set, if a single digitser message has metadata timestamp set to a future time,
 //!   this will cause the component to reject all subsequent messages (correctly timestamped)
 //!   until the time of the erroneous future timestamp arrives.
-//! * If a digitser message has metadata timestamp set earlier than intended, it will be ignored
+//! * If a digitser message has metadata timestamp set earlier than intended, and within the timestamp window, it will be ignored
 //!   unless it happens to be before the timestamp of the last message to be dispatched.
 //!   In this case the digitser message may be inserted into the wrong frame, or may result in a
 //!   new (erroneous) frame.
@@ -33,31 +45,66 @@
 //! [voltage]: DigitizerEventListMessage::voltage
 //! [id]: DigitizerEventListMessage::digitizer_id()
 //! [metadata]: DigitizerEventListMessage::metadata()
+mod control;
 mod data;
 mod frame;
+mod histogram;
+mod monitor;
+mod offsets;
+mod rejected;
+mod resume;
 
-use crate::data::EventData;
+use crate::data::{EventData, FrameEncodingOptions};
+use chrono::DateTime;
 use clap::Parser;
-use frame::{AggregatedFrame, FrameCache};
+use control::ControlMessage;
+use frame::{
+    AdaptiveTtlOptions, AggregatedFrame, CORRECTION_FRAMES_SENT, DIGITISER_LATENESS,
+    EXPECTED_DIGITISERS, ExpectedDigitisers, FRAME_METADATA_DISAGREEMENTS, FRAME_TTL, FrameCache,
+    FrameTtl, FrameValidationOptions, REJECTED_MESSAGES,
+};
+use histogram::{
+    HISTOGRAMS_SENT, HistogramOptions, Histogrammer, load_latest_run_control_message,
+    process_run_control_message,
+};
 use metrics::counter;
 use metrics_exporter_prometheus::PrometheusBuilder;
 use miette::{Context, IntoDiagnostic};
+use monitor::{
+    CHANNEL_EVENT_RATE, ChannelMonitor, ChannelMonitorOptions, DIGITISER_EVENT_RATE,
+    FLAGGED_CHANNELS, publish_summary,
+};
+use offsets::{MessageOffset, OffsetTracker, OffsetTrackingContext, positions_to_list};
 use rdkafka::{
     consumer::{CommitMode, Consumer},
+    error::KafkaError,
     message::{BorrowedMessage, Message},
     producer::{FutureProducer, FutureRecord},
     util::Timeout,
 };
-use std::{fmt::Debug, net::SocketAddr, time::Duration};
+use rejected::RejectedMessageForwarder;
+use resume::ResumeLookup;
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 use supermusr_common::{
-    CommonKafkaOpts, DigitizerId, init_tracer,
+    CommonKafkaOpts, DigitizerId,
+    backpressure::{Backpressure, BackpressureOpts, SendBufferFullPolicy},
+    create_latest_message_consumer, init_tracer, load_latest_message,
     metrics::{
         component_info_metric,
         failures::{self, FailureKind},
         messages_received::{self, MessageKind},
-        names::{FAILURES, FRAMES_SENT, MESSAGES_PROCESSED, MESSAGES_RECEIVED},
+        names::{
+            CONSUMER_PAUSED, FAILURES, FRAMES_SENT, MESSAGES_PROCESSED, MESSAGES_RECEIVED,
+            SEND_BUFFER_DEPTH,
+        },
     },
-    record_metadata_fields_to_span,
+    record_metadata_fields_to_span, recv_if_present,
     spanned::Spanned,
     tracer::{FutureRecordTracerExt, OptionalHeaderTracerExt, TracerEngine, TracerOptions},
 };
@@ -66,12 +113,14 @@ use supermusr_streaming_types::{
         DigitizerEventListMessage, digitizer_event_list_message_buffer_has_identifier,
         root_as_digitizer_event_list_message,
     },
+    dev3_digitizer_event_v3_generated as dev3,
     flatbuffers::InvalidFlatbuffer,
+    frame_metadata_v2_generated::FrameMetadataV2,
 };
 use tokio::{
     select,
     signal::unix::{Signal, SignalKind, signal},
-    sync::mpsc::{Receiver, Sender, error::SendError},
+    sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender, error::TrySendError},
     task::JoinHandle,
 };
 use tracing::{debug, error, info, info_span, instrument, warn};
@@ -79,8 +128,29 @@ use tracing::{debug, error, info, info_span, instrument, warn};
 /// Triggers error if the producer takes longer than this to dispatch a message.
 const PRODUCER_TIMEOUT: Timeout = Timeout::After(Duration::from_millis(100));
 
+/// Number of times delivery of a frame is attempted before the component exits.
+const DELIVERY_ATTEMPTS: u32 = 5;
+
+/// Time to wait before the first retry of a failed delivery, which is doubled for each subsequent retry.
+const DELIVERY_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
 type AggregatedFrameToBufferSender = Sender<AggregatedFrame<EventData>>;
-type SendAggregatedFrameError = SendError<AggregatedFrame<EventData>>;
+type SendAggregatedFrameError = TrySendError<AggregatedFrame<EventData>>;
+type DeliveredOffsetsSender = UnboundedSender<Vec<MessageOffset>>;
+
+/// The state which is updated by each digitiser message.
+struct MessageContext<'a> {
+    /// The cache in which frames are stored whilst awaiting digitiser messages.
+    cache: &'a mut FrameCache<EventData>,
+    /// Tracks the offsets of messages whose data is held in the cache, or which are being forwarded.
+    offsets: &'a mut OffsetTracker,
+    /// Forwards messages rejected by the cache.
+    rejected: &'a RejectedMessageForwarder,
+    /// Counts the events of each channel and digitiser.
+    monitor: &'a mut ChannelMonitor,
+    /// Accumulates the histograms of dispatched frames.
+    histogrammer: &'a mut Histogrammer,
+}
 
 /// [clap] derived struct to handle command line parameters.
 #[derive(Debug, Parser)]
@@ -107,21 +177,79 @@ struct Cli {
     #[clap(short, long, value_delimiter = ',')]
     digitiser_ids: Vec<DigitizerId>,
 
+    /// If set, an expected digitiser which is missing from this many consecutive frames is marked absent,
+    /// and frames are complete without it until it sends another message.
+    #[clap(long)]
+    absent_after_frames: Option<NonZeroUsize>,
+
+    /// If set, control messages are consumed from this Kafka topic as JSON messages.
+    /// These can replace the expected digitiser IDs whilst running. See README.md.
+    #[clap(long)]
+    control_topic: Option<String>,
+
     /// Frame TTL in milliseconds.
     /// The time in which messages for a given frame must have been received from all digitisers.
+    /// If `--adaptive-frame-ttl` is set, this is the initial TTL.
     #[clap(long, default_value = "500")]
     frame_ttl_ms: u64,
 
+    #[clap(flatten)]
+    adaptive_ttl_options: AdaptiveTtlOptions,
+
+    /// If set, a digitiser message is quarantined if its timestamp differs by more than this many milliseconds
+    /// from the broker timestamp of the message, or from the median of recent messages if it has no broker timestamp.
+    #[clap(long)]
+    timestamp_window_ms: Option<u64>,
+
+    /// If set, quarantined digitiser messages are forwarded to this Kafka topic.
+    #[clap(long)]
+    quarantine_topic: Option<String>,
+
+    /// Number of dispatched frames which are remembered, so that digitiser messages arriving after their frame
+    /// was dispatched are identified as late rather than starting a new frame.
+    #[clap(long, default_value = "64")]
+    dispatched_frame_history: usize,
+
+    /// If set, digitiser messages arriving after their frame was dispatched are forwarded to this Kafka topic.
+    #[clap(long)]
+    late_data_topic: Option<String>,
+
+    /// If set, a digitiser message arriving after its frame was dispatched without its digitiser is sent
+    /// to the output topic as a correction frame, rather than being rejected. See README.md.
+    #[clap(long)]
+    correction_frames: bool,
+
+    #[clap(flatten)]
+    validation_options: FrameValidationOptions,
+
+    #[clap(flatten)]
+    encoding_options: FrameEncodingOptions,
+
     /// Frame cache poll interval in milliseconds.
     /// This may affect the rate at which incomplete frames are transmitted.
     #[clap(long, default_value = "500")]
     cache_poll_ms: u64,
 
+    /// Set if the input topic is partitioned by frame, using the `--frame-partitions` option of `trace-to-events`.
+    /// Frames are then tracked per partition, and dispatched to the partition of the output topic with the same
+    /// number as their input partition, so several instances in the same consumer group can share the load. See README.md.
+    #[clap(long)]
+    sharded: bool,
+
     /// Size of the send frame buffer.
-    /// If this limit is exceeded, the component will exit.
+    /// If this limit is reached, the behaviour is determined by `--send-buffer-full-policy`.
     #[clap(long, default_value = "1024")]
     send_frame_buffer_size: usize,
 
+    #[clap(flatten)]
+    backpressure_options: BackpressureOpts,
+
+    #[clap(flatten)]
+    monitor_options: ChannelMonitorOptions,
+
+    #[clap(flatten)]
+    histogram_options: HistogramOptions,
+
     /// Endpoint on which Prometheus text format metrics are available
     #[clap(long, env, default_value = "127.0.0.1:9090")]
     observability_address: SocketAddr,
@@ -147,26 +275,54 @@ async fn main() -> miette::Result<()> {
 
     let kafka_opts = args.common_kafka_options;
 
-    let consumer = supermusr_common::create_default_consumer(
+    // Tracks which offsets of the input topic can be committed.
+    // This is shared with the consumer, which forgets the offsets of partitions revoked by a rebalance.
+    let offsets = Arc::new(Mutex::new(OffsetTracker::default()));
+
+    let consumer = supermusr_common::create_default_consumer_with_context(
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
         &args.consumer_group,
         Some(&[args.input_topic.as_str()]),
+        OffsetTrackingContext::new(&args.input_topic, offsets.clone()),
     )
     .into_diagnostic()?;
 
-    let producer: FutureProducer = supermusr_common::generate_kafka_client_config(
+    let control_consumer = args
+        .control_topic
+        .as_deref()
+        .map(|topic| {
+            create_latest_message_consumer(
+                &kafka_opts,
+                &format!("{}-control", args.consumer_group),
+                topic,
+            )
+        })
+        .transpose()
+        .into_diagnostic()?;
+
+    let run_control_consumer = args
+        .histogram_options
+        .run_control_topic
+        .as_deref()
+        .map(|topic| {
+            create_latest_message_consumer(
+                &kafka_opts,
+                &format!("{}-run-control", args.consumer_group),
+                topic,
+            )
+        })
+        .transpose()
+        .into_diagnostic()?;
+
+    let client_config = supermusr_common::generate_kafka_client_config(
         &kafka_opts.broker,
         &kafka_opts.username,
         &kafka_opts.password,
-    )
-    .create()
-    .into_diagnostic()?;
-
-    let ttl = Duration::from_millis(args.frame_ttl_ms);
+    );
 
-    let mut cache = FrameCache::<EventData>::new(ttl, args.digitiser_ids.clone());
+    let producer: FutureProducer = client_config.create().into_diagnostic()?;
 
     // Install exporter and register metrics
     let builder = PrometheusBuilder::new();
@@ -195,18 +351,151 @@ async fn main() -> miette::Result<()> {
         metrics::Unit::Count,
         "Number of complete frames sent by the aggregator"
     );
+    metrics::describe_counter!(
+        CORRECTION_FRAMES_SENT,
+        metrics::Unit::Count,
+        "Number of correction frames, containing late data for frames already dispatched, sent by the aggregator"
+    );
+    metrics::describe_gauge!(
+        SEND_BUFFER_DEPTH,
+        "Number of frames awaiting delivery to the broker"
+    );
+    metrics::describe_gauge!(
+        CONSUMER_PAUSED,
+        "Whether consumption of digitiser messages is paused until the send buffer drains"
+    );
+    metrics::describe_histogram!(
+        DIGITISER_LATENESS,
+        metrics::Unit::Seconds,
+        "Time from the first message of a frame to the arrival of each digitiser's message"
+    );
+    metrics::describe_gauge!(
+        EXPECTED_DIGITISERS,
+        "Number of digitisers from which a frame currently requires messages to be complete"
+    );
+    metrics::describe_counter!(
+        REJECTED_MESSAGES,
+        metrics::Unit::Count,
+        "Number of digitiser messages rejected by the frame cache"
+    );
+    metrics::describe_counter!(
+        FRAME_METADATA_DISAGREEMENTS,
+        metrics::Unit::Count,
+        "Number of frames whose digitisers disagreed on a metadata field"
+    );
+    metrics::describe_gauge!(
+        CHANNEL_EVENT_RATE,
+        "Rolling rate of events on each channel, in events per second"
+    );
+    metrics::describe_gauge!(
+        DIGITISER_EVENT_RATE,
+        "Rolling rate of events from each digitiser, in events per second"
+    );
+    metrics::describe_gauge!(
+        FLAGGED_CHANNELS,
+        "Whether each channel is flagged as silent or hot compared with its neighbours"
+    );
+    metrics::describe_counter!(
+        HISTOGRAMS_SENT,
+        metrics::Unit::Count,
+        "Number of histogram snapshots sent by the aggregator"
+    );
+    metrics::describe_gauge!(
+        FRAME_TTL,
+        metrics::Unit::Seconds,
+        "Time-to-live given to new partial frames"
+    );
+
+    let ttl = Duration::from_millis(args.frame_ttl_ms);
+    let ttl = if args.adaptive_ttl_options.adaptive_frame_ttl {
+        FrameTtl::adaptive(ttl, args.adaptive_ttl_options.clone())
+    } else {
+        FrameTtl::fixed(ttl)
+    };
+
+    let expected_digitisers = ExpectedDigitisers::new(
+        args.digitiser_ids.clone(),
+        args.absent_after_frames.map(NonZeroUsize::get),
+    );
+
+    let mut cache = FrameCache::<EventData>::new(ttl, expected_digitisers);
+    if let Some(window_ms) = args.timestamp_window_ms {
+        cache.set_timestamp_window(Duration::from_millis(window_ms));
+    }
+    cache.set_dispatched_history(args.dispatched_frame_history);
+    cache.set_correction_frames(args.correction_frames);
+    cache.set_sharded(args.sharded);
+    cache.set_validation(args.validation_options.clone());
+
+    // The most recent control message is applied before any digitiser messages are consumed.
+    if let Some((consumer, num_last_messages)) = &control_consumer {
+        if let Some(msg) = load_latest_message(consumer, *num_last_messages)
+            .await
+            .into_diagnostic()?
+        {
+            process_control_message(&mut cache, &msg);
+        }
+    }
+
+    // Messages of frames delivered before a restart, or before a partition was reassigned, may be consumed again,
+    // so these are rejected. The last frame dispatched from each partition is found when it is assigned.
+    let resume = ResumeLookup::new(
+        &client_config,
+        &args.consumer_group,
+        &args.output_topic,
+        args.sharded,
+    );
 
     let mut cache_poll_interval = tokio::time::interval(Duration::from_millis(args.cache_poll_ms));
 
+    // Receives the offsets of the messages of each frame, once the frame has been delivered.
+    let (delivered_send, mut delivered_recv) =
+        tokio::sync::mpsc::unbounded_channel::<Vec<MessageOffset>>();
+
+    let rejected = RejectedMessageForwarder::new(
+        producer.clone(),
+        args.late_data_topic.clone(),
+        args.quarantine_topic.clone(),
+        delivered_send.clone(),
+    );
+
     // Creates Send-Frame thread and returns channel sender
-    let (channel_send, producer_task_handle) = create_producer_task(
+    let (channel_send, mut producer_task_handle) = create_producer_task(
         tracer.use_otel(),
         args.send_frame_buffer_size,
         &producer,
         &args.output_topic,
+        args.encoding_options,
+        delivered_send,
     )
     .into_diagnostic()?;
 
+    let policy = args.backpressure_options.send_buffer_full_policy;
+    let mut backpressure = Backpressure::new(policy, channel_send.max_capacity());
+    let mut backpressure_poll_interval =
+        tokio::time::interval(args.backpressure_options.poll_interval());
+
+    let mut monitor = ChannelMonitor::new(args.monitor_options.clone(), &args.digitiser_ids);
+    let monitor_period = args.monitor_options.interval();
+    let mut monitor_interval =
+        tokio::time::interval_at(tokio::time::Instant::now() + monitor_period, monitor_period);
+
+    let mut histogrammer = Histogrammer::new(args.histogram_options.clone());
+    // The histograms of a run in progress carry its name, if its `RunStart` is the latest run control message.
+    if let Some((consumer, num_last_messages)) = &run_control_consumer {
+        if let Some(msg) = load_latest_message(consumer, *num_last_messages)
+            .await
+            .into_diagnostic()?
+        {
+            load_latest_run_control_message(&mut histogrammer, &msg);
+        }
+    }
+    let histogram_period = args.histogram_options.interval();
+    let mut histogram_interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + histogram_period,
+        histogram_period,
+    );
+
     // Is used to await any sigint signals
     let mut sigint = signal(SignalKind::interrupt()).into_diagnostic()?;
 
@@ -215,23 +504,69 @@ async fn main() -> miette::Result<()> {
     loop {
         tokio::select! {
             event = consumer.recv() => {
+                apply_partition_changes(&mut cache, &offsets, &resume);
                 match event {
                     Ok(msg) => {
-                        process_kafka_message(tracer.use_otel(), &channel_send, &mut cache, &msg).await.into_diagnostic().wrap_err("Failed to process incomming message")?;
-                        consumer.commit_message(&msg, CommitMode::Async)
-                            .expect("Message should commit");
+                        let mut offsets = lock_offsets(&offsets);
+                        let mut context = MessageContext {
+                            cache: &mut cache,
+                            offsets: &mut offsets,
+                            rejected: &rejected,
+                            monitor: &mut monitor,
+                            histogrammer: &mut histogrammer,
+                        };
+                        process_kafka_message(tracer.use_otel(), &channel_send, policy, &mut context, &msg).into_diagnostic().wrap_err("Failed to process incomming message")?;
+                        offsets.consume(MessageOffset::from(&msg));
+                        commit_offsets(&consumer, &args.input_topic, &mut offsets, CommitMode::Async);
+                        backpressure.update(&consumer, send_buffer_depth(&channel_send));
                     }
                     Err(e) => warn!("Kafka error: {}", e),
                 };
             }
             _ = cache_poll_interval.tick() => {
-                cache_poll(&channel_send, &mut cache).await.into_diagnostic()?;
+                apply_partition_changes(&mut cache, &offsets, &resume);
+                cache_poll(&channel_send, policy, &mut cache, &mut histogrammer).into_diagnostic()?;
+                backpressure.update(&consumer, send_buffer_depth(&channel_send));
+            }
+            _ = backpressure_poll_interval.tick(), if backpressure.is_paused() => {
+                apply_partition_changes(&mut cache, &offsets, &resume);
+                cache_poll(&channel_send, policy, &mut cache, &mut histogrammer).into_diagnostic()?;
+                backpressure.update(&consumer, send_buffer_depth(&channel_send));
+            }
+            _ = monitor_interval.tick(), if monitor.is_enabled() => {
+                let summary = monitor.update();
+                if let Some(topic) = &args.monitor_options.monitor_topic {
+                    publish_summary(&producer, topic, &summary);
+                }
+            }
+            _ = histogram_interval.tick(), if histogrammer.is_enabled() => {
+                histogrammer.publish(&producer);
+            }
+            msg = recv_if_present(run_control_consumer.as_ref().map(|(consumer, _)| consumer)) => match msg {
+                Ok(msg) => process_run_control_message(&mut histogrammer, &producer, &msg),
+                Err(e) => warn!("Kafka error: {}", e),
+            },
+            msg = recv_if_present(control_consumer.as_ref().map(|(consumer, _)| consumer)) => match msg {
+                Ok(msg) => process_control_message(&mut cache, &msg),
+                Err(e) => warn!("Kafka error: {}", e),
+            },
+            Some(delivered) = delivered_recv.recv() => {
+                let mut offsets = lock_offsets(&offsets);
+                offsets.release(&delivered);
+                commit_offsets(&consumer, &args.input_topic, &mut offsets, CommitMode::Async);
+            }
+            // The producer task only ends before the component is interrupted if a frame could not be delivered.
+            // Its offsets are never released, so the messages from which it was built are consumed again on restart.
+            result = &mut producer_task_handle => {
+                commit_delivered_offsets(&consumer, &args.input_topic, &offsets, &mut delivered_recv);
+                return result.into_diagnostic()?.into_diagnostic().wrap_err("Failed to deliver frame");
             }
             _ = sigint.recv() => {
                 //  Wait for the channel to close and
                 //  all pending production tasks to finish
-                producer_task_handle.await.into_diagnostic()?;
-                return Ok(());
+                let result = (&mut producer_task_handle).await;
+                commit_delivered_offsets(&consumer, &args.input_topic, &offsets, &mut delivered_recv);
+                return result.into_diagnostic()?.into_diagnostic().wrap_err("Failed to deliver frame");
             }
         }
     }
@@ -245,19 +580,148 @@ fn spanned_root_as_digitizer_event_list_message(
     root_as_digitizer_event_list_message(payload)
 }
 
+///  This function wraps the [dev3::root_as_digitizer_event_list_message] function, allowing it to be instrumented.
+#[instrument(skip_all, level = "trace", err(level = "warn"))]
+fn spanned_root_as_digitizer_event_list_message_v3(
+    payload: &[u8],
+) -> Result<dev3::DigitizerEventListMessage<'_>, InvalidFlatbuffer> {
+    dev3::root_as_digitizer_event_list_message(payload)
+}
+
+/// Locks the [OffsetTracker] shared with the consumer's [OffsetTrackingContext].
+///
+/// The lock must not be held whilst the consumer is polled, as this is when rebalance callbacks run.
+fn lock_offsets(offsets: &Mutex<OffsetTracker>) -> MutexGuard<'_, OffsetTracker> {
+    offsets
+        .lock()
+        .expect("Offset tracker lock should not be poisoned")
+}
+
+/// Applies the partitions of the input topic revoked and assigned by rebalances since this was last called.
+///
+/// This must be called after the consumer is polled, during which rebalances occur, and before the cache is polled
+/// or a message is processed, so frames from revoked partitions are never dispatched, and the messages of newly assigned
+/// partitions are only processed once the last frame dispatched from them has been found.
+/// # Parameters
+/// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+/// - offsets: the tracker shared with the consumer's [OffsetTrackingContext], in which rebalances are recorded.
+/// - resume: finds the last frame dispatched from each assigned partition.
+fn apply_partition_changes(
+    cache: &mut FrameCache<EventData>,
+    offsets: &Mutex<OffsetTracker>,
+    resume: &ResumeLookup,
+) {
+    let changes = lock_offsets(offsets).take_partition_changes();
+    if !changes.revoked.is_empty() {
+        // The messages of the evicted frames are consumed again by the new owners of the revoked partitions.
+        // Any of their messages from partitions which remain assigned must be released, or no further offsets
+        // of those partitions could be committed.
+        let evicted = cache.evict_partitions(&changes.revoked);
+        info!(
+            "Evicted frames of revoked partitions {:?}, containing {} messages",
+            changes.revoked,
+            evicted.len()
+        );
+        lock_offsets(offsets).release(&evicted);
+    }
+    if !changes.assigned.is_empty() {
+        resume.resume(cache, &changes.assigned);
+    }
+}
+
+/// Commits the position of each partition of the input topic which has advanced since the last commit.
+/// # Parameters
+/// - consumer: the consumer of the input topic.
+/// - input_topic: the Kafka topic from which digitiser messages are consumed.
+/// - offsets: tracks the offsets which can be committed.
+/// - mode: whether to commit synchronously or asynchronously.
+fn commit_offsets(
+    consumer: &impl Consumer,
+    input_topic: &str,
+    offsets: &mut OffsetTracker,
+    mode: CommitMode,
+) {
+    let positions = offsets.positions_to_commit();
+    if positions.is_empty() {
+        return;
+    }
+    let list = positions_to_list(input_topic, &positions);
+    if let Err(e) = consumer.commit(&list, mode) {
+        warn!("Failed to commit offsets: {e}");
+    }
+}
+
+/// Releases the offsets of every frame delivered before the producer task ended, and synchronously commits the positions
+/// of the input topic. The offsets of frames which were never delivered remain held, so are not committed.
+/// # Parameters
+/// - consumer: the consumer of the input topic.
+/// - input_topic: the Kafka topic from which digitiser messages are consumed.
+/// - offsets: tracks the offsets which can be committed.
+/// - delivered_recv: receive channel which takes the offsets of the messages of each frame once it has been delivered.
+fn commit_delivered_offsets(
+    consumer: &impl Consumer,
+    input_topic: &str,
+    offsets: &Mutex<OffsetTracker>,
+    delivered_recv: &mut UnboundedReceiver<Vec<MessageOffset>>,
+) {
+    let mut offsets = lock_offsets(offsets);
+    while let Ok(delivered) = delivered_recv.try_recv() {
+        offsets.release(&delivered);
+    }
+    commit_offsets(consumer, input_topic, &mut offsets, CommitMode::Sync);
+}
+
+/// Parses a control message and, if successful, applies it to the cache.
+/// # Parameters
+/// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+/// - msg: the message.
+#[instrument(skip_all, level = "info")]
+fn process_control_message(cache: &mut FrameCache<EventData>, msg: &impl Message) {
+    let Some(payload) = msg.payload() else {
+        return;
+    };
+    match serde_json::from_slice::<ControlMessage>(payload) {
+        Ok(control) => {
+            if let Some(expected_digitisers) = control.expected_digitisers {
+                cache.set_expected_digitisers(expected_digitisers);
+            }
+        }
+        Err(e) => {
+            warn!("Failed to parse control message: {}", e);
+            counter!(
+                FAILURES,
+                &[failures::get_label(FailureKind::UnableToDecodeMessage)]
+            )
+            .increment(1);
+        }
+    }
+}
+
+/// Records the failure to decode a digitiser message.
+fn report_parse_message_failure(e: InvalidFlatbuffer) {
+    warn!("Failed to parse message: {}", e);
+    counter!(
+        FAILURES,
+        &[failures::get_label(FailureKind::UnableToDecodeMessage)]
+    )
+    .increment(1);
+}
+
 /// Extracts the payload of a Kafka message and passes it to [process_digitiser_event_list_message]
 /// # Parameters
 /// - use_otel: if true, then attempts to extract a parent [Span] from the Kafka headers.
 /// - channel_send: send channel which takes [AggregatedFrame] objects to dispatch.
-/// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+/// - policy: determines what happens if the send channel is full.
+/// - context: the state which is updated by the message.
 /// - msg: the message.
 ///
 /// [Span]: tracing::Span
 #[instrument(skip_all, level = "debug", err(level = "warn"))]
-async fn process_kafka_message(
+fn process_kafka_message(
     use_otel: bool,
     channel_send: &AggregatedFrameToBufferSender,
-    cache: &mut FrameCache<EventData>,
+    policy: SendBufferFullPolicy,
+    context: &mut MessageContext<'_>,
     msg: &BorrowedMessage<'_>,
 ) -> Result<(), SendAggregatedFrameError> {
     msg.headers().conditional_extract_to_current_span(use_otel);
@@ -271,23 +735,37 @@ async fn process_kafka_message(
             .increment(1);
             match spanned_root_as_digitizer_event_list_message(payload) {
                 Ok(data) => {
-                    let kafka_timestamp_ms = msg.timestamp().to_millis().unwrap_or(-1);
                     process_digitiser_event_list_message(
                         channel_send,
-                        cache,
-                        kafka_timestamp_ms,
-                        data,
-                    )
-                    .await?;
+                        policy,
+                        context,
+                        msg,
+                        data.digitizer_id(),
+                        data.metadata(),
+                        data.into(),
+                    )?;
                 }
-                Err(e) => {
-                    warn!("Failed to parse message: {}", e);
-                    counter!(
-                        FAILURES,
-                        &[failures::get_label(FailureKind::UnableToDecodeMessage)]
-                    )
-                    .increment(1);
+                Err(e) => report_parse_message_failure(e),
+            }
+        } else if dev3::digitizer_event_list_message_buffer_has_identifier(payload) {
+            counter!(
+                MESSAGES_RECEIVED,
+                &[messages_received::get_label(MessageKind::Event)]
+            )
+            .increment(1);
+            match spanned_root_as_digitizer_event_list_message_v3(payload) {
+                Ok(data) => {
+                    process_digitiser_event_list_message(
+                        channel_send,
+                        policy,
+                        context,
+                        msg,
+                        data.digitizer_id(),
+                        data.metadata(),
+                        data.into(),
+                    )?;
                 }
+                Err(e) => report_parse_message_failure(e),
             }
         } else {
             warn!("Unexpected message type on topic \"{}\"", msg.topic());
@@ -303,43 +781,74 @@ async fn process_kafka_message(
     Ok(())
 }
 
-/// Processes a [DigitizerEventListMessage], pushing it to the given [FrameCache].
+/// Processes the contents of a digitiser event list message, pushing it to the given [FrameCache].
 /// # Parameters
 /// - channel_send: send channel which takes [AggregatedFrame] objects to dispatch.
-/// - kafka_message_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header. Only used for tracing.
-/// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
-/// - message: the digitiser message.
+/// - policy: determines what happens if the send channel is full.
+/// - context: the state which is updated by the message.
+/// - msg: the Kafka message.
+/// - digitizer_id: the id of the digitiser which sent the message.
+/// - message_metadata: the frame metadata of the message.
+/// - data: the event list of the message.
 #[tracing::instrument(skip_all, fields(
-    digitiser_id = message.digitizer_id(),
-    kafka_message_timestamp_ms=kafka_message_timestamp_ms,
+    digitiser_id = digitizer_id,
+    kafka_message_timestamp_ms = msg.timestamp().to_millis().unwrap_or(-1),
     metadata_timestamp,
     metadata_frame_number,
     metadata_period_number,
     metadata_veto_flags,
     metadata_protons_per_pulse,
     metadata_running,
-    num_cached_frames = cache.get_num_partial_frames(),
+    num_cached_frames = context.cache.get_num_partial_frames(),
     timestamp_too_early = false,
     id_already_present = false,
+    timestamp_quarantined = false,
+    late_for_dispatched_frame = false,
+    inconsistent_metadata = false,
 ))]
-async fn process_digitiser_event_list_message(
+fn process_digitiser_event_list_message(
     channel_send: &AggregatedFrameToBufferSender,
-    cache: &mut FrameCache<EventData>,
-    kafka_message_timestamp_ms: i64,
-    message: DigitizerEventListMessage<'_>,
+    policy: SendBufferFullPolicy,
+    context: &mut MessageContext<'_>,
+    msg: &BorrowedMessage<'_>,
+    digitizer_id: DigitizerId,
+    message_metadata: FrameMetadataV2<'_>,
+    data: EventData,
 ) -> Result<(), SendAggregatedFrameError> {
-    match message.metadata().try_into() {
+    match message_metadata.try_into() {
         Ok(metadata) => {
-            debug!("Event packet: metadata: {:?}", message.metadata());
+            debug!("Event packet: metadata: {:?}", message_metadata);
+
+            let offset = MessageOffset::from(msg);
+            let broker_timestamp = msg
+                .timestamp()
+                .to_millis()
+                .filter(|&timestamp_ms| timestamp_ms >= 0)
+                .and_then(DateTime::from_timestamp_millis);
+
+            // Events are counted whether or not the message is accepted, as they show the detector is live.
+            context.monitor.record(digitizer_id, data.channels());
 
             // Push the current digitiser message to the frame cache, possibly creating a new partial frame
-            if let Err(err) = cache.push(message.digitizer_id(), &metadata, message.into()) {
-                tracing::Span::current().record(err.into(), true);
+            match context
+                .cache
+                .push(digitizer_id, &metadata, data, offset, broker_timestamp)
+            {
+                Ok(()) => context.offsets.hold(offset),
+                Err(err) => {
+                    let reason: &'static str = err.into();
+                    tracing::Span::current().record(reason, true);
+                    counter!(REJECTED_MESSAGES, &[("reason", reason)]).increment(1);
+                    // The offset of a forwarded message is only committed once it has been delivered.
+                    if context.rejected.forward(msg, err) {
+                        context.offsets.hold(offset);
+                    }
+                }
             }
 
             record_metadata_fields_to_span!(&metadata, tracing::Span::current());
 
-            cache_poll(channel_send, cache).await?;
+            cache_poll(channel_send, policy, context.cache, context.histogrammer)?;
         }
         Err(e) => {
             warn!("Invalid Metadata: {e}");
@@ -356,15 +865,47 @@ async fn process_digitiser_event_list_message(
 /// Polls the given [FrameCache] to see if there are any [AggregatedFrame]s ready to be dispatched.
 ///
 /// If there are, this function removes them from the cache and sends them to the given send channel.
+/// A frame is only removed once space for it is reserved in the channel, so this never waits for space.
 /// # Parameters
 /// - channel_send: send channel which takes [AggregatedFrame] objects to dispatch.
+/// - policy: if [SendBufferFullPolicy::Backpressure], then frames are left in the cache whilst the send channel is full,
+///   to be dispatched by a later poll once it has drained, otherwise a full channel returns an error.
 /// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+/// - histogrammer: accumulates the histograms of dispatched frames.
 #[tracing::instrument(skip_all, level = "trace")]
-async fn cache_poll(
+fn cache_poll(
     channel_send: &AggregatedFrameToBufferSender,
+    policy: SendBufferFullPolicy,
     cache: &mut FrameCache<EventData>,
+    histogrammer: &mut Histogrammer,
 ) -> Result<(), SendAggregatedFrameError> {
-    while let Some(frame) = cache.poll() {
+    loop {
+        let permit = match channel_send.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Full(())) if policy == SendBufferFullPolicy::Backpressure => {
+                return Ok(());
+            }
+            Err(e) => {
+                let Some(frame) = cache.poll() else {
+                    return Ok(());
+                };
+                return Err(match e {
+                    TrySendError::Closed(()) => {
+                        error!("Send-Frame Error");
+                        TrySendError::Closed(frame)
+                    }
+                    TrySendError::Full(()) => {
+                        error!("Send-Frame Buffer Full");
+                        TrySendError::Full(frame)
+                    }
+                });
+            }
+        };
+        let Some(frame) = cache.poll() else {
+            return Ok(());
+        };
+        histogrammer.accumulate(&frame);
+
         let span = info_span!("Frame Completed");
         span.follows_from(
             frame
@@ -374,32 +915,35 @@ async fn cache_poll(
         );
         let _guard = span.enter();
 
-        // Reserves space in the message queue if it is available
-        // Or waits for space if none is available.
-        match channel_send.reserve().await {
-            Ok(permit) => permit.send(frame),
-            Err(_) => {
-                error!("Send-Frame Error");
-                return Err(SendError(frame));
-            }
-        }
+        permit.send(frame);
     }
-    Ok(())
+}
+
+/// The number of frames in the send channel's buffer.
+fn send_buffer_depth(channel_send: &AggregatedFrameToBufferSender) -> usize {
+    channel_send.max_capacity() - channel_send.capacity()
 }
 
 // The following functions control the kafka producer thread.
 /// Create a new thread and setup the producer task.
 /// # Parameters
 /// - use_otel: if true, then the thread attempts to inject [AggregatedFrame::span()] into the Kafka header.
-/// - send_frame_buffer_size: the maximum number of [AggregatedFrame] objects to store in the channel's buffer. If the buffer is filled, then sending another frame will block until there is sufficient space in the buffer.
+/// - send_frame_buffer_size: the maximum number of [AggregatedFrame] objects to store in the channel's buffer. What happens when the buffer is filled is determined by the [SendBufferFullPolicy].
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.
+/// - encoding: the order and encoding of the events of each frame.
+/// - delivered_send: send channel which takes the offsets of the messages of each frame once it has been delivered.
 fn create_producer_task(
     use_otel: bool,
     send_frame_buffer_size: usize,
     producer: &FutureProducer,
     output_topic: &str,
-) -> std::io::Result<(AggregatedFrameToBufferSender, JoinHandle<()>)> {
+    encoding: FrameEncodingOptions,
+    delivered_send: DeliveredOffsetsSender,
+) -> std::io::Result<(
+    AggregatedFrameToBufferSender,
+    JoinHandle<Result<(), KafkaError>>,
+)> {
     let (channel_send, channel_recv) =
         tokio::sync::mpsc::channel::<AggregatedFrame<EventData>>(send_frame_buffer_size);
 
@@ -409,6 +953,8 @@ fn create_producer_task(
         channel_recv,
         producer.to_owned(),
         output_topic.to_owned(),
+        encoding,
+        delivered_send,
         sigint,
     ));
     Ok((channel_send, handle))
@@ -416,6 +962,7 @@ fn create_producer_task(
 
 /// Runs infinitely, and waits on any [AggregatedFrame]s received through the given receive channel.
 ///
+/// Returns once the channel is closed, or with an error if a frame could not be delivered.
 /// Calling this function returns a Future, which should be passed to a async task,
 /// as in function [create_producer_task]. The general form of this is:
 /// ```rust
@@ -426,29 +973,33 @@ fn create_producer_task(
 /// - channel_recv: receive channel that can receive [AggregatedFrame] objects.
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.
+/// - encoding: the order and encoding of the events of each frame.
+/// - delivered_send: send channel which takes the offsets of the messages of each frame once it has been delivered.
 async fn produce_to_kafka(
     use_otel: bool,
     mut channel_recv: Receiver<AggregatedFrame<EventData>>,
     producer: FutureProducer,
     output_topic: String,
+    encoding: FrameEncodingOptions,
+    delivered_send: DeliveredOffsetsSender,
     mut sigint: Signal,
-) {
+) -> Result<(), KafkaError> {
     loop {
         select! {
             message = channel_recv.recv() => {
                 // Blocks until a frame is received
                 match message {
                     Some(frame) => {
-                        produce_frame_to_kafka(use_otel, frame, &producer, &output_topic).await;
+                        produce_frame_to_kafka(use_otel, frame, &producer, &output_topic, encoding, &delivered_send).await?;
                     }
                     None => {
                         info!("Send-Frame channel closed");
-                        return;
+                        return Ok(());
                     }
                 }
             }
             _ = sigint.recv() => {
-                close_and_flush_producer_channel(use_otel,&mut channel_recv,&producer,&output_topic).await;
+                close_and_flush_producer_channel(use_otel,&mut channel_recv,&producer,&output_topic,encoding,&delivered_send).await?;
             }
         }
     }
@@ -460,19 +1011,31 @@ async fn produce_to_kafka(
 /// - channel_recv: receive channel that can receive [AggregatedFrame] objects.
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.
+/// - encoding: the order and encoding of the events of each frame.
+/// - delivered_send: send channel which takes the offsets of the messages of each frame once it has been delivered.
 #[tracing::instrument(skip_all, name = "Closing", level = "info", fields(capacity = channel_recv.capacity(), max_capacity = channel_recv.max_capacity()))]
 async fn close_and_flush_producer_channel(
     use_otel: bool,
     channel_recv: &mut Receiver<AggregatedFrame<EventData>>,
     producer: &FutureProducer,
     output_topic: &str,
-) -> Option<()> {
+    encoding: FrameEncodingOptions,
+    delivered_send: &DeliveredOffsetsSender,
+) -> Result<(), KafkaError> {
     channel_recv.close();
 
-    loop {
-        let frame = channel_recv.recv().await?;
-        flush_frame(use_otel, frame, producer, output_topic).await?;
+    while let Some(frame) = channel_recv.recv().await {
+        flush_frame(
+            use_otel,
+            frame,
+            producer,
+            output_topic,
+            encoding,
+            delivered_send,
+        )
+        .await?;
     }
+    Ok(())
 }
 
 /// Dispatches the given frame to the Kafka broker on the given topic.
@@ -483,49 +1046,184 @@ async fn close_and_flush_producer_channel(
 /// - frame: the frame to dispatch.
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.|
+/// - encoding: the order and encoding of the events of the frame.
+/// - delivered_send: send channel which takes the offsets of the frame's messages once it has been delivered.
 #[tracing::instrument(skip_all, name = "Flush Frame")]
 async fn flush_frame(
     use_otel: bool,
     frame: AggregatedFrame<EventData>,
     producer: &FutureProducer,
     output_topic: &str,
-) -> Option<()> {
-    produce_frame_to_kafka(use_otel, frame, producer, output_topic).await;
-    Some(())
+    encoding: FrameEncodingOptions,
+    delivered_send: &DeliveredOffsetsSender,
+) -> Result<(), KafkaError> {
+    produce_frame_to_kafka(
+        use_otel,
+        frame,
+        producer,
+        output_topic,
+        encoding,
+        delivered_send,
+    )
+    .await
 }
 
 /// Dispatches the given frame to the Kafka broker on the given topic.
+///
+/// Delivery is attempted up to [DELIVERY_ATTEMPTS] times, with a backoff between attempts doubling from [DELIVERY_RETRY_BACKOFF].
 /// # Parameters
 /// - use_otel: if true, then the thread attempts to inject [AggregatedFrame::span()] into the Kafka header.
 /// - frame: the frame to dispatch.
 /// - producer: the Kafka producer object.
 /// - output_topic: the Kafka topic to produce the message to.
+/// - encoding: the order and encoding of the events of the frame.
+/// - delivered_send: send channel which takes the offsets of the frame's messages once it has been delivered.
+/// # Error
+/// The error of the final attempt, if the frame could not be delivered.
+/// The offsets of the frame's messages are then never released, so are not committed.
 async fn produce_frame_to_kafka(
     use_otel: bool,
-    frame: AggregatedFrame<EventData>,
+    mut frame: AggregatedFrame<EventData>,
     producer: &FutureProducer,
     output_topic: &str,
-) {
+    encoding: FrameEncodingOptions,
+    delivered_send: &DeliveredOffsetsSender,
+) -> Result<(), KafkaError> {
     let frame_span = frame.span().get().expect("Span should exist").clone();
-    let data: Vec<u8> = frame.into();
+    let offsets = std::mem::take(&mut frame.offsets);
+    let correction = frame.correction;
+    let partition = frame.partition;
+    let data = frame.encode(encoding);
 
-    let future_record = FutureRecord::to(output_topic)
-        .payload(data.as_slice())
-        .conditional_inject_span_into_headers(use_otel, &frame_span)
-        .key("Frame Events List");
+    let mut attempt = 1;
+    let mut backoff = DELIVERY_RETRY_BACKOFF;
+    loop {
+        let mut future_record = FutureRecord::to(output_topic)
+            .payload(data.as_slice())
+            .conditional_inject_span_into_headers(use_otel, &frame_span)
+            .key("Frame Events List");
+        if let Some(partition) = partition {
+            future_record = future_record.partition(partition);
+        }
 
-    match producer.send(future_record, PRODUCER_TIMEOUT).await {
-        Ok(r) => {
-            debug!("Delivery: {:?}", r);
-            counter!(FRAMES_SENT).increment(1)
+        match producer.send(future_record, PRODUCER_TIMEOUT).await {
+            Ok(r) => {
+                debug!("Delivery: {:?}", r);
+                if correction {
+                    counter!(CORRECTION_FRAMES_SENT).increment(1)
+                } else {
+                    counter!(FRAMES_SENT).increment(1)
+                }
+                if delivered_send.send(offsets).is_err() {
+                    warn!("Delivered offsets channel closed");
+                }
+                return Ok(());
+            }
+            Err((e, _)) => {
+                counter!(
+                    FAILURES,
+                    &[failures::get_label(FailureKind::KafkaPublishFailed)]
+                )
+                .increment(1);
+                if attempt == DELIVERY_ATTEMPTS {
+                    error!("Delivery failed after {attempt} attempts: {e}");
+                    return Err(e);
+                }
+                warn!("Delivery attempt {attempt} failed, retrying in {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+                backoff *= 2;
+            }
         }
-        Err(e) => {
-            error!("Delivery failed: {:?}", e);
-            counter!(
-                FAILURES,
-                &[failures::get_label(FailureKind::KafkaPublishFailed)]
-            )
-            .increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use supermusr_streaming_types::FrameMetadata;
+
+    fn cache_with_complete_frames(num_frames: u32) -> FrameCache<EventData> {
+        let mut cache = FrameCache::<EventData>::new(
+            FrameTtl::fixed(Duration::from_millis(100)),
+            ExpectedDigitisers::new(vec![0], None),
+        );
+        let timestamp = Utc::now();
+        for frame_number in 0..num_frames {
+            let metadata = FrameMetadata {
+                timestamp: timestamp + Duration::from_millis(frame_number.into()),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number,
+                veto_flags: 0,
+            };
+            cache
+                .push(
+                    0,
+                    &metadata,
+                    EventData::dummy_data(0, 5, &[0]),
+                    MessageOffset::default(),
+                    None,
+                )
+                .unwrap();
         }
+        cache
+    }
+
+    fn histogrammer() -> Histogrammer {
+        Histogrammer::new(HistogramOptions {
+            histogram_topic: None,
+            histogram_interval_ms: 1000,
+            histogram_tof_min_ns: 0,
+            histogram_tof_max_ns: 100,
+            histogram_bins: NonZeroUsize::new(1).unwrap(),
+            run_control_topic: None,
+        })
+    }
+
+    #[test]
+    fn full_channel_leaves_frames_in_cache_under_backpressure() {
+        let (channel_send, mut channel_recv) = tokio::sync::mpsc::channel(1);
+        let mut cache = cache_with_complete_frames(2);
+        let mut histogrammer = histogrammer();
+
+        cache_poll(
+            &channel_send,
+            SendBufferFullPolicy::Backpressure,
+            &mut cache,
+            &mut histogrammer,
+        )
+        .unwrap();
+        assert_eq!(channel_recv.try_recv().unwrap().metadata.frame_number, 0);
+        assert!(channel_recv.try_recv().is_err());
+        assert_eq!(cache.get_num_partial_frames(), 1);
+
+        cache_poll(
+            &channel_send,
+            SendBufferFullPolicy::Backpressure,
+            &mut cache,
+            &mut histogrammer,
+        )
+        .unwrap();
+        assert_eq!(channel_recv.try_recv().unwrap().metadata.frame_number, 1);
+        assert_eq!(cache.get_num_partial_frames(), 0);
+    }
+
+    #[test]
+    fn full_channel_is_an_error_under_exit_policy() {
+        let (channel_send, _channel_recv) = tokio::sync::mpsc::channel(1);
+        let mut cache = cache_with_complete_frames(2);
+
+        assert!(matches!(
+            cache_poll(
+                &channel_send,
+                SendBufferFullPolicy::Exit,
+                &mut cache,
+                &mut histogrammer(),
+            ),
+            Err(TrySendError::Full(_))
+        ));
     }
 }
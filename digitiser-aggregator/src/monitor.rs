@@ -0,0 +1,512 @@
+//! Monitors the rate of events on each channel and from each digitiser, and flags channels
+//! which are silent or hot compared with their neighbours.
+use chrono::{DateTime, Utc};
+use clap::Args;
+use const_format::concatcp;
+use metrics::{counter, gauge};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    time::Duration,
+};
+use supermusr_common::{
+    Channel, DigitizerId,
+    metrics::{
+        failures::{self, FailureKind},
+        names::{FAILURES, METRIC_NAME_PREFIX},
+    },
+};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// The rolling event rate of each channel, labelled by channel.
+pub(crate) const CHANNEL_EVENT_RATE: &str = concatcp!(METRIC_NAME_PREFIX, "channel_event_rate");
+/// The rolling event rate of each digitiser, labelled by digitiser id.
+pub(crate) const DIGITISER_EVENT_RATE: &str = concatcp!(METRIC_NAME_PREFIX, "digitiser_event_rate");
+/// Whether each channel is flagged, labelled by channel and condition.
+pub(crate) const FLAGGED_CHANNELS: &str = concatcp!(METRIC_NAME_PREFIX, "flagged_channels");
+
+#[derive(Clone, Debug, Args)]
+pub(crate) struct ChannelMonitorOptions {
+    /// If set, the event rate of each channel and digitiser is monitored, and channels which are silent
+    /// or hot compared with their neighbours are flagged. See README.md.
+    #[clap(long)]
+    pub(crate) channel_monitor: bool,
+
+    /// If set, a JSON summary of the event rates and flagged channels is sent to this Kafka topic at each monitor interval.
+    #[clap(long)]
+    pub(crate) monitor_topic: Option<String>,
+
+    /// Interval in milliseconds at which event rates are updated.
+    #[clap(long, default_value = "10000")]
+    pub(crate) monitor_interval_ms: u64,
+
+    /// Number of monitor intervals over which event rates are averaged.
+    #[clap(long, default_value = "6")]
+    pub(crate) monitor_window: NonZeroUsize,
+
+    /// Comma separated list of channels, or inclusive ranges of channels such as `0-47`, which are monitored from startup,
+    /// so one which registers no events is flagged silent. Other channels are monitored from their first event.
+    #[clap(long, value_delimiter = ',', value_parser = parse_channel_range)]
+    pub(crate) monitor_channels: Vec<RangeInclusive<Channel>>,
+
+    /// Number of channels either side of each channel with whose event rates it is compared.
+    #[clap(long, default_value = "4")]
+    pub(crate) monitor_neighbours: usize,
+
+    /// A channel is flagged silent if its event rate is less than this fraction of the median rate of its neighbours.
+    #[clap(long, default_value = "0.1")]
+    pub(crate) silent_channel_fraction: f64,
+
+    /// A channel is flagged hot if its event rate is more than this multiple of the median rate of its neighbours.
+    #[clap(long, default_value = "10")]
+    pub(crate) hot_channel_factor: f64,
+}
+
+/// Parses a channel, or an inclusive range of channels such as `0-47`.
+fn parse_channel_range(value: &str) -> Result<RangeInclusive<Channel>, String> {
+    let parse = |channel: &str| {
+        channel
+            .trim()
+            .parse::<Channel>()
+            .map_err(|e| format!("{e}"))
+    };
+    let range = match value.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => {
+            let channel = parse(value)?;
+            channel..=channel
+        }
+    };
+    if range.is_empty() {
+        Err(format!("{value} is an empty range of channels"))
+    } else {
+        Ok(range)
+    }
+}
+
+impl ChannelMonitorOptions {
+    /// Returns the interval at which event rates are updated.
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_millis(self.monitor_interval_ms)
+    }
+}
+
+/// The condition of a channel which is flagged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChannelCondition {
+    /// The channel's event rate is far below that of its neighbours.
+    Silent,
+    /// The channel's event rate is far above that of its neighbours.
+    Hot,
+}
+
+impl From<ChannelCondition> for &'static str {
+    fn from(value: ChannelCondition) -> Self {
+        match value {
+            ChannelCondition::Silent => "silent",
+            ChannelCondition::Hot => "hot",
+        }
+    }
+}
+
+/// A summary of the event rates, sent to the monitor topic at each monitor interval.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MonitorSummary {
+    /// The time at which the summary was made.
+    timestamp: DateTime<Utc>,
+    /// The event rate of each digitiser, in events per second.
+    digitiser_rates: BTreeMap<DigitizerId, f64>,
+    /// The channels whose event rates are far below those of their neighbours.
+    silent_channels: Vec<Channel>,
+    /// The channels whose event rates are far above those of their neighbours.
+    hot_channels: Vec<Channel>,
+}
+
+/// The event counts of recent monitor intervals, for a channel or digitiser.
+#[derive(Default)]
+struct EventCounts {
+    /// The number of events in the current interval.
+    current: u64,
+    /// The number of events in each completed interval of the window, the most recent last.
+    history: VecDeque<u64>,
+}
+
+impl EventCounts {
+    /// Completes the current interval, and returns the mean rate over the window in events per second.
+    /// # Parameters
+    /// - window: the maximum number of intervals in the history.
+    /// - durations: the duration of each completed interval, the most recent last, of which there are at least as many as in the history.
+    fn roll(&mut self, window: usize, durations: &VecDeque<Duration>) -> f64 {
+        if self.history.len() >= window {
+            self.history.pop_front();
+        }
+        self.history.push_back(std::mem::take(&mut self.current));
+
+        let seconds = durations
+            .iter()
+            .rev()
+            .take(self.history.len())
+            .map(Duration::as_secs_f64)
+            .sum::<f64>();
+        if seconds > 0.0 {
+            self.history.iter().sum::<u64>() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Keeps the rolling event rates of each channel and digitiser.
+///
+/// The expected digitisers and the channels of `--monitor-channels` are monitored from startup,
+/// so that one which is silent from startup is compared with its neighbours. Others are monitored from their first event.
+pub(crate) struct ChannelMonitor {
+    options: ChannelMonitorOptions,
+    /// Time at which the current interval began.
+    interval_start: Instant,
+    /// The duration of each completed interval of the window, the most recent last.
+    durations: VecDeque<Duration>,
+    channels: BTreeMap<Channel, EventCounts>,
+    digitisers: BTreeMap<DigitizerId, EventCounts>,
+    /// The channels which are currently flagged.
+    flagged: BTreeMap<Channel, ChannelCondition>,
+}
+
+impl ChannelMonitor {
+    /// Creates and returns a new [ChannelMonitor] instance.
+    /// # Parameters
+    /// - options: the monitor options, including the channels monitored from startup.
+    /// - digitisers: the digitisers expected to contribute to each frame, which are monitored from startup.
+    pub(crate) fn new(options: ChannelMonitorOptions, digitisers: &[DigitizerId]) -> Self {
+        let channels = options
+            .monitor_channels
+            .iter()
+            .cloned()
+            .flatten()
+            .map(|channel| (channel, EventCounts::default()))
+            .collect();
+        let digitisers = digitisers
+            .iter()
+            .map(|&id| (id, EventCounts::default()))
+            .collect();
+        Self {
+            interval_start: Instant::now(),
+            durations: VecDeque::with_capacity(options.monitor_window.get()),
+            options,
+            channels,
+            digitisers,
+            flagged: Default::default(),
+        }
+    }
+
+    /// Returns `true` if event rates are monitored.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.options.channel_monitor
+    }
+
+    /// Counts the events of a digitiser message.
+    /// # Parameters
+    /// - digitiser_id: the id of the digitiser which sent the message.
+    /// - channels: the channel of each event in the message.
+    pub(crate) fn record(&mut self, digitiser_id: DigitizerId, channels: &[Channel]) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.digitisers.entry(digitiser_id).or_default().current += channels.len() as u64;
+        for &channel in channels {
+            self.channels.entry(channel).or_default().current += 1;
+        }
+    }
+
+    /// Completes the current interval, updates the event rates and flagged channels, and exports them as gauges.
+    pub(crate) fn update(&mut self) -> MonitorSummary {
+        let now = Instant::now();
+        let elapsed = now - self.interval_start;
+        self.interval_start = now;
+        self.roll(elapsed)
+    }
+
+    /// Completes the current interval, of the given duration. See [Self::update].
+    fn roll(&mut self, elapsed: Duration) -> MonitorSummary {
+        let window = self.options.monitor_window.get();
+        if self.durations.len() >= window {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(elapsed);
+
+        let digitiser_rates = self
+            .digitisers
+            .iter_mut()
+            .map(|(&id, counts)| (id, counts.roll(window, &self.durations)))
+            .collect::<BTreeMap<_, _>>();
+        for (id, rate) in &digitiser_rates {
+            gauge!(DIGITISER_EVENT_RATE, &[("digitizer_id", id.to_string())]).set(*rate);
+        }
+
+        let channel_rates = self
+            .channels
+            .iter_mut()
+            .map(|(&channel, counts)| (channel, counts.roll(window, &self.durations)))
+            .collect::<Vec<_>>();
+        for (channel, rate) in &channel_rates {
+            gauge!(CHANNEL_EVENT_RATE, &[("channel", channel.to_string())]).set(*rate);
+        }
+
+        for (index, (channel, rate)) in channel_rates.iter().enumerate() {
+            let condition =
+                neighbour_median(&channel_rates, index, self.options.monitor_neighbours)
+                    .and_then(|median| self.condition(*rate, median));
+            self.set_condition(*channel, condition);
+        }
+
+        let flagged = |condition| {
+            self.flagged
+                .iter()
+                .filter(|(_, flagged)| **flagged == condition)
+                .map(|(channel, _)| *channel)
+                .collect::<Vec<_>>()
+        };
+        MonitorSummary {
+            timestamp: Utc::now(),
+            digitiser_rates,
+            silent_channels: flagged(ChannelCondition::Silent),
+            hot_channels: flagged(ChannelCondition::Hot),
+        }
+    }
+
+    /// Returns the condition of a channel with the given rate, or [None] if it is not flagged.
+    /// # Parameters
+    /// - rate: the event rate of the channel.
+    /// - median: the median event rate of the channel's neighbours.
+    fn condition(&self, rate: f64, median: f64) -> Option<ChannelCondition> {
+        if median <= 0.0 {
+            None
+        } else if rate < self.options.silent_channel_fraction * median {
+            Some(ChannelCondition::Silent)
+        } else if rate > self.options.hot_channel_factor * median {
+            Some(ChannelCondition::Hot)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the condition of a channel, logging and exporting any change.
+    fn set_condition(&mut self, channel: Channel, condition: Option<ChannelCondition>) {
+        let previous = match condition {
+            Some(condition) => self.flagged.insert(channel, condition),
+            None => self.flagged.remove(&channel),
+        };
+        if previous == condition {
+            return;
+        }
+        if let Some(previous) = previous {
+            let previous: &'static str = previous.into();
+            gauge!(
+                FLAGGED_CHANNELS,
+                &[
+                    ("channel", channel.to_string()),
+                    ("condition", previous.to_owned())
+                ]
+            )
+            .set(0);
+        }
+        match condition {
+            Some(condition) => {
+                let condition: &'static str = condition.into();
+                warn!("Channel {channel} flagged {condition}");
+                gauge!(
+                    FLAGGED_CHANNELS,
+                    &[
+                        ("channel", channel.to_string()),
+                        ("condition", condition.to_owned())
+                    ]
+                )
+                .set(1);
+            }
+            None => info!("Channel {channel} no longer flagged"),
+        }
+    }
+}
+
+/// Returns the median event rate of the neighbours of a channel, or [None] if it has none.
+/// # Parameters
+/// - channel_rates: the event rate of each channel, in increasing order of channel.
+/// - index: the index of the channel in `channel_rates`.
+/// - neighbours: the number of channels either side of the channel which are its neighbours.
+fn neighbour_median(
+    channel_rates: &[(Channel, f64)],
+    index: usize,
+    neighbours: usize,
+) -> Option<f64> {
+    let &(channel, _) = channel_rates.get(index)?;
+    let mut rates = channel_rates
+        .iter()
+        .filter(|(other, _)| *other != channel && other.abs_diff(channel) as usize <= neighbours)
+        .map(|(_, rate)| *rate)
+        .collect::<Vec<_>>();
+    rates.sort_unstable_by(f64::total_cmp);
+    rates.get(rates.len() / 2).copied()
+}
+
+/// Sends a summary of the event rates to the monitor topic.
+/// # Parameters
+/// - producer: the Kafka producer object.
+/// - topic: the monitor topic.
+/// - summary: the summary to send.
+pub(crate) fn publish_summary(producer: &FutureProducer, topic: &str, summary: &MonitorSummary) {
+    let payload = match serde_json::to_vec(summary) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("Failed to serialise monitor summary: {e}");
+            return;
+        }
+    };
+    match producer.send_result(FutureRecord::<(), _>::to(topic).payload(&payload)) {
+        Ok(delivery) => {
+            tokio::spawn(async move {
+                match delivery.await {
+                    Ok(Ok(delivery)) => debug!("Monitor summary delivery: {delivery:?}"),
+                    Ok(Err((e, _))) => report_publish_failure(&e),
+                    Err(e) => report_publish_failure(&e),
+                }
+            });
+        }
+        Err((e, _)) => report_publish_failure(&e),
+    }
+}
+
+/// Records the failure to send a monitor summary.
+fn report_publish_failure(e: &dyn std::error::Error) {
+    warn!("Failed to send monitor summary: {e}");
+    counter!(
+        FAILURES,
+        &[failures::get_label(FailureKind::KafkaPublishFailed)]
+    )
+    .increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ChannelMonitorOptions {
+        ChannelMonitorOptions {
+            channel_monitor: true,
+            monitor_topic: None,
+            monitor_interval_ms: 1000,
+            monitor_window: NonZeroUsize::new(2).unwrap(),
+            monitor_channels: Vec::new(),
+            monitor_neighbours: 2,
+            silent_channel_fraction: 0.1,
+            hot_channel_factor: 10.0,
+        }
+    }
+
+    fn monitor() -> ChannelMonitor {
+        ChannelMonitor::new(options(), &[])
+    }
+
+    #[test]
+    fn digitiser_rates_averaged_over_window() {
+        let mut monitor = monitor();
+        monitor.record(0, &[0; 10]);
+        monitor.record(1, &[1; 4]);
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert_eq!(
+            summary.digitiser_rates,
+            BTreeMap::from([(0, 10.0), (1, 4.0)])
+        );
+
+        monitor.record(0, &[0; 20]);
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert_eq!(
+            summary.digitiser_rates,
+            BTreeMap::from([(0, 15.0), (1, 2.0)])
+        );
+
+        // The first interval has left the window.
+        let summary = monitor.roll(Duration::from_secs(2));
+        assert_eq!(
+            summary.digitiser_rates,
+            BTreeMap::from([(0, 20.0 / 3.0), (1, 0.0)])
+        );
+    }
+
+    #[test]
+    fn silent_and_hot_channels_flagged() {
+        let mut monitor = monitor();
+        for channel in 0..8 {
+            monitor.record(0, &[channel; 100]);
+        }
+        monitor.record(0, &[3; 2000]);
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert!(summary.silent_channels.is_empty());
+        assert_eq!(summary.hot_channels, [3]);
+
+        // Channel 5 goes silent, and channel 3 returns to normal.
+        for channel in (0..8).filter(|&channel| channel != 5) {
+            monitor.record(0, &[channel; 100]);
+        }
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert!(summary.silent_channels.is_empty());
+
+        for channel in (0..8).filter(|&channel| channel != 5) {
+            monitor.record(0, &[channel; 100]);
+        }
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert_eq!(summary.silent_channels, [5]);
+        assert!(summary.hot_channels.is_empty());
+    }
+
+    #[test]
+    fn channels_dead_from_startup_flagged() {
+        let mut monitor = ChannelMonitor::new(
+            ChannelMonitorOptions {
+                monitor_channels: vec![0..=3, 4..=7],
+                ..options()
+            },
+            &[0, 1],
+        );
+        // Channel 5 and digitiser 1 never register an event.
+        for channel in (0..8).filter(|&channel| channel != 5) {
+            monitor.record(0, &[channel; 100]);
+        }
+        let summary = monitor.roll(Duration::from_secs(1));
+        assert_eq!(summary.silent_channels, [5]);
+        assert_eq!(
+            summary.digitiser_rates,
+            BTreeMap::from([(0, 700.0), (1, 0.0)])
+        );
+    }
+
+    #[test]
+    fn channel_ranges_parsed() {
+        assert_eq!(parse_channel_range("12"), Ok(12..=12));
+        assert_eq!(parse_channel_range("0-47"), Ok(0..=47));
+        assert!(parse_channel_range("47-0").is_err());
+        assert!(parse_channel_range("0-").is_err());
+    }
+
+    #[test]
+    fn nothing_recorded_when_disabled() {
+        let mut monitor = ChannelMonitor::new(
+            ChannelMonitorOptions {
+                channel_monitor: false,
+                ..options()
+            },
+            &[],
+        );
+        monitor.record(0, &[0; 10]);
+        assert!(
+            monitor
+                .roll(Duration::from_secs(1))
+                .digitiser_rates
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Tracks the offsets of consumed digitiser messages, so that an offset is only committed
+//! once the data of every message before it has been delivered to the broker.
+//!
+//! If the component stops whilst frames are still being assembled, the messages from which
+//! they were built are therefore consumed again on restart, and no frames are lost.
+use rdkafka::{
+    ClientContext, Message, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance},
+    message::BorrowedMessage,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// Identifies a digitiser message by its partition and offset in the input topic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct MessageOffset {
+    pub(crate) partition: i32,
+    pub(crate) offset: i64,
+}
+
+impl From<&BorrowedMessage<'_>> for MessageOffset {
+    fn from(msg: &BorrowedMessage<'_>) -> Self {
+        Self {
+            partition: msg.partition(),
+            offset: msg.offset(),
+        }
+    }
+}
+
+/// The offsets of a single partition.
+#[derive(Default)]
+struct PartitionOffsets {
+    /// Offsets of messages whose data is held in a frame that is yet to be delivered,
+    /// mapped to the number of frames holding them.
+    held: BTreeMap<i64, usize>,
+    /// The offset following the last message consumed.
+    next: i64,
+    /// The offset last returned by [OffsetTracker::positions_to_commit].
+    committed: Option<i64>,
+}
+
+impl PartitionOffsets {
+    /// The offset from which consumption should resume were the component to restart.
+    fn position(&self) -> i64 {
+        self.held
+            .first_key_value()
+            .map(|(&offset, _)| offset)
+            .unwrap_or(self.next)
+    }
+}
+
+/// Partitions of the input topic revoked or assigned by rebalances, which are yet to be applied to the frame cache.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub(crate) struct PartitionChanges {
+    /// Partitions whose frames being assembled must be evicted from the cache.
+    pub(crate) revoked: Vec<i32>,
+    /// Partitions for which the last frame dispatched must be found before their messages are processed.
+    pub(crate) assigned: Vec<i32>,
+}
+
+/// Tracks, for each partition, the messages that have been consumed and those whose data is yet to be delivered.
+#[derive(Default)]
+pub(crate) struct OffsetTracker {
+    partitions: HashMap<i32, PartitionOffsets>,
+    /// Rebalances since the last call to [Self::take_partition_changes].
+    changes: PartitionChanges,
+}
+
+impl OffsetTracker {
+    /// Records that a message has been consumed.
+    pub(crate) fn consume(&mut self, offset: MessageOffset) {
+        let partition = self.partitions.entry(offset.partition).or_default();
+        partition.next = partition.next.max(offset.offset + 1);
+    }
+
+    /// Records that the data of a message is held in a frame, so must be [released] before the message is committed.
+    ///
+    /// [released]: Self::release
+    pub(crate) fn hold(&mut self, offset: MessageOffset) {
+        *self
+            .partitions
+            .entry(offset.partition)
+            .or_default()
+            .held
+            .entry(offset.offset)
+            .or_default() += 1;
+    }
+
+    /// Records that the data of the given messages has been delivered, and need no longer be held.
+    pub(crate) fn release(&mut self, offsets: &[MessageOffset]) {
+        for offset in offsets {
+            let Some(partition) = self.partitions.get_mut(&offset.partition) else {
+                continue;
+            };
+            let Some(count) = partition.held.get_mut(&offset.offset) else {
+                continue;
+            };
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                partition.held.remove(&offset.offset);
+            }
+        }
+    }
+
+    /// Returns the position of each partition which has advanced since it was last returned.
+    ///
+    /// The position of a partition is the offset of its earliest message which is still held,
+    /// or if there is none, the offset following the last message consumed.
+    pub(crate) fn positions_to_commit(&mut self) -> Vec<(i32, i64)> {
+        self.partitions
+            .iter_mut()
+            .filter_map(|(&id, partition)| {
+                let position = partition.position();
+                if partition
+                    .committed
+                    .is_some_and(|committed| committed >= position)
+                {
+                    return None;
+                }
+                partition.committed = Some(position);
+                Some((id, position))
+            })
+            .collect()
+    }
+
+    /// Forgets the given partitions, so that their offsets are no longer committed,
+    /// and records that their frames must be evicted from the cache.
+    ///
+    /// Returns the position of each forgotten partition which has advanced since it was last committed.
+    pub(crate) fn revoke(&mut self, partitions: &[i32]) -> Vec<(i32, i64)> {
+        self.changes
+            .assigned
+            .retain(|partition| !partitions.contains(partition));
+        self.changes.revoked.extend_from_slice(partitions);
+        partitions
+            .iter()
+            .filter_map(|id| {
+                let partition = self.partitions.remove(id)?;
+                let position = partition.position();
+                partition
+                    .committed
+                    .is_none_or(|committed| committed < position)
+                    .then_some((*id, position))
+            })
+            .collect()
+    }
+
+    /// Records that the given partitions have been assigned, so the last frame dispatched from each must be found.
+    pub(crate) fn assign(&mut self, partitions: &[i32]) {
+        self.changes.assigned.extend_from_slice(partitions);
+    }
+
+    /// Returns the partitions revoked and assigned since this was last called.
+    pub(crate) fn take_partition_changes(&mut self) -> PartitionChanges {
+        std::mem::take(&mut self.changes)
+    }
+}
+
+/// Creates a list of the given positions of the partitions of a topic, to be committed.
+/// # Parameters
+/// - topic: the Kafka topic.
+/// - positions: the partition and offset of each position.
+pub(crate) fn positions_to_list(topic: &str, positions: &[(i32, i64)]) -> TopicPartitionList {
+    let mut list = TopicPartitionList::new();
+    for &(partition, offset) in positions {
+        if let Err(e) = list.add_partition_offset(topic, partition, Offset::Offset(offset)) {
+            warn!("Invalid offset {offset} for partition {partition}: {e}");
+        }
+    }
+    list
+}
+
+/// Consumer context which, when partitions of the input topic are revoked by a rebalance,
+/// commits their positions and removes them from the [OffsetTracker].
+///
+/// Otherwise, positions of a revoked partition could later be committed over those of the consumer it is assigned to.
+/// Revoked and assigned partitions are also recorded in the [OffsetTracker], to be applied to the frame cache
+/// before any further frames are dispatched or messages processed.
+pub(crate) struct OffsetTrackingContext {
+    input_topic: String,
+    offsets: Arc<Mutex<OffsetTracker>>,
+}
+
+impl OffsetTrackingContext {
+    /// Creates a new [OffsetTrackingContext].
+    /// # Parameters
+    /// - input_topic: the Kafka topic from which digitiser messages are consumed.
+    /// - offsets: the tracker shared with the component's consumer loop.
+    pub(crate) fn new(input_topic: &str, offsets: Arc<Mutex<OffsetTracker>>) -> Self {
+        Self {
+            input_topic: input_topic.to_owned(),
+            offsets,
+        }
+    }
+}
+
+impl ClientContext for OffsetTrackingContext {}
+
+impl OffsetTrackingContext {
+    /// Returns the partitions of the input topic in the list.
+    fn partitions(&self, list: &TopicPartitionList) -> Vec<i32> {
+        list.elements_for_topic(&self.input_topic)
+            .iter()
+            .map(|element| element.partition())
+            .collect()
+    }
+}
+
+impl ConsumerContext for OffsetTrackingContext {
+    fn pre_rebalance(&self, base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Revoke(list) = rebalance else {
+            return;
+        };
+        let partitions = self.partitions(list);
+        info!("Partitions revoked: {partitions:?}");
+
+        let positions = self
+            .offsets
+            .lock()
+            .expect("Offset tracker lock should not be poisoned")
+            .revoke(&partitions);
+        if positions.is_empty() {
+            return;
+        }
+        let list = positions_to_list(&self.input_topic, &positions);
+        if let Err(e) = base_consumer.commit(&list, CommitMode::Sync) {
+            warn!("Failed to commit offsets of revoked partitions: {e}");
+        }
+    }
+
+    fn post_rebalance(&self, _: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Assign(list) = rebalance else {
+            return;
+        };
+        let partitions = self.partitions(list);
+        info!("Partitions assigned: {partitions:?}");
+        self.offsets
+            .lock()
+            .expect("Offset tracker lock should not be poisoned")
+            .assign(&partitions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(partition: i32, offset: i64) -> MessageOffset {
+        MessageOffset { partition, offset }
+    }
+
+    #[test]
+    fn commit_follows_released_messages() {
+        let mut tracker = OffsetTracker::default();
+        for n in 10..13 {
+            tracker.consume(offset(0, n));
+        }
+        tracker.hold(offset(0, 10));
+        tracker.hold(offset(0, 12));
+        assert_eq!(tracker.positions_to_commit(), vec![(0, 10)]);
+
+        // Nothing has changed, so nothing is committed.
+        assert!(tracker.positions_to_commit().is_empty());
+
+        tracker.release(&[offset(0, 12)]);
+        assert!(tracker.positions_to_commit().is_empty());
+
+        tracker.release(&[offset(0, 10)]);
+        assert_eq!(tracker.positions_to_commit(), vec![(0, 13)]);
+    }
+
+    #[test]
+    fn partitions_are_tracked_independently() {
+        let mut tracker = OffsetTracker::default();
+        tracker.consume(offset(0, 5));
+        tracker.hold(offset(0, 5));
+        tracker.consume(offset(1, 7));
+
+        let mut positions = tracker.positions_to_commit();
+        positions.sort();
+        assert_eq!(positions, vec![(0, 5), (1, 8)]);
+    }
+
+    #[test]
+    fn revoked_partitions_are_forgotten() {
+        let mut tracker = OffsetTracker::default();
+        tracker.consume(offset(0, 5));
+        tracker.consume(offset(1, 7));
+        tracker.hold(offset(1, 7));
+        let mut positions = tracker.positions_to_commit();
+        positions.sort();
+        assert_eq!(positions, vec![(0, 6), (1, 7)]);
+        tracker.consume(offset(0, 6));
+
+        // Only partition 0 has advanced since it was committed.
+        assert_eq!(tracker.revoke(&[0, 1]), vec![(0, 7)]);
+
+        // Releasing the held message of a revoked partition does not resurrect it.
+        tracker.release(&[offset(1, 7)]);
+        assert!(tracker.positions_to_commit().is_empty());
+    }
+
+    #[test]
+    fn partition_changes_taken_once() {
+        let mut tracker = OffsetTracker::default();
+        tracker.assign(&[0, 1]);
+        tracker.revoke(&[1]);
+        tracker.assign(&[2]);
+        assert_eq!(
+            tracker.take_partition_changes(),
+            PartitionChanges {
+                revoked: vec![1],
+                assigned: vec![0, 2],
+            }
+        );
+        assert_eq!(
+            tracker.take_partition_changes(),
+            PartitionChanges::default()
+        );
+    }
+}
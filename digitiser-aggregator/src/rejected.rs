@@ -0,0 +1,118 @@
+//! Forwards digitiser messages rejected by the frame cache to Kafka topics, so they are not lost silently.
+use crate::{frame::RejectMessageError, offsets::MessageOffset};
+use metrics::counter;
+use rdkafka::{
+    message::{BorrowedMessage, Header, Message, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+};
+use supermusr_common::metrics::{
+    failures::{self, FailureKind},
+    names::FAILURES,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, warn};
+
+/// The Kafka header containing the reason a forwarded message was rejected.
+const REJECT_REASON_HEADER: &str = "reject-reason";
+
+/// Forwards rejected digitiser messages to the topic for their reason of rejection, if one is configured.
+pub(crate) struct RejectedMessageForwarder {
+    producer: FutureProducer,
+    /// Topic to which messages arriving after their frame has been dispatched are forwarded.
+    late_data_topic: Option<String>,
+    /// Topic to which messages with quarantined timestamps are forwarded.
+    quarantine_topic: Option<String>,
+    /// Send channel which takes the offset of each forwarded message once it has been delivered.
+    delivered_send: UnboundedSender<Vec<MessageOffset>>,
+}
+
+impl RejectedMessageForwarder {
+    /// Creates and returns a new [RejectedMessageForwarder] instance.
+    /// # Parameters
+    /// - producer: the Kafka producer object.
+    /// - late_data_topic: if [Some], messages arriving after their frame has been dispatched are forwarded to this topic.
+    /// - quarantine_topic: if [Some], messages with quarantined timestamps are forwarded to this topic.
+    /// - delivered_send: send channel which takes the offset of each forwarded message once it has been delivered.
+    pub(crate) fn new(
+        producer: FutureProducer,
+        late_data_topic: Option<String>,
+        quarantine_topic: Option<String>,
+        delivered_send: UnboundedSender<Vec<MessageOffset>>,
+    ) -> Self {
+        Self {
+            producer,
+            late_data_topic,
+            quarantine_topic,
+            delivered_send,
+        }
+    }
+
+    /// Returns the topic to which messages rejected for the given reason are forwarded, if any.
+    fn topic(&self, reason: RejectMessageError) -> Option<&str> {
+        match reason {
+            RejectMessageError::LateForDispatchedFrame => self.late_data_topic.as_deref(),
+            RejectMessageError::TimestampQuarantined => self.quarantine_topic.as_deref(),
+            RejectMessageError::IdAlreadyPresent
+            | RejectMessageError::TimestampTooEarly
+            | RejectMessageError::InconsistentMetadata => None,
+        }
+    }
+
+    /// Forwards a rejected message, with its headers and the reason for its rejection, if a topic is configured for the reason.
+    /// # Return
+    /// `true` if the message is being forwarded, in which case its offset is sent through the delivered
+    /// channel once delivery has completed, whether or not it was successful.
+    pub(crate) fn forward(&self, msg: &BorrowedMessage<'_>, reason: RejectMessageError) -> bool {
+        let Some(topic) = self.topic(reason) else {
+            return false;
+        };
+        let reason: &'static str = reason.into();
+        let headers = msg
+            .headers()
+            .map(|headers| headers.detach())
+            .unwrap_or_else(OwnedHeaders::new)
+            .insert(Header {
+                key: REJECT_REASON_HEADER,
+                value: Some(reason),
+            });
+        let mut record = FutureRecord::to(topic).headers(headers);
+        if let Some(payload) = msg.payload() {
+            record = record.payload(payload);
+        }
+        if let Some(key) = msg.key() {
+            record = record.key(key);
+        }
+
+        match self.producer.send_result(record) {
+            Ok(delivery) => {
+                let offset = MessageOffset::from(msg);
+                let delivered_send = self.delivered_send.clone();
+                tokio::spawn(async move {
+                    match delivery.await {
+                        Ok(Ok(delivery)) => debug!("Rejected message delivery: {delivery:?}"),
+                        Ok(Err((e, _))) => report_forward_failure(&e),
+                        Err(e) => report_forward_failure(&e),
+                    }
+                    if delivered_send.send(vec![offset]).is_err() {
+                        warn!("Delivered offsets channel closed");
+                    }
+                });
+                true
+            }
+            Err((e, _)) => {
+                report_forward_failure(&e);
+                false
+            }
+        }
+    }
+}
+
+/// Records the failure to forward a rejected message.
+fn report_forward_failure(e: &dyn std::error::Error) {
+    warn!("Failed to forward rejected message: {e}");
+    counter!(
+        FAILURES,
+        &[failures::get_label(FailureKind::KafkaPublishFailed)]
+    )
+    .increment(1);
+}
@@ -0,0 +1,283 @@
+//! Finds the last frame dispatched by a previous instance of the component, or by the instance
+//! which previously owned a partition of the input topic.
+//!
+//! As offsets are only committed once frames are delivered, digitiser messages belonging to frames
+//! which had already been delivered may be consumed again on restart, or when partitions are reassigned.
+//! Rejecting messages whose timestamp is no later than that of the last frame delivered prevents these
+//! frames from being dispatched twice.
+//!
+//! Correction frames carry the metadata of frames dispatched earlier, so may follow frames with later timestamps.
+//! The latest timestamp is therefore taken over the last few messages of each partition, ignoring corrections.
+use crate::{
+    data::{Accumulate, DigitiserData},
+    frame::FrameCache,
+};
+use chrono::{DateTime, Utc};
+use rdkafka::{
+    ClientConfig, Message, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer},
+    error::KafkaResult,
+    util::Timeout,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use supermusr_streaming_types::{
+    FrameMetadata,
+    aev2_frame_assembled_event_v2_generated::{
+        frame_assembled_event_list_message_buffer_has_identifier,
+        root_as_frame_assembled_event_list_message,
+    },
+    aev3_frame_assembled_event_v3_generated as aev3,
+    aev4_frame_assembled_event_v4_generated as aev4,
+};
+use tracing::{debug, info, warn};
+
+/// The maximum time to spend reading the output topic.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of messages at the end of each partition of the output topic which are read.
+const RESUME_TAIL_LENGTH: i64 = 64;
+
+/// Finds the last frame dispatched from partitions of the input topic as they are assigned to the component.
+pub(crate) struct ResumeLookup {
+    client_config: ClientConfig,
+    consumer_group: String,
+    output_topic: String,
+    /// If `true`, each partition of the output topic holds the frames of the input partition with the same number.
+    sharded: bool,
+}
+
+impl ResumeLookup {
+    /// Creates a new [ResumeLookup].
+    /// # Parameters
+    /// - client_config: the configuration of the Kafka client.
+    /// - consumer_group: the consumer group of the component, from which the group of the temporary consumer is derived.
+    /// - output_topic: the Kafka topic to which frames are dispatched.
+    /// - sharded: whether the input topic is partitioned by frame.
+    pub(crate) fn new(
+        client_config: &ClientConfig,
+        consumer_group: &str,
+        output_topic: &str,
+        sharded: bool,
+    ) -> Self {
+        Self {
+            client_config: client_config.clone(),
+            consumer_group: consumer_group.to_owned(),
+            output_topic: output_topic.to_owned(),
+            sharded,
+        }
+    }
+
+    /// Sets in the cache the timestamp of the last frame dispatched from each of the given partitions of the input topic.
+    ///
+    /// When sharded, only the output partitions with the same numbers are read, otherwise the latest frame of any output partition is used.
+    /// Failing to find the last frame is not fatal, and is reported by [find_latest_dispatched_timestamps].
+    /// # Parameters
+    /// - cache: the cache in which frames are stored whilst awaiting digitiser messages.
+    /// - partitions: the newly assigned partitions of the input topic.
+    pub(crate) fn resume<D: Debug>(&self, cache: &mut FrameCache<D>, partitions: &[i32])
+    where
+        DigitiserData<D>: Accumulate<D>,
+    {
+        let Ok(timestamps) = find_latest_dispatched_timestamps(
+            &self.client_config,
+            &self.consumer_group,
+            &self.output_topic,
+            self.sharded.then_some(partitions),
+        ) else {
+            return;
+        };
+        if self.sharded {
+            for (partition, timestamp) in timestamps {
+                info!(
+                    "Resuming partition {partition} after last dispatched frame, with timestamp {timestamp}"
+                );
+                cache.resume_after(Some(partition), timestamp);
+            }
+        } else if let Some(timestamp) = timestamps.into_values().max() {
+            info!("Resuming after last dispatched frame, with timestamp {timestamp}");
+            cache.resume_after(None, timestamp);
+        }
+    }
+}
+
+/// Reads the last messages of each partition of the output topic, and returns the latest frame timestamp found in each.
+/// # Parameters
+/// - client_config: the configuration of the Kafka client.
+/// - consumer_group: the consumer group of the component, from which the group of the temporary consumer is derived.
+/// - output_topic: the Kafka topic to which frames are dispatched.
+/// - partitions: if [Some], only these partitions of the output topic are read.
+/// # Return
+/// The latest timestamp of the frames, other than corrections, at the end of each partition which contains one.
+#[tracing::instrument(skip(client_config), err(level = "warn"))]
+fn find_latest_dispatched_timestamps(
+    client_config: &ClientConfig,
+    consumer_group: &str,
+    output_topic: &str,
+    partitions: Option<&[i32]>,
+) -> KafkaResult<BTreeMap<i32, DateTime<Utc>>> {
+    let consumer: BaseConsumer = client_config
+        .clone()
+        .set("group.id", format!("{consumer_group}-resume"))
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "false")
+        .create()?;
+
+    let timeout = Timeout::After(RESUME_TIMEOUT);
+    let metadata = consumer.fetch_metadata(Some(output_topic), timeout)?;
+    let mut tails = TopicPartitionList::new();
+    // The offset of the last message of each partition which is yet to be read.
+    let mut last_offsets = HashMap::new();
+    for partition in metadata
+        .topics()
+        .iter()
+        .flat_map(|topic| topic.partitions())
+        .filter(|partition| {
+            partitions.is_none_or(|partitions| partitions.contains(&partition.id()))
+        })
+    {
+        let (low, high) = consumer.fetch_watermarks(output_topic, partition.id(), timeout)?;
+        if high > low {
+            tails.add_partition_offset(
+                output_topic,
+                partition.id(),
+                Offset::Offset(low.max(high - RESUME_TAIL_LENGTH)),
+            )?;
+            last_offsets.insert(partition.id(), high - 1);
+        }
+    }
+    let mut latest = BTreeMap::new();
+    if tails.count() == 0 {
+        return Ok(latest);
+    }
+    consumer.assign(&tails)?;
+
+    let deadline = Instant::now() + RESUME_TIMEOUT;
+    while !last_offsets.is_empty() {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let Some(message) = consumer.poll(timeout) else {
+            warn!("Timed out reading the last frames of the output topic");
+            break;
+        };
+        let message = message?;
+        if let Some(payload) = message.payload() {
+            record_frame_timestamp(&mut latest, message.partition(), payload);
+        }
+        if last_offsets
+            .get(&message.partition())
+            .is_some_and(|&last| message.offset() >= last)
+        {
+            last_offsets.remove(&message.partition());
+            match latest.get(&message.partition()) {
+                Some(timestamp) => debug!(
+                    "Latest frame in partition {}: {timestamp}",
+                    message.partition()
+                ),
+                None => warn!(
+                    "Last messages in partition {} contain no frames",
+                    message.partition()
+                ),
+            }
+        }
+    }
+    Ok(latest)
+}
+
+/// Records the timestamp of a frame from the given partition, if it is later than that of any earlier frame.
+/// # Parameters
+/// - latest: the latest frame timestamp of each partition.
+/// - partition: the partition of the output topic from which the message was read.
+/// - payload: the payload of the message, which is ignored if it is not a frame, or is a correction frame.
+fn record_frame_timestamp(
+    latest: &mut BTreeMap<i32, DateTime<Utc>>,
+    partition: i32,
+    payload: &[u8],
+) {
+    if let Some(timestamp) = frame_timestamp(payload) {
+        latest
+            .entry(partition)
+            .and_modify(|latest| *latest = timestamp.max(*latest))
+            .or_insert(timestamp);
+    }
+}
+
+/// Returns the metadata timestamp of a frame assembled event list message, of any schema,
+/// or [None] if the message is not a frame, or is a correction frame.
+fn frame_timestamp(payload: &[u8]) -> Option<DateTime<Utc>> {
+    let metadata = if frame_assembled_event_list_message_buffer_has_identifier(payload) {
+        root_as_frame_assembled_event_list_message(payload)
+            .ok()?
+            .metadata()
+    } else if aev3::frame_assembled_event_list_message_buffer_has_identifier(payload) {
+        let message = aev3::root_as_frame_assembled_event_list_message(payload).ok()?;
+        if message.correction() {
+            return None;
+        }
+        message.metadata()
+    } else if aev4::frame_assembled_event_list_message_buffer_has_identifier(payload) {
+        let message = aev4::root_as_frame_assembled_event_list_message(payload).ok()?;
+        if message.correction() {
+            return None;
+        }
+        message.metadata()
+    } else {
+        return None;
+    };
+    FrameMetadata::try_from(metadata)
+        .ok()
+        .map(|metadata| metadata.timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{EventData, FrameEncodingOptions};
+    use crate::frame::AggregatedFrame;
+
+    fn encoded_frame(timestamp: DateTime<Utc>, correction: bool) -> Vec<u8> {
+        let mut frame = AggregatedFrame::new(
+            FrameMetadata {
+                timestamp,
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 0,
+            },
+            true,
+            vec![0],
+            EventData::dummy_data(0, 5, &[0]),
+        );
+        frame.correction = correction;
+        frame.encode(FrameEncodingOptions::default())
+    }
+
+    #[test]
+    fn frame_timestamp_found() {
+        let now = Utc::now();
+        assert_eq!(frame_timestamp(&encoded_frame(now, false)), Some(now));
+    }
+
+    #[test]
+    fn correction_frame_is_skipped() {
+        let now = Utc::now();
+        assert_eq!(frame_timestamp(&encoded_frame(now, true)), None);
+    }
+
+    #[test]
+    fn latest_frame_found_when_last_message_is_correction() {
+        let now = Utc::now();
+        let mut latest = BTreeMap::new();
+        for (offset_ms, correction) in [(0, false), (100, false), (200, true), (50, true)] {
+            let timestamp = now + Duration::from_millis(offset_ms);
+            record_frame_timestamp(&mut latest, 0, &encoded_frame(timestamp, correction));
+        }
+        record_frame_timestamp(&mut latest, 1, b"not a frame");
+
+        assert_eq!(
+            latest,
+            BTreeMap::from([(0, now + Duration::from_millis(100))])
+        );
+    }
+}
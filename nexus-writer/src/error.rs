@@ -3,7 +3,9 @@ use crate::{hdf5_handlers::NexusHDF5Error, run_engine::NexusDateTime};
 use glob::{GlobError, PatternError};
 use rdkafka::error::KafkaError;
 use std::{num::TryFromIntError, path::PathBuf};
-use supermusr_streaming_types::time_conversions::GpsTimeConversionError;
+use supermusr_streaming_types::{
+    flatbuffers::InvalidFlatbuffer, time_conversions::GpsTimeConversionError,
+};
 use thiserror::Error;
 
 pub(crate) type NexusWriterResult<T> = Result<T, NexusWriterError>;
@@ -59,6 +61,9 @@ pub(crate) enum NexusWriterError {
     /// A missing element of a flatbuffer message.
     #[error("{0} at {1}")]
     FlatBufferMissing(FlatBufferMissingError, ErrorCodeLocation),
+    /// A flatbuffer message which could not be parsed.
+    #[error("Invalid Flatbuffer: {0}")]
+    InvalidFlatBuffer(#[from] InvalidFlatbuffer),
     /// A general Kafka error.
     #[error("Kafka Error: {0}")]
     KafkaError(#[from] KafkaError),
@@ -4,7 +4,8 @@ use super::{
     error::{ConvertResult, NexusHDF5Result},
 };
 use crate::run_engine::NexusDateTime;
-use hdf5::{Attribute, Dataset, H5Type, types::VarLenUnicode};
+use hdf5::{Attribute, Dataset, H5Type, h5check, sync::sync, types::VarLenUnicode};
+use hdf5_sys::h5d::H5Dflush;
 use ndarray::s;
 
 impl HasAttributesExt for Dataset {
@@ -79,4 +80,11 @@ impl DatasetExt for Dataset {
         self.write_slice(value, s![cur_size..new_size])
             .err_dataset(self)
     }
+
+    #[tracing::instrument(skip_all, level = "trace", err(level = "warn"))]
+    fn flush_to_swmr_readers(&self) -> NexusHDF5Result<()> {
+        // SAFETY: the identifier is valid for the lifetime of `self`.
+        sync(|| unsafe { h5check(H5Dflush(self.id())) }).err_dataset(self)?;
+        Ok(())
+    }
 }
@@ -124,6 +124,12 @@ pub(crate) enum NexusHDF5Error {
         /// HDF5 path of the error.
         hdf5_path: Option<String>,
     },
+    /// An object would be created whilst the file is in SWMR writing mode, which HDF5 does not support.
+    #[error("Cannot Create Object During SWMR Writing at {0}", hdf5_path.as_deref().unwrap_or(NO_HDF5_PATH_SET))]
+    ObjectCreationDuringSwmr {
+        /// HDF5 path of the error.
+        hdf5_path: Option<String>,
+    },
 }
 
 impl NexusHDF5Error {
@@ -228,6 +234,9 @@ impl NexusHDF5Error {
                 error,
                 hdf5_path: Some(path),
             },
+            Self::ObjectCreationDuringSwmr { hdf5_path: None } => Self::ObjectCreationDuringSwmr {
+                hdf5_path: Some(path),
+            },
             other => other,
         }
     }
@@ -262,6 +271,14 @@ impl NexusHDF5Error {
         }
     }
 
+    ///  Creates a [NexusHDF5Error::ObjectCreationDuringSwmr] error, which is returned, before anything is written,
+    /// by handlers which would otherwise create an object whilst the file is in SWMR writing mode.
+    /// # Return
+    /// - A [NexusHDF5Error::ObjectCreationDuringSwmr] error with unset `hdf5_path`.
+    pub(crate) fn object_creation_during_swmr() -> Self {
+        Self::ObjectCreationDuringSwmr { hdf5_path: None }
+    }
+
     ///  Wraps a `TypeDescriptor` in a hdf5 type conversion error.
     /// # Parameters
     /// - error: the `TypeDescriptor` causing the error.
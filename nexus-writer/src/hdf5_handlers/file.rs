@@ -0,0 +1,69 @@
+//! This module implements the traits to extend the hdf5 [File] type to support single-writer/multiple-reader (SWMR) access.
+//!
+//! The hdf5 crate does not wrap the SWMR functions of the HDF5 library, so these call the library directly.
+use super::{
+    FileExt,
+    error::{ConvertResult, NexusHDF5Result},
+};
+use hdf5::{File, h5check, plist::FileAccess, sync::sync};
+use hdf5_sys::{
+    h5f::{H5F_ACC_RDWR, H5Fopen, H5Fstart_swmr_write},
+    h5p::H5Pset,
+};
+use std::{
+    ffi::{CStr, CString, c_void},
+    path::Path,
+};
+
+/// Name of the HDF5 file access property which, when set, clears the superblock flags marking the file as open for writing.
+/// This is the property `h5clear` uses.
+const CLEAR_STATUS_FLAGS: &CStr = c"clear_status_flags";
+
+/// Converts a path to a [CString] to pass to the HDF5 library.
+fn path_to_cstring(file_path: &Path) -> NexusHDF5Result<CString> {
+    let file_path = file_path
+        .to_str()
+        .ok_or_else(|| hdf5::Error::from(format!("Invalid UTF-8 in file name: {file_path:?}")))?;
+    Ok(CString::new(file_path).map_err(|e| hdf5::Error::from(e.to_string()))?)
+}
+
+impl FileExt for File {
+    fn create_with_swmr_support(file_path: &Path) -> NexusHDF5Result<Self> {
+        Ok(File::with_options()
+            .with_fapl(|fapl| fapl.libver_v110())
+            .create(file_path)?)
+    }
+
+    fn open_interrupted(file_path: &Path, swmr: bool) -> NexusHDF5Result<Self> {
+        let mut builder = FileAccess::build();
+        if swmr {
+            builder.libver_v110();
+        }
+        let fapl = builder.finish()?;
+        let file_path = path_to_cstring(file_path)?;
+        let mut clear_status_flags = true;
+        Ok(sync(|| {
+            // SAFETY: the property list and the file name are valid for the duration of the calls,
+            // and the property holds a `bool`, so is set from a pointer to one.
+            unsafe {
+                h5check(H5Pset(
+                    fapl.id(),
+                    CLEAR_STATUS_FLAGS.as_ptr(),
+                    (&raw mut clear_status_flags).cast::<c_void>(),
+                ))?;
+                hdf5::from_id::<File>(h5check(H5Fopen(
+                    file_path.as_ptr(),
+                    H5F_ACC_RDWR,
+                    fapl.id(),
+                ))?)
+            }
+        })?)
+    }
+
+    #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
+    fn start_swmr_write(&self) -> NexusHDF5Result<()> {
+        // SAFETY: the file identifier is valid for the lifetime of `self`.
+        sync(|| unsafe { h5check(H5Fstart_swmr_write(self.id())) }).err_group(self)?;
+        Ok(())
+    }
+}
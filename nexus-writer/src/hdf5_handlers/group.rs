@@ -5,6 +5,7 @@ use super::{
 };
 use hdf5::{
     Attribute, Dataset, DatasetBuilderEmpty, Group, H5Type, SimpleExtents,
+    filters::Filter,
     types::{FloatSize, IntSize, TypeDescriptor, VarLenArray, VarLenUnicode},
 };
 
@@ -142,10 +143,12 @@ impl GroupExt for Group {
         &self,
         name: &str,
         chunk_size: usize,
+        filters: &[Filter],
     ) -> NexusHDF5Result<Dataset> {
         self.new_dataset::<T>()
             .shape(SimpleExtents::resizable(vec![0]))
             .chunk(vec![chunk_size])
+            .set_filters(filters)
             .create(name)
             .err_group(self)
     }
@@ -156,11 +159,21 @@ impl GroupExt for Group {
         name: &str,
         type_descriptor: &TypeDescriptor,
         chunk_size: usize,
+        filters: &[Filter],
     ) -> NexusHDF5Result<Dataset> {
+        let filters: &[Filter] = if matches!(
+            type_descriptor,
+            TypeDescriptor::VarLenUnicode | TypeDescriptor::VarLenArray(_)
+        ) {
+            &[]
+        } else {
+            filters
+        };
         get_dataset_builder(type_descriptor, self)
             .err_group(self)?
             .shape(SimpleExtents::resizable(vec![0]))
             .chunk(chunk_size)
+            .set_filters(filters)
             .create(name)
             .err_group(self)
     }
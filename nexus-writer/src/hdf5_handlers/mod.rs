@@ -4,11 +4,13 @@ mod attribute;
 mod dataset;
 mod dataset_flatbuffers;
 mod error;
+mod file;
 mod group;
 
 use crate::run_engine::NexusDateTime;
 pub(crate) use error::{ConvertResult, NexusHDF5Error, NexusHDF5Result};
-use hdf5::{Attribute, Dataset, Group, H5Type, types::TypeDescriptor};
+use hdf5::{Attribute, Dataset, Group, H5Type, filters::Filter, types::TypeDescriptor};
+use std::path::Path;
 use supermusr_streaming_types::{
     ecs_f144_logdata_generated::f144_LogData, ecs_se00_data_generated::se00_SampleEnvironmentData,
 };
@@ -101,6 +103,8 @@ pub(crate) trait GroupExt {
     /// Creates a new one-dimensional dataset in this group with static type `T`.
     /// # Parameters
     ///  - name: name of the dataset to add.
+    ///  - chunk_size: number of values in each chunk.
+    ///  - filters: the filters with which each chunk is compressed, empty for none.
     /// # Error
     /// Any errors are tagged with the relevant hdf5 path by [err_group].
     ///
@@ -109,11 +113,16 @@ pub(crate) trait GroupExt {
         &self,
         name: &str,
         chunk_size: usize,
+        filters: &[Filter],
     ) -> NexusHDF5Result<Dataset>;
 
     /// Creates a new one-dimensional dataset in this group with type dynamically specified by `type_descriptor`.
     /// # Parameters
     ///  - name: name of the dataset to add.
+    ///  - type_descriptor: the type of the dataset.
+    ///  - chunk_size: number of values in each chunk.
+    ///  - filters: the filters with which each chunk is compressed, empty for none.
+    ///    These are not applied to variable length types, as they would only compress the references to the values.
     /// # Error
     /// Any errors are tagged with the relevant hdf5 path by [err_group].
     ///
@@ -123,6 +132,7 @@ pub(crate) trait GroupExt {
         name: &str,
         type_descriptor: &TypeDescriptor,
         chunk_size: usize,
+        filters: &[Filter],
     ) -> NexusHDF5Result<Dataset>;
 
     /// Creates a new scalar dataset in this group with static type `T`.
@@ -264,6 +274,17 @@ pub(crate) trait DatasetExt {
     /// [err_dataset]: ConvertResult::err_dataset
     fn append_slice<T: H5Type>(&self, value: &[T]) -> NexusHDF5Result<()>;
 
+    /// Flushes the dataset, so that its new size and values are visible to SWMR readers.
+    /// This is only needed whilst the file is in SWMR writing mode, otherwise the dataset is written when the file is flushed,
+    /// so callers keep track of whether SWMR writing has started, rather than querying the file on each call.
+    ///
+    /// Datasets which index one another should be flushed in order, so that readers never see an index beyond the data it refers to.
+    /// # Error
+    /// Any errors are tagged with the relevant hdf5 path by [err_dataset].
+    ///
+    /// [err_dataset]: ConvertResult::err_dataset
+    fn flush_to_swmr_readers(&self) -> NexusHDF5Result<()>;
+
     /// Return a [String] with the contents of the dataset.
     /// # Error
     /// Emits an error if either of the following requirements on the [Dataset] are violated:
@@ -372,6 +393,28 @@ pub(crate) trait AttributeExt {
     fn get_string(&self) -> NexusHDF5Result<String>;
 }
 
+/// Provides methods to be used on [File] objects, to support single-writer/multiple-reader (SWMR) access.
+///
+/// [File]: hdf5::File
+pub(crate) trait FileExt: Sized {
+    /// Creates a new file, with the file format required for SWMR writing.
+    /// # Parameters
+    /// - file_path: path at which to create the file.
+    fn create_with_swmr_support(file_path: &Path) -> NexusHDF5Result<Self>;
+
+    /// Opens the file of an interrupted run for writing, clearing any flags left by the interruption,
+    /// which mark the file as still open for writing.
+    /// # Parameters
+    /// - file_path: path of the file to open.
+    /// - swmr: if true, the file is opened with the file format required for SWMR writing.
+    fn open_interrupted(file_path: &Path, swmr: bool) -> NexusHDF5Result<Self>;
+
+    /// Switches the file to SWMR writing mode.
+    /// # Error
+    /// Emits an error if any attributes are open, or if the file was not created or opened with SWMR support.
+    fn start_swmr_write(&self) -> NexusHDF5Result<()>;
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env::temp_dir, ops::Deref, path::PathBuf};
@@ -445,6 +488,35 @@ mod tests {
         assert_eq!(maybe_dataset.unwrap().name().as_str(), "/my_dataset");
     }
 
+    #[test]
+    fn create_compressed_dataset() {
+        let file = OneTempFile::new("create_compressed_dataset");
+        let filters = [Filter::Shuffle, Filter::Deflate(4)];
+        let dataset = file
+            .create_resizable_empty_dataset::<u32>("my_dataset", 64, &filters)
+            .unwrap();
+        dataset.append_slice(&[1u32, 2, 3]).unwrap();
+
+        let dataset = file.get_dataset("my_dataset").unwrap();
+        assert_eq!(dataset.filters(), filters);
+        assert_eq!(dataset.read_raw::<u32>().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn variable_length_dataset_is_not_compressed() {
+        let file = OneTempFile::new("variable_length_dataset_is_not_compressed");
+        let dataset = file
+            .create_dynamic_resizable_empty_dataset(
+                "my_dataset",
+                &TypeDescriptor::VarLenUnicode,
+                64,
+                &[Filter::Deflate(4)],
+            )
+            .unwrap();
+
+        assert!(dataset.filters().is_empty());
+    }
+
     #[test]
     fn open_nonexistant_group() {
         let file = OneTempFile::new("open_nonexistant_group");
@@ -9,7 +9,7 @@
 //!
 //! ## Features
 //! * Detects and resumes interupted runs on startup.
-//! * Allows user-specified HDF5 settings to be used such as chunk sizes.
+//! * Allows user-specified HDF5 settings to be used such as chunk sizes and compression filters.
 //! * Appends internally generated warning messages to the run file, in cases of abnormal execution.
 //!
 mod error;
@@ -38,7 +38,10 @@ use rdkafka::{
     consumer::{CommitMode, Consumer},
     message::{BorrowedMessage, Message},
 };
-use run_engine::{NexusConfiguration, NexusEngine, NexusEngineDependencies, NexusSettings};
+use run_engine::{
+    CompressionOptions, CompressionSettings, NexusConfiguration, NexusEngine,
+    NexusEngineDependencies, NexusSettings,
+};
 use std::{fs::create_dir_all, marker::PhantomData, net::SocketAddr, path::PathBuf};
 use supermusr_common::{
     CommonKafkaOpts, init_tracer,
@@ -129,6 +132,13 @@ struct Cli {
     /// The HDF5 chunk size in bytes used when writing the frame list
     #[clap(long, default_value = "1024")]
     frame_list_chunk_size: usize,
+
+    #[clap(flatten)]
+    compression_options: CompressionOptions,
+
+    /// If set, NeXus files are written in HDF5 single-writer/multiple-reader (SWMR) mode, so they can be read whilst their runs are being written
+    #[clap(long)]
+    swmr: bool,
 }
 
 /// Empty struct which is used to inject dependencies into [NexusEngine].
@@ -181,8 +191,10 @@ async fn main() -> miette::Result<()> {
         args.local_path.as_path(),
         args.frame_list_chunk_size,
         args.event_list_chunk_size,
+        CompressionSettings::new(&args.compression_options).into_diagnostic()?,
         args.archive_path.as_deref(),
         args.archive_flush_interval_sec,
+        args.swmr,
     );
 
     let mut cache_poll_interval =
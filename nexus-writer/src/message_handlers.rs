@@ -2,7 +2,10 @@
 //! flatbuffer objects and pushes them to a [NexusEngine] instance.
 use crate::{
     EngineDependencies,
-    run_engine::{NexusEngine, run_messages::SampleEnvironmentLog},
+    run_engine::{
+        NexusEngine,
+        run_messages::{FramePulseShapes, SampleEnvironmentLog},
+    },
 };
 use metrics::counter;
 use supermusr_common::{
@@ -19,6 +22,8 @@ use supermusr_streaming_types::{
         frame_assembled_event_list_message_buffer_has_identifier,
         root_as_frame_assembled_event_list_message,
     },
+    aev3_frame_assembled_event_v3_generated as aev3,
+    aev4_frame_assembled_event_v4_generated as aev4,
     ecs_6s4t_run_stop_generated::{root_as_run_stop, run_stop_buffer_has_identifier},
     ecs_al00_alarm_generated::{alarm_buffer_has_identifier, root_as_alarm},
     ecs_f144_logdata_generated::{f_144_log_data_buffer_has_identifier, root_as_f_144_log_data},
@@ -26,7 +31,9 @@ use supermusr_streaming_types::{
     ecs_se00_data_generated::{
         root_as_se_00_sample_environment_data, se_00_sample_environment_data_buffer_has_identifier,
     },
-    flatbuffers::InvalidFlatbuffer,
+    flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer},
+    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
+    time_deltas::{TimeDeltaDecodeError, decode_time_deltas},
 };
 use tracing::{instrument, warn, warn_span};
 
@@ -40,8 +47,33 @@ pub(crate) fn process_payload_on_frame_event_list_topic(
     message_kafka_timestamp_ms: i64,
     payload: &[u8],
 ) {
-    if frame_assembled_event_list_message_buffer_has_identifier(payload) {
+    if frame_assembled_event_list_message_buffer_has_identifier(payload)
+        || aev3::frame_assembled_event_list_message_buffer_has_identifier(payload)
+    {
         push_frame_event_list(nexus_engine, message_kafka_timestamp_ms, payload);
+    } else if aev4::frame_assembled_event_list_message_buffer_has_identifier(payload) {
+        // `aev4` messages are converted to `aev3`, so they are written identically.
+        let payload =
+            match spanned_root_as(aev4::root_as_frame_assembled_event_list_message, payload) {
+                Ok(message) => aev4_to_aev3(message),
+                Err(e) => {
+                    report_parse_message_failure(e);
+                    return;
+                }
+            };
+        match payload {
+            Ok(payload) => {
+                push_frame_event_list(nexus_engine, message_kafka_timestamp_ms, &payload)
+            }
+            Err(e) => {
+                warn!("Failed to decode event times: {}", e);
+                counter!(
+                    FAILURES,
+                    &[failures::get_label(FailureKind::UnableToDecodeMessage)]
+                )
+                .increment(1);
+            }
+        }
     } else {
         warn!("Incorrect message identifier on frame event list topic");
     }
@@ -167,7 +199,11 @@ fn push_run_start(
     }
 }
 
-/// Decode, validate and process a flatbuffer `FrameEventList` message
+/// Decode, validate and process a flatbuffer `FrameEventList` message.
+///
+/// Both `aev2` and `aev3` messages are accepted, as are `aev4` messages once converted by [aev4_to_aev3].
+/// As `aev3` extends `aev2`, the common fields of either are read through the `aev2` schema,
+/// and the pulse shape fields of `aev3` messages are read separately.
 /// # Parameters
 /// - nexus_engine: the engine to push the message to.
 /// - kafka_message_timestamp_ms: the timestamp in milliseconds as reported in the Kafka message header. Only used for tracing.
@@ -183,6 +219,7 @@ fn push_run_start(
         metadata_protons_per_pulse,
         metadata_running,
         frame_is_complete,
+        frame_is_correction,
         has_run,
     )
 )]
@@ -192,6 +229,18 @@ fn push_frame_event_list(
     payload: &[u8],
 ) {
     increment_message_received_counter(MessageKind::Event);
+    let (pulse_shapes, correction) =
+        if aev3::frame_assembled_event_list_message_buffer_has_identifier(payload) {
+            match spanned_root_as(aev3::root_as_frame_assembled_event_list_message, payload) {
+                Ok(data) => (FramePulseShapes::from(data), data.correction()),
+                Err(e) => {
+                    report_parse_message_failure(e);
+                    return;
+                }
+            }
+        } else {
+            (FramePulseShapes::default(), false)
+        };
     match spanned_root_as(root_as_frame_assembled_event_list_message, payload) {
         Ok(data) => {
             data.metadata()
@@ -199,9 +248,10 @@ fn push_frame_event_list(
                 .map(|metadata: FrameMetadata| {
                     record_metadata_fields_to_span!(metadata, tracing::Span::current());
                     tracing::Span::current().record("frame_is_complete", data.complete());
+                    tracing::Span::current().record("frame_is_correction", correction);
                 })
                 .ok();
-            if let Err(e) = nexus_engine.push_frame_event_list(data) {
+            if let Err(e) = nexus_engine.push_frame_event_list(data, pulse_shapes, correction) {
                 warn!("Failed to save frame assembled event list to file: {}", e);
             }
         }
@@ -209,6 +259,99 @@ fn push_frame_event_list(
     }
 }
 
+/// Converts an `aev4` message to an `aev3` message with the same events.
+///
+/// The event times are decoded from their differences, and if the events are grouped by channel,
+/// the channel of each event is taken from its group. The group index is retained.
+/// # Parameters
+/// - message: the `aev4` message.
+fn aev4_to_aev3(
+    message: aev4::FrameAssembledEventListMessage<'_>,
+) -> Result<Vec<u8>, TimeDeltaDecodeError> {
+    let group_start = message
+        .group_start()
+        .map(|starts| starts.iter().collect::<Vec<_>>());
+    let time = decode_time_deltas(
+        message.time_delta().map(|v| v.bytes()).unwrap_or_default(),
+        group_start.as_deref().unwrap_or_default(),
+    )?;
+    let channel = match (message.channel(), message.group_channel(), &group_start) {
+        (Some(channel), _, _) => channel.iter().collect(),
+        (None, Some(group_channel), Some(group_start)) => {
+            let group_ends = group_start
+                .iter()
+                .skip(1)
+                .map(|&end| end as usize)
+                .chain(std::iter::once(time.len()));
+            group_channel
+                .iter()
+                .zip(group_start.iter().zip(group_ends))
+                .flat_map(|(channel, (&start, end))| {
+                    std::iter::repeat_n(channel, end.saturating_sub(start as usize))
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let mut fbb = FlatBufferBuilder::new();
+    let metadata = message.metadata();
+    let metadata = FrameMetadataV2::create(
+        &mut fbb,
+        &FrameMetadataV2Args {
+            timestamp: metadata.timestamp(),
+            period_number: metadata.period_number(),
+            protons_per_pulse: metadata.protons_per_pulse(),
+            running: metadata.running(),
+            frame_number: metadata.frame_number(),
+            veto_flags: metadata.veto_flags(),
+        },
+    );
+    let time = Some(fbb.create_vector(&time));
+    let voltage = message
+        .voltage()
+        .map(|v| fbb.create_vector_from_iter(v.iter()));
+    let channel = Some(fbb.create_vector(&channel));
+    let digitizers_present = message
+        .digitizers_present()
+        .map(|v| fbb.create_vector(v.bytes()));
+    let width = message
+        .width()
+        .map(|v| fbb.create_vector_from_iter(v.iter()));
+    let area = message
+        .area()
+        .map(|v| fbb.create_vector_from_iter(v.iter()));
+    let rise_time = message
+        .rise_time()
+        .map(|v| fbb.create_vector_from_iter(v.iter()));
+    let inconsistent_digitizers = message
+        .inconsistent_digitizers()
+        .map(|v| fbb.create_vector(v.bytes()));
+    let group_channel = message
+        .group_channel()
+        .map(|v| fbb.create_vector_from_iter(v.iter()));
+    let group_start = group_start.map(|starts| fbb.create_vector(&starts));
+    let args = aev3::FrameAssembledEventListMessageArgs {
+        metadata: Some(metadata),
+        time,
+        voltage,
+        channel,
+        complete: message.complete(),
+        digitizers_present,
+        width,
+        area,
+        rise_time,
+        correction: message.correction(),
+        inconsistent_digitizers,
+        metadata_disagreements: message.metadata_disagreements(),
+        group_channel,
+        group_start,
+    };
+    let converted = aev3::FrameAssembledEventListMessage::create(&mut fbb, &args);
+    aev3::finish_frame_assembled_event_list_message_buffer(&mut fbb, converted);
+    Ok(fbb.finished_data().to_vec())
+}
+
 /// Decode, validate and process a flatbuffer `RunLog` message
 /// # Parameters
 /// - nexus_engine: the engine to push the message to.
@@ -329,3 +472,69 @@ fn push_run_stop(
         Err(e) => report_parse_message_failure(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use supermusr_streaming_types::{
+        frame_metadata_v2_generated::GpsTime, time_deltas::encode_time_deltas,
+    };
+
+    #[test]
+    fn channel_grouped_aev4_converted_to_aev3() {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = GpsTime::new(24, 1, 0, 0, 0, 0, 0, 0);
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                timestamp: Some(&timestamp),
+                period_number: 2,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+        );
+        let group_start = [0, 2];
+        let args = aev4::FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            order: aev4::EventOrder::Channel,
+            time_delta: Some(fbb.create_vector(&encode_time_deltas(&[3, 9, 1], &group_start))),
+            voltage: Some(fbb.create_vector(&[10u16, 20, 30])),
+            group_channel: Some(fbb.create_vector(&[5u32, 7])),
+            group_start: Some(fbb.create_vector(&group_start)),
+            complete: true,
+            digitizers_present: Some(fbb.create_vector(&[0u8, 1])),
+            correction: true,
+            ..Default::default()
+        };
+        let message = aev4::FrameAssembledEventListMessage::create(&mut fbb, &args);
+        aev4::finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+
+        let message =
+            aev4::root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        let converted = aev4_to_aev3(message).unwrap();
+        let converted = aev3::root_as_frame_assembled_event_list_message(&converted).unwrap();
+
+        assert_eq!(converted.metadata().frame_number(), 1337);
+        assert_eq!(
+            converted.time().unwrap().iter().collect::<Vec<_>>(),
+            [3, 9, 1]
+        );
+        assert_eq!(
+            converted.channel().unwrap().iter().collect::<Vec<_>>(),
+            [5, 5, 7]
+        );
+        assert_eq!(
+            converted.voltage().unwrap().iter().collect::<Vec<_>>(),
+            [10, 20, 30]
+        );
+        assert!(converted.complete());
+        assert!(converted.correction());
+        assert!(converted.width().is_none());
+        assert_eq!(
+            converted.group_start().unwrap().iter().collect::<Vec<_>>(),
+            group_start
+        );
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
     hdf5_handlers::NexusHDF5Result,
     nexus::NexusMessageHandler,
     nexus_structure::Root,
-    run_engine::{ChunkSizeSettings, RunParameters, run_messages::HandlesAllNexusMessages},
+    run_engine::{DatasetSettings, RunParameters, run_messages::HandlesAllNexusMessages},
 };
 use std::path::Path;
 
@@ -15,14 +15,22 @@ pub(crate) struct NexusNoFile;
 impl HandlesAllNexusMessages for NexusNoFile {}
 
 impl NexusFileInterface for NexusNoFile {
-    fn build_new_file(_: &Path, _: &ChunkSizeSettings) -> NexusHDF5Result<Self> {
+    fn build_new_file(_: &Path, _: &DatasetSettings, _: bool) -> NexusHDF5Result<Self> {
         Ok(Self)
     }
 
-    fn open_from_file(_: &Path) -> NexusHDF5Result<Self> {
+    fn open_from_file(_: &Path, _: bool) -> NexusHDF5Result<Self> {
         Ok(Self)
     }
 
+    fn start_swmr_write(&mut self, _: &DatasetSettings) -> NexusHDF5Result<()> {
+        Ok(())
+    }
+
+    fn end_swmr_write(self) -> NexusHDF5Result<Self> {
+        Ok(self)
+    }
+
     /// This should never be called, panics if it is.
     fn extract_run_parameters(&self) -> NexusHDF5Result<RunParameters> {
         unreachable!()
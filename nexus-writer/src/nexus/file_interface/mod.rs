@@ -12,7 +12,7 @@ mod nexus_file;
 
 use crate::{
     hdf5_handlers::NexusHDF5Result,
-    run_engine::{ChunkSizeSettings, RunParameters, run_messages::HandlesAllNexusMessages},
+    run_engine::{DatasetSettings, RunParameters, run_messages::HandlesAllNexusMessages},
 };
 #[cfg(test)]
 pub(crate) use mock_nexus_file::NexusNoFile;
@@ -26,17 +26,38 @@ pub(crate) trait NexusFileInterface: Sized + HandlesAllNexusMessages {
     /// Creates a new NeXus file and initialise a new [Root] group structure with it.
     /// # Parameters
     /// - file_path: path at which to create the file.
-    /// - settings: hdf5 chunk sizes to use.
+    /// - settings: hdf5 chunk sizes and filters to use.
+    /// - swmr: if true, the file is created so that SWMR writing can later be started with [Self::start_swmr_write].
     ///
     /// [Root]: crate::nexus_structure::Root
-    fn build_new_file(file_path: &Path, settings: &ChunkSizeSettings) -> NexusHDF5Result<Self>;
+    fn build_new_file(
+        file_path: &Path,
+        settings: &DatasetSettings,
+        swmr: bool,
+    ) -> NexusHDF5Result<Self>;
 
     /// Opens the NeXus file and populate a new [Root] group structure with its data.
+    /// As the file is expected to be that of an interrupted run, any flags marking it as still open for writing are cleared.
     /// # Parameters
     /// - file_path: path of the file to open.
+    /// - swmr: if true, the file is opened so that SWMR writing can later be started with [Self::start_swmr_write].
     ///
     /// [Root]: crate::nexus_structure::Root
-    fn open_from_file(file_path: &Path) -> NexusHDF5Result<Self>;
+    fn open_from_file(file_path: &Path, swmr: bool) -> NexusHDF5Result<Self>;
+
+    /// Switches the file to single-writer/multiple-reader (SWMR) mode, after which it can be read whilst it is being written.
+    /// This should be called once the group structure has been built, as HDF5 does not support the creation of objects during SWMR writing.
+    /// Any objects the writer needs during the run are created first, and thereafter a message which would create an object
+    /// is refused with [NexusHDF5Error::ObjectCreationDuringSwmr].
+    /// # Parameters
+    /// - settings: hdf5 chunk sizes and filters with which to create the objects.
+    ///
+    /// [NexusHDF5Error::ObjectCreationDuringSwmr]: crate::hdf5_handlers::NexusHDF5Error::ObjectCreationDuringSwmr
+    fn start_swmr_write(&mut self, settings: &DatasetSettings) -> NexusHDF5Result<()>;
+
+    /// Takes ownership and ends SWMR writing, so that new objects can be created.
+    /// As SWMR readers cannot see objects created thereafter until they reopen the file, this should only be called once the run has ended.
+    fn end_swmr_write(self) -> NexusHDF5Result<Self>;
 
     /// Creates a [RunParameters] object from the NeXus file.
     fn extract_run_parameters(&self) -> NexusHDF5Result<RunParameters>;
@@ -6,13 +6,16 @@
 //! [NexusEngine]: crate::run_engine::NexusEngine
 use super::NexusFileInterface;
 use crate::{
-    hdf5_handlers::NexusHDF5Result,
+    hdf5_handlers::{FileExt, NexusHDF5Result},
     nexus::{NexusMessageHandler, NexusSchematic},
     nexus_structure::Root,
-    run_engine::{ChunkSizeSettings, RunParameters, run_messages::HandlesAllNexusMessages},
+    run_engine::{
+        DatasetSettings, RunParameters,
+        run_messages::{HandlesAllNexusMessages, StartSwmrWrite},
+    },
 };
 use hdf5::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Encapsulates the creation, loading, and message handling of a NeXus file.
 pub(crate) struct NexusFile {
@@ -25,14 +28,37 @@ pub(crate) struct NexusFile {
 impl HandlesAllNexusMessages for NexusFile {}
 
 impl NexusFileInterface for NexusFile {
-    fn build_new_file(file_path: &Path, settings: &ChunkSizeSettings) -> NexusHDF5Result<Self> {
-        let file = File::create(file_path)?;
+    fn build_new_file(
+        file_path: &Path,
+        settings: &DatasetSettings,
+        swmr: bool,
+    ) -> NexusHDF5Result<Self> {
+        let file = if swmr {
+            File::create_with_swmr_support(file_path)?
+        } else {
+            File::create(file_path)?
+        };
         let root = Root::build_group_structure(&file, settings)?;
         Ok(Self { file, root })
     }
 
-    fn open_from_file(file_path: &Path) -> NexusHDF5Result<Self> {
-        let file = File::open_rw(file_path)?;
+    fn open_from_file(file_path: &Path, swmr: bool) -> NexusHDF5Result<Self> {
+        let file = File::open_interrupted(file_path, swmr)?;
+        let root = Root::populate_group_structure(&file)?;
+        Ok(Self { file, root })
+    }
+
+    fn start_swmr_write(&mut self, settings: &DatasetSettings) -> NexusHDF5Result<()> {
+        self.root.handle_message(&StartSwmrWrite { settings })?;
+        self.file.start_swmr_write()
+    }
+
+    /// The HDF5 library provides no means of ending SWMR writing other than closing the file,
+    /// so the file is closed and reopened.
+    fn end_swmr_write(self) -> NexusHDF5Result<Self> {
+        let file_path = PathBuf::from(self.file.filename());
+        self.close()?;
+        let file = File::open_rw(&file_path)?;
         let root = Root::populate_group_structure(&file)?;
         Ok(Self { file, root })
     }
@@ -46,6 +72,8 @@ impl NexusFileInterface for NexusFile {
     }
 
     fn close(self) -> NexusHDF5Result<()> {
+        // The file is only closed once every handle to its objects, including those of the group structure, is dropped.
+        drop(self.root);
         Ok(self.file.close()?)
     }
 }
@@ -58,3 +86,385 @@ where
         self.root.handle_message(message)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hdf5_handlers::NexusHDF5Error,
+        run_engine::{
+            CompressionSettings, NexusConfiguration, NexusSettings,
+            run_messages::{
+                FramePulseShapes, InitialiseNewNexusStructure, PushAlarm, PushFrameEventList,
+                PushNexusStructure, PushRunLog,
+            },
+        },
+    };
+    use hdf5::{Dataset, h5check, sync::sync};
+    use hdf5_sys::{
+        h5d::H5Drefresh,
+        h5f::{H5F_ACC_RDONLY, H5F_ACC_SWMR_READ, H5Fopen},
+        h5p::H5P_DEFAULT,
+    };
+    use std::{
+        env::temp_dir,
+        ffi::CString,
+        io::{BufRead, BufReader, Write},
+        process::{Command, Stdio},
+    };
+    use supermusr_streaming_types::{
+        aev2_frame_assembled_event_v2_generated::{
+            FrameAssembledEventListMessage, FrameAssembledEventListMessageArgs,
+            finish_frame_assembled_event_list_message_buffer,
+            root_as_frame_assembled_event_list_message,
+        },
+        ecs_al00_alarm_generated::{
+            Alarm, AlarmArgs, Severity, finish_alarm_buffer, root_as_alarm,
+        },
+        ecs_f144_logdata_generated::{
+            Double, DoubleArgs, Value, f144_LogData, f144_LogDataArgs,
+            finish_f_144_log_data_buffer, root_as_f_144_log_data,
+        },
+        ecs_pl72_run_start_generated::{
+            RunStart, RunStartArgs, finish_run_start_buffer, root_as_run_start,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+    };
+
+    // Suitably long temp file name, unlikely to clash with anything else
+    const TEMP_FILE_PREFIX: &str = "temp_supermusr_pipeline_nexus_writer_swmr";
+
+    fn temp_path(test_name: &str) -> PathBuf {
+        let mut path = temp_dir();
+        path.push(format!("{TEMP_FILE_PREFIX}_{test_name}.nxs"));
+        path
+    }
+
+    fn create_settings() -> NexusSettings {
+        NexusSettings::new(
+            &temp_dir(),
+            1024,
+            1024,
+            CompressionSettings::default(),
+            None,
+            0,
+            true,
+        )
+    }
+
+    fn create_parameters(name: &str) -> RunParameters {
+        let mut fbb = FlatBufferBuilder::new();
+        let args = RunStartArgs {
+            start_time: 16,
+            run_name: Some(fbb.create_string(name)),
+            filename: Some(fbb.create_string(name)),
+            ..Default::default()
+        };
+        let message = RunStart::create(&mut fbb, &args);
+        finish_run_start_buffer(&mut fbb, message);
+        RunParameters::new(root_as_run_start(fbb.finished_data()).unwrap()).unwrap()
+    }
+
+    fn push_frame(file: &mut NexusFile, frame_number: u32, num_events: usize) {
+        let mut fbb = FlatBufferBuilder::new();
+        let timestamp = GpsTime::new(22, 205, 10, 55, 30, 0, 1, frame_number.try_into().unwrap());
+        let metadata = FrameMetadataV2::create(
+            &mut fbb,
+            &FrameMetadataV2Args {
+                timestamp: Some(&timestamp),
+                period_number: 0,
+                protons_per_pulse: 0,
+                running: true,
+                frame_number,
+                veto_flags: 0,
+            },
+        );
+        let args = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            time: Some(fbb.create_vector(&vec![1; num_events])),
+            voltage: Some(fbb.create_vector(&vec![2; num_events])),
+            channel: Some(fbb.create_vector(&vec![3; num_events])),
+            complete: true,
+            ..Default::default()
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &args);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        file.handle_message(&PushFrameEventList {
+            message: &message,
+            pulse_shapes: FramePulseShapes::default(),
+            correction: false,
+        })
+        .unwrap();
+    }
+
+    fn push_run_log(
+        file: &mut NexusFile,
+        parameters: &RunParameters,
+        name: &str,
+        value: f64,
+    ) -> NexusHDF5Result<()> {
+        let mut fbb = FlatBufferBuilder::new();
+        let value = Double::create(&mut fbb, &DoubleArgs { value }).as_union_value();
+        let args = f144_LogDataArgs {
+            source_name: Some(fbb.create_string(name)),
+            timestamp: 20,
+            value_type: Value::Double,
+            value: Some(value),
+        };
+        let message = f144_LogData::create(&mut fbb, &args);
+        finish_f_144_log_data_buffer(&mut fbb, message);
+        let message = root_as_f_144_log_data(fbb.finished_data()).unwrap();
+        file.handle_message(&PushRunLog {
+            message: &message,
+            origin: &parameters.collect_from,
+            settings: create_settings().get_dataset_settings(),
+        })
+    }
+
+    fn push_alarm(
+        file: &mut NexusFile,
+        parameters: &RunParameters,
+        name: &str,
+    ) -> NexusHDF5Result<()> {
+        let mut fbb = FlatBufferBuilder::new();
+        let args = AlarmArgs {
+            source_name: Some(fbb.create_string(name)),
+            timestamp: 20,
+            severity: Severity::MINOR,
+            message: Some(fbb.create_string("Alarm")),
+        };
+        let message = Alarm::create(&mut fbb, &args);
+        finish_alarm_buffer(&mut fbb, message);
+        let message = root_as_alarm(fbb.finished_data()).unwrap();
+        file.handle_message(&PushAlarm {
+            message: &message,
+            origin: &parameters.collect_from,
+            settings: create_settings().get_dataset_settings(),
+        })
+    }
+
+    /// Opens a file as a SWMR reader, which the hdf5 crate does not support.
+    fn open_swmr_reader(file_path: &Path) -> File {
+        let file_path = CString::new(file_path.to_str().unwrap()).unwrap();
+        sync(|| unsafe {
+            hdf5::from_id::<File>(
+                h5check(H5Fopen(
+                    file_path.as_ptr(),
+                    H5F_ACC_RDONLY | H5F_ACC_SWMR_READ,
+                    H5P_DEFAULT,
+                ))
+                .unwrap(),
+            )
+        })
+        .unwrap()
+    }
+
+    fn refreshed_size(dataset: &Dataset) -> usize {
+        sync(|| unsafe { h5check(H5Drefresh(dataset.id())) }).unwrap();
+        dataset.size()
+    }
+
+    #[test]
+    fn swmr_reader_sees_complete_frames() {
+        let path = temp_path("swmr_reader_sees_complete_frames");
+        let parameters = create_parameters("swmr_reader_sees_complete_frames");
+        let mut file =
+            NexusFile::build_new_file(&path, create_settings().get_dataset_settings(), true)
+                .unwrap();
+        file.handle_message(&InitialiseNewNexusStructure {
+            parameters: &parameters,
+            configuration: &NexusConfiguration::new(None),
+        })
+        .unwrap();
+        file.start_swmr_write(create_settings().get_dataset_settings())
+            .unwrap();
+        file.flush().unwrap();
+
+        let reader = open_swmr_reader(&path);
+        let detector = reader.group("raw_data_1/detector_1").unwrap();
+        let event_time_zero = detector.dataset("event_time_zero").unwrap();
+        let event_index = detector.dataset("event_index").unwrap();
+        let event_time_offset = detector.dataset("event_time_offset").unwrap();
+        assert_eq!(refreshed_size(&event_time_zero), 0);
+
+        let mut total_events = 0;
+        for (frame_number, num_events) in [(1, 3), (2, 0), (3, 5)] {
+            push_frame(&mut file, frame_number, num_events);
+            file.flush().unwrap();
+
+            let num_frames = refreshed_size(&event_time_zero);
+            assert_eq!(num_frames, frame_number as usize);
+            assert_eq!(refreshed_size(&event_index), num_frames);
+            assert_eq!(
+                event_index.read_raw::<u64>().unwrap().last(),
+                Some(&(total_events as u64))
+            );
+            total_events += num_events;
+            assert_eq!(refreshed_size(&event_time_offset), total_events);
+        }
+
+        reader.close().unwrap();
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resumed_file_restarts_swmr() {
+        let path = temp_path("resumed_file_restarts_swmr");
+        let parameters = create_parameters("resumed_file_restarts_swmr");
+        let mut file =
+            NexusFile::build_new_file(&path, create_settings().get_dataset_settings(), true)
+                .unwrap();
+        file.handle_message(&InitialiseNewNexusStructure {
+            parameters: &parameters,
+            configuration: &NexusConfiguration::new(None),
+        })
+        .unwrap();
+        file.start_swmr_write(create_settings().get_dataset_settings())
+            .unwrap();
+        push_frame(&mut file, 1, 3);
+        file.close().unwrap();
+
+        let mut file = NexusFile::open_from_file(&path, true).unwrap();
+        file.start_swmr_write(create_settings().get_dataset_settings())
+            .unwrap();
+        push_frame(&mut file, 2, 2);
+        file.close().unwrap();
+
+        let file = File::open(&path).unwrap();
+        let detector = file.group("raw_data_1/detector_1").unwrap();
+        assert_eq!(detector.dataset("event_time_zero").unwrap().size(), 2);
+        assert_eq!(detector.dataset("event_time_offset").unwrap().size(), 5);
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Environment variable naming the file which [swmr_reader_process] reads.
+    const SWMR_READER_FILE: &str = "SUPERMUSR_NEXUS_WRITER_SWMR_READER_FILE";
+    /// Prefix of the lines which [swmr_reader_process] prints, to distinguish them from those of the test harness.
+    const SWMR_READER_SIZES: &str = "swmr reader sizes: ";
+
+    /// Reads the file named by [SWMR_READER_FILE] as a SWMR reader, printing the sizes of the datasets
+    /// each time a line is read from stdin, until stdin is closed.
+    /// This is run as a child process by [separate_process_reads_during_swmr_write], and does nothing otherwise.
+    #[test]
+    fn swmr_reader_process() {
+        let Ok(path) = std::env::var(SWMR_READER_FILE) else {
+            return;
+        };
+        let reader = open_swmr_reader(Path::new(&path));
+        let datasets = [
+            "raw_data_1/detector_1/event_time_zero",
+            "raw_data_1/detector_1/event_width",
+            "raw_data_1/runlog/Temperature/value",
+            "raw_data_1/selog/Valve/alarm_time",
+            "raw_data_1/runlog/SuperMuSRDataPipeline_RunAborted/value",
+        ]
+        .map(|name| reader.dataset(name).unwrap());
+        for _ in std::io::stdin().lines() {
+            let sizes = datasets
+                .iter()
+                .map(|dataset| refreshed_size(dataset).to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("{SWMR_READER_SIZES}{sizes}");
+        }
+        reader.close().unwrap();
+    }
+
+    #[test]
+    fn separate_process_reads_during_swmr_write() {
+        let path = temp_path("separate_process_reads_during_swmr_write");
+        let parameters = create_parameters("separate_process_reads_during_swmr_write");
+        let settings = create_settings();
+        let mut file =
+            NexusFile::build_new_file(&path, settings.get_dataset_settings(), true).unwrap();
+        file.handle_message(&InitialiseNewNexusStructure {
+            parameters: &parameters,
+            configuration: &NexusConfiguration::new(None),
+        })
+        .unwrap();
+        file.handle_message(&PushNexusStructure {
+            json: r#"{"children": [{"type": "group", "name": "raw_data_1", "children": [
+                {"type": "group", "name": "temperature", "children": [
+                    {"module": "f144", "config": {"source": "Temperature", "dtype": "double"}}
+                ]},
+                {"type": "group", "name": "valve", "children": [
+                    {"module": "al00", "config": {"source": "Valve"}}
+                ]}
+            ]}]}"#,
+            settings: settings.get_dataset_settings(),
+        })
+        .unwrap();
+        file.start_swmr_write(settings.get_dataset_settings())
+            .unwrap();
+        file.flush().unwrap();
+
+        // The reader is a separate process, so does not share the writer's open file.
+        let mut reader = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "nexus::file_interface::nexus_file::tests::swmr_reader_process",
+                "--nocapture",
+            ])
+            .env(SWMR_READER_FILE, &path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut reader_stdin = reader.stdin.take().unwrap();
+        let mut reader_sizes = BufReader::new(reader.stdout.take().unwrap())
+            .lines()
+            .map(Result::unwrap)
+            .filter_map(|line| line.strip_prefix(SWMR_READER_SIZES).map(ToOwned::to_owned));
+        let mut read_sizes = move || {
+            writeln!(reader_stdin).unwrap();
+            reader_sizes.next().unwrap()
+        };
+
+        // The template's logs, and the pulse shape and internal log datasets, were created before SWMR writing started.
+        assert_eq!(read_sizes(), "0 0 0 0 0");
+
+        push_frame(&mut file, 1, 3);
+        push_run_log(&mut file, &parameters, "Temperature", 1.0).unwrap();
+        push_alarm(&mut file, &parameters, "Valve").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read_sizes(), "1 3 1 1 0");
+
+        // A log not in the template cannot be created during SWMR writing.
+        assert!(matches!(
+            push_run_log(&mut file, &parameters, "Pressure", 5.0),
+            Err(NexusHDF5Error::ObjectCreationDuringSwmr { .. })
+        ));
+
+        push_frame(&mut file, 2, 2);
+        push_run_log(&mut file, &parameters, "Temperature", 2.0).unwrap();
+        file.flush().unwrap();
+        assert_eq!(read_sizes(), "2 5 2 1 0");
+
+        // Closing the reader's stdin ends it.
+        drop(read_sizes);
+        assert!(reader.wait().unwrap().success());
+
+        // Once the run has ended, SWMR writing is ended so the log can be created.
+        let mut file = file.end_swmr_write().unwrap();
+        push_run_log(&mut file, &parameters, "Pressure", 5.0).unwrap();
+        file.close().unwrap();
+
+        let file = File::open(&path).unwrap();
+        let pressure = file.dataset("raw_data_1/runlog/Pressure/value").unwrap();
+        assert_eq!(pressure.read_raw::<f64>().unwrap(), vec![5.0]);
+        let event_width = file.dataset("raw_data_1/detector_1/event_width").unwrap();
+        assert!(
+            event_width
+                .read_raw::<f32>()
+                .unwrap()
+                .iter()
+                .all(|width| width.is_nan())
+        );
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
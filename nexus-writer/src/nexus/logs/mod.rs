@@ -107,7 +107,7 @@ fn adjust_nanoseconds_by_origin_to_sec(nanoseconds: i64, origin_time: &NexusDate
 /// - text: a string slice of the form: "prefix_1:prefix_2:...:prefix_n:LOG_NAME".
 /// # Return
 /// A string containing "LOG_NAME".
-fn remove_prefixes(text: &str) -> String {
+pub(crate) fn remove_prefixes(text: &str) -> String {
     text.rsplit(":CS:SB:").next().unwrap_or(text).to_owned()
 }
 
@@ -10,7 +10,7 @@ pub(crate) use classes::NexusClass;
 pub(crate) use file_interface::NexusNoFile;
 pub(crate) use file_interface::{NexusFile, NexusFileInterface};
 use hdf5::Group;
-pub(crate) use logs::{AlarmMessage, LogMessage};
+pub(crate) use logs::{AlarmMessage, LogMessage, remove_prefixes};
 pub(crate) use units::{DatasetUnitExt, NexusUnits};
 
 /// The format to use in the `start_time` and `end_time` NeXus file fields.
@@ -5,7 +5,7 @@ use hdf5::Dataset;
 /// For a NeXus field which contains a physical quantity, the dataset containing the value
 /// often has a "units" attribute. This object provides a single point of call
 /// to manage all of these units as well as their string representations.
-#[derive(strum::Display)]
+#[derive(Clone, Copy, strum::Display)]
 pub(crate) enum NexusUnits {
     /// Measures frequency (equal to Seconds^-1).
     #[strum(to_string = "Hz")]
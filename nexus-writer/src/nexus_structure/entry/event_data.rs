@@ -8,13 +8,17 @@ use crate::{
     nexus::{DatasetUnitExt, NexusClass, NexusUnits},
     nexus_structure::{NexusMessageHandler, NexusSchematic},
     run_engine::{
-        EventChunkSize, FrameChunkSize, NexusDateTime,
-        run_messages::{InitialiseNewNexusRun, PushFrameEventList},
+        DatasetSettings, EventChunkSize, NexusDateTime, detector_spectrum_map, mapped_channels,
+        run_messages::{InitialiseNewNexusRun, PushFrameEventList, PushRunStart, StartSwmrWrite},
     },
 };
-use hdf5::{Attribute, Dataset, Group};
+use hdf5::{Dataset, Group, filters::Filter};
+use std::collections::HashSet;
 use supermusr_common::{Channel, Time};
-use supermusr_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
+use supermusr_streaming_types::{
+    aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage, flatbuffers::Vector,
+};
+use tracing::warn;
 
 /// Field names for [EventData].
 mod labels {
@@ -29,14 +33,124 @@ mod labels {
     pub(super) const FRAME_COMPLETE: &str = "frame_complete";
     pub(super) const RUNNING: &str = "running";
     pub(super) const VETO_FLAGS: &str = "veto_flags";
+    pub(super) const EVENT_WIDTH: &str = "event_width";
+    pub(super) const EVENT_AREA: &str = "event_area";
+    pub(super) const EVENT_RISE_TIME: &str = "event_rise_time";
+    pub(super) const SPECTRUM_INDEX: &str = "spectrum_index";
+    pub(super) const DETECTOR_NUMBER: &str = "detector_number";
+}
+
+/// An optional dataset, indexed by muon event, of one of the pulse shape fields of `aev3` messages.
+///
+/// The dataset is only created once a frame containing the field arrives, or when SWMR writing starts,
+/// at which point it is back-filled with NaN for all previous events.
+/// Thereafter NaN is appended for each event of a frame lacking the field.
+struct PulseShapeDataset {
+    /// Name of the dataset.
+    label: &'static str,
+    /// Units of the dataset, if any.
+    units: Option<NexusUnits>,
+    /// The dataset, if it has been created.
+    dataset: Option<Dataset>,
+}
+
+impl PulseShapeDataset {
+    fn new(label: &'static str, units: Option<NexusUnits>) -> Self {
+        Self {
+            label,
+            units,
+            dataset: None,
+        }
+    }
+
+    fn open(group: &Group, label: &'static str, units: Option<NexusUnits>) -> Self {
+        Self {
+            label,
+            units,
+            dataset: group.dataset(label).ok(),
+        }
+    }
+
+    /// Returns true if the dataset must be created to append the given values.
+    fn requires_creation(&self, values: Option<Vector<'_, f32>>) -> bool {
+        self.dataset.is_none() && values.is_some()
+    }
+
+    /// Creates the dataset, back-filled with NaN for all previous events, unless it already exists.
+    /// # Parameters
+    /// - group: the group in which to create the dataset.
+    /// - chunk_size: the chunk size with which to create the dataset.
+    /// - filters: the filters with which to create the dataset.
+    /// - num_previous_events: the number of events previously written to the group.
+    fn create_if_absent(
+        &mut self,
+        group: &Group,
+        chunk_size: EventChunkSize,
+        filters: &[Filter],
+        num_previous_events: usize,
+    ) -> NexusHDF5Result<()> {
+        if self.dataset.is_none() {
+            let dataset =
+                group.create_resizable_empty_dataset::<f32>(self.label, chunk_size, filters)?;
+            let dataset = match self.units {
+                Some(units) => dataset.with_units(units)?,
+                None => dataset,
+            };
+            dataset.append_slice(&vec![f32::NAN; num_previous_events])?;
+            self.dataset = Some(dataset);
+        }
+        Ok(())
+    }
+
+    /// Appends the field's values for a single frame, creating the dataset if required.
+    /// # Parameters
+    /// - group: the group in which to create the dataset.
+    /// - chunk_size: the chunk size with which to create the dataset.
+    /// - filters: the filters with which to create the dataset.
+    /// - num_previous_events: the number of events previously written to the group.
+    /// - num_new_events: the number of events in the frame.
+    /// - values: the field's values, if present in the frame.
+    fn append(
+        &mut self,
+        group: &Group,
+        chunk_size: EventChunkSize,
+        filters: &[Filter],
+        num_previous_events: usize,
+        num_new_events: usize,
+        values: Option<Vector<'_, f32>>,
+    ) -> NexusHDF5Result<()> {
+        if self.requires_creation(values) {
+            self.create_if_absent(group, chunk_size, filters, num_previous_events)?;
+        }
+        if let Some(dataset) = &self.dataset {
+            // The values are padded or truncated to the number of events, so the dataset stays aligned with the other event fields.
+            let values = values
+                .into_iter()
+                .flatten()
+                .chain(std::iter::repeat(f32::NAN))
+                .take(num_new_events)
+                .collect::<Vec<_>>();
+            dataset.append_slice(&values)?;
+        }
+        Ok(())
+    }
 }
 
 pub(crate) struct EventData {
+    /// The group in which the optional pulse shape datasets are created.
+    group: Group,
+    /// Chunk size with which the optional pulse shape datasets are created.
+    event_chunk_size: EventChunkSize,
+    /// Filters with which the optional pulse shape datasets are created.
+    event_filters: Vec<Filter>,
     /// Number of messages pushed via [NexusMessageHandler<PushFrameEventList<'_>>]. This is equal to the number of frames.
     num_messages: usize,
     /// Number of muon events appended through the [NexusMessageHandler<PushFrameEventList<'_>>] messages.
     num_events: usize,
-    /// Optional value stored in [Self::event_time_zero_offset].
+    /// If true, the file is in SWMR writing mode, so datasets are flushed to SWMR readers once appended to,
+    /// and the optional pulse shape datasets, which are created before SWMR writing starts, cannot be created.
+    swmr: bool,
+    /// Optional value stored in the `offset` attribute of [Self::event_time_zero].
     offset: Option<NexusDateTime>,
     /// Vector of muon event intensities.
     pulse_height: Dataset,
@@ -46,8 +160,6 @@ pub(crate) struct EventData {
     event_time_offset: Dataset,
     /// Vector of frame times (in ns) specifying the start time of each frame (relative to this [Dataset]'s offset [Attribute]).
     event_time_zero: Dataset,
-    /// Timestamp indicating the time which values in [Self::event_time_zero] are relative to.
-    event_time_zero_offset: Attribute,
     /// Vector of indices in [Self::pulse_height], [Self::event_id], and [Self::event_time_offset] which denote the start of each frame.
     event_index: Dataset,
     /// Vector of numbers specifying to period each frame belongs.
@@ -60,50 +172,105 @@ pub(crate) struct EventData {
     running: Dataset,
     /// Vector specifying the veto_flags of each each frame.
     veto_flags: Dataset,
+    /// Optional vector of muon event pulse widths (in ns).
+    event_width: PulseShapeDataset,
+    /// Optional vector of muon event pulse areas.
+    event_area: PulseShapeDataset,
+    /// Optional vector of muon event pulse rise times (in ns).
+    event_rise_time: PulseShapeDataset,
+    /// Optional vector of the spectrum number of each detector, from the `RunStart` message's detector-spectrum map.
+    spectrum_index: Option<Dataset>,
+    /// Optional vector of detector ids, mapped to the spectra in [Self::spectrum_index].
+    detector_number: Option<Dataset>,
 }
 
 impl NexusSchematic for EventData {
     const CLASS: NexusClass = NexusClass::EventData;
-    type Settings = (EventChunkSize, FrameChunkSize);
+    type Settings = DatasetSettings;
 
     fn build_group_structure(
         group: &Group,
-        (event_chunk_size, frame_chunk_size): &Self::Settings,
+        DatasetSettings {
+            chunk_sizes,
+            compression,
+        }: &Self::Settings,
     ) -> NexusHDF5Result<Self> {
+        let (event_chunk_size, event_filters) = (chunk_sizes.event, compression.event.as_slice());
+        let (frame_chunk_size, frame_filters) = (chunk_sizes.frame, compression.frame.as_slice());
         let event_time_zero = group
-            .create_resizable_empty_dataset::<u64>(labels::EVENT_TIME_ZERO, *frame_chunk_size)?
+            .create_resizable_empty_dataset::<u64>(
+                labels::EVENT_TIME_ZERO,
+                frame_chunk_size,
+                frame_filters,
+            )?
             .with_units(NexusUnits::Nanoseconds)?;
-        let event_time_zero_offset =
-            event_time_zero.add_string_attribute(labels::EVENT_TIME_ZERO_OFFSET)?;
+        // The attribute is not kept open, as SWMR writing cannot start whilst any attributes are open.
+        event_time_zero.add_string_attribute(labels::EVENT_TIME_ZERO_OFFSET)?;
 
         Ok(Self {
+            group: group.clone(),
+            event_chunk_size,
+            event_filters: event_filters.to_vec(),
             num_messages: Default::default(),
             num_events: Default::default(),
+            swmr: false,
             offset: None,
-            pulse_height: group
-                .create_resizable_empty_dataset::<f64>(labels::PULSE_HEIGHT, *event_chunk_size)?,
-            event_id: group
-                .create_resizable_empty_dataset::<Channel>(labels::EVENT_ID, *event_chunk_size)?,
+            pulse_height: group.create_resizable_empty_dataset::<f64>(
+                labels::PULSE_HEIGHT,
+                event_chunk_size,
+                event_filters,
+            )?,
+            event_id: group.create_resizable_empty_dataset::<Channel>(
+                labels::EVENT_ID,
+                event_chunk_size,
+                event_filters,
+            )?,
             event_time_offset: group
                 .create_resizable_empty_dataset::<Time>(
                     labels::EVENT_TIME_OFFSET,
-                    *event_chunk_size,
+                    event_chunk_size,
+                    event_filters,
                 )?
                 .with_units(NexusUnits::Nanoseconds)?,
             event_time_zero,
-            event_time_zero_offset,
-            event_index: group
-                .create_resizable_empty_dataset::<u64>(labels::EVENT_INDEX, *frame_chunk_size)?,
-            period_number: group
-                .create_resizable_empty_dataset::<u64>(labels::PERIOD_NUMBER, *frame_chunk_size)?,
-            frame_number: group
-                .create_resizable_empty_dataset::<u64>(labels::FRAME_NUMBER, *frame_chunk_size)?,
-            frame_complete: group
-                .create_resizable_empty_dataset::<u64>(labels::FRAME_COMPLETE, *frame_chunk_size)?,
-            running: group
-                .create_resizable_empty_dataset::<bool>(labels::RUNNING, *frame_chunk_size)?,
-            veto_flags: group
-                .create_resizable_empty_dataset::<u16>(labels::VETO_FLAGS, *frame_chunk_size)?,
+            event_index: group.create_resizable_empty_dataset::<u64>(
+                labels::EVENT_INDEX,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            period_number: group.create_resizable_empty_dataset::<u64>(
+                labels::PERIOD_NUMBER,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            frame_number: group.create_resizable_empty_dataset::<u64>(
+                labels::FRAME_NUMBER,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            frame_complete: group.create_resizable_empty_dataset::<u64>(
+                labels::FRAME_COMPLETE,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            running: group.create_resizable_empty_dataset::<bool>(
+                labels::RUNNING,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            veto_flags: group.create_resizable_empty_dataset::<u16>(
+                labels::VETO_FLAGS,
+                frame_chunk_size,
+                frame_filters,
+            )?,
+            event_width: PulseShapeDataset::new(labels::EVENT_WIDTH, Some(NexusUnits::Nanoseconds)),
+            event_area: PulseShapeDataset::new(labels::EVENT_AREA, None),
+            event_rise_time: PulseShapeDataset::new(
+                labels::EVENT_RISE_TIME,
+                Some(NexusUnits::Nanoseconds),
+            ),
+            spectrum_index: None,
+            detector_number: None,
         })
     }
 
@@ -120,12 +287,24 @@ impl NexusSchematic for EventData {
         let running = group.get_dataset(labels::RUNNING)?;
         let veto_flags = group.get_dataset(labels::VETO_FLAGS)?;
 
-        let event_time_zero_offset =
-            event_time_zero.get_attribute(labels::EVENT_TIME_ZERO_OFFSET)?;
+        let offset = Some(
+            event_time_zero
+                .get_attribute(labels::EVENT_TIME_ZERO_OFFSET)?
+                .get_datetime()?,
+        );
 
-        let offset = Some(event_time_zero_offset.get_datetime()?);
+        // Should a pulse shape dataset be created in a resumed run, it uses the chunk size and filters of the existing event fields.
+        let event_chunk_size = event_time_offset
+            .chunk()
+            .and_then(|chunk| chunk.first().copied())
+            .unwrap_or(1);
+        let event_filters = event_time_offset.filters();
 
         Ok(Self {
+            group: group.clone(),
+            event_chunk_size,
+            event_filters,
+            swmr: false,
             offset,
             num_messages: event_time_zero.size(),
             num_events: event_time_offset.size(),
@@ -134,12 +313,24 @@ impl NexusSchematic for EventData {
             pulse_height,
             event_time_offset,
             event_time_zero,
-            event_time_zero_offset,
             period_number,
             frame_number,
             frame_complete,
             running,
             veto_flags,
+            event_width: PulseShapeDataset::open(
+                group,
+                labels::EVENT_WIDTH,
+                Some(NexusUnits::Nanoseconds),
+            ),
+            event_area: PulseShapeDataset::open(group, labels::EVENT_AREA, None),
+            event_rise_time: PulseShapeDataset::open(
+                group,
+                labels::EVENT_RISE_TIME,
+                Some(NexusUnits::Nanoseconds),
+            ),
+            spectrum_index: group.dataset(labels::SPECTRUM_INDEX).ok(),
+            detector_number: group.dataset(labels::DETECTOR_NUMBER).ok(),
         })
     }
 }
@@ -151,13 +342,93 @@ impl NexusMessageHandler<InitialiseNewNexusRun<'_>> for EventData {
         &InitialiseNewNexusRun { parameters }: &InitialiseNewNexusRun<'_>,
     ) -> NexusHDF5Result<()> {
         self.offset = Some(parameters.collect_from);
-        self.event_time_zero_offset
+        self.event_time_zero
+            .get_attribute(labels::EVENT_TIME_ZERO_OFFSET)?
             .set_string(&parameters.collect_from.to_rfc3339())?;
         Ok(())
     }
 }
 
+/// Writes the `RunStart` message's detector-spectrum map, if it has one.
+/// A map whose fields differ in length cannot be written, so is only warned of.
+impl NexusMessageHandler<PushRunStart<'_>> for EventData {
+    fn handle_message(
+        &mut self,
+        PushRunStart(run_start): &PushRunStart<'_>,
+    ) -> NexusHDF5Result<()> {
+        let Some((spectra, detectors)) = detector_spectrum_map(run_start) else {
+            if run_start.detector_spectrum_map().is_some() {
+                warn!("Detector-spectrum map has fields of differing lengths, so is not written");
+            }
+            return Ok(());
+        };
+        self.spectrum_index = Some(
+            self.group
+                .new_dataset_builder()
+                .with_data(spectra.as_slice())
+                .create(labels::SPECTRUM_INDEX)
+                .err_group(&self.group)?,
+        );
+        self.detector_number = Some(
+            self.group
+                .new_dataset_builder()
+                .with_data(detectors.as_slice())
+                .create(labels::DETECTOR_NUMBER)
+                .err_group(&self.group)?,
+        );
+        Ok(())
+    }
+}
+
+/// Creates any of the optional pulse shape datasets which do not exist, as they may be needed during SWMR writing,
+/// then flushes datasets to SWMR readers once appended to, from now on.
+impl NexusMessageHandler<StartSwmrWrite<'_>> for EventData {
+    fn handle_message(&mut self, _: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        for pulse_shape in [
+            &mut self.event_width,
+            &mut self.event_area,
+            &mut self.event_rise_time,
+        ] {
+            pulse_shape.create_if_absent(
+                &self.group,
+                self.event_chunk_size,
+                &self.event_filters,
+                self.num_events,
+            )?;
+        }
+        self.swmr = true;
+        Ok(())
+    }
+}
+
 impl EventData {
+    /// Flushes the dataset to SWMR readers, if the file is in SWMR writing mode.
+    fn flush_to_swmr_readers(&self, dataset: &Dataset) -> NexusHDF5Result<()> {
+        if self.swmr {
+            dataset.flush_to_swmr_readers()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// As the mapped channels are stored directly in the [RunParameters] object, this method extracts
+    /// them from the detector-spectrum map of an existing NeXus file.
+    /// # Return
+    /// The mapped channels, or [None] if the file has no map.
+    ///
+    /// [RunParameters]: crate::run_engine::RunParameters
+    pub(super) fn extract_mapped_channels(&self) -> NexusHDF5Result<Option<HashSet<Channel>>> {
+        self.spectrum_index
+            .as_ref()
+            .map(|spectrum_index| {
+                let spectra = spectrum_index
+                    .read_raw::<i32>()
+                    .err_dataset(spectrum_index)?;
+                Ok(mapped_channels(spectra.into_iter()))
+            })
+            .transpose()
+    }
+
     /// Extracts the timestamp from the message's metadata and convert it to nanoseconds since [Self::offset].
     /// # Parameters
     /// - message: the frame event list to extract the timestamp from.
@@ -190,10 +461,24 @@ impl EventData {
 impl NexusMessageHandler<PushFrameEventList<'_>> for EventData {
     fn handle_message(
         &mut self,
-        &PushFrameEventList { message }: &PushFrameEventList<'_>,
+        &PushFrameEventList {
+            message,
+            pulse_shapes,
+            ..
+        }: &PushFrameEventList<'_>,
     ) -> NexusHDF5Result<()> {
-        // Fields Indexed By Frame
-        self.event_index.append_value(self.num_events)?;
+        // New datasets cannot be created during SWMR writing, so this is checked before anything is appended.
+        if self.swmr
+            && [
+                self.event_width.requires_creation(pulse_shapes.width),
+                self.event_area.requires_creation(pulse_shapes.area),
+                self.event_rise_time
+                    .requires_creation(pulse_shapes.rise_time),
+            ]
+            .contains(&true)
+        {
+            return Err(NexusHDF5Error::object_creation_during_swmr());
+        }
 
         // Recalculate time_zero of the frame to be relative to the offset value
         // (set at the start of the run).
@@ -201,20 +486,6 @@ impl NexusMessageHandler<PushFrameEventList<'_>> for EventData {
             .get_time_zero(message)
             .err_dataset(&self.event_time_zero)?;
 
-        self.event_time_zero.append_value(time_zero)?;
-        self.period_number
-            .append_value(message.metadata().period_number())?;
-        self.frame_number
-            .append_value(message.metadata().frame_number())?;
-        self.frame_complete.append_value(message.complete())?;
-
-        self.running.append_value(message.metadata().running())?;
-
-        self.veto_flags
-            .append_value(message.metadata().veto_flags())?;
-
-        // Fields Indexed By Event
-
         let intensities = &message
             .voltage()
             .ok_or(FlatBufferMissingError::Intensities)?
@@ -236,9 +507,58 @@ impl NexusMessageHandler<PushFrameEventList<'_>> for EventData {
         let num_new_events = channels.len();
         let total_events = self.num_events + num_new_events;
 
+        // The fields are appended, and flushed to any SWMR readers, in an order such that the events of a frame
+        // are visible before [Self::event_index], and the frame's fields before [Self::event_time_zero],
+        // so a reader never sees a frame whose events or fields are missing.
+
+        // Fields Indexed By Event
         self.pulse_height.append_slice(intensities)?;
+        self.flush_to_swmr_readers(&self.pulse_height)?;
         self.event_time_offset.append_slice(times)?;
+        self.flush_to_swmr_readers(&self.event_time_offset)?;
         self.event_id.append_slice(channels)?;
+        self.flush_to_swmr_readers(&self.event_id)?;
+
+        for (pulse_shape, values) in [
+            (&mut self.event_width, pulse_shapes.width),
+            (&mut self.event_area, pulse_shapes.area),
+            (&mut self.event_rise_time, pulse_shapes.rise_time),
+        ] {
+            pulse_shape.append(
+                &self.group,
+                self.event_chunk_size,
+                &self.event_filters,
+                self.num_events,
+                num_new_events,
+                values,
+            )?;
+            if let (true, Some(dataset)) = (self.swmr, &pulse_shape.dataset) {
+                dataset.flush_to_swmr_readers()?;
+            }
+        }
+
+        // Fields Indexed By Frame
+        self.period_number
+            .append_value(message.metadata().period_number())?;
+        self.flush_to_swmr_readers(&self.period_number)?;
+        self.frame_number
+            .append_value(message.metadata().frame_number())?;
+        self.flush_to_swmr_readers(&self.frame_number)?;
+        self.frame_complete.append_value(message.complete())?;
+        self.flush_to_swmr_readers(&self.frame_complete)?;
+
+        self.running.append_value(message.metadata().running())?;
+        self.flush_to_swmr_readers(&self.running)?;
+
+        self.veto_flags
+            .append_value(message.metadata().veto_flags())?;
+        self.flush_to_swmr_readers(&self.veto_flags)?;
+
+        self.event_index.append_value(self.num_events)?;
+        self.flush_to_swmr_readers(&self.event_index)?;
+
+        self.event_time_zero.append_value(time_zero)?;
+        self.flush_to_swmr_readers(&self.event_time_zero)?;
 
         self.num_events = total_events;
         self.num_messages += 1;
@@ -1,5 +1,5 @@
 //! Defines [Instrument] group structure which contains details about the instrument used to probe the sample.
-//! Currently unknown where this data is obtained from.
+//! The instrument name is taken from the `RunStart` message, and the source details from its `metadata`.
 mod source;
 
 use crate::{
@@ -7,7 +7,7 @@ use crate::{
     hdf5_handlers::{DatasetExt, GroupExt, NexusHDF5Result},
     nexus::NexusClass,
     nexus_structure::{NexusGroup, NexusMessageHandler, NexusSchematic},
-    run_engine::run_messages::PushRunStart,
+    run_engine::run_messages::{PushRunMetadata, PushRunStart},
 };
 use hdf5::{Dataset, Group};
 use source::Source;
@@ -23,7 +23,7 @@ pub(crate) struct Instrument {
     /// Name of the instrument.
     name: Dataset,
     /// The particle beam source used to probe the sample.
-    _source: NexusGroup<Source>,
+    source: NexusGroup<Source>,
 }
 
 impl NexusSchematic for Instrument {
@@ -33,14 +33,14 @@ impl NexusSchematic for Instrument {
     fn build_group_structure(group: &Group, _: &Self::Settings) -> NexusHDF5Result<Self> {
         Ok(Self {
             name: group.create_string_dataset("name")?,
-            _source: Source::build_new_group(group, "source", &())?,
+            source: Source::build_new_group(group, "source", &())?,
         })
     }
 
     fn populate_group_structure(group: &Group) -> NexusHDF5Result<Self> {
         Ok(Self {
             name: group.get_dataset(labels::NAME)?,
-            _source: Source::open_group(group, labels::SOURCE)?,
+            source: Source::open_group(group, labels::SOURCE)?,
         })
     }
 }
@@ -58,3 +58,10 @@ impl NexusMessageHandler<PushRunStart<'_>> for Instrument {
         )
     }
 }
+
+/// Direct `PushRunMetadata` to the group(s) that need it
+impl NexusMessageHandler<PushRunMetadata<'_>> for Instrument {
+    fn handle_message(&mut self, message: &PushRunMetadata<'_>) -> NexusHDF5Result<()> {
+        self.source.handle_message(message)
+    }
+}
@@ -1,9 +1,10 @@
 //! Defines [Source] group structure which contains details about the particle source used to probe the sample.
-//! Currently unknown where this data is obtained from.
-use super::NexusSchematic;
+//! Its contents are set from the `metadata` of the `RunStart` message.
+use super::{NexusMessageHandler, NexusSchematic};
 use crate::{
-    hdf5_handlers::{GroupExt, HasAttributesExt, NexusHDF5Result},
+    hdf5_handlers::{DatasetExt, GroupExt, HasAttributesExt, NexusHDF5Result},
     nexus::{DatasetUnitExt, NexusClass, NexusUnits},
+    run_engine::run_messages::PushRunMetadata,
 };
 use hdf5::{Dataset, Group};
 
@@ -48,19 +49,19 @@ pub(crate) struct Source {
     _source_type: Dataset,
     _probe: Dataset,
     _source_frame_pattern: Dataset,
-    _source_frequency: Dataset,
-    _source_energy: Dataset,
-    _source_current: Dataset,
-    _source_pulse_width: Dataset,
-    _target_material: Dataset,
-    _target_thickness: Dataset,
-    _pion_momentum: Dataset,
-    _muon_energy: Dataset,
-    _muon_momentum: Dataset,
+    source_frequency: Dataset,
+    source_energy: Dataset,
+    source_current: Dataset,
+    source_pulse_width: Dataset,
+    target_material: Dataset,
+    target_thickness: Dataset,
+    pion_momentum: Dataset,
+    muon_energy: Dataset,
+    muon_momentum: Dataset,
     _muon_pulse_pattern: Dataset,
-    _muon_pulse_width: Dataset,
-    _muon_pulse_separation: Dataset,
-    _notes: Dataset,
+    muon_pulse_width: Dataset,
+    muon_pulse_separation: Dataset,
+    notes: Dataset,
 }
 
 impl NexusSchematic for Source {
@@ -69,14 +70,14 @@ impl NexusSchematic for Source {
 
     fn build_group_structure(group: &Group, _: &Self::Settings) -> NexusHDF5Result<Self> {
         let _source_frame_pattern = group
-            .create_resizable_empty_dataset::<u32>(labels::SOURCE_FRAME_PATTERN, 1)?
+            .create_resizable_empty_dataset::<u32>(labels::SOURCE_FRAME_PATTERN, 1, &[])?
             .with_attribute::<u32>(labels::SOURCE_FRAME_PATTERN_REP_LEN)?
             .with_attribute::<f32>(labels::SOURCE_FRAME_PATTERN_PERIOD)?
             .with_units(NexusUnits::Milliseconds)?
             .with_attribute::<f32>(labels::SOURCE_FRAME_PATTERN_PULSES_PER_FRAME)?;
 
         let _muon_pulse_pattern = group
-            .create_resizable_empty_dataset::<u32>(labels::MUON_PULSE_PATTERN, 1)?
+            .create_resizable_empty_dataset::<u32>(labels::MUON_PULSE_PATTERN, 1, &[])?
             .with_attribute::<u32>(labels::MUON_PULSE_PATTERN_REP_LEN)?
             .with_attribute::<f32>(labels::MUON_PULSE_PATTERN_PERIOD)?
             .with_units(NexusUnits::Milliseconds)?
@@ -86,40 +87,40 @@ impl NexusSchematic for Source {
             _name: group.create_constant_string_dataset(labels::NAME, NAME)?,
             _source_type: group.create_constant_string_dataset(labels::SOURCE_TYPE, SOURCE_TYPE)?,
             _probe: group.create_constant_string_dataset(labels::PROBE, PROBE)?, // TODO  Is this correct?,
-            _source_frequency: group
-                .create_string_dataset(labels::SOURCE_FREQUENCY)?
+            source_frequency: group
+                .create_scalar_dataset::<f32>(labels::SOURCE_FREQUENCY)?
                 .with_units(NexusUnits::Hertz)?,
             _source_frame_pattern,
-            _source_energy: group
-                .create_string_dataset(labels::SOURCE_ENERGY)?
+            source_energy: group
+                .create_scalar_dataset::<f32>(labels::SOURCE_ENERGY)?
                 .with_units(NexusUnits::MegaElectronVolts)?,
-            _source_current: group
-                .create_string_dataset(labels::SOURCE_CURRENT)?
+            source_current: group
+                .create_scalar_dataset::<f32>(labels::SOURCE_CURRENT)?
                 .with_units(NexusUnits::MicroAmps)?,
-            _source_pulse_width: group
-                .create_string_dataset(labels::SOURCE_PULSE_WIDTH)?
-                .with_string_attribute("Units")?,
-            _target_material: group.create_string_dataset(labels::TARGET_MATERIAL)?,
-            _target_thickness: group
-                .create_string_dataset(labels::TARGET_THICKNESS)?
+            source_pulse_width: group
+                .create_scalar_dataset::<f32>(labels::SOURCE_PULSE_WIDTH)?
+                .with_units(NexusUnits::Nanoseconds)?,
+            target_material: group.create_string_dataset(labels::TARGET_MATERIAL)?,
+            target_thickness: group
+                .create_scalar_dataset::<f32>(labels::TARGET_THICKNESS)?
                 .with_units(NexusUnits::Millimeters)?,
-            _pion_momentum: group
-                .create_string_dataset(labels::PION_MOMENTUM)?
+            pion_momentum: group
+                .create_scalar_dataset::<f32>(labels::PION_MOMENTUM)?
                 .with_units(NexusUnits::MegaElectronVoltsOverC)?,
-            _muon_energy: group
-                .create_string_dataset(labels::MUON_ENERGY)?
+            muon_energy: group
+                .create_scalar_dataset::<f32>(labels::MUON_ENERGY)?
                 .with_units(NexusUnits::ElectronVolts)?,
-            _muon_momentum: group
-                .create_string_dataset(labels::MUON_MOMENTUM)?
+            muon_momentum: group
+                .create_scalar_dataset::<f32>(labels::MUON_MOMENTUM)?
                 .with_units(NexusUnits::MegaElectronVoltsOverC)?,
             _muon_pulse_pattern,
-            _muon_pulse_width: group
-                .create_string_dataset(labels::MUON_PULSE_WIDTH)?
+            muon_pulse_width: group
+                .create_scalar_dataset::<f32>(labels::MUON_PULSE_WIDTH)?
                 .with_units(NexusUnits::Nanoseconds)?,
-            _muon_pulse_separation: group
+            muon_pulse_separation: group
                 .create_scalar_dataset::<f32>(labels::MUON_PULSE_SEPARATION)?
                 .with_units(NexusUnits::Nanoseconds)?,
-            _notes: group.create_string_dataset(labels::NOTES)?,
+            notes: group.create_string_dataset(labels::NOTES)?,
         })
     }
 
@@ -129,19 +130,54 @@ impl NexusSchematic for Source {
             _source_type: group.get_dataset(labels::SOURCE_TYPE)?,
             _probe: group.get_dataset(labels::PROBE)?,
             _source_frame_pattern: group.get_dataset(labels::SOURCE_FRAME_PATTERN)?,
-            _source_pulse_width: group.get_dataset(labels::SOURCE_PULSE_WIDTH)?,
-            _source_frequency: group.get_dataset(labels::SOURCE_TYPE)?,
-            _source_energy: group.get_dataset(labels::SOURCE_ENERGY)?,
-            _source_current: group.get_dataset(labels::SOURCE_ENERGY)?,
-            _target_material: group.get_dataset(labels::TARGET_MATERIAL)?,
-            _target_thickness: group.get_dataset(labels::TARGET_THICKNESS)?,
-            _pion_momentum: group.get_dataset(labels::PION_MOMENTUM)?,
-            _muon_energy: group.get_dataset(labels::MUON_ENERGY)?,
-            _muon_momentum: group.get_dataset(labels::MUON_MOMENTUM)?,
+            source_pulse_width: group.get_dataset(labels::SOURCE_PULSE_WIDTH)?,
+            source_frequency: group.get_dataset(labels::SOURCE_FREQUENCY)?,
+            source_energy: group.get_dataset(labels::SOURCE_ENERGY)?,
+            source_current: group.get_dataset(labels::SOURCE_CURRENT)?,
+            target_material: group.get_dataset(labels::TARGET_MATERIAL)?,
+            target_thickness: group.get_dataset(labels::TARGET_THICKNESS)?,
+            pion_momentum: group.get_dataset(labels::PION_MOMENTUM)?,
+            muon_energy: group.get_dataset(labels::MUON_ENERGY)?,
+            muon_momentum: group.get_dataset(labels::MUON_MOMENTUM)?,
             _muon_pulse_pattern: group.get_dataset(labels::MUON_PULSE_PATTERN)?,
-            _muon_pulse_width: group.get_dataset(labels::MUON_PULSE_WIDTH)?,
-            _muon_pulse_separation: group.get_dataset(labels::MUON_PULSE_SEPARATION)?,
-            _notes: group.get_dataset(labels::MUON_MOMENTUM)?,
+            muon_pulse_width: group.get_dataset(labels::MUON_PULSE_WIDTH)?,
+            muon_pulse_separation: group.get_dataset(labels::MUON_PULSE_SEPARATION)?,
+            notes: group.get_dataset(labels::NOTES)?,
         })
     }
 }
+
+/// Sets the source's fields from the `RunStart` message's metadata.
+impl NexusMessageHandler<PushRunMetadata<'_>> for Source {
+    fn handle_message(
+        &mut self,
+        PushRunMetadata(metadata): &PushRunMetadata<'_>,
+    ) -> NexusHDF5Result<()> {
+        let source = &metadata.source;
+        for (dataset, value) in [
+            (&self.source_frequency, source.source_frequency),
+            (&self.source_energy, source.source_energy),
+            (&self.source_current, source.source_current),
+            (&self.source_pulse_width, source.source_pulse_width),
+            (&self.target_thickness, source.target_thickness),
+            (&self.pion_momentum, source.pion_momentum),
+            (&self.muon_energy, source.muon_energy),
+            (&self.muon_momentum, source.muon_momentum),
+            (&self.muon_pulse_width, source.muon_pulse_width),
+            (&self.muon_pulse_separation, source.muon_pulse_separation),
+        ] {
+            if let Some(value) = value {
+                dataset.set_scalar(&value)?;
+            }
+        }
+        for (dataset, value) in [
+            (&self.target_material, &source.target_material),
+            (&self.notes, &source.notes),
+        ] {
+            if let Some(value) = value {
+                dataset.set_string(value)?;
+            }
+        }
+        Ok(())
+    }
+}
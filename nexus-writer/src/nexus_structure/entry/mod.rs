@@ -11,11 +11,11 @@ use crate::{
     hdf5_handlers::{DatasetExt, GroupExt, HasAttributesExt, NexusHDF5Result},
     nexus::{DATETIME_FORMAT, DatasetUnitExt, NexusClass, NexusUnits},
     run_engine::{
-        ChunkSizeSettings, RunParameters, RunStopParameters,
+        DatasetSettings, RunParameters, RunStopParameters,
         run_messages::{
-            InitialiseNewNexusRun, InitialiseNewNexusStructure, PushAlarm, PushFrameEventList,
-            PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart, PushSampleEnvironmentLog,
-            SetEndTime, UpdatePeriodList,
+            CreateStreamLog, InitialiseNewNexusRun, InitialiseNewNexusStructure, PushAlarm,
+            PushFrameEventList, PushInternallyGeneratedLogWarning, PushRunLog, PushRunMetadata,
+            PushRunStart, PushSampleEnvironmentLog, SetEndTime, StartSwmrWrite, UpdatePeriodList,
         },
     },
 };
@@ -50,6 +50,7 @@ mod labels {
     pub(super) const SELOGS: &str = "selog";
     pub(super) const SAMPLE: &str = "sample";
     pub(super) const DETECTOR_1: &str = "detector_1";
+    pub(super) const DETECTOR_1_CORRECTIONS: &str = "detector_1_corrections";
 }
 
 // Values of Nexus Constant
@@ -70,13 +71,13 @@ pub(crate) struct Entry {
     _definition: Dataset,
     /// The name of the creating program (i.e. the data pipeline).
     program_name: Dataset,
-    /// Run number. Currently don't know where this data comes from.
+    /// Run number, from the `RunStart` message's metadata, or else from the digits of the run name.
     run_number: Dataset,
     /// Indicates whether we using protons or anti-protons. Currently don't know where this data comes from.
     _proton_charge: Dataset,
     /// Duration of measurement i.e. (endstart)
     _duration: Dataset,
-    /// Experiment number, for ISIS, the RB number . Currently don't know where this data comes from.
+    /// Experiment number, for ISIS, the RB number, from the `RunStart` message's metadata.
     experiment_identifier: Dataset,
     /// Start time and date of measurement
     start_time: Dataset,
@@ -95,13 +96,16 @@ pub(crate) struct Entry {
     /// Log of period parameters, inevitably specific to each facility.
     periods: NexusGroup<Period>,
     /// Details of the sample under investigation.
-    _sample: NexusGroup<Sample>,
+    sample: NexusGroup<Sample>,
 
     /// Container for log(s) of sample environment parameters, that may be specific to each experiment.
     selogs: NexusGroup<SELog>,
 
     /// The data collected.
     detector_1: NexusGroup<EventData>,
+    /// Late data for frames already written to [Self::detector_1], with one entry per correction frame.
+    /// This is [None] if a resumed file was created without the group.
+    detector_1_corrections: Option<NexusGroup<EventData>>,
 }
 
 impl Entry {
@@ -124,15 +128,18 @@ impl Entry {
             run_name,
             periods: self.periods.extract(Period::extract_periods)?,
             file_name: filename,
+            mapped_channels: self
+                .detector_1
+                .extract(EventData::extract_mapped_channels)?,
         })
     }
 }
 
 impl NexusSchematic for Entry {
     const CLASS: NexusClass = NexusClass::Entry;
-    type Settings = ChunkSizeSettings;
+    type Settings = DatasetSettings;
 
-    fn build_group_structure(group: &Group, settings: &ChunkSizeSettings) -> NexusHDF5Result<Self> {
+    fn build_group_structure(group: &Group, settings: &DatasetSettings) -> NexusHDF5Result<Self> {
         Ok(Self {
             _idf_version: group
                 .create_constant_scalar_dataset::<u32>(labels::IDF_VERSION, &IDF_VERSION)?,
@@ -157,14 +164,15 @@ impl NexusSchematic for Entry {
             title: group.create_constant_string_dataset(labels::TITLE, "")?,
             instrument: Instrument::build_new_group(group, labels::INSTRUMENT, &())?,
             run_logs: RunLog::build_new_group(group, labels::RUNLOGS, &())?,
-            periods: Period::build_new_group(group, labels::PERIODS, &settings.period)?,
+            periods: Period::build_new_group(group, labels::PERIODS, &settings.chunk_sizes.period)?,
             selogs: SELog::build_new_group(group, labels::SELOGS, &())?,
-            _sample: Sample::build_new_group(group, labels::SAMPLE, settings)?,
-            detector_1: EventData::build_new_group(
+            sample: Sample::build_new_group(group, labels::SAMPLE, settings)?,
+            detector_1: EventData::build_new_group(group, labels::DETECTOR_1, settings)?,
+            detector_1_corrections: Some(EventData::build_new_group(
                 group,
-                "detector_1",
-                &(settings.event, settings.frame),
-            )?,
+                labels::DETECTOR_1_CORRECTIONS,
+                settings,
+            )?),
         })
     }
 
@@ -185,12 +193,14 @@ impl NexusSchematic for Entry {
 
         let instrument = Instrument::open_group(group, labels::INSTRUMENT)?;
         let periods = Period::open_group(group, labels::PERIODS)?;
-        let _sample = Sample::open_group(group, labels::SAMPLE)?;
+        let sample = Sample::open_group(group, labels::SAMPLE)?;
 
         let run_logs = RunLog::open_group(group, labels::RUNLOGS)?;
         let selogs = SELog::open_group(group, labels::SELOGS)?;
 
         let detector_1 = EventData::open_group(group, labels::DETECTOR_1)?;
+        let detector_1_corrections =
+            EventData::open_group(group, labels::DETECTOR_1_CORRECTIONS).ok();
 
         Ok(Self {
             _idf_version,
@@ -206,10 +216,11 @@ impl NexusSchematic for Entry {
             _proton_charge,
             experiment_identifier,
             run_logs,
-            _sample,
+            sample,
             instrument,
             periods,
             detector_1,
+            detector_1_corrections,
         })
     }
 }
@@ -272,6 +283,9 @@ impl NexusMessageHandler<InitialiseNewNexusStructure<'_>> for Entry {
 
         self.detector_1
             .handle_message(&InitialiseNewNexusRun { parameters })?;
+        if let Some(detector_1_corrections) = &mut self.detector_1_corrections {
+            detector_1_corrections.handle_message(&InitialiseNewNexusRun { parameters })?;
+        }
         Ok(())
     }
 }
@@ -279,6 +293,23 @@ impl NexusMessageHandler<InitialiseNewNexusStructure<'_>> for Entry {
 /// Direct `PushRunStart` to the group(s) that need it
 impl NexusMessageHandler<PushRunStart<'_>> for Entry {
     fn handle_message(&mut self, message: &PushRunStart<'_>) -> NexusHDF5Result<()> {
+        self.instrument.handle_message(message)?;
+        self.detector_1.handle_message(message)
+    }
+}
+
+/// Sets the fields given by the `RunStart` message's metadata, and directs it to the group(s) that need it
+impl NexusMessageHandler<PushRunMetadata<'_>> for Entry {
+    fn handle_message(&mut self, message: &PushRunMetadata<'_>) -> NexusHDF5Result<()> {
+        let PushRunMetadata(metadata) = message;
+        if let Some(run_number) = metadata.run_number {
+            self.run_number.set_scalar(&run_number)?;
+        }
+        if let Some(experiment_identifier) = &metadata.experiment_identifier {
+            self.experiment_identifier
+                .set_string(experiment_identifier)?;
+        }
+        self.sample.handle_message(message)?;
         self.instrument.handle_message(message)
     }
 }
@@ -286,7 +317,16 @@ impl NexusMessageHandler<PushRunStart<'_>> for Entry {
 /// Direct `PushFrameEventList` to the group(s) that need it
 impl NexusMessageHandler<PushFrameEventList<'_>> for Entry {
     fn handle_message(&mut self, message: &PushFrameEventList<'_>) -> NexusHDF5Result<()> {
-        self.detector_1.handle_message(message)
+        if !message.correction {
+            return self.detector_1.handle_message(message);
+        }
+        match &mut self.detector_1_corrections {
+            Some(detector_1_corrections) => detector_1_corrections.handle_message(message),
+            None => {
+                warn!("File has no corrections group, discarding correction frame");
+                Ok(())
+            }
+        }
     }
 }
 
@@ -328,6 +368,26 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Entry {
     }
 }
 
+// Direct `CreateStreamLog` to the group(s) that need it
+impl NexusMessageHandler<CreateStreamLog<'_>> for Entry {
+    fn handle_message(&mut self, message: &CreateStreamLog<'_>) -> NexusHDF5Result<()> {
+        self.run_logs.handle_message(message)?;
+        self.selogs.handle_message(message)
+    }
+}
+
+// Direct `StartSwmrWrite` to the group(s) which are appended to during the run
+impl NexusMessageHandler<StartSwmrWrite<'_>> for Entry {
+    fn handle_message(&mut self, message: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        self.detector_1.handle_message(message)?;
+        if let Some(detector_1_corrections) = &mut self.detector_1_corrections {
+            detector_1_corrections.handle_message(message)?;
+        }
+        self.run_logs.handle_message(message)?;
+        self.selogs.handle_message(message)
+    }
+}
+
 // Set `end_time` field
 impl NexusMessageHandler<SetEndTime<'_>> for Entry {
     fn handle_message(&mut self, message: &SetEndTime<'_>) -> NexusHDF5Result<()> {
@@ -65,8 +65,11 @@ impl NexusSchematic for Period {
     fn build_group_structure(group: &Group, settings: &Self::Settings) -> NexusHDF5Result<Self> {
         Ok(Self {
             number: group.create_scalar_dataset::<u32>(labels::NUMBER)?,
-            peroid_type: group
-                .create_resizable_empty_dataset::<u32>(labels::PERIOD_TYPE, *settings)?,
+            peroid_type: group.create_resizable_empty_dataset::<u32>(
+                labels::PERIOD_TYPE,
+                *settings,
+                &[],
+            )?,
             labels: group
                 .create_string_dataset(labels::LABELS)?
                 .with_constant_string_attribute(labels::LABELS_SEPARATOR, LABELS_SEPARATOR)?,
@@ -1,13 +1,17 @@
 //! Defines group structure which contains the run logs of the run.
 use crate::{
-    hdf5_handlers::NexusHDF5Result,
+    hdf5_handlers::{NexusHDF5Error, NexusHDF5Result},
     nexus::{LogMessage, NexusClass, NexusGroup, NexusMessageHandler},
     nexus_structure::{
         NexusSchematic,
         logs::{Log, LogSettings},
     },
-    run_engine::run_messages::{
-        InternallyGeneratedLog, PushInternallyGeneratedLogWarning, PushRunLog,
+    run_engine::{
+        DatasetSettings,
+        run_messages::{
+            CreateStreamLog, InternallyGeneratedLog, PushInternallyGeneratedLogWarning, PushRunLog,
+            StartSwmrWrite, StreamLog,
+        },
     },
 };
 use hdf5::{
@@ -22,6 +26,8 @@ use std::collections::{HashMap, hash_map::Entry};
 pub(crate) struct RunLog {
     group: Group,
     runlogs: HashMap<String, NexusGroup<Log>>,
+    /// If true, the file is in SWMR writing mode, so new logs cannot be created.
+    swmr: bool,
 }
 
 impl NexusSchematic for RunLog {
@@ -32,6 +38,7 @@ impl NexusSchematic for RunLog {
         Ok(Self {
             group: group.clone(),
             runlogs: HashMap::default(),
+            swmr: false,
         })
     }
 
@@ -44,10 +51,38 @@ impl NexusSchematic for RunLog {
                 .map(NexusGroup::<Log>::open_from_existing_group)
                 .map(|group| group.map(|nexus_group| (nexus_group.get_name(), nexus_group)))
                 .collect::<Result<_, _>>()?,
+            swmr: false,
         })
     }
 }
 
+impl RunLog {
+    /// Creates a new log, unless it already exists.
+    /// # Parameters
+    /// - name: the name of the log.
+    /// - type_descriptor: the hdf5 data type of the log's values.
+    /// - settings: the sizes of the chunks, and the filters, to use.
+    fn create_log_if_absent(
+        &mut self,
+        name: &str,
+        type_descriptor: &TypeDescriptor,
+        settings: &DatasetSettings,
+    ) -> NexusHDF5Result<()> {
+        if let Entry::Vacant(vacant_entry) = self.runlogs.entry(name.to_owned()) {
+            vacant_entry.insert(Log::build_new_group(
+                &self.group,
+                name,
+                &LogSettings {
+                    type_descriptor: type_descriptor.clone(),
+                    chunk_size: settings.chunk_sizes.runlog,
+                    filters: settings.compression.runlog.clone(),
+                },
+            )?);
+        }
+        Ok(())
+    }
+}
+
 /// If the run log already exists then add the data to the appropriate log,
 /// otherwise create a new log and append the data to it.
 impl NexusMessageHandler<PushRunLog<'_>> for RunLog {
@@ -55,13 +90,15 @@ impl NexusMessageHandler<PushRunLog<'_>> for RunLog {
     fn handle_message(&mut self, message: &PushRunLog<'_>) -> NexusHDF5Result<()> {
         match self.runlogs.entry(message.get_name()) {
             Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(_) if self.swmr => Err(NexusHDF5Error::object_creation_during_swmr()),
             Entry::Vacant(vacant_entry) => vacant_entry
                 .insert(Log::build_new_group(
                     &self.group,
                     &message.get_name(),
                     &LogSettings {
                         type_descriptor: message.get_type_descriptor()?,
-                        chunk_size: message.settings.runlog,
+                        chunk_size: message.settings.chunk_sizes.runlog,
+                        filters: message.settings.compression.runlog.clone(),
                     },
                 )?)
                 .handle_message(message),
@@ -73,9 +110,28 @@ const RUN_RESUMED_LOG_NAME: &str = "SuperMuSRDataPipeline_RunResumed";
 const RUN_RESUMED_TYPE_DESCRIPTOR: TypeDescriptor = TypeDescriptor::Float(FloatSize::U4);
 const INCOMPLETE_FRAME_LOG_NAME: &str = "SuperMuSRDataPipeline_DigitisersPresentInIncompleteFrame";
 const INCOMPLETE_FRAME_TYPE_DESCRIPTOR: TypeDescriptor = TypeDescriptor::VarLenUnicode;
+const UNMAPPED_CHANNELS_LOG_NAME: &str = "SuperMuSRDataPipeline_UnmappedChannelsInFrame";
+const UNMAPPED_CHANNELS_TYPE_DESCRIPTOR: TypeDescriptor = TypeDescriptor::VarLenUnicode;
+const INVALID_RUN_METADATA_LOG_NAME: &str = "SuperMuSRDataPipeline_InvalidRunMetadata";
+const INVALID_RUN_METADATA_TYPE_DESCRIPTOR: TypeDescriptor = TypeDescriptor::VarLenUnicode;
 const RUN_ABORTED_LOG_NAME: &str = "SuperMuSRDataPipeline_RunAborted";
 const RUN_ABORTED_TYPE_DESCRIPTOR: TypeDescriptor = TypeDescriptor::Float(FloatSize::U4);
 
+/// The names and data types of the logs generated by the writer itself.
+const INTERNALLY_GENERATED_LOGS: [(&str, TypeDescriptor); 5] = [
+    (RUN_RESUMED_LOG_NAME, RUN_RESUMED_TYPE_DESCRIPTOR),
+    (INCOMPLETE_FRAME_LOG_NAME, INCOMPLETE_FRAME_TYPE_DESCRIPTOR),
+    (
+        UNMAPPED_CHANNELS_LOG_NAME,
+        UNMAPPED_CHANNELS_TYPE_DESCRIPTOR,
+    ),
+    (
+        INVALID_RUN_METADATA_LOG_NAME,
+        INVALID_RUN_METADATA_TYPE_DESCRIPTOR,
+    ),
+    (RUN_ABORTED_LOG_NAME, RUN_ABORTED_TYPE_DESCRIPTOR),
+];
+
 /// If the run log for the internally generated message already exists,
 /// then add the data to the appropriate log, otherwise create a new log
 /// and append the data to it.
@@ -92,6 +148,14 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for RunLog {
             InternallyGeneratedLog::IncompleteFrame { .. } => {
                 (INCOMPLETE_FRAME_LOG_NAME, INCOMPLETE_FRAME_TYPE_DESCRIPTOR)
             }
+            InternallyGeneratedLog::UnmappedChannels { .. } => (
+                UNMAPPED_CHANNELS_LOG_NAME,
+                UNMAPPED_CHANNELS_TYPE_DESCRIPTOR,
+            ),
+            InternallyGeneratedLog::InvalidRunMetadata { .. } => (
+                INVALID_RUN_METADATA_LOG_NAME,
+                INVALID_RUN_METADATA_TYPE_DESCRIPTOR,
+            ),
             InternallyGeneratedLog::AbortRun { .. } => {
                 (RUN_ABORTED_LOG_NAME, RUN_ABORTED_TYPE_DESCRIPTOR)
             }
@@ -99,16 +163,48 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for RunLog {
 
         match self.runlogs.entry(log_name.to_string()) {
             Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(_) if self.swmr => Err(NexusHDF5Error::object_creation_during_swmr()),
             Entry::Vacant(vacant_entry) => vacant_entry
                 .insert(Log::build_new_group(
                     &self.group,
                     log_name,
                     &LogSettings {
                         type_descriptor,
-                        chunk_size: message.settings.runlog,
+                        chunk_size: message.settings.chunk_sizes.runlog,
+                        filters: message.settings.compression.runlog.clone(),
                     },
                 )?)
                 .handle_message(message),
         }
     }
 }
+
+/// Creates the run log of an `f144` stream of the template, if it does not exist.
+impl NexusMessageHandler<CreateStreamLog<'_>> for RunLog {
+    fn handle_message(
+        &mut self,
+        &CreateStreamLog { log, settings }: &CreateStreamLog<'_>,
+    ) -> NexusHDF5Result<()> {
+        match log {
+            StreamLog::RunLog {
+                name,
+                type_descriptor,
+            } => self.create_log_if_absent(name, type_descriptor, settings),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Creates any of the internally generated logs which do not exist, as they may be needed during SWMR writing,
+/// then propagates [StartSwmrWrite] to each log, and refuses to create new logs from now on.
+impl NexusMessageHandler<StartSwmrWrite<'_>> for RunLog {
+    fn handle_message(&mut self, message: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        for (name, type_descriptor) in &INTERNALLY_GENERATED_LOGS {
+            self.create_log_if_absent(name, type_descriptor, message.settings)?;
+        }
+        self.swmr = true;
+        self.runlogs
+            .values_mut()
+            .try_for_each(|runlog| runlog.handle_message(message))
+    }
+}
@@ -1,10 +1,10 @@
 //! Defines [Geometry] group structure which contains details about the physical attributes of the sample being probed.
-//! Currently unknown where this data is obtained from.
+//! Its contents are set from the `metadata` of the `RunStart` message, via the `sample` group.
 use crate::{
-    hdf5_handlers::{GroupExt, NexusHDF5Result},
+    hdf5_handlers::{DatasetExt, GroupExt, NexusHDF5Result},
     nexus::NexusClass,
-    nexus_structure::NexusSchematic,
-    run_engine::ChunkSizeSettings,
+    nexus_structure::{NexusMessageHandler, NexusSchematic},
+    run_engine::{DatasetSettings, run_messages::PushRunMetadata},
 };
 use hdf5::{Dataset, Group};
 
@@ -16,25 +16,42 @@ mod labels {
 
 /// Contains details about the physical attributes of the sample being probed.
 pub(crate) struct Geometry {
-    _description: Dataset,
-    _component_index: Dataset,
+    description: Dataset,
+    component_index: Dataset,
 }
 
 impl NexusSchematic for Geometry {
     const CLASS: NexusClass = NexusClass::Geometry;
-    type Settings = ChunkSizeSettings;
+    type Settings = DatasetSettings;
 
     fn build_group_structure(group: &Group, _settings: &Self::Settings) -> NexusHDF5Result<Self> {
         Ok(Self {
-            _description: group.create_string_dataset(labels::DESCRIPTION)?,
-            _component_index: group.create_scalar_dataset::<i32>(labels::COMPONENT_INDEX)?,
+            description: group.create_string_dataset(labels::DESCRIPTION)?,
+            component_index: group.create_scalar_dataset::<i32>(labels::COMPONENT_INDEX)?,
         })
     }
 
     fn populate_group_structure(group: &Group) -> NexusHDF5Result<Self> {
         Ok(Self {
-            _description: group.get_dataset(labels::DESCRIPTION)?,
-            _component_index: group.get_dataset(labels::COMPONENT_INDEX)?,
+            description: group.get_dataset(labels::DESCRIPTION)?,
+            component_index: group.get_dataset(labels::COMPONENT_INDEX)?,
         })
     }
 }
+
+/// Sets the geometry's fields from the `RunStart` message's metadata.
+impl NexusMessageHandler<PushRunMetadata<'_>> for Geometry {
+    fn handle_message(
+        &mut self,
+        PushRunMetadata(metadata): &PushRunMetadata<'_>,
+    ) -> NexusHDF5Result<()> {
+        let geometry = &metadata.sample.geometry;
+        if let Some(description) = &geometry.description {
+            self.description.set_string(description)?;
+        }
+        if let Some(component_index) = geometry.component_index {
+            self.component_index.set_scalar(&component_index)?;
+        }
+        Ok(())
+    }
+}
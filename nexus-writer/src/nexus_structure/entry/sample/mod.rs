@@ -1,12 +1,12 @@
 //! Defines [Sample] group structure which contains details about the sample which is being probed.
-//! Currently unknown where this data is obtained from.
+//! Its contents are set from the `metadata` of the `RunStart` message, and may be supplemented by its `nexus_structure` template.
 mod geometry;
 
 use crate::{
-    hdf5_handlers::{GroupExt, NexusHDF5Result},
+    hdf5_handlers::{DatasetExt, GroupExt, NexusHDF5Result},
     nexus::{DatasetUnitExt, NexusClass, NexusGroup, NexusUnits},
-    nexus_structure::NexusSchematic,
-    run_engine::ChunkSizeSettings,
+    nexus_structure::{NexusMessageHandler, NexusSchematic},
+    run_engine::{DatasetSettings, run_messages::PushRunMetadata},
 };
 use geometry::Geometry;
 use hdf5::{Dataset, Group};
@@ -26,40 +26,52 @@ mod labels {
 
 /// Contains details about the sample being probed.
 pub(crate) struct Sample {
-    _name: Dataset,
-    _description: Dataset,
-    _sample_type: Dataset,
-    _geometry: NexusGroup<Geometry>,
-    _thickness: Dataset,
-    _mass: Dataset,
-    _density: Dataset,
-    _temperature: Dataset,
-    _magnetic_field: Dataset,
+    name: Dataset,
+    description: Dataset,
+    sample_type: Dataset,
+    geometry: NexusGroup<Geometry>,
+    thickness: Dataset,
+    mass: Dataset,
+    density: Dataset,
+    temperature: Dataset,
+    magnetic_field: Dataset,
 }
 
 impl NexusSchematic for Sample {
     const CLASS: NexusClass = NexusClass::Sample;
-    type Settings = ChunkSizeSettings;
+    type Settings = DatasetSettings;
 
     fn build_group_structure(group: &Group, settings: &Self::Settings) -> NexusHDF5Result<Self> {
         Ok(Self {
-            _name: group.create_string_dataset(labels::NAME)?,
-            _description: group.create_string_dataset(labels::DESCRIPTION)?,
-            _sample_type: group.create_string_dataset(labels::SAMPLE_TYPE)?,
-            _geometry: Geometry::build_new_group(group, labels::GEOMETRY, settings)?,
-            _thickness: group
-                .create_resizable_empty_dataset::<f32>(labels::THICKNESS, settings.period)?
+            name: group.create_string_dataset(labels::NAME)?,
+            description: group.create_string_dataset(labels::DESCRIPTION)?,
+            sample_type: group.create_string_dataset(labels::SAMPLE_TYPE)?,
+            geometry: Geometry::build_new_group(group, labels::GEOMETRY, settings)?,
+            thickness: group
+                .create_resizable_empty_dataset::<f32>(
+                    labels::THICKNESS,
+                    settings.chunk_sizes.period,
+                    &[],
+                )?
                 .with_units(NexusUnits::Millimeters)?,
-            _mass: group
-                .create_resizable_empty_dataset::<f32>(labels::MASS, settings.period)?
+            mass: group
+                .create_resizable_empty_dataset::<f32>(
+                    labels::MASS,
+                    settings.chunk_sizes.period,
+                    &[],
+                )?
                 .with_units(NexusUnits::Milligrams)?,
-            _density: group
-                .create_resizable_empty_dataset::<f32>(labels::DENSITY, settings.period)?
+            density: group
+                .create_resizable_empty_dataset::<f32>(
+                    labels::DENSITY,
+                    settings.chunk_sizes.period,
+                    &[],
+                )?
                 .with_units(NexusUnits::MilligramsPerCm3)?,
-            _temperature: group
+            temperature: group
                 .create_scalar_dataset::<f32>(labels::TEMPERATURE)?
                 .with_units(NexusUnits::Kelvin)?,
-            _magnetic_field: group
+            magnetic_field: group
                 .create_scalar_dataset::<f32>(labels::MAGNETIC_FIELD)?
                 .with_units(NexusUnits::Gauss)?,
         })
@@ -67,15 +79,50 @@ impl NexusSchematic for Sample {
 
     fn populate_group_structure(group: &Group) -> NexusHDF5Result<Self> {
         Ok(Self {
-            _name: group.get_dataset(labels::NAME)?,
-            _description: group.get_dataset(labels::DESCRIPTION)?,
-            _sample_type: group.get_dataset(labels::SAMPLE_TYPE)?,
-            _geometry: Geometry::open_group(group, labels::GEOMETRY)?,
-            _thickness: group.get_dataset(labels::THICKNESS)?,
-            _mass: group.get_dataset(labels::MASS)?,
-            _density: group.get_dataset(labels::DENSITY)?,
-            _temperature: group.get_dataset(labels::TEMPERATURE)?,
-            _magnetic_field: group.get_dataset(labels::MAGNETIC_FIELD)?,
+            name: group.get_dataset(labels::NAME)?,
+            description: group.get_dataset(labels::DESCRIPTION)?,
+            sample_type: group.get_dataset(labels::SAMPLE_TYPE)?,
+            geometry: Geometry::open_group(group, labels::GEOMETRY)?,
+            thickness: group.get_dataset(labels::THICKNESS)?,
+            mass: group.get_dataset(labels::MASS)?,
+            density: group.get_dataset(labels::DENSITY)?,
+            temperature: group.get_dataset(labels::TEMPERATURE)?,
+            magnetic_field: group.get_dataset(labels::MAGNETIC_FIELD)?,
         })
     }
 }
+
+/// Sets the sample's fields from the `RunStart` message's metadata.
+impl NexusMessageHandler<PushRunMetadata<'_>> for Sample {
+    fn handle_message(&mut self, message: &PushRunMetadata<'_>) -> NexusHDF5Result<()> {
+        let PushRunMetadata(metadata) = message;
+        let sample = &metadata.sample;
+        for (dataset, value) in [
+            (&self.name, &sample.name),
+            (&self.description, &sample.description),
+            (&self.sample_type, &sample.sample_type),
+        ] {
+            if let Some(value) = value {
+                dataset.set_string(value)?;
+            }
+        }
+        for (dataset, values) in [
+            (&self.thickness, &sample.thickness),
+            (&self.mass, &sample.mass),
+            (&self.density, &sample.density),
+        ] {
+            if let Some(values) = values {
+                dataset.set_slice(values)?;
+            }
+        }
+        for (dataset, value) in [
+            (&self.temperature, sample.temperature),
+            (&self.magnetic_field, sample.magnetic_field),
+        ] {
+            if let Some(value) = value {
+                dataset.set_scalar(&value)?;
+            }
+        }
+        self.geometry.handle_message(message)
+    }
+}
@@ -1,9 +1,11 @@
 //! Defines group structure which contains the sample environment logs of the run.
 use crate::{
-    hdf5_handlers::NexusHDF5Result,
+    hdf5_handlers::{NexusHDF5Error, NexusHDF5Result},
     nexus::{AlarmMessage, LogMessage, NexusClass, NexusGroup, NexusMessageHandler},
     nexus_structure::{NexusSchematic, logs::ValueLog},
-    run_engine::run_messages::{PushAlarm, PushSampleEnvironmentLog},
+    run_engine::run_messages::{
+        CreateStreamLog, PushAlarm, PushSampleEnvironmentLog, StartSwmrWrite, StreamLog,
+    },
 };
 use hdf5::Group;
 use std::collections::{HashMap, hash_map::Entry};
@@ -14,6 +16,8 @@ use std::collections::{HashMap, hash_map::Entry};
 pub(crate) struct SELog {
     group: Group,
     selogs: HashMap<String, NexusGroup<ValueLog>>,
+    /// If true, the file is in SWMR writing mode, so new logs cannot be created.
+    swmr: bool,
 }
 
 impl NexusSchematic for SELog {
@@ -24,6 +28,7 @@ impl NexusSchematic for SELog {
         Ok(Self {
             group: group.clone(),
             selogs: HashMap::default(),
+            swmr: false,
         })
     }
 
@@ -36,6 +41,7 @@ impl NexusSchematic for SELog {
                 .map(NexusGroup::<ValueLog>::open_from_existing_group)
                 .map(|group| group.map(|nexus_group| (nexus_group.get_name(), nexus_group)))
                 .collect::<Result<_, _>>()?,
+            swmr: false,
         })
     }
 }
@@ -47,6 +53,7 @@ impl NexusMessageHandler<PushSampleEnvironmentLog<'_>> for SELog {
     fn handle_message(&mut self, message: &PushSampleEnvironmentLog<'_>) -> NexusHDF5Result<()> {
         match self.selogs.entry(message.get_name()) {
             Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(_) if self.swmr => Err(NexusHDF5Error::object_creation_during_swmr()),
             Entry::Vacant(vacant_entry) => vacant_entry
                 .insert(ValueLog::build_new_group(
                     &self.group,
@@ -65,6 +72,7 @@ impl NexusMessageHandler<PushAlarm<'_>> for SELog {
     fn handle_message(&mut self, message: &PushAlarm<'_>) -> NexusHDF5Result<()> {
         match self.selogs.entry(message.get_name()?) {
             Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(_) if self.swmr => Err(NexusHDF5Error::object_creation_during_swmr()),
             Entry::Vacant(vacant_entry) => vacant_entry
                 .insert(ValueLog::build_new_group(
                     &self.group,
@@ -75,3 +83,30 @@ impl NexusMessageHandler<PushAlarm<'_>> for SELog {
         }
     }
 }
+
+/// Creates the sample environment log of an `se00` or `al00` stream of the template, if it does not exist,
+/// and propagates [CreateStreamLog] to it.
+impl NexusMessageHandler<CreateStreamLog<'_>> for SELog {
+    fn handle_message(&mut self, message: &CreateStreamLog<'_>) -> NexusHDF5Result<()> {
+        let name = match message.log {
+            StreamLog::SampleEnvironmentLog { name, .. } | StreamLog::Alarm { name } => name,
+            StreamLog::RunLog { .. } => return Ok(()),
+        };
+        match self.selogs.entry(name.clone()) {
+            Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().handle_message(message),
+            Entry::Vacant(vacant_entry) => vacant_entry
+                .insert(ValueLog::build_new_group(&self.group, name, &())?)
+                .handle_message(message),
+        }
+    }
+}
+
+/// Propagates [StartSwmrWrite] to each log, and refuses to create new logs from now on.
+impl NexusMessageHandler<StartSwmrWrite<'_>> for SELog {
+    fn handle_message(&mut self, message: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        self.swmr = true;
+        self.selogs
+            .values_mut()
+            .try_for_each(|selog| selog.handle_message(message))
+    }
+}
@@ -1,9 +1,12 @@
 //! Implements the [AlarmLog] struct which represents some of the fields in a NeXus group of class `NXLog`.
 
 use crate::{
-    hdf5_handlers::{GroupExt, NexusHDF5Result},
+    hdf5_handlers::{DatasetExt, GroupExt, NexusHDF5Result},
     nexus::{AlarmMessage, NexusClass, NexusMessageHandler, NexusSchematic},
-    run_engine::{AlarmChunkSize, run_messages::PushAlarm},
+    run_engine::{
+        AlarmChunkSize,
+        run_messages::{PushAlarm, StartSwmrWrite},
+    },
 };
 use hdf5::{Dataset, Group, types::VarLenUnicode};
 
@@ -11,6 +14,8 @@ pub(crate) struct AlarmLog {
     alarm_severity: Dataset,
     alarm_status: Dataset,
     alarm_time: Dataset,
+    /// If true, the file is in SWMR writing mode, so the datasets are flushed to SWMR readers once appended to.
+    swmr: bool,
 }
 
 impl NexusSchematic for AlarmLog {
@@ -28,13 +33,19 @@ impl NexusSchematic for AlarmLog {
             alarm_severity: group.create_resizable_empty_dataset::<VarLenUnicode>(
                 "alarm_severity",
                 alarm_chunk_size,
+                &[],
             )?,
             alarm_status: group.create_resizable_empty_dataset::<VarLenUnicode>(
                 "alarm_status",
                 alarm_chunk_size,
+                &[],
             )?,
-            alarm_time: group
-                .create_resizable_empty_dataset::<i64>("alarm_time", alarm_chunk_size)?,
+            alarm_time: group.create_resizable_empty_dataset::<i64>(
+                "alarm_time",
+                alarm_chunk_size,
+                &[],
+            )?,
+            swmr: false,
         })
     }
 
@@ -43,6 +54,7 @@ impl NexusSchematic for AlarmLog {
             alarm_severity: group.get_dataset("alarm_severity")?,
             alarm_status: group.get_dataset("alarm_status")?,
             alarm_time: group.get_dataset("alarm_time")?,
+            swmr: false,
         })
     }
 }
@@ -55,9 +67,23 @@ impl NexusMessageHandler<PushAlarm<'_>> for AlarmLog {
     /// - Propagates errors from [AlarmMessage::append_message_to()].
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     fn handle_message(&mut self, message: &PushAlarm<'_>) -> NexusHDF5Result<()> {
-        message.append_timestamp_to(&self.alarm_time, message.origin)?;
+        // The timestamp is flushed to any SWMR readers last,
+        // so that a reader never sees an alarm time without its severity and status.
         message.append_severity_to(&self.alarm_severity)?;
         message.append_message_to(&self.alarm_status)?;
+        message.append_timestamp_to(&self.alarm_time, message.origin)?;
+        if self.swmr {
+            self.alarm_severity.flush_to_swmr_readers()?;
+            self.alarm_status.flush_to_swmr_readers()?;
+            self.alarm_time.flush_to_swmr_readers()?;
+        }
+        Ok(())
+    }
+}
+
+impl NexusMessageHandler<StartSwmrWrite<'_>> for AlarmLog {
+    fn handle_message(&mut self, _: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        self.swmr = true;
         Ok(())
     }
 }
@@ -11,13 +11,14 @@ use crate::{
         NexusDateTime,
         run_messages::{
             InternallyGeneratedLog, PushInternallyGeneratedLogWarning, PushRunLog,
-            PushSampleEnvironmentLog, SampleEnvironmentLog,
+            PushSampleEnvironmentLog, SampleEnvironmentLog, StartSwmrWrite,
         },
     },
 };
-use hdf5::{Dataset, Group, types::TypeDescriptor};
+use hdf5::{Dataset, Group, filters::Filter, types::TypeDescriptor};
 use std::ops::Deref;
-use supermusr_common::DigitizerId;
+use supermusr_common::{Channel, DigitizerId};
+use supermusr_streaming_types::aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage;
 
 /// Wrapper for all settings needed to construct the [Log] group structure.
 pub(crate) struct LogSettings {
@@ -25,6 +26,8 @@ pub(crate) struct LogSettings {
     pub(crate) type_descriptor: TypeDescriptor,
     /// The size of the chunk used for this particular log.
     pub(crate) chunk_size: usize,
+    /// The filters with which the chunks of this particular log are compressed.
+    pub(crate) filters: Vec<Filter>,
 }
 
 /// Group structure for a RunLog message.
@@ -35,13 +38,15 @@ pub(crate) struct LogSettings {
 pub(crate) struct Log {
     time: Dataset,
     value: Dataset,
+    /// If true, the file is in SWMR writing mode, so the datasets are flushed to SWMR readers once appended to.
+    swmr: bool,
 }
 
 impl NexusSchematic for Log {
     /// The nexus class of this group.
     const CLASS: NexusClass = NexusClass::Log;
 
-    /// This group structure needs the data type and chunk size to build.
+    /// This group structure needs the data type, chunk size and filters to build.
     type Settings = LogSettings;
 
     fn build_group_structure(
@@ -49,9 +54,11 @@ impl NexusSchematic for Log {
         LogSettings {
             type_descriptor,
             chunk_size,
+            filters,
         }: &Self::Settings,
     ) -> NexusHDF5Result<Self> {
-        let time_dataset = group.create_resizable_empty_dataset::<f64>("time", *chunk_size)?;
+        let time_dataset =
+            group.create_resizable_empty_dataset::<f64>("time", *chunk_size, filters)?;
 
         time_dataset.add_constant_string_attribute("units", &Seconds.to_string())?;
 
@@ -61,7 +68,9 @@ impl NexusSchematic for Log {
                 "value",
                 type_descriptor,
                 *chunk_size,
+                filters,
             )?,
+            swmr: false,
         })
     }
 
@@ -69,10 +78,31 @@ impl NexusSchematic for Log {
         Ok(Self {
             time: group.get_dataset("time")?,
             value: group.get_dataset("value")?,
+            swmr: false,
         })
     }
 }
 
+impl Log {
+    /// If the file is in SWMR writing mode, flushes [Self::value] before [Self::time],
+    /// so that a SWMR reader never sees a timestamp without its value.
+    /// For this reason, values are appended before their timestamps.
+    fn flush_to_swmr_readers(&self) -> NexusHDF5Result<()> {
+        if self.swmr {
+            self.value.flush_to_swmr_readers()?;
+            self.time.flush_to_swmr_readers()?;
+        }
+        Ok(())
+    }
+}
+
+impl NexusMessageHandler<StartSwmrWrite<'_>> for Log {
+    fn handle_message(&mut self, _: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        self.swmr = true;
+        Ok(())
+    }
+}
+
 impl NexusMessageHandler<PushRunLog<'_>> for Log {
     /// Appends timestamps and values to the appropriate datasets.
     /// # Error Modes
@@ -80,9 +110,9 @@ impl NexusMessageHandler<PushRunLog<'_>> for Log {
     /// - Propagates errors from [LogMessage::append_values_to()].
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     fn handle_message(&mut self, message: &PushRunLog<'_>) -> NexusHDF5Result<()> {
-        message.append_timestamps_to(&self.time, message.origin)?;
         message.append_values_to(&self.value)?;
-        Ok(())
+        message.append_timestamps_to(&self.time, message.origin)?;
+        self.flush_to_swmr_readers()
     }
 }
 
@@ -91,18 +121,39 @@ impl NexusMessageHandler<PushSampleEnvironmentLog<'_>> for Log {
     fn handle_message(&mut self, message: &PushSampleEnvironmentLog<'_>) -> NexusHDF5Result<()> {
         match message.deref() {
             SampleEnvironmentLog::LogData(f144_message) => {
-                f144_message.append_timestamps_to(&self.time, message.origin)?;
                 f144_message.append_values_to(&self.value)?;
+                f144_message.append_timestamps_to(&self.time, message.origin)?;
             }
             SampleEnvironmentLog::SampleEnvironmentData(se00_message) => {
-                se00_message.append_timestamps_to(&self.time, message.origin)?;
                 se00_message.append_values_to(&self.value)?;
+                se00_message.append_timestamps_to(&self.time, message.origin)?;
             }
         }
-        Ok(())
+        self.flush_to_swmr_readers()
     }
 }
 
+/// Returns the timestamp of the frame in nanoseconds, relative to the start of the run.
+/// # Parameters
+/// - frame: the frame event list to extract the timestamp from.
+/// - origin: the timestamp at which the run started.
+fn frame_time_zero(
+    frame: &FrameAssembledEventListMessage<'_>,
+    origin: &NexusDateTime,
+) -> NexusHDF5Result<i64> {
+    let timestamp: NexusDateTime = (*frame
+        .metadata()
+        .timestamp()
+        .ok_or(FlatBufferMissingError::Timestamp)?)
+    .try_into()?;
+
+    // Recalculate time_zero of the frame to be relative to the offset value
+    // (set at the start of the run).
+    (timestamp - origin)
+        .num_nanoseconds()
+        .ok_or_else(|| NexusHDF5Error::timedelta_convert_to_ns(timestamp - origin))
+}
+
 impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Log {
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     fn handle_message(
@@ -111,28 +162,15 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Log {
     ) -> NexusHDF5Result<()> {
         match message.message {
             InternallyGeneratedLog::RunResume { resume_time } => {
+                self.value.append_value(0)?; // This is a default value, I'm not sure if this field is needed
                 self.time.append_value(
                     (*resume_time - message.origin)
                         .num_nanoseconds()
                         .unwrap_or_default(),
                 )?;
-                self.value.append_value(0)?; // This is a default value, I'm not sure if this field is needed
             }
             InternallyGeneratedLog::IncompleteFrame { frame } => {
-                let timestamp: NexusDateTime = (*frame
-                    .metadata()
-                    .timestamp()
-                    .ok_or(FlatBufferMissingError::Timestamp)?)
-                .try_into()?;
-
-                // Recalculate time_zero of the frame to be relative to the offset value
-                // (set at the start of the run).
-                let time_zero =
-                    (timestamp - message.origin)
-                        .num_nanoseconds()
-                        .ok_or_else(|| {
-                            NexusHDF5Error::timedelta_convert_to_ns(timestamp - message.origin)
-                        })?;
+                let time_zero = frame_time_zero(frame, message.origin)?;
 
                 let digitisers_present = frame
                     .digitizers_present()
@@ -143,8 +181,28 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Log {
                     .join(",")
                     .parse::<hdf5::types::VarLenUnicode>()?;
 
-                self.time.append_value(time_zero)?;
                 self.value.append_value(digitisers_present)?;
+                self.time.append_value(time_zero)?;
+            }
+            InternallyGeneratedLog::UnmappedChannels { frame, channels } => {
+                // The time dataset is in seconds.
+                let time = frame_time_zero(frame, message.origin)? as f64 / 1_000_000_000.0;
+
+                let channels = channels
+                    .iter()
+                    .map(Channel::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+                    .parse::<hdf5::types::VarLenUnicode>()?;
+
+                self.value.append_value(channels)?;
+                self.time.append_value(time)?;
+            }
+            InternallyGeneratedLog::InvalidRunMetadata { error } => {
+                // The metadata is given at the start of the run.
+                self.value
+                    .append_value(error.to_string().parse::<hdf5::types::VarLenUnicode>()?)?;
+                self.time.append_value(0.0)?;
             }
             InternallyGeneratedLog::AbortRun { stop_time_ms } => {
                 let time = (message
@@ -153,10 +211,10 @@ impl NexusMessageHandler<PushInternallyGeneratedLogWarning<'_>> for Log {
                     .map(|origin_time_ns| 1_000_000 * stop_time_ms - origin_time_ns)
                     .unwrap_or_default() as f64)
                     / 1_000_000_000.0;
-                self.time.append_value(time)?;
                 self.value.append_value(0)?; // This is a default value, I'm not sure if this field is needed
+                self.time.append_value(time)?;
             }
         }
-        Ok(())
+        self.flush_to_swmr_readers()
     }
 }
@@ -2,9 +2,11 @@
 
 use super::{AlarmLog, Log, LogSettings};
 use crate::{
-    hdf5_handlers::NexusHDF5Result,
+    hdf5_handlers::{NexusHDF5Error, NexusHDF5Result},
     nexus::{LogMessage, NexusClass, NexusGroup, NexusMessageHandler, NexusSchematic},
-    run_engine::run_messages::{PushAlarm, PushSampleEnvironmentLog},
+    run_engine::run_messages::{
+        CreateStreamLog, PushAlarm, PushSampleEnvironmentLog, StartSwmrWrite, StreamLog,
+    },
 };
 use hdf5::Group;
 
@@ -17,6 +19,8 @@ pub(crate) struct ValueLog {
     group: Group,
     alarm: Option<AlarmLog>,
     log: Option<NexusGroup<Log>>,
+    /// If true, the file is in SWMR writing mode, so [Self::alarm] and [Self::log] cannot be created.
+    swmr: bool,
 }
 
 impl NexusSchematic for ValueLog {
@@ -31,6 +35,7 @@ impl NexusSchematic for ValueLog {
             group: group.clone(),
             alarm: None,
             log: None,
+            swmr: false,
         })
     }
 
@@ -39,6 +44,7 @@ impl NexusSchematic for ValueLog {
             group: group.clone(),
             alarm: AlarmLog::populate_group_structure(group).ok(),
             log: Log::open_group(group, labels::VALUE_LOG).ok(),
+            swmr: false,
         })
     }
 }
@@ -46,16 +52,21 @@ impl NexusSchematic for ValueLog {
 impl NexusMessageHandler<PushSampleEnvironmentLog<'_>> for ValueLog {
     /// Appends timestamps and values to the appropriate datasets.
     /// # Error Modes
+    /// - Returns [NexusHDF5Error::ObjectCreationDuringSwmr] if the log must be created during SWMR writing.
     /// - Propagates errors from [Log::build_group_structure()].
     /// - Propagates errors from [Log::handle_message()].
     fn handle_message(&mut self, message: &PushSampleEnvironmentLog<'_>) -> NexusHDF5Result<()> {
         if self.log.is_none() {
+            if self.swmr {
+                return Err(NexusHDF5Error::object_creation_during_swmr());
+            }
             self.log = Some(Log::build_new_group(
                 &self.group,
                 labels::VALUE_LOG,
                 &LogSettings {
                     type_descriptor: message.get_type_descriptor()?,
-                    chunk_size: message.settings.selog,
+                    chunk_size: message.settings.chunk_sizes.selog,
+                    filters: message.settings.compression.selog.clone(),
                 },
             )?);
         }
@@ -71,14 +82,18 @@ impl NexusMessageHandler<PushAlarm<'_>> for ValueLog {
     /// If the alarm structure exists, appends the alarm data to it,
     /// otherwise create it and append.
     /// # Error Modes
+    /// - Returns [NexusHDF5Error::ObjectCreationDuringSwmr] if the alarm structure must be created during SWMR writing.
     /// - Propagates errors from [AlarmLog::build_group_structure()].
     /// - Propagates errors from [AlarmLog::handle_message()].
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     fn handle_message(&mut self, message: &PushAlarm<'_>) -> NexusHDF5Result<()> {
         if self.alarm.is_none() {
+            if self.swmr {
+                return Err(NexusHDF5Error::object_creation_during_swmr());
+            }
             self.alarm = Some(AlarmLog::build_group_structure(
                 &self.group,
-                &message.settings.alarm,
+                &message.settings.chunk_sizes.alarm,
             )?);
         }
 
@@ -88,3 +103,52 @@ impl NexusMessageHandler<PushAlarm<'_>> for ValueLog {
             .handle_message(message)
     }
 }
+
+impl NexusMessageHandler<CreateStreamLog<'_>> for ValueLog {
+    /// Creates the log or alarm structure of the stream, if it does not exist.
+    /// # Error Modes
+    /// - Propagates errors from [Log::build_group_structure()].
+    /// - Propagates errors from [AlarmLog::build_group_structure()].
+    fn handle_message(
+        &mut self,
+        &CreateStreamLog { log, settings }: &CreateStreamLog<'_>,
+    ) -> NexusHDF5Result<()> {
+        match log {
+            StreamLog::SampleEnvironmentLog {
+                type_descriptor, ..
+            } if self.log.is_none() => {
+                self.log = Some(Log::build_new_group(
+                    &self.group,
+                    labels::VALUE_LOG,
+                    &LogSettings {
+                        type_descriptor: type_descriptor.clone(),
+                        chunk_size: settings.chunk_sizes.selog,
+                        filters: settings.compression.selog.clone(),
+                    },
+                )?);
+            }
+            StreamLog::Alarm { .. } if self.alarm.is_none() => {
+                self.alarm = Some(AlarmLog::build_group_structure(
+                    &self.group,
+                    &settings.chunk_sizes.alarm,
+                )?);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Propagates [StartSwmrWrite] to the log and alarm structures, and refuses to create them from now on.
+impl NexusMessageHandler<StartSwmrWrite<'_>> for ValueLog {
+    fn handle_message(&mut self, message: &StartSwmrWrite<'_>) -> NexusHDF5Result<()> {
+        self.swmr = true;
+        if let Some(alarm) = &mut self.alarm {
+            alarm.handle_message(message)?;
+        }
+        if let Some(log) = &mut self.log {
+            log.handle_message(message)?;
+        }
+        Ok(())
+    }
+}
@@ -4,18 +4,25 @@
 //! set out in the appropriate nexus version.
 //! The [logs] submodule consists of groups that appear in the [entry] module
 //! as extensible vectors of groups.
+//! The [template] submodule adds the groups described by the `nexus_structure` field of the `RunStart` message.
 
 mod entry;
 mod logs;
+mod template;
 
 use crate::{
     hdf5_handlers::{HasAttributesExt, NexusHDF5Result},
     nexus::{NexusClass, NexusGroup, NexusMessageHandler, NexusSchematic},
-    run_engine::{ChunkSizeSettings, RunParameters},
+    run_engine::{
+        DatasetSettings, RunParameters,
+        run_messages::{CreateStreamLog, PushNexusStructure},
+    },
 };
 use chrono::{SecondsFormat, Utc};
 use entry::Entry;
-use hdf5::{Attribute, Group};
+use hdf5::Group;
+use template::NexusStructureTemplate;
+use tracing::warn;
 
 /// Field names for [Root].
 mod labels {
@@ -27,15 +34,15 @@ mod labels {
 }
 
 /// Encapsulates the top-level of a NeXus file,
+///
+/// The top-level group has the following attributes, which are not kept open, as SWMR writing cannot start whilst any attributes are open:
+/// - `HDF5_version`: version of HDF library used by nexus to create file, set from `hdf5::HDF5_VERSION`
+/// - `NeXuS_version`: version of nexus API used in writing the file
+/// - `file_name`: File name of current file, to assist identification if the external name has been changed
+/// - `file_time`: Is set to the time this object is created
 pub(crate) struct Root {
-    /// version of HDF library used by nexus to create file, should be set from `hdf5::HDF5_VERSION`
-    _hdf5_version: Attribute,
-    /// version of nexus API used in writing the file
-    _nexus_version: Attribute,
-    /// File name of current file, to assist identification if the external name has been changed
-    _file_name: Attribute,
-    /// Is set to the time this object is created
-    _file_time: Attribute,
+    /// Handle to the top-level group of the file, in which the `nexus_structure` template is materialised
+    group: Group,
     /// All incoming data goes here
     raw_data_1: NexusGroup<Entry>,
 }
@@ -49,38 +56,43 @@ impl Root {
 
 impl NexusSchematic for Root {
     const CLASS: NexusClass = NexusClass::Root;
-    type Settings = ChunkSizeSettings;
+    type Settings = DatasetSettings;
 
-    fn build_group_structure(group: &Group, settings: &ChunkSizeSettings) -> NexusHDF5Result<Self> {
+    fn build_group_structure(group: &Group, settings: &DatasetSettings) -> NexusHDF5Result<Self> {
+        group.add_constant_string_attribute(
+            labels::HDF5_VERSION,
+            &format!(
+                "{0}.{1}.{2}",
+                hdf5::HDF5_VERSION.major,
+                hdf5::HDF5_VERSION.minor,
+                hdf5::HDF5_VERSION.micro
+            ),
+        )?;
+        group.add_constant_string_attribute(labels::NEXUS_VERSION, "")?; // Where does this come from?
+        group.add_constant_string_attribute(labels::FILE_NAME, &group.filename())?;
+        group.add_constant_string_attribute(
+            labels::FILE_TIME,
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+                .as_str(),
+        )?;
         Ok(Self {
-            _hdf5_version: group.add_constant_string_attribute(
-                labels::HDF5_VERSION,
-                &format!(
-                    "{0}.{1}.{2}",
-                    hdf5::HDF5_VERSION.major,
-                    hdf5::HDF5_VERSION.minor,
-                    hdf5::HDF5_VERSION.micro
-                ),
-            )?,
-            _nexus_version: group.add_constant_string_attribute(labels::NEXUS_VERSION, "")?, // Where does this come from?
-            _file_name: group
-                .add_constant_string_attribute(labels::FILE_NAME, &group.filename())?,
-            _file_time: group.add_constant_string_attribute(
-                labels::FILE_TIME,
-                Utc::now()
-                    .to_rfc3339_opts(SecondsFormat::Secs, true)
-                    .as_str(),
-            )?,
+            group: group.clone(),
             raw_data_1: Entry::build_new_group(group, labels::RAW_DATA_1, settings)?,
         })
     }
 
     fn populate_group_structure(group: &Group) -> NexusHDF5Result<Self> {
+        for label in [
+            labels::HDF5_VERSION,
+            labels::NEXUS_VERSION,
+            labels::FILE_NAME,
+            labels::FILE_TIME,
+        ] {
+            group.get_attribute(label)?;
+        }
         Ok(Self {
-            _hdf5_version: group.get_attribute(labels::HDF5_VERSION)?,
-            _nexus_version: group.get_attribute(labels::NEXUS_VERSION)?,
-            _file_name: group.get_attribute(labels::FILE_NAME)?,
-            _file_time: group.get_attribute(labels::FILE_TIME)?,
+            group: group.clone(),
             raw_data_1: Entry::open_group(group, labels::RAW_DATA_1)?,
         })
     }
@@ -98,3 +110,28 @@ where
         self.raw_data_1.handle_message(message)
     }
 }
+
+/// Creates the logs of the `nexus_structure` template's streams, then materialises the template in the file.
+/// An invalid template is not an error, as the file is still usable without it, so is only warned of.
+impl NexusMessageHandler<PushNexusStructure<'_>> for Root {
+    fn handle_message(
+        &mut self,
+        &PushNexusStructure { json, settings }: &PushNexusStructure<'_>,
+    ) -> NexusHDF5Result<()> {
+        match NexusStructureTemplate::parse(json) {
+            Ok(template) => {
+                for log in &template.stream_logs() {
+                    if let Err(e) = self
+                        .raw_data_1
+                        .handle_message(&CreateStreamLog { log, settings })
+                    {
+                        warn!("Failed to create log of nexus structure template stream: {e}");
+                    }
+                }
+                template.apply(&self.group);
+            }
+            Err(e) => warn!("Invalid nexus structure template: {e}"),
+        }
+        Ok(())
+    }
+}
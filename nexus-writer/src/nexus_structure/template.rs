@@ -0,0 +1,865 @@
+//! Materialises the `nexus_structure` JSON template of a `RunStart` message into the NeXus file.
+//!
+//! The template is in the format used by [kafka-to-nexus](https://github.com/ess-dmsc/kafka-to-nexus/),
+//! and is rooted at the top level of the file. Its static groups, datasets and attributes are added to the
+//! fixed structure built by [Root]: groups which already exist are added to, and datasets and attributes which
+//! already exist are overwritten, except for datasets written by the stream handlers, which are left untouched.
+//! A group containing a stream module is created as a soft link to the group
+//! written by the corresponding handler, so the data of the stream appears at the templated path.
+//! The attributes and static datasets of such a group are written to the linked group, if it exists.
+//! The logs of the template's `f144`, `se00` and `al00` streams are created before the template is applied,
+//! so they exist to be written to, and need not be created during SWMR writing.
+//!
+//! Nodes which cannot be materialised are skipped with a warning, so a faulty template never prevents a run.
+//!
+//! [Root]: super::Root
+use crate::{
+    hdf5_handlers::{ConvertResult, GroupExt, NexusHDF5Result},
+    nexus::remove_prefixes,
+    run_engine::run_messages::StreamLog,
+};
+use hdf5::{
+    Dataset, Group, H5Type, Location,
+    types::{TypeDescriptor, VarLenUnicode},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+/// Paths of the groups to which the stream modules of a template are bound.
+mod paths {
+    pub(super) const RUNLOG: &str = "/raw_data_1/runlog";
+    pub(super) const SELOG: &str = "/raw_data_1/selog";
+    pub(super) const DETECTOR_1: &str = "/raw_data_1/detector_1";
+    pub(super) const PERIODS: &str = "/raw_data_1/periods";
+
+    /// Paths of the groups whose datasets are owned by the writer, which the template may not overwrite.
+    pub(super) const WRITER_OWNED: [&str; 4] = [DETECTOR_1, RUNLOG, SELOG, PERIODS];
+}
+
+/// Names of the modules which may appear in a template.
+mod modules {
+    pub(super) const DATASET: &str = "dataset";
+    pub(super) const F144: &str = "f144";
+    pub(super) const SE00: &str = "se00";
+    pub(super) const AL00: &str = "al00";
+    pub(super) const EV42: &str = "ev42";
+    pub(super) const EV44: &str = "ev44";
+}
+
+/// The `nexus_structure` JSON template of a `RunStart` message.
+#[derive(Debug, Deserialize)]
+pub(crate) struct NexusStructureTemplate {
+    #[serde(default)]
+    children: Vec<Node>,
+}
+
+/// A node of the template.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Node {
+    /// A group, whose `type` is `"group"`.
+    Group {
+        #[serde(rename = "type")]
+        node_type: String,
+        name: String,
+        #[serde(default)]
+        children: Vec<Node>,
+        #[serde(default)]
+        attributes: Option<Attributes>,
+    },
+    /// A static dataset, or a stream placeholder.
+    Module {
+        module: String,
+        config: ModuleConfig,
+        #[serde(default)]
+        attributes: Option<Attributes>,
+    },
+    /// Any other node, which is skipped.
+    Other(Value),
+}
+
+/// The configuration of a module node.
+#[derive(Debug, Deserialize)]
+struct ModuleConfig {
+    /// The name of a static dataset.
+    name: Option<String>,
+    /// The values of a static dataset.
+    values: Option<Value>,
+    /// The data type of a static dataset.
+    #[serde(alias = "type")]
+    dtype: Option<String>,
+    /// The source name of a stream.
+    source: Option<String>,
+}
+
+/// The attributes of a node, either as a list or as a map from name to value.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Attributes {
+    List(Vec<AttributeNode>),
+    Map(Map<String, Value>),
+}
+
+impl Attributes {
+    /// Returns the names of the attributes.
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Attributes::List(attributes) => attributes
+                .iter()
+                .map(|attribute| attribute.name.as_str())
+                .collect(),
+            Attributes::Map(attributes) => attributes.keys().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// An attribute given in list form.
+#[derive(Debug, Deserialize)]
+struct AttributeNode {
+    name: String,
+    values: Value,
+    #[serde(default, alias = "type")]
+    dtype: Option<String>,
+}
+
+/// The data types which may be given in a template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DataType {
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+}
+
+impl DataType {
+    /// Returns the data type of the given name, or that inferred from the values if no name is given.
+    /// # Parameters
+    /// - dtype: the name of the data type, if given.
+    /// - values: the values, or array of values, to infer the type from.
+    fn new(dtype: Option<&str>, values: &Value) -> Option<Self> {
+        match dtype {
+            Some("int8") => Some(Self::Int8),
+            Some("int16") => Some(Self::Int16),
+            Some("int32" | "int") => Some(Self::Int32),
+            Some("int64") => Some(Self::Int64),
+            Some("uint8") => Some(Self::UInt8),
+            Some("uint16") => Some(Self::UInt16),
+            Some("uint32") => Some(Self::UInt32),
+            Some("uint64") => Some(Self::UInt64),
+            Some("float" | "float32") => Some(Self::Float32),
+            Some("double" | "float64") => Some(Self::Float64),
+            Some("string") => Some(Self::String),
+            Some(_) => None,
+            None => {
+                let first = match values {
+                    Value::Array(values) => {
+                        if values.iter().any(Value::is_f64) {
+                            return Some(Self::Float64);
+                        }
+                        values.first()?
+                    }
+                    value => value,
+                };
+                match first {
+                    Value::Bool(_) => Some(Self::UInt8),
+                    Value::Number(number) if number.is_f64() => Some(Self::Float64),
+                    Value::Number(_) => Some(Self::Int64),
+                    Value::String(_) => Some(Self::String),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Calls the generic expression with the type alias `$t` set to the rust type of the [DataType].
+macro_rules! with_data_type {
+    ($data_type:expr, $t:ident => $body:expr) => {
+        match $data_type {
+            DataType::Int8 => {
+                type $t = i8;
+                $body
+            }
+            DataType::Int16 => {
+                type $t = i16;
+                $body
+            }
+            DataType::Int32 => {
+                type $t = i32;
+                $body
+            }
+            DataType::Int64 => {
+                type $t = i64;
+                $body
+            }
+            DataType::UInt8 => {
+                type $t = u8;
+                $body
+            }
+            DataType::UInt16 => {
+                type $t = u16;
+                $body
+            }
+            DataType::UInt32 => {
+                type $t = u32;
+                $body
+            }
+            DataType::UInt64 => {
+                type $t = u64;
+                $body
+            }
+            DataType::Float32 => {
+                type $t = f32;
+                $body
+            }
+            DataType::Float64 => {
+                type $t = f64;
+                $body
+            }
+            DataType::String => {
+                type $t = VarLenUnicode;
+                $body
+            }
+        }
+    };
+}
+
+impl DataType {
+    /// Returns the hdf5 data type of the [DataType].
+    fn type_descriptor(self) -> TypeDescriptor {
+        with_data_type!(self, T => T::type_descriptor())
+    }
+}
+
+/// Converts a JSON value to a value which can be written to the file.
+trait FromJson: H5Type + Sized {
+    fn from_json(value: &Value) -> Option<Self>;
+}
+
+macro_rules! impl_from_json_for_int {
+    ($($t:ty),*) => {
+        $(impl FromJson for $t {
+            fn from_json(value: &Value) -> Option<Self> {
+                match value {
+                    Value::Bool(value) => Some(Self::from(*value)),
+                    value => value
+                        .as_i64()
+                        .and_then(|value| value.try_into().ok())
+                        .or_else(|| value.as_u64().and_then(|value| value.try_into().ok())),
+                }
+            }
+        })*
+    };
+}
+
+impl_from_json_for_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl FromJson for f32 {
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_f64().map(|value| value as f32)
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &Value) -> Option<Self> {
+        value.as_f64()
+    }
+}
+
+impl FromJson for VarLenUnicode {
+    fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(value) => value.parse().ok(),
+            value => value.to_string().parse().ok(),
+        }
+    }
+}
+
+/// The values of a dataset or attribute, converted to type `T`.
+enum Values<T> {
+    Scalar(T),
+    Array(Vec<T>),
+}
+
+impl<T: FromJson> Values<T> {
+    fn new(values: &Value) -> Option<Self> {
+        match values {
+            Value::Array(values) => values
+                .iter()
+                .map(T::from_json)
+                .collect::<Option<_>>()
+                .map(Self::Array),
+            value => T::from_json(value).map(Self::Scalar),
+        }
+    }
+}
+
+impl NexusStructureTemplate {
+    /// Parses the template from its JSON description.
+    /// # Parameters
+    /// - json: the `nexus_structure` string of the `RunStart` message.
+    pub(crate) fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Returns the logs to which the template's `f144`, `se00` and `al00` streams are bound.
+    /// The data type of a log is given by the `dtype` of its stream module, or is `double` if none is given.
+    pub(crate) fn stream_logs(&self) -> Vec<StreamLog> {
+        let mut logs = Vec::new();
+        collect_stream_logs(&self.children, &mut logs);
+        logs
+    }
+
+    /// Materialises the template in the given group, which should be the root of the file.
+    /// # Parameters
+    /// - root: the group in which to materialise the template.
+    pub(crate) fn apply(&self, root: &Group) {
+        apply_children(root, &self.children);
+    }
+}
+
+/// Materialises each node in the given group, warning of any which cannot be.
+fn apply_children(parent: &Group, children: &[Node]) {
+    for child in children {
+        if let Err(e) = apply_node(parent, child) {
+            warn!("Failed to apply nexus structure template node: {e}");
+        }
+    }
+}
+
+/// Returns the first stream module of a group's children, if any, which determines the group to which it is bound.
+fn stream_module(children: &[Node]) -> Option<(&String, &ModuleConfig)> {
+    children.iter().find_map(|child| match child {
+        Node::Module { module, config, .. } if module != modules::DATASET => Some((module, config)),
+        _ => None,
+    })
+}
+
+/// Appends the logs to which the streams of the nodes, and of their descendants, are bound.
+/// Streams which cannot be bound are skipped, as they are warned of when the template is applied.
+fn collect_stream_logs(children: &[Node], logs: &mut Vec<StreamLog>) {
+    for child in children {
+        let Node::Group {
+            node_type,
+            name: group_name,
+            children,
+            ..
+        } = child
+        else {
+            continue;
+        };
+        if node_type != "group" {
+            continue;
+        }
+        let Some((module, config)) = stream_module(children) else {
+            collect_stream_logs(children, logs);
+            continue;
+        };
+        let Some(source) = &config.source else {
+            continue;
+        };
+        let name = remove_prefixes(source);
+        let type_descriptor = match config.dtype.as_deref() {
+            None => Some(f64::type_descriptor()),
+            dtype => DataType::new(dtype, &Value::Null).map(DataType::type_descriptor),
+        };
+        let log = match (module.as_str(), type_descriptor) {
+            (modules::F144, Some(type_descriptor)) => StreamLog::RunLog {
+                name,
+                type_descriptor,
+            },
+            (modules::SE00, Some(type_descriptor)) => StreamLog::SampleEnvironmentLog {
+                name,
+                type_descriptor,
+            },
+            (modules::AL00, _) => StreamLog::Alarm { name },
+            (modules::F144 | modules::SE00, None) => {
+                warn!(
+                    "Not creating the log of stream module {module} in {group_name}, whose type is unsupported"
+                );
+                continue;
+            }
+            _ => continue,
+        };
+        logs.push(log);
+    }
+}
+
+fn apply_node(parent: &Group, node: &Node) -> NexusHDF5Result<()> {
+    match node {
+        Node::Group {
+            node_type,
+            name,
+            children,
+            attributes,
+        } => {
+            if node_type != "group" {
+                warn!("Skipping nexus structure template node {name} of unknown type {node_type}");
+                return Ok(());
+            }
+            if let Some((module, config)) = stream_module(children) {
+                let target = bind_stream(parent, name, module, config)?;
+                apply_to_stream_target(parent, name, target.as_deref(), children, attributes);
+                return Ok(());
+            }
+            let group = if parent.link_exists(name) {
+                parent.get_group(name)?
+            } else {
+                parent.create_group(name).err_group(parent)?
+            };
+            if let Some(attributes) = attributes {
+                apply_attributes(&group, attributes);
+            }
+            apply_children(&group, children);
+            Ok(())
+        }
+        Node::Module {
+            module,
+            config,
+            attributes,
+        } => {
+            if module != modules::DATASET {
+                warn!("Skipping stream module {module} which is not within a group");
+                return Ok(());
+            }
+            let (Some(name), Some(values)) = (&config.name, &config.values) else {
+                warn!("Skipping dataset module without a name and values");
+                return Ok(());
+            };
+            let Some(data_type) = DataType::new(config.dtype.as_deref(), values) else {
+                warn!("Skipping dataset {name} of unsupported type");
+                return Ok(());
+            };
+            let written =
+                with_data_type!(data_type, T => write_dataset::<T>(parent, name, values))?;
+            if let (Some(dataset), Some(attributes)) = (written, attributes) {
+                apply_attributes(&dataset, attributes);
+            }
+            Ok(())
+        }
+        Node::Other(value) => {
+            warn!("Skipping unrecognised nexus structure template node {value}");
+            Ok(())
+        }
+    }
+}
+
+/// Creates a soft link in `parent` to the group to which the stream is written.
+/// # Parameters
+/// - parent: the group in which to create the link.
+/// - name: the name of the link.
+/// - module: the name of the stream module.
+/// - config: the configuration of the stream module.
+/// # Return
+/// The path of the group to which the stream is written, or [None] if the stream is not bound.
+fn bind_stream(
+    parent: &Group,
+    name: &str,
+    module: &str,
+    config: &ModuleConfig,
+) -> NexusHDF5Result<Option<String>> {
+    let target = match (module, &config.source) {
+        (modules::F144, Some(source)) => format!("{}/{}", paths::RUNLOG, remove_prefixes(source)),
+        (modules::SE00 | modules::AL00, Some(source)) => {
+            format!("{}/{}", paths::SELOG, remove_prefixes(source))
+        }
+        (modules::EV42 | modules::EV44, _) => paths::DETECTOR_1.to_owned(),
+        (modules::F144 | modules::SE00 | modules::AL00, None) => {
+            warn!("Skipping stream module {module} in {name} without a source");
+            return Ok(None);
+        }
+        _ => {
+            warn!("Skipping unsupported stream module {module} in {name}");
+            return Ok(None);
+        }
+    };
+    if parent.link_exists(name) {
+        warn!("Cannot bind stream module {module} to {name}, which already exists");
+        return Ok(None);
+    }
+    parent.link_soft(&target, name).err_group(parent)?;
+    Ok(Some(target))
+}
+
+/// Writes the attributes and static datasets of a stream group to the group to which the stream is written.
+/// If that group does not exist, each attribute and dataset is skipped with a warning.
+/// # Parameters
+/// - parent: the group containing the stream group.
+/// - name: the name of the stream group.
+/// - target: the path of the group to which the stream is written, if the stream is bound.
+/// - children: the children of the stream group.
+/// - attributes: the attributes of the stream group.
+fn apply_to_stream_target(
+    parent: &Group,
+    name: &str,
+    target: Option<&str>,
+    children: &[Node],
+    attributes: &Option<Attributes>,
+) {
+    let datasets = children
+        .iter()
+        .filter(|child| matches!(child, Node::Module { module, .. } if module == modules::DATASET));
+    let target = target.and_then(|target| parent.file().and_then(|file| file.group(target)).ok());
+    match target {
+        Some(target) => {
+            if let Some(attributes) = attributes {
+                apply_attributes(&target, attributes);
+            }
+            for dataset in datasets {
+                if let Err(e) = apply_node(&target, dataset) {
+                    warn!("Failed to apply nexus structure template node: {e}");
+                }
+            }
+        }
+        None => {
+            for attribute in attributes.iter().flat_map(Attributes::names) {
+                warn!(
+                    "Skipping attribute {attribute} of stream group {name}, whose stream is not written"
+                );
+            }
+            for dataset in datasets.filter_map(|dataset| match dataset {
+                Node::Module { config, .. } => config.name.as_deref(),
+                _ => None,
+            }) {
+                warn!(
+                    "Skipping dataset {dataset} of stream group {name}, whose stream is not written"
+                );
+            }
+        }
+    }
+}
+
+/// Writes a dataset, creating it if it does not exist.
+/// An existing dataset is not overwritten if it is resizable, or lies within a group owned by the writer.
+/// # Return
+/// The dataset, or [None] if the values are not of type `T` or the dataset cannot be overwritten.
+fn write_dataset<T: FromJson>(
+    parent: &Group,
+    name: &str,
+    values: &Value,
+) -> NexusHDF5Result<Option<Dataset>> {
+    let Some(values) = Values::<T>::new(values) else {
+        warn!("Skipping dataset {name} whose values are not of its type");
+        return Ok(None);
+    };
+    let exists = parent.link_exists(name);
+    if exists {
+        let path = format!("{}/{name}", parent.name());
+        if paths::WRITER_OWNED
+            .iter()
+            .any(|owned| path == owned || path.starts_with(&format!("{owned}/")))
+            || parent.get_dataset(name)?.is_resizable()
+        {
+            warn!("Skipping dataset {path}, which is written by the nexus writer");
+            return Ok(None);
+        }
+    }
+    let dataset = match (exists, values) {
+        (true, Values::Scalar(value)) => {
+            let dataset = parent.get_dataset(name)?;
+            dataset.write_scalar(&value).err_dataset(&dataset)?;
+            dataset
+        }
+        (true, Values::Array(values)) => {
+            let dataset = parent.get_dataset(name)?;
+            if dataset.size() != values.len() {
+                warn!("Skipping dataset {name} whose length differs from the existing dataset");
+                return Ok(None);
+            }
+            dataset.write_raw(values.as_slice()).err_dataset(&dataset)?;
+            dataset
+        }
+        (false, Values::Scalar(value)) => {
+            let dataset = parent.create_scalar_dataset::<T>(name)?;
+            dataset.write_scalar(&value).err_dataset(&dataset)?;
+            dataset
+        }
+        (false, Values::Array(values)) => parent
+            .new_dataset_builder()
+            .with_data(values.as_slice())
+            .create(name)
+            .err_group(parent)?,
+    };
+    Ok(Some(dataset))
+}
+
+/// Writes each attribute to the group or dataset, warning of any which cannot be.
+fn apply_attributes(location: &Location, attributes: &Attributes) {
+    let attributes: Vec<(&str, &Value, Option<&str>)> = match attributes {
+        Attributes::List(attributes) => attributes
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute.name.as_str(),
+                    &attribute.values,
+                    attribute.dtype.as_deref(),
+                )
+            })
+            .collect(),
+        Attributes::Map(attributes) => attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value, None))
+            .collect(),
+    };
+    for (name, values, dtype) in attributes {
+        let Some(data_type) = DataType::new(dtype, values) else {
+            warn!("Skipping attribute {name} of unsupported type");
+            continue;
+        };
+        if let Err(e) =
+            with_data_type!(data_type, T => write_attribute::<T>(location, name, values))
+        {
+            warn!("Failed to write attribute {name}: {e}");
+        }
+    }
+}
+
+/// Writes an attribute, creating it if it does not exist.
+fn write_attribute<T: FromJson>(
+    location: &Location,
+    name: &str,
+    values: &Value,
+) -> NexusHDF5Result<()> {
+    let Some(values) = Values::<T>::new(values) else {
+        warn!("Skipping attribute {name} whose values are not of its type");
+        return Ok(());
+    };
+    let exists = location
+        .attr_names()?
+        .iter()
+        .any(|existing| existing == name);
+    match (exists, values) {
+        (true, Values::Scalar(value)) => location.attr(name)?.write_scalar(&value)?,
+        (true, Values::Array(values)) => location.attr(name)?.write_raw(values.as_slice())?,
+        (false, Values::Scalar(value)) => location
+            .new_attr::<T>()
+            .create(name)?
+            .write_scalar(&value)?,
+        (false, Values::Array(values)) => {
+            location
+                .new_attr_builder()
+                .with_data(values.as_slice())
+                .create(name)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"{
+        "children": [{
+            "type": "group",
+            "name": "raw_data_1",
+            "attributes": [{"name": "NX_class", "values": "NXentry"}],
+            "children": [
+                {"module": "dataset", "config": {"name": "title", "values": "Silver calibration"}},
+                {
+                    "type": "group",
+                    "name": "beamline",
+                    "attributes": {"NX_class": "NXcollection"},
+                    "children": [
+                        {
+                            "module": "dataset",
+                            "config": {"name": "slits", "values": [1.5, 2.5], "dtype": "float"},
+                            "attributes": [{"name": "units", "values": "mm"}]
+                        },
+                        {"type": "group", "name": "temperature", "children": [
+                            {"module": "f144", "config": {"source": "IN:MUSR:CS:SB:Temp", "topic": "runlog"}}
+                        ]}
+                    ]
+                },
+                {"module": "mdat", "config": {"name": "start_time"}}
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn parse_template() {
+        let template = NexusStructureTemplate::parse(TEMPLATE).unwrap();
+        let [Node::Group { name, children, .. }] = template.children.as_slice() else {
+            panic!("Expected a single group");
+        };
+        assert_eq!(name, "raw_data_1");
+        assert_eq!(children.len(), 3);
+        assert!(matches!(
+            children.first(),
+            Some(Node::Module { module, config, .. })
+                if module == "dataset" && config.name.as_deref() == Some("title")
+        ));
+        assert!(matches!(children.get(1), Some(Node::Group { name, .. }) if name == "beamline"));
+    }
+
+    #[test]
+    fn stream_logs_of_template() {
+        let template = NexusStructureTemplate::parse(
+            r#"{"children": [{"type": "group", "name": "raw_data_1", "children": [
+                {"type": "group", "name": "instrument", "children": [
+                    {"type": "group", "name": "temperature", "children": [
+                        {"module": "f144", "config": {"source": "IN:MUSR:CS:SB:Temp"}}
+                    ]}
+                ]},
+                {"type": "group", "name": "field", "children": [
+                    {"module": "se00", "config": {"source": "Field", "dtype": "int32"}}
+                ]},
+                {"type": "group", "name": "valve", "children": [
+                    {"module": "al00", "config": {"source": "Valve"}}
+                ]},
+                {"type": "group", "name": "pressure", "children": [
+                    {"module": "f144", "config": {"source": "Pressure", "dtype": "complex"}}
+                ]},
+                {"type": "group", "name": "detector", "children": [
+                    {"module": "ev44", "config": {"source": "detector"}}
+                ]}
+            ]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template.stream_logs(),
+            vec![
+                StreamLog::RunLog {
+                    name: "Temp".to_owned(),
+                    type_descriptor: f64::type_descriptor()
+                },
+                StreamLog::SampleEnvironmentLog {
+                    name: "Field".to_owned(),
+                    type_descriptor: i32::type_descriptor()
+                },
+                StreamLog::Alarm {
+                    name: "Valve".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_template() {
+        let path = std::env::temp_dir()
+            .join("temp_supermusr_pipeline_nexus_writer_file_apply_template.nxs");
+        let file = hdf5::File::create(&path).unwrap();
+        let entry = file.create_group("raw_data_1").unwrap();
+        entry.create_group("runlog").unwrap();
+
+        NexusStructureTemplate::parse(TEMPLATE)
+            .unwrap()
+            .apply(&file);
+
+        let title = file.dataset("raw_data_1/title").unwrap();
+        assert_eq!(
+            title.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            "Silver calibration"
+        );
+        let slits = file.dataset("raw_data_1/beamline/slits").unwrap();
+        assert_eq!(slits.read_raw::<f32>().unwrap(), [1.5, 2.5]);
+        let units = slits.attr("units").unwrap();
+        assert_eq!(units.read_scalar::<VarLenUnicode>().unwrap().as_str(), "mm");
+
+        // The stream is bound once the log is created.
+        entry.group("runlog").unwrap().create_group("Temp").unwrap();
+        assert!(file.group("raw_data_1/beamline/temperature").is_ok());
+        assert!(!file.link_exists("raw_data_1/start_time"));
+
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writer_datasets_not_overwritten() {
+        let path = std::env::temp_dir()
+            .join("temp_supermusr_pipeline_nexus_writer_file_writer_datasets.nxs");
+        let file = hdf5::File::create(&path).unwrap();
+        let entry = file.create_group("raw_data_1").unwrap();
+        let detector = entry.create_group("detector_1").unwrap();
+        detector.create_scalar_dataset::<i64>("spectrum").unwrap();
+        entry
+            .create_resizable_empty_dataset::<i64>("counts", 8, &[])
+            .unwrap();
+        let other_detector = entry.create_group("detector_10").unwrap();
+        other_detector
+            .create_scalar_dataset::<i64>("spectrum")
+            .unwrap();
+
+        let written = write_dataset::<i64>(&detector, "spectrum", &Value::from(3)).unwrap();
+        assert!(written.is_none());
+        let written = write_dataset::<i64>(&entry, "counts", &serde_json::json!([1, 2])).unwrap();
+        assert!(written.is_none());
+        assert_eq!(entry.dataset("counts").unwrap().size(), 0);
+        let written = write_dataset::<i64>(&other_detector, "spectrum", &Value::from(3)).unwrap();
+        assert_eq!(written.unwrap().read_scalar::<i64>().unwrap(), 3);
+
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stream_group_applied_to_target() {
+        let path = std::env::temp_dir()
+            .join("temp_supermusr_pipeline_nexus_writer_file_stream_group_target.nxs");
+        let file = hdf5::File::create(&path).unwrap();
+        let entry = file.create_group("raw_data_1").unwrap();
+        entry
+            .create_group("runlog")
+            .unwrap()
+            .create_group("Temp")
+            .unwrap();
+
+        NexusStructureTemplate::parse(
+            r#"{"children": [{"type": "group", "name": "raw_data_1", "children": [
+                {"type": "group", "name": "temperature", "attributes": {"units": "K"}, "children": [
+                    {"module": "f144", "config": {"source": "IN:MUSR:CS:SB:Temp"}},
+                    {"module": "dataset", "config": {"name": "target", "values": 4.5}}
+                ]},
+                {"type": "group", "name": "pressure", "attributes": {"units": "Pa"}, "children": [
+                    {"module": "f144", "config": {"source": "IN:MUSR:CS:SB:Pressure"}}
+                ]}
+            ]}]}"#,
+        )
+        .unwrap()
+        .apply(&file);
+
+        let temp = file.group("raw_data_1/runlog/Temp").unwrap();
+        let units = temp.attr("units").unwrap();
+        assert_eq!(units.read_scalar::<VarLenUnicode>().unwrap().as_str(), "K");
+        let target = file.dataset("raw_data_1/temperature/target").unwrap();
+        assert_eq!(target.read_scalar::<f64>().unwrap(), 4.5);
+        // The pressure log does not exist, so its attributes are skipped.
+        assert!(file.link_exists("raw_data_1/pressure"));
+        assert!(!file.link_exists("raw_data_1/runlog/Pressure"));
+
+        file.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn data_types() {
+        assert_eq!(
+            DataType::new(None, &Value::from("a")),
+            Some(DataType::String)
+        );
+        assert_eq!(DataType::new(None, &Value::from(3)), Some(DataType::Int64));
+        assert_eq!(
+            DataType::new(None, &serde_json::json!([1.5, 2])),
+            Some(DataType::Float64)
+        );
+        assert_eq!(
+            DataType::new(None, &serde_json::json!([1, 2.5])),
+            Some(DataType::Float64)
+        );
+        assert_eq!(
+            DataType::new(Some("uint16"), &Value::from(3)),
+            Some(DataType::UInt16)
+        );
+        assert_eq!(DataType::new(Some("complex"), &Value::from(3)), None);
+        assert!(Values::<u8>::new(&Value::from(300)).is_none());
+    }
+}
@@ -1,5 +1,5 @@
 //! Defines and implements the [NexusEngine] struct.
-use super::run_messages::SampleEnvironmentLog;
+use super::run_messages::{FramePulseShapes, SampleEnvironmentLog};
 use crate::{
     TopicMode,
     error::{ErrorCodeLocation, FlatBufferMissingError, NexusWriterError, NexusWriterResult},
@@ -215,11 +215,15 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
     /// If no run is found then this method does nothing.
     /// Should a warning be emitted?
     /// # Parameters
-    /// - data: the `RunLog` message to push.
+    /// - message: the frame event list message to push.
+    /// - pulse_shapes: the optional pulse shape fields of the message.
+    /// - correction: if `true`, the message contains late data for a frame which has already been pushed.
     #[tracing::instrument(skip_all, level = "debug")]
     pub(crate) fn push_frame_event_list(
         &mut self,
         message: FrameAssembledEventListMessage<'_>,
+        pulse_shapes: FramePulseShapes<'_>,
+        correction: bool,
     ) -> NexusWriterResult<()> {
         let timestamp: NexusDateTime =
             (*message
@@ -232,7 +236,7 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
             .try_into()?;
 
         if let Some(run) = self.run_cache.find_run_containing(&timestamp) {
-            run.push_frame_event_list(&self.nexus_settings, message)?;
+            run.push_frame_event_list(&self.nexus_settings, message, pulse_shapes, correction)?;
         }
         Ok(())
     }
@@ -336,6 +340,8 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
                 if let Err(e) = run.end_span() {
                     warn!("Run span drop failed {e}")
                 }
+                // Deferred logs are written before the file is moved, as the file must be reopened to write them.
+                let run = run.write_deferred_logs(&self.nexus_settings)?;
                 run.move_to_completed(
                     self.nexus_settings.get_local_path(),
                     self.nexus_settings.get_local_completed_path(),
@@ -357,7 +363,7 @@ impl<D: NexusEngineDependencies> NexusEngine<D> {
 
     pub(crate) fn close_all(self) -> NexusWriterResult<()> {
         for run in self.run_cache.into_iter() {
-            run.close()?;
+            run.write_deferred_logs(&self.nexus_settings)?.close()?;
         }
         Ok(())
     }
@@ -380,6 +386,7 @@ mod test {
         ecs_6s4t_run_stop_generated::{
             RunStop, RunStopArgs, finish_run_stop_buffer, root_as_run_stop,
         },
+        ecs_df12_det_spec_map_generated::{SpectraDetectorMapping, SpectraDetectorMappingArgs},
         ecs_pl72_run_start_generated::{
             RunStart, RunStartArgs, finish_run_start_buffer, root_as_run_start,
         },
@@ -550,7 +557,9 @@ mod test {
 
         fbb.reset();
         let message = create_frame_assembled_message(&mut fbb, &ts).unwrap();
-        nexus.push_frame_event_list(message).unwrap();
+        nexus
+            .push_frame_event_list(message, Default::default(), false)
+            .unwrap();
 
         let mut fbb = FlatBufferBuilder::new(); //  Need to create a new instance as we use m1 later
         let stop = create_stop(&mut fbb, "Test1", ts_end.timestamp_millis() as u64).unwrap();
@@ -604,4 +613,80 @@ mod test {
         let _ = nexus.flush(&Duration::zero());
         assert_eq!(nexus.cache_iter().len(), 0);
     }
+
+    #[test]
+    fn detector_spectrum_map() {
+        let mut nexus = NexusEngine::<MockDependencies>::new(
+            NexusSettings::default(),
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        let mut fbb = FlatBufferBuilder::new();
+        let spectrum = Some(fbb.create_vector(&[1, 2, 3]));
+        let detector_id = Some(fbb.create_vector(&[101, 102, 103]));
+        let map = SpectraDetectorMapping::create(
+            &mut fbb,
+            &SpectraDetectorMappingArgs {
+                spectrum,
+                detector_id,
+                n_spectra: 3,
+            },
+        );
+        let args = RunStartArgs {
+            start_time: 16,
+            run_name: Some(fbb.create_string("Test1")),
+            filename: Some(fbb.create_string("Test1")),
+            detector_spectrum_map: Some(map),
+            ..Default::default()
+        };
+        let message = RunStart::create(&mut fbb, &args);
+        finish_run_start_buffer(&mut fbb, message);
+        let start = root_as_run_start(fbb.finished_data()).unwrap();
+        nexus.push_run_start(start).unwrap();
+
+        let parameters = nexus.run_cache.front().unwrap().parameters();
+        assert_eq!(
+            parameters.unmapped_channels([2, 5, 3, 7, 5].into_iter()),
+            [5, 7]
+        );
+    }
+
+    #[test]
+    fn mismatched_detector_spectrum_map_is_ignored() {
+        let mut nexus = NexusEngine::<MockDependencies>::new(
+            NexusSettings::default(),
+            NexusConfiguration::new(None),
+            NoKafka,
+        );
+        let mut fbb = FlatBufferBuilder::new();
+        let spectrum = Some(fbb.create_vector(&[1, 2, 3]));
+        let detector_id = Some(fbb.create_vector(&[101, 102]));
+        let map = SpectraDetectorMapping::create(
+            &mut fbb,
+            &SpectraDetectorMappingArgs {
+                spectrum,
+                detector_id,
+                n_spectra: 3,
+            },
+        );
+        let args = RunStartArgs {
+            start_time: 16,
+            run_name: Some(fbb.create_string("Test1")),
+            filename: Some(fbb.create_string("Test1")),
+            detector_spectrum_map: Some(map),
+            ..Default::default()
+        };
+        let message = RunStart::create(&mut fbb, &args);
+        finish_run_start_buffer(&mut fbb, message);
+        let start = root_as_run_start(fbb.finished_data()).unwrap();
+        nexus.push_run_start(start).unwrap();
+
+        let parameters = nexus.run_cache.front().unwrap().parameters();
+        assert!(parameters.mapped_channels.is_none());
+        assert!(
+            parameters
+                .unmapped_channels([2, 5, 3, 7, 5].into_iter())
+                .is_empty()
+        );
+    }
 }
@@ -6,10 +6,13 @@ mod settings;
 
 use chrono::{DateTime, Utc};
 pub(crate) use engine::{NexusEngine, NexusEngineDependencies};
-pub(crate) use run::{NexusConfiguration, Run, RunParameters, RunStopParameters};
+pub(crate) use run::{
+    NexusConfiguration, Run, RunMetadata, RunMetadataError, RunParameters, RunStopParameters,
+    detector_spectrum_map, mapped_channels,
+};
 pub(crate) use settings::{
-    AlarmChunkSize, ChunkSizeSettings, EventChunkSize, FrameChunkSize, NexusSettings,
-    PeriodChunkSize,
+    AlarmChunkSize, CompressionOptions, CompressionSettings, DatasetSettings, EventChunkSize,
+    NexusSettings, PeriodChunkSize,
 };
 
 /// UTC-timezoned DateTime type to reduce boiler plate.
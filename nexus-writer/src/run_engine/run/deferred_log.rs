@@ -0,0 +1,93 @@
+//! Stores log messages whose groups could not be created during SWMR writing, so they can be written once it has ended.
+use crate::{
+    error::NexusWriterResult,
+    nexus::NexusFileInterface,
+    run_engine::{
+        DatasetSettings, NexusDateTime,
+        run_messages::{PushAlarm, PushRunLog, PushSampleEnvironmentLog, SampleEnvironmentLog},
+    },
+};
+use supermusr_streaming_types::{
+    ecs_al00_alarm_generated::{Alarm, root_as_alarm},
+    ecs_f144_logdata_generated::{f144_LogData, root_as_f_144_log_data},
+    ecs_se00_data_generated::root_as_se_00_sample_environment_data,
+};
+
+/// A log message whose group could not be created during SWMR writing.
+/// As the message borrows the buffer it was received in, the buffer is copied.
+pub(crate) enum DeferredLog {
+    /// The buffer of an `f144` run log.
+    RunLog(Vec<u8>),
+    /// The buffer of an `f144` sample environment log.
+    SampleEnvironmentLogData(Vec<u8>),
+    /// The buffer of an `se00` sample environment log.
+    SampleEnvironmentData(Vec<u8>),
+    /// The buffer of an `al00` alarm.
+    Alarm(Vec<u8>),
+}
+
+impl DeferredLog {
+    /// Copies the buffer of a run log.
+    pub(crate) fn run_log(logdata: &f144_LogData) -> Self {
+        Self::RunLog(logdata._tab.buf().to_vec())
+    }
+
+    /// Copies the buffer of a sample environment log.
+    pub(crate) fn sample_environment_log(selog: &SampleEnvironmentLog) -> Self {
+        match selog {
+            SampleEnvironmentLog::LogData(logdata) => {
+                Self::SampleEnvironmentLogData(logdata._tab.buf().to_vec())
+            }
+            SampleEnvironmentLog::SampleEnvironmentData(data) => {
+                Self::SampleEnvironmentData(data._tab.buf().to_vec())
+            }
+        }
+    }
+
+    /// Copies the buffer of an alarm.
+    pub(crate) fn alarm(alarm: &Alarm) -> Self {
+        Self::Alarm(alarm._tab.buf().to_vec())
+    }
+
+    /// Parses the buffer, and pushes the message to the file.
+    /// # Parameters
+    /// - file: the file to push the message to.
+    /// - origin: the timestamp to which the log times should be relative to.
+    /// - settings: the sizes of the chunks, and the filters, to use.
+    pub(crate) fn push_to<I: NexusFileInterface>(
+        &self,
+        file: &mut I,
+        origin: &NexusDateTime,
+        settings: &DatasetSettings,
+    ) -> NexusWriterResult<()> {
+        match self {
+            Self::RunLog(buffer) => file.handle_message(&PushRunLog {
+                message: &root_as_f_144_log_data(buffer)?,
+                origin,
+                settings,
+            })?,
+            Self::SampleEnvironmentLogData(buffer) => {
+                file.handle_message(&PushSampleEnvironmentLog {
+                    message: &SampleEnvironmentLog::LogData(root_as_f_144_log_data(buffer)?),
+                    origin,
+                    settings,
+                })?
+            }
+            Self::SampleEnvironmentData(buffer) => {
+                file.handle_message(&PushSampleEnvironmentLog {
+                    message: &SampleEnvironmentLog::SampleEnvironmentData(
+                        root_as_se_00_sample_environment_data(buffer)?,
+                    ),
+                    origin,
+                    settings,
+                })?
+            }
+            Self::Alarm(buffer) => file.handle_message(&PushAlarm {
+                message: &root_as_alarm(buffer)?,
+                origin,
+                settings,
+            })?,
+        }
+        Ok(())
+    }
+}
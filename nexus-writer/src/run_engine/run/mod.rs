@@ -1,18 +1,29 @@
 //! Encapsulates a single run and provides methods for handling flatbuffer messages, intended for this run.
+mod deferred_log;
+mod run_metadata;
 mod run_parameters;
 mod run_spans;
 
 use super::{
     NexusDateTime, NexusSettings,
     run_messages::{
-        InitialiseNewNexusStructure, InternallyGeneratedLog, PushAlarm, PushFrameEventList,
-        PushInternallyGeneratedLogWarning, PushRunLog, PushRunStart, PushSampleEnvironmentLog,
-        SampleEnvironmentLog, SetEndTime, UpdatePeriodList,
+        FramePulseShapes, InitialiseNewNexusStructure, InternallyGeneratedLog, PushAlarm,
+        PushFrameEventList, PushInternallyGeneratedLogWarning, PushNexusStructure, PushRunLog,
+        PushRunMetadata, PushRunStart, PushSampleEnvironmentLog, SampleEnvironmentLog, SetEndTime,
+        UpdatePeriodList,
     },
 };
-use crate::{error::NexusWriterResult, hdf5_handlers::NexusHDF5Result, nexus::NexusFileInterface};
+use crate::{
+    error::NexusWriterResult,
+    hdf5_handlers::{NexusHDF5Error, NexusHDF5Result},
+    nexus::NexusFileInterface,
+};
 use chrono::{Duration, Utc};
-pub(crate) use run_parameters::{NexusConfiguration, RunParameters, RunStopParameters};
+use deferred_log::DeferredLog;
+pub(crate) use run_metadata::{RunMetadata, RunMetadataError};
+pub(crate) use run_parameters::{
+    NexusConfiguration, RunParameters, RunStopParameters, detector_spectrum_map, mapped_channels,
+};
 pub(crate) use run_spans::RunSpan;
 use std::{io, path::Path};
 use supermusr_common::spanned::SpanOnce;
@@ -21,7 +32,7 @@ use supermusr_streaming_types::{
     ecs_6s4t_run_stop_generated::RunStop, ecs_al00_alarm_generated::Alarm,
     ecs_f144_logdata_generated::f144_LogData, ecs_pl72_run_start_generated::RunStart,
 };
-use tracing::{error, info, info_span};
+use tracing::{debug, error, info, info_span, warn};
 
 /// Represents a single run.
 ///
@@ -37,6 +48,8 @@ pub(crate) struct Run<I: NexusFileInterface> {
     parameters: RunParameters,
     /// Must implement the [NexusFileInterface] trait, allows for the creation of and interaction with HDF5 files.
     file: I,
+    /// Log messages whose groups could not be created during SWMR writing, which are written once the run has ended.
+    deferred_logs: Vec<DeferredLog>,
 }
 
 impl<I: NexusFileInterface> Run<I> {
@@ -56,19 +69,45 @@ impl<I: NexusFileInterface> Run<I> {
             nexus_settings.get_local_path(),
             &parameters.file_name,
         );
-        let mut file = I::build_new_file(&file_path, nexus_settings.get_chunk_sizes())?;
+        let mut file = I::build_new_file(
+            &file_path,
+            nexus_settings.get_dataset_settings(),
+            nexus_settings.is_swmr_enabled(),
+        )?;
 
         file.handle_message(&InitialiseNewNexusStructure {
             parameters: &parameters,
             configuration: nexus_configuration,
         })?;
         file.handle_message(&PushRunStart(run_start))?;
+
+        let (metadata, metadata_errors) = RunMetadata::parse(run_start.metadata());
+        file.handle_message(&PushRunMetadata(&metadata))?;
+        for error in &metadata_errors {
+            file.handle_message(&PushInternallyGeneratedLogWarning {
+                message: InternallyGeneratedLog::InvalidRunMetadata { error },
+                origin: &parameters.collect_from,
+                settings: nexus_settings.get_dataset_settings(),
+            })?;
+        }
+
+        if let Some(json) = run_start.nexus_structure().filter(|json| !json.is_empty()) {
+            file.handle_message(&PushNexusStructure {
+                json,
+                settings: nexus_settings.get_dataset_settings(),
+            })?;
+        }
+        // SWMR writing starts once the group structure is built, as objects should not be created during it.
+        if nexus_settings.is_swmr_enabled() {
+            file.start_swmr_write(nexus_settings.get_dataset_settings())?;
+        }
         file.flush()?;
 
         let mut run = Self {
             span: Default::default(),
             parameters,
             file,
+            deferred_logs: Vec::new(),
         };
         run.link_run_start_span();
 
@@ -84,21 +123,28 @@ impl<I: NexusFileInterface> Run<I> {
         filename: &str,
     ) -> NexusWriterResult<Self> {
         let file_path = RunParameters::get_hdf5_filename(nexus_settings.get_local_path(), filename);
-        let mut file = I::open_from_file(&file_path)?;
+        let mut file = I::open_from_file(&file_path, nexus_settings.is_swmr_enabled())?;
         let parameters = file.extract_run_parameters()?;
         file.handle_message(&PushInternallyGeneratedLogWarning {
             message: InternallyGeneratedLog::RunResume {
                 resume_time: &Utc::now(),
             },
             origin: &parameters.collect_from,
-            settings: nexus_settings.get_chunk_sizes(),
+            settings: nexus_settings.get_dataset_settings(),
         })?;
+        // A file not created with SWMR support cannot be switched to SWMR mode, but can still be resumed.
+        if nexus_settings.is_swmr_enabled() {
+            if let Err(e) = file.start_swmr_write(nexus_settings.get_dataset_settings()) {
+                warn!("Resumed run {filename} is not written in SWMR mode: {e}");
+            }
+        }
         file.flush()?;
 
         Ok(Self {
             span: Default::default(),
             parameters,
             file,
+            deferred_logs: Vec::new(),
         })
     }
 
@@ -142,15 +188,22 @@ impl<I: NexusFileInterface> Run<I> {
     /// # Parameters
     /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
     /// - message: message to push.
+    /// - pulse_shapes: the optional pulse shape fields of the message.
+    /// - correction: if `true`, the message contains late data for a frame which has already been pushed.
     #[tracing::instrument(skip_all, level = "debug", err(level = "warn"))]
     pub(crate) fn push_frame_event_list(
         &mut self,
         nexus_settings: &NexusSettings,
         message: FrameAssembledEventListMessage,
+        pulse_shapes: FramePulseShapes,
+        correction: bool,
     ) -> NexusWriterResult<()> {
         self.link_frame_event_list_span(message);
-        self.file
-            .handle_message(&PushFrameEventList { message: &message })?;
+        self.file.handle_message(&PushFrameEventList {
+            message: &message,
+            pulse_shapes,
+            correction,
+        })?;
 
         if !self
             .parameters
@@ -165,12 +218,28 @@ impl<I: NexusFileInterface> Run<I> {
             })?;
         }
 
-        if !message.complete() {
+        // Correction frames only contain the late digitisers, so are never complete.
+        if !message.complete() && !correction {
             self.file
                 .handle_message(&PushInternallyGeneratedLogWarning {
                     message: InternallyGeneratedLog::IncompleteFrame { frame: &message },
                     origin: &self.parameters.collect_from,
-                    settings: nexus_settings.get_chunk_sizes(),
+                    settings: nexus_settings.get_dataset_settings(),
+                })?;
+        }
+
+        let unmapped_channels = self
+            .parameters
+            .unmapped_channels(message.channel().into_iter().flatten());
+        if !unmapped_channels.is_empty() {
+            self.file
+                .handle_message(&PushInternallyGeneratedLogWarning {
+                    message: InternallyGeneratedLog::UnmappedChannels {
+                        frame: &message,
+                        channels: &unmapped_channels,
+                    },
+                    origin: &self.parameters.collect_from,
+                    settings: nexus_settings.get_dataset_settings(),
                 })?;
         }
 
@@ -192,11 +261,12 @@ impl<I: NexusFileInterface> Run<I> {
     ) -> NexusWriterResult<()> {
         self.link_run_log_span();
 
-        self.file.handle_message(&PushRunLog {
+        let result = self.file.handle_message(&PushRunLog {
             message: logdata,
             origin: &self.parameters.collect_from,
-            settings: nexus_settings.get_chunk_sizes(),
-        })?;
+            settings: nexus_settings.get_dataset_settings(),
+        });
+        self.defer_if_swmr(result, || DeferredLog::run_log(logdata))?;
         self.file.flush()?;
 
         self.parameters.update_last_modified();
@@ -215,11 +285,12 @@ impl<I: NexusFileInterface> Run<I> {
     ) -> NexusWriterResult<()> {
         self.link_sample_environment_log_span();
 
-        self.file.handle_message(&PushSampleEnvironmentLog {
+        let result = self.file.handle_message(&PushSampleEnvironmentLog {
             message: selog,
             origin: &self.parameters.collect_from,
-            settings: nexus_settings.get_chunk_sizes(),
-        })?;
+            settings: nexus_settings.get_dataset_settings(),
+        });
+        self.defer_if_swmr(result, || DeferredLog::sample_environment_log(selog))?;
         self.file.flush()?;
 
         self.parameters.update_last_modified();
@@ -238,17 +309,77 @@ impl<I: NexusFileInterface> Run<I> {
     ) -> NexusWriterResult<()> {
         self.link_alarm_span();
 
-        self.file.handle_message(&PushAlarm {
+        let result = self.file.handle_message(&PushAlarm {
             message: alarm,
             origin: &self.parameters.collect_from,
-            settings: nexus_settings.get_chunk_sizes(),
-        })?;
+            settings: nexus_settings.get_dataset_settings(),
+        });
+        self.defer_if_swmr(result, || DeferredLog::alarm(alarm))?;
         self.file.flush()?;
 
         self.parameters.update_last_modified();
         Ok(())
     }
 
+    /// Defers a log message if its group could not be created during SWMR writing, otherwise returns the result of pushing it.
+    /// # Parameters
+    /// - result: the result of pushing the log message.
+    /// - deferred_log: creates the deferred log message.
+    fn defer_if_swmr(
+        &mut self,
+        result: NexusHDF5Result<()>,
+        deferred_log: impl FnOnce() -> DeferredLog,
+    ) -> NexusHDF5Result<()> {
+        match result {
+            Err(NexusHDF5Error::ObjectCreationDuringSwmr { hdf5_path }) => {
+                debug!(
+                    "Deferring log until the run has ended, as its group cannot be created in {} during SWMR writing",
+                    hdf5_path.unwrap_or_default()
+                );
+                self.deferred_logs.push(deferred_log());
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// If any log messages have been deferred, ends SWMR writing and writes them.
+    /// As SWMR readers cannot see the new groups until they reopen the file, this should only be called once the run has ended.
+    /// A deferred message which cannot be written is only warned of, so the others are still written.
+    /// # Parameters
+    /// - nexus_settings: settings pertaining to local storage and hdf5 file properties.
+    pub(crate) fn write_deferred_logs(
+        self,
+        nexus_settings: &NexusSettings,
+    ) -> NexusWriterResult<Self> {
+        if self.deferred_logs.is_empty() {
+            return Ok(self);
+        }
+        let Self {
+            span,
+            parameters,
+            file,
+            deferred_logs,
+        } = self;
+        let mut file = file.end_swmr_write()?;
+        for deferred_log in &deferred_logs {
+            if let Err(e) = deferred_log.push_to(
+                &mut file,
+                &parameters.collect_from,
+                nexus_settings.get_dataset_settings(),
+            ) {
+                warn!("Failed to write deferred log: {e}");
+            }
+        }
+        file.flush()?;
+        Ok(Self {
+            span,
+            parameters,
+            file,
+            deferred_logs: Vec::new(),
+        })
+    }
+
     #[cfg(test)]
     pub(crate) fn get_name(&self) -> &str {
         &self.parameters.run_name
@@ -310,7 +441,7 @@ impl<I: NexusFileInterface> Run<I> {
                     stop_time_ms: relative_stop_time_ms,
                 },
                 origin: &self.parameters.collect_from,
-                settings: nexus_settings.get_chunk_sizes(),
+                settings: nexus_settings.get_dataset_settings(),
             })?;
         self.file.flush()?;
 
@@ -0,0 +1,423 @@
+//! Parses the `metadata` JSON string of a `RunStart` message into the values written to the NeXus file.
+//!
+//! The metadata is a JSON object, all of whose keys are optional except where stated,
+//! and whose numeric values are given in the units listed:
+//! ```json
+//! {
+//!     "run_number": 1234,                     // unsigned integer
+//!     "experiment_identifier": "RB2400001",   // string, required
+//!     "sample": {                             // required
+//!         "name": "Silver",                   // string, required
+//!         "description": "Calibration disc",  // string
+//!         "type": "calibration sample",       // string
+//!         "thickness": 1.0,                   // number, or array of numbers (mm)
+//!         "mass": 120.0,                      // number, or array of numbers (mg)
+//!         "density": 10490.0,                 // number, or array of numbers (mg/cm^3)
+//!         "temperature": 290.0,               // number (K)
+//!         "magnetic_field": 20.0,             // number (G)
+//!         "geometry": {
+//!             "description": "disc",          // string
+//!             "component_index": 0            // integer
+//!         }
+//!     },
+//!     "instrument": {
+//!         "source": {
+//!             "source_frequency": 50.0,       // number (Hz)
+//!             "source_energy": 800.0,         // number (MeV)
+//!             "source_current": 200.0,        // number (uA)
+//!             "source_pulse_width": 100.0,    // number (ns)
+//!             "target_material": "Carbon",    // string
+//!             "target_thickness": 10.0,       // number (mm)
+//!             "pion_momentum": 29.8,          // number (MeV/c)
+//!             "muon_energy": 4000000.0,       // number (eV)
+//!             "muon_momentum": 28.0,          // number (MeV/c)
+//!             "muon_pulse_width": 70.0,       // number (ns)
+//!             "muon_pulse_separation": 320.0, // number (ns)
+//!             "notes": "..."                  // string
+//!         }
+//!     }
+//! }
+//! ```
+//! Each missing required key, key of the wrong type, or unrecognised key, is recorded as a [RunMetadataError],
+//! and the remaining keys are still used.
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+/// Describes a problem with a single key of the metadata, or with the metadata as a whole.
+#[derive(Debug, Error)]
+pub(crate) enum RunMetadataError {
+    #[error("RunStart message has no metadata")]
+    Missing,
+    #[error("Metadata is not valid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("Metadata key {0} is missing")]
+    MissingKey(String),
+    #[error("Metadata key {key} should be {expected}")]
+    InvalidKey { key: String, expected: &'static str },
+    #[error("Metadata key {0} is not recognised")]
+    UnknownKey(String),
+}
+
+/// Keys of the metadata.
+mod keys {
+    pub(super) const RUN_NUMBER: &str = "run_number";
+    pub(super) const EXPERIMENT_IDENTIFIER: &str = "experiment_identifier";
+    pub(super) const SAMPLE: &str = "sample";
+    pub(super) const NAME: &str = "name";
+    pub(super) const DESCRIPTION: &str = "description";
+    pub(super) const SAMPLE_TYPE: &str = "type";
+    pub(super) const THICKNESS: &str = "thickness";
+    pub(super) const MASS: &str = "mass";
+    pub(super) const DENSITY: &str = "density";
+    pub(super) const TEMPERATURE: &str = "temperature";
+    pub(super) const MAGNETIC_FIELD: &str = "magnetic_field";
+    pub(super) const GEOMETRY: &str = "geometry";
+    pub(super) const COMPONENT_INDEX: &str = "component_index";
+    pub(super) const INSTRUMENT: &str = "instrument";
+    pub(super) const SOURCE: &str = "source";
+    pub(super) const SOURCE_FREQUENCY: &str = "source_frequency";
+    pub(super) const SOURCE_ENERGY: &str = "source_energy";
+    pub(super) const SOURCE_CURRENT: &str = "source_current";
+    pub(super) const SOURCE_PULSE_WIDTH: &str = "source_pulse_width";
+    pub(super) const TARGET_MATERIAL: &str = "target_material";
+    pub(super) const TARGET_THICKNESS: &str = "target_thickness";
+    pub(super) const PION_MOMENTUM: &str = "pion_momentum";
+    pub(super) const MUON_ENERGY: &str = "muon_energy";
+    pub(super) const MUON_MOMENTUM: &str = "muon_momentum";
+    pub(super) const MUON_PULSE_WIDTH: &str = "muon_pulse_width";
+    pub(super) const MUON_PULSE_SEPARATION: &str = "muon_pulse_separation";
+    pub(super) const NOTES: &str = "notes";
+}
+
+/// Values of the `sample/geometry` group.
+#[derive(Default, Debug)]
+pub(crate) struct GeometryMetadata {
+    pub(crate) description: Option<String>,
+    pub(crate) component_index: Option<i32>,
+}
+
+/// Values of the `sample` group.
+#[derive(Default, Debug)]
+pub(crate) struct SampleMetadata {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) sample_type: Option<String>,
+    /// Thickness (mm), which may have more than one value.
+    pub(crate) thickness: Option<Vec<f32>>,
+    /// Mass (mg), which may have more than one value.
+    pub(crate) mass: Option<Vec<f32>>,
+    /// Density (mg/cm^3), which may have more than one value.
+    pub(crate) density: Option<Vec<f32>>,
+    /// Temperature (K).
+    pub(crate) temperature: Option<f32>,
+    /// Magnetic field (G).
+    pub(crate) magnetic_field: Option<f32>,
+    pub(crate) geometry: GeometryMetadata,
+}
+
+/// Values of the `instrument/source` group.
+#[derive(Default, Debug)]
+pub(crate) struct SourceMetadata {
+    /// Source frequency (Hz).
+    pub(crate) source_frequency: Option<f32>,
+    /// Source energy (MeV).
+    pub(crate) source_energy: Option<f32>,
+    /// Source current (uA).
+    pub(crate) source_current: Option<f32>,
+    /// Source pulse width (ns).
+    pub(crate) source_pulse_width: Option<f32>,
+    pub(crate) target_material: Option<String>,
+    /// Target thickness (mm).
+    pub(crate) target_thickness: Option<f32>,
+    /// Pion momentum (MeV/c).
+    pub(crate) pion_momentum: Option<f32>,
+    /// Muon energy (eV).
+    pub(crate) muon_energy: Option<f32>,
+    /// Muon momentum (MeV/c).
+    pub(crate) muon_momentum: Option<f32>,
+    /// Muon pulse width (ns).
+    pub(crate) muon_pulse_width: Option<f32>,
+    /// Muon pulse separation (ns).
+    pub(crate) muon_pulse_separation: Option<f32>,
+    pub(crate) notes: Option<String>,
+}
+
+/// The values given by the `metadata` of a `RunStart` message.
+#[derive(Default, Debug)]
+pub(crate) struct RunMetadata {
+    pub(crate) run_number: Option<u32>,
+    pub(crate) experiment_identifier: Option<String>,
+    pub(crate) sample: SampleMetadata,
+    pub(crate) source: SourceMetadata,
+}
+
+impl RunMetadata {
+    /// Parses the metadata, keeping all valid values.
+    /// # Parameters
+    /// - metadata: the `metadata` string of the `RunStart` message, if present.
+    /// # Return
+    /// The valid values, and an error for each problem found.
+    pub(crate) fn parse(metadata: Option<&str>) -> (Self, Vec<RunMetadataError>) {
+        let mut errors = Vec::new();
+        let value = match metadata.map(serde_json::from_str::<Value>) {
+            Some(Ok(value)) => value,
+            Some(Err(e)) => return (Self::default(), vec![e.into()]),
+            None => return (Self::default(), vec![RunMetadataError::Missing]),
+        };
+        let Some(mut root) = Fields::new(String::new(), value, &mut errors) else {
+            return (Self::default(), errors);
+        };
+
+        let metadata = Self {
+            run_number: root.integer(keys::RUN_NUMBER, &mut errors),
+            experiment_identifier: root.string(keys::EXPERIMENT_IDENTIFIER, &mut errors),
+            sample: Self::parse_sample(&mut root, &mut errors),
+            source: Self::parse_source(&mut root, &mut errors),
+        };
+        if metadata.experiment_identifier.is_none() {
+            errors.push(RunMetadataError::MissingKey(
+                keys::EXPERIMENT_IDENTIFIER.to_owned(),
+            ));
+        }
+        root.finish(&mut errors);
+        (metadata, errors)
+    }
+
+    fn parse_sample(root: &mut Fields, errors: &mut Vec<RunMetadataError>) -> SampleMetadata {
+        let Some(mut sample) = root.object(keys::SAMPLE, errors) else {
+            errors.push(RunMetadataError::MissingKey(keys::SAMPLE.to_owned()));
+            return Default::default();
+        };
+        let geometry = match sample.object(keys::GEOMETRY, errors) {
+            Some(mut geometry) => {
+                let values = GeometryMetadata {
+                    description: geometry.string(keys::DESCRIPTION, errors),
+                    component_index: geometry.integer(keys::COMPONENT_INDEX, errors),
+                };
+                geometry.finish(errors);
+                values
+            }
+            None => Default::default(),
+        };
+        let values = SampleMetadata {
+            name: sample.string(keys::NAME, errors),
+            description: sample.string(keys::DESCRIPTION, errors),
+            sample_type: sample.string(keys::SAMPLE_TYPE, errors),
+            thickness: sample.numbers(keys::THICKNESS, errors),
+            mass: sample.numbers(keys::MASS, errors),
+            density: sample.numbers(keys::DENSITY, errors),
+            temperature: sample.number(keys::TEMPERATURE, errors),
+            magnetic_field: sample.number(keys::MAGNETIC_FIELD, errors),
+            geometry,
+        };
+        if values.name.is_none() {
+            errors.push(RunMetadataError::MissingKey(sample.key(keys::NAME)));
+        }
+        sample.finish(errors);
+        values
+    }
+
+    fn parse_source(root: &mut Fields, errors: &mut Vec<RunMetadataError>) -> SourceMetadata {
+        let Some(mut instrument) = root.object(keys::INSTRUMENT, errors) else {
+            return Default::default();
+        };
+        let values = match instrument.object(keys::SOURCE, errors) {
+            Some(mut source) => {
+                let values = SourceMetadata {
+                    source_frequency: source.number(keys::SOURCE_FREQUENCY, errors),
+                    source_energy: source.number(keys::SOURCE_ENERGY, errors),
+                    source_current: source.number(keys::SOURCE_CURRENT, errors),
+                    source_pulse_width: source.number(keys::SOURCE_PULSE_WIDTH, errors),
+                    target_material: source.string(keys::TARGET_MATERIAL, errors),
+                    target_thickness: source.number(keys::TARGET_THICKNESS, errors),
+                    pion_momentum: source.number(keys::PION_MOMENTUM, errors),
+                    muon_energy: source.number(keys::MUON_ENERGY, errors),
+                    muon_momentum: source.number(keys::MUON_MOMENTUM, errors),
+                    muon_pulse_width: source.number(keys::MUON_PULSE_WIDTH, errors),
+                    muon_pulse_separation: source.number(keys::MUON_PULSE_SEPARATION, errors),
+                    notes: source.string(keys::NOTES, errors),
+                };
+                source.finish(errors);
+                values
+            }
+            None => Default::default(),
+        };
+        instrument.finish(errors);
+        values
+    }
+}
+
+/// The fields of a JSON object of the metadata which have not yet been read.
+struct Fields {
+    /// Path of the object, which prefixes its keys in any errors.
+    path: String,
+    fields: Map<String, Value>,
+}
+
+impl Fields {
+    /// Returns the object's fields, or [None] if the value is not an object.
+    fn new(path: String, value: Value, errors: &mut Vec<RunMetadataError>) -> Option<Self> {
+        match value {
+            Value::Object(fields) => Some(Self { path, fields }),
+            _ => {
+                errors.push(RunMetadataError::InvalidKey {
+                    key: if path.is_empty() {
+                        "metadata".to_owned()
+                    } else {
+                        path
+                    },
+                    expected: "an object",
+                });
+                None
+            }
+        }
+    }
+
+    /// Returns the full path of a key of this object.
+    fn key(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{key}", self.path)
+        }
+    }
+
+    /// Removes the key, converting its value with `f`, and recording an error if it cannot be converted.
+    fn take<T>(
+        &mut self,
+        key: &str,
+        expected: &'static str,
+        errors: &mut Vec<RunMetadataError>,
+        f: impl FnOnce(Value) -> Option<T>,
+    ) -> Option<T> {
+        let value = f(self.fields.remove(key)?);
+        if value.is_none() {
+            errors.push(RunMetadataError::InvalidKey {
+                key: self.key(key),
+                expected,
+            });
+        }
+        value
+    }
+
+    fn string(&mut self, key: &str, errors: &mut Vec<RunMetadataError>) -> Option<String> {
+        self.take(key, "a string", errors, |value| match value {
+            Value::String(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    fn number(&mut self, key: &str, errors: &mut Vec<RunMetadataError>) -> Option<f32> {
+        self.take(key, "a number", errors, |value| {
+            value.as_f64().map(|value| value as f32)
+        })
+    }
+
+    fn numbers(&mut self, key: &str, errors: &mut Vec<RunMetadataError>) -> Option<Vec<f32>> {
+        self.take(
+            key,
+            "a number or array of numbers",
+            errors,
+            |value| match value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| value.as_f64().map(|value| value as f32))
+                    .collect(),
+                value => value.as_f64().map(|value| vec![value as f32]),
+            },
+        )
+    }
+
+    fn integer<T: TryFrom<i64>>(
+        &mut self,
+        key: &str,
+        errors: &mut Vec<RunMetadataError>,
+    ) -> Option<T> {
+        self.take(key, "an integer in range", errors, |value| {
+            value.as_i64().and_then(|value| T::try_from(value).ok())
+        })
+    }
+
+    fn object(&mut self, key: &str, errors: &mut Vec<RunMetadataError>) -> Option<Fields> {
+        let value = self.fields.remove(key)?;
+        Self::new(self.key(key), value, errors)
+    }
+
+    /// Records an error for each key which has not been read.
+    fn finish(self, errors: &mut Vec<RunMetadataError>) {
+        for key in self.fields.keys() {
+            errors.push(RunMetadataError::UnknownKey(self.key(key)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_metadata() {
+        let (metadata, errors) = RunMetadata::parse(Some(
+            r#"{
+                "run_number": 1234,
+                "experiment_identifier": "RB2400001",
+                "sample": {
+                    "name": "Silver",
+                    "thickness": [1.0, 2.0],
+                    "temperature": 290,
+                    "geometry": {"component_index": 3}
+                },
+                "instrument": {"source": {"muon_momentum": 28.0, "target_material": "Carbon"}}
+            }"#,
+        ));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(metadata.run_number, Some(1234));
+        assert_eq!(metadata.experiment_identifier.as_deref(), Some("RB2400001"));
+        assert_eq!(metadata.sample.name.as_deref(), Some("Silver"));
+        assert_eq!(metadata.sample.thickness, Some(vec![1.0, 2.0]));
+        assert_eq!(metadata.sample.temperature, Some(290.0));
+        assert_eq!(metadata.sample.geometry.component_index, Some(3));
+        assert_eq!(metadata.source.muon_momentum, Some(28.0));
+        assert_eq!(metadata.source.target_material.as_deref(), Some("Carbon"));
+    }
+
+    #[test]
+    fn invalid_keys_are_reported_and_skipped() {
+        let (metadata, errors) = RunMetadata::parse(Some(
+            r#"{
+                "run_number": -1,
+                "sample": {"temperature": "hot", "mass": 5, "colour": "grey"}
+            }"#,
+        ));
+        assert_eq!(metadata.run_number, None);
+        assert_eq!(metadata.sample.temperature, None);
+        assert_eq!(metadata.sample.mass, Some(vec![5.0]));
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "Metadata key run_number should be an integer in range",
+                "Metadata key sample.temperature should be a number",
+                "Metadata key sample.name is missing",
+                "Metadata key sample.colour is not recognised",
+                "Metadata key experiment_identifier is missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_or_invalid_metadata() {
+        assert!(matches!(
+            RunMetadata::parse(None).1.as_slice(),
+            [RunMetadataError::Missing]
+        ));
+        assert!(matches!(
+            RunMetadata::parse(Some("{")).1.as_slice(),
+            [RunMetadataError::InvalidJson(_)]
+        ));
+        assert!(matches!(
+            RunMetadata::parse(Some("[]")).1.as_slice(),
+            [RunMetadataError::InvalidKey { .. }]
+        ));
+    }
+}
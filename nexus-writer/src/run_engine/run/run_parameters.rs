@@ -4,7 +4,11 @@ use crate::{
     run_engine::NexusDateTime,
 };
 use chrono::Utc;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use supermusr_common::Channel;
 use supermusr_streaming_types::{
     ecs_6s4t_run_stop_generated::RunStop, ecs_pl72_run_start_generated::RunStart,
 };
@@ -48,6 +52,9 @@ pub(crate) struct RunParameters {
     pub(crate) periods: Vec<u64>,
     /// Filename for the run
     pub(crate) file_name: String,
+    /// Channels which are mapped to a detector by the `RunStart` message's detector-spectrum map,
+    /// or [None] if the message had no map, in which case no channel is checked.
+    pub(crate) mapped_channels: Option<HashSet<Channel>>,
 }
 
 impl RunParameters {
@@ -82,6 +89,8 @@ impl RunParameters {
             run_name,
             periods: Default::default(),
             file_name,
+            mapped_channels: detector_spectrum_map(&data)
+                .map(|(spectra, _)| mapped_channels(spectra.into_iter())),
         })
     }
 
@@ -182,6 +191,25 @@ impl RunParameters {
         }
     }
 
+    /// Returns the channels in `channels` which are not mapped to a detector, in increasing order.
+    /// If the run has no detector-spectrum map, no channels are returned.
+    /// # Parameters
+    /// - channels: the channels of each event in a frame.
+    pub(crate) fn unmapped_channels(
+        &self,
+        channels: impl Iterator<Item = Channel>,
+    ) -> Vec<Channel> {
+        let Some(mapped_channels) = &self.mapped_channels else {
+            return Vec::new();
+        };
+        let mut unmapped = channels
+            .filter(|channel| !mapped_channels.contains(channel))
+            .collect::<Vec<_>>();
+        unmapped.sort_unstable();
+        unmapped.dedup();
+        unmapped
+    }
+
     /// Constructs the file path from a directory and string for the run name.
     pub(crate) fn get_hdf5_filename(path: &Path, file_name: &str) -> PathBuf {
         let mut path = path.to_owned();
@@ -190,3 +218,36 @@ impl RunParameters {
         path
     }
 }
+
+/// Returns the spectrum numbers and detector ids of the `RunStart` message's detector-spectrum map.
+/// A map whose fields differ in length does not describe which detector each spectrum belongs to,
+/// so is treated as absent.
+/// # Parameters
+/// - run_start: the message whose map is returned.
+/// # Return
+/// The spectrum numbers and detector ids, or [None] if the message has no valid map.
+pub(crate) fn detector_spectrum_map(run_start: &RunStart<'_>) -> Option<(Vec<i32>, Vec<i32>)> {
+    let map = run_start.detector_spectrum_map()?;
+    let spectra = map
+        .spectrum()
+        .unwrap_or_default()
+        .iter()
+        .collect::<Vec<_>>();
+    let detectors = map
+        .detector_id()
+        .unwrap_or_default()
+        .iter()
+        .collect::<Vec<_>>();
+    (spectra.len() == detectors.len()).then_some((spectra, detectors))
+}
+
+/// Collects the spectrum numbers of a detector-spectrum map into the set of mapped channels,
+/// as events are recorded against the spectrum number of their channel.
+/// Negative spectrum numbers cannot be channels, so are ignored.
+/// # Parameters
+/// - spectra: the spectrum number of each detector.
+pub(crate) fn mapped_channels(spectra: impl Iterator<Item = i32>) -> HashSet<Channel> {
+    spectra
+        .filter_map(|spectrum| Channel::try_from(spectrum).ok())
+        .collect()
+}
@@ -2,13 +2,19 @@
 //!
 //! Given a message type `M` and a type `T` implementing `NexusHandleMessage<M>`, we pass
 //! the message to an instance of `T` via `T::handle_message(m)` where `m : M`.
-use super::{ChunkSizeSettings, NexusConfiguration, NexusDateTime, RunParameters};
+use super::{
+    DatasetSettings, NexusConfiguration, NexusDateTime, RunMetadata, RunMetadataError,
+    RunParameters,
+};
 use crate::nexus::NexusMessageHandler;
+use hdf5::types::TypeDescriptor;
 use std::ops::Deref;
+use supermusr_common::Channel;
 use supermusr_streaming_types::{
     aev2_frame_assembled_event_v2_generated::FrameAssembledEventListMessage,
-    ecs_al00_alarm_generated::Alarm, ecs_f144_logdata_generated::f144_LogData,
-    ecs_pl72_run_start_generated::RunStart, ecs_se00_data_generated::se00_SampleEnvironmentData,
+    aev3_frame_assembled_event_v3_generated as aev3, ecs_al00_alarm_generated::Alarm,
+    ecs_f144_logdata_generated::f144_LogData, ecs_pl72_run_start_generated::RunStart,
+    ecs_se00_data_generated::se00_SampleEnvironmentData, flatbuffers::Vector,
 };
 
 /// As Sample Environment Logs can be delivered via both f144 or se00 type messages,
@@ -19,6 +25,28 @@ pub(crate) enum SampleEnvironmentLog<'a> {
     SampleEnvironmentData(se00_SampleEnvironmentData<'a>),
 }
 
+/// The optional pulse shape fields of a frame event list, which are only present in `aev3` messages.
+/// Each field, if present, has one value per event, with NaN for events for which it was not measured.
+#[derive(Default, Clone, Copy)]
+pub(crate) struct FramePulseShapes<'a> {
+    /// Time from the start to the end of each event's pulse (ns).
+    pub(crate) width: Option<Vector<'a, f32>>,
+    /// Integral of each event's pulse over its width.
+    pub(crate) area: Option<Vector<'a, f32>>,
+    /// Time from the start to the peak of each event's pulse (ns).
+    pub(crate) rise_time: Option<Vector<'a, f32>>,
+}
+
+impl<'a> From<aev3::FrameAssembledEventListMessage<'a>> for FramePulseShapes<'a> {
+    fn from(message: aev3::FrameAssembledEventListMessage<'a>) -> Self {
+        Self {
+            width: message.width(),
+            area: message.area(),
+            rise_time: message.rise_time(),
+        }
+    }
+}
+
 /// Initialises the fields which are initialised by [RunParameters] or [NexusConfiguration]
 pub(crate) struct InitialiseNewNexusStructure<'a> {
     /// The parameters to initialise with.
@@ -41,6 +69,57 @@ pub(crate) struct InitialiseNewNexusRun<'a> {
 /// [nexus_structure]: crate::nexus_structure
 pub(crate) struct PushRunStart<'a>(pub(crate) RunStart<'a>);
 
+/// Tells [nexus_structure] to set the fields given by the `metadata` of a [RunStart] message.
+///
+/// [nexus_structure]: crate::nexus_structure
+pub(crate) struct PushRunMetadata<'a>(pub(crate) &'a RunMetadata);
+
+/// Tells [nexus_structure] to materialise the `nexus_structure` JSON template of a [RunStart] message.
+/// This is applied after [PushRunStart], so values in the template take precedence.
+///
+/// [nexus_structure]: crate::nexus_structure
+pub(crate) struct PushNexusStructure<'a> {
+    /// The `nexus_structure` JSON template.
+    pub(crate) json: &'a str,
+    /// The sizes of the chunks, and the filters, with which to create the logs of the template's streams.
+    pub(crate) settings: &'a DatasetSettings,
+}
+
+/// A log to which a stream of the `nexus_structure` template is bound.
+#[derive(Debug, PartialEq)]
+pub(crate) enum StreamLog {
+    /// The run log of an `f144` stream.
+    RunLog {
+        /// The name of the log.
+        name: String,
+        /// The hdf5 data type of the log's values.
+        type_descriptor: TypeDescriptor,
+    },
+    /// The sample environment log of an `se00` stream.
+    SampleEnvironmentLog {
+        /// The name of the log.
+        name: String,
+        /// The hdf5 data type of the log's values.
+        type_descriptor: TypeDescriptor,
+    },
+    /// The alarm log of an `al00` stream.
+    Alarm {
+        /// The name of the log.
+        name: String,
+    },
+}
+
+/// Tells [nexus_structure] to create the log to which a stream of the `nexus_structure` template is bound, if it does not exist.
+/// These are created before the template is applied, so they need not be created during SWMR writing.
+///
+/// [nexus_structure]: crate::nexus_structure
+pub(crate) struct CreateStreamLog<'a> {
+    /// The log to create.
+    pub(crate) log: &'a StreamLog,
+    /// The sizes of the chunks, and the filters, to use.
+    pub(crate) settings: &'a DatasetSettings,
+}
+
 /// Tells [nexus_structure] to input values from a new [FrameAssembledEventListMessage].
 /// Note this does not handle values in the `Period` hdf5 group.
 ///
@@ -48,6 +127,10 @@ pub(crate) struct PushRunStart<'a>(pub(crate) RunStart<'a>);
 pub(crate) struct PushFrameEventList<'a> {
     /// The frame event list message to push.
     pub(crate) message: &'a FrameAssembledEventListMessage<'a>,
+    /// The pulse shape fields of the message, if it is an `aev3` message.
+    pub(crate) pulse_shapes: FramePulseShapes<'a>,
+    /// If `true`, the message is a correction, containing late data for a frame which has already been pushed.
+    pub(crate) correction: bool,
 }
 
 /// Tells [nexus_structure] to update the periods list in the `Periods` hdf5 group.
@@ -66,8 +149,8 @@ pub(crate) struct PushLog<'a, T> {
     pub(crate) message: T,
     /// The timestamp to which the log times should be relative to.
     pub(crate) origin: &'a NexusDateTime,
-    /// The sizes of the chunks to use.
-    pub(crate) settings: &'a ChunkSizeSettings,
+    /// The sizes of the chunks, and the filters, to use.
+    pub(crate) settings: &'a DatasetSettings,
 }
 
 impl<T> Deref for PushLog<'_, T> {
@@ -105,6 +188,18 @@ pub(crate) enum InternallyGeneratedLog<'a> {
         /// The frame event list that is incomplete.
         frame: &'a FrameAssembledEventListMessage<'a>,
     },
+    /// When events of a frame arrive on channels which are not in the detector-spectrum map.
+    UnmappedChannels {
+        /// The frame event list containing the events.
+        frame: &'a FrameAssembledEventListMessage<'a>,
+        /// The unmapped channels, in increasing order.
+        channels: &'a [Channel],
+    },
+    /// When the `metadata` of the `RunStart` message is missing or has an invalid key.
+    InvalidRunMetadata {
+        /// The problem with the metadata.
+        error: &'a RunMetadataError,
+    },
     /// When an run should be aborted.
     AbortRun {
         /// The ms since epoch to record as the stop time.
@@ -125,6 +220,20 @@ pub(crate) struct SetEndTime<'a> {
     pub(crate) end_time: &'a NexusDateTime,
 }
 
+/// Tells [nexus_structure] that the file is about to be switched to single-writer/multiple-reader (SWMR) writing mode.
+///
+/// As HDF5 does not support the creation of objects during SWMR writing, the objects written by the writer itself,
+/// such as its internal logs and the pulse shape datasets, are first created if they do not exist.
+/// From then on, datasets are flushed to SWMR readers as they are appended to,
+/// and a handler which would create an object instead returns [NexusHDF5Error::ObjectCreationDuringSwmr], before anything is written.
+///
+/// [nexus_structure]: crate::nexus_structure
+/// [NexusHDF5Error::ObjectCreationDuringSwmr]: crate::hdf5_handlers::NexusHDF5Error::ObjectCreationDuringSwmr
+pub(crate) struct StartSwmrWrite<'a> {
+    /// The sizes of the chunks, and the filters, with which to create the objects.
+    pub(crate) settings: &'a DatasetSettings,
+}
+
 /// Ensures anything implementing [NexusFileInterface] must implement the correct [NexusMessageHandler]s.
 /// Any new message that is added to this module should be added here.
 ///
@@ -135,9 +244,13 @@ pub(crate) trait HandlesAllNexusMessages:
     + for<'a> NexusMessageHandler<UpdatePeriodList<'a>>
     + for<'a> NexusMessageHandler<PushRunLog<'a>>
     + for<'a> NexusMessageHandler<PushRunStart<'a>>
+    + for<'a> NexusMessageHandler<PushRunMetadata<'a>>
+    + for<'a> NexusMessageHandler<PushNexusStructure<'a>>
+    + for<'a> NexusMessageHandler<CreateStreamLog<'a>>
     + for<'a> NexusMessageHandler<PushSampleEnvironmentLog<'a>>
     + for<'a> NexusMessageHandler<PushInternallyGeneratedLogWarning<'a>>
     + for<'a> NexusMessageHandler<PushAlarm<'a>>
     + for<'a> NexusMessageHandler<SetEndTime<'a>>
+    + for<'a> NexusMessageHandler<StartSwmrWrite<'a>>
 {
 }
@@ -1,6 +1,11 @@
 //! This module defines types used to configure `NexusEngine`
 //! and the modules of `nexus_structure`.
+use clap::{Args, ValueEnum};
+use hdf5::filters::Filter;
+#[cfg(feature = "hdf5-plugins")]
+use hdf5::filters::{Blosc, BloscShuffle};
 use std::path::{Path, PathBuf};
+use thiserror::Error;
 use tokio::time::Interval;
 
 /// Creates the glob patterns for matching all NeXus files in a directory.
@@ -65,6 +70,171 @@ impl ChunkSizeSettings {
     }
 }
 
+/// The compression filter applied to the chunks of a dataset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CompressionFilter {
+    /// Chunks are not compressed.
+    #[default]
+    None,
+    /// Chunks are compressed with the gzip algorithm, which is available to all HDF5 readers.
+    Deflate,
+    /// Chunks are compressed with the fast LZF algorithm. Requires the `hdf5-plugins` feature, and readers need the LZF plugin.
+    Lzf,
+    /// Chunks are compressed with Blosc, using the zstd codec. Requires the `hdf5-plugins` feature, and readers need the Blosc plugin.
+    BloscZstd,
+}
+
+/// Errors arising from the requested compression settings.
+#[derive(Debug, Error)]
+pub(crate) enum CompressionError {
+    #[cfg_attr(feature = "hdf5-plugins", allow(dead_code))]
+    #[error("{0:?} compression requires nexus-writer to be built with the `hdf5-plugins` feature")]
+    PluginUnavailable(CompressionFilter),
+}
+
+/// Builds the hdf5 filter pipeline for one class of datasets.
+/// # Parameters
+/// - filter: the compression filter to use.
+/// - level: the compression level, ignored by [CompressionFilter::Lzf].
+/// - shuffle: if true, the bytes of each value are shuffled before compression, which is ignored if no compression is used.
+fn filter_pipeline(
+    filter: CompressionFilter,
+    level: u8,
+    shuffle: bool,
+) -> Result<Vec<Filter>, CompressionError> {
+    let shuffle = shuffle.then_some(Filter::Shuffle);
+    Ok(match filter {
+        CompressionFilter::None => Vec::new(),
+        CompressionFilter::Deflate => shuffle
+            .into_iter()
+            .chain([Filter::Deflate(level)])
+            .collect(),
+        #[cfg(feature = "hdf5-plugins")]
+        CompressionFilter::Lzf => shuffle.into_iter().chain([Filter::LZF]).collect(),
+        // Blosc applies its own shuffle.
+        #[cfg(feature = "hdf5-plugins")]
+        CompressionFilter::BloscZstd => vec![Filter::Blosc(
+            Blosc::ZStd,
+            level,
+            if shuffle.is_some() {
+                BloscShuffle::Byte
+            } else {
+                BloscShuffle::None
+            },
+        )],
+        #[cfg(not(feature = "hdf5-plugins"))]
+        CompressionFilter::Lzf | CompressionFilter::BloscZstd => {
+            return Err(CompressionError::PluginUnavailable(filter));
+        }
+    })
+}
+
+/// [clap] derived struct to handle the compression command line parameters.
+#[derive(Clone, Debug, Args)]
+pub(crate) struct CompressionOptions {
+    /// The HDF5 compression filter used when writing the event list
+    #[clap(long, default_value = "none")]
+    event_list_compression: CompressionFilter,
+
+    /// The compression level (0-9) used when writing the event list
+    #[clap(long, default_value = "4", value_parser = clap::value_parser!(u8).range(0..=9))]
+    event_list_compression_level: u8,
+
+    /// If set, the bytes of each value in the event list are shuffled before compression
+    #[clap(long)]
+    event_list_shuffle: bool,
+
+    /// The HDF5 compression filter used when writing the frame list
+    #[clap(long, default_value = "none")]
+    frame_list_compression: CompressionFilter,
+
+    /// The compression level (0-9) used when writing the frame list
+    #[clap(long, default_value = "4", value_parser = clap::value_parser!(u8).range(0..=9))]
+    frame_list_compression_level: u8,
+
+    /// If set, the bytes of each value in the frame list are shuffled before compression
+    #[clap(long)]
+    frame_list_shuffle: bool,
+
+    /// The HDF5 compression filter used when writing run logs
+    #[clap(long, default_value = "none")]
+    runlog_compression: CompressionFilter,
+
+    /// The compression level (0-9) used when writing run logs
+    #[clap(long, default_value = "4", value_parser = clap::value_parser!(u8).range(0..=9))]
+    runlog_compression_level: u8,
+
+    /// If set, the bytes of each value in run logs are shuffled before compression
+    #[clap(long)]
+    runlog_shuffle: bool,
+
+    /// The HDF5 compression filter used when writing sample environment logs
+    #[clap(long, default_value = "none")]
+    selog_compression: CompressionFilter,
+
+    /// The compression level (0-9) used when writing sample environment logs
+    #[clap(long, default_value = "4", value_parser = clap::value_parser!(u8).range(0..=9))]
+    selog_compression_level: u8,
+
+    /// If set, the bytes of each value in sample environment logs are shuffled before compression
+    #[clap(long)]
+    selog_shuffle: bool,
+}
+
+/// Contains the hdf5 filters to use in constructing one-dimensional datasets.
+/// An empty pipeline means the datasets are not compressed.
+/// Alarm and period datasets are small, so are never compressed.
+#[derive(Default, Debug)]
+pub(crate) struct CompressionSettings {
+    /// Filters for fields in `EventData` which increment each frame.
+    pub(crate) frame: Vec<Filter>,
+    /// Filters for fields in `EventData` which increment each muon event.
+    pub(crate) event: Vec<Filter>,
+    /// Filters for runlog fields.
+    pub(crate) runlog: Vec<Filter>,
+    /// Filters for selog fields.
+    pub(crate) selog: Vec<Filter>,
+}
+
+impl CompressionSettings {
+    /// Creates a new [CompressionSettings] from the command line parameters.
+    /// # Error Modes
+    /// - Returns [CompressionError::PluginUnavailable] if a filter needs the `hdf5-plugins` feature, and it is not enabled.
+    pub(crate) fn new(options: &CompressionOptions) -> Result<Self, CompressionError> {
+        Ok(Self {
+            frame: filter_pipeline(
+                options.frame_list_compression,
+                options.frame_list_compression_level,
+                options.frame_list_shuffle,
+            )?,
+            event: filter_pipeline(
+                options.event_list_compression,
+                options.event_list_compression_level,
+                options.event_list_shuffle,
+            )?,
+            runlog: filter_pipeline(
+                options.runlog_compression,
+                options.runlog_compression_level,
+                options.runlog_shuffle,
+            )?,
+            selog: filter_pipeline(
+                options.selog_compression,
+                options.selog_compression_level,
+                options.selog_shuffle,
+            )?,
+        })
+    }
+}
+
+/// Contains all settings needed to construct hdf5 datasets.
+#[derive(Default, Debug)]
+pub(crate) struct DatasetSettings {
+    /// The hdf5 chunk sizes to use.
+    pub(crate) chunk_sizes: ChunkSizeSettings,
+    /// The hdf5 filters to use.
+    pub(crate) compression: CompressionSettings,
+}
+
 /// Contains all settings which persist across all runs.
 #[derive(Default, Debug)]
 pub(crate) struct NexusSettings {
@@ -72,12 +242,14 @@ pub(crate) struct NexusSettings {
     local_path: PathBuf,
     /// Path to directory which NeXus files are moved immeditately upon completion.
     local_path_completed: PathBuf,
-    /// The hdf5 chunk sizes to use.
-    chunk_sizes: ChunkSizeSettings,
+    /// The hdf5 chunk sizes and filters to use.
+    dataset_settings: DatasetSettings,
     /// Optional path to directory which completed NeXus files are moved periodically. This can be a remote directory.
     archive_path: Option<PathBuf>,
     /// Interval (in seconds) in which the NeXus files in `local_path_completed` are moved to `archive_path` (if set).
     archive_flush_interval_sec: u64,
+    /// If true, NeXus files are written in single-writer/multiple-reader (SWMR) mode.
+    swmr: bool,
 }
 
 impl NexusSettings {
@@ -86,8 +258,10 @@ impl NexusSettings {
         local_path: &Path,
         framelist_chunk_size: usize,
         eventlist_chunk_size: usize,
+        compression: CompressionSettings,
         archive_path: Option<&Path>,
         archive_flush_interval_sec: u64,
+        swmr: bool,
     ) -> Self {
         let local_path = local_path.to_path_buf();
         let mut local_path_completed = local_path.to_path_buf();
@@ -95,9 +269,13 @@ impl NexusSettings {
         Self {
             local_path,
             local_path_completed,
-            chunk_sizes: ChunkSizeSettings::new(framelist_chunk_size, eventlist_chunk_size),
+            dataset_settings: DatasetSettings {
+                chunk_sizes: ChunkSizeSettings::new(framelist_chunk_size, eventlist_chunk_size),
+                compression,
+            },
             archive_path: archive_path.map(Path::to_owned),
             archive_flush_interval_sec,
+            swmr,
         }
     }
 
@@ -139,8 +317,46 @@ impl NexusSettings {
         ))
     }
 
-    /// Returns the sizes of the hdf5 chunks to use.
-    pub(crate) fn get_chunk_sizes(&self) -> &ChunkSizeSettings {
-        &self.chunk_sizes
+    /// Returns the sizes of the hdf5 chunks, and the filters, to use.
+    pub(crate) fn get_dataset_settings(&self) -> &DatasetSettings {
+        &self.dataset_settings
+    }
+
+    /// Returns true if NeXus files should be written in single-writer/multiple-reader (SWMR) mode.
+    pub(crate) fn is_swmr_enabled(&self) -> bool {
+        self.swmr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shuffle_precedes_deflate() {
+        assert_eq!(
+            filter_pipeline(CompressionFilter::Deflate, 6, true).unwrap(),
+            [Filter::Shuffle, Filter::Deflate(6)]
+        );
+        assert_eq!(
+            filter_pipeline(CompressionFilter::Deflate, 6, false).unwrap(),
+            [Filter::Deflate(6)]
+        );
+    }
+
+    #[test]
+    fn no_compression_ignores_shuffle() {
+        assert!(
+            filter_pipeline(CompressionFilter::None, 6, true)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[cfg(not(feature = "hdf5-plugins"))]
+    #[test]
+    fn plugin_filters_need_feature() {
+        assert!(filter_pipeline(CompressionFilter::Lzf, 0, false).is_err());
+        assert!(filter_pipeline(CompressionFilter::BloscZstd, 6, true).is_err());
     }
 }
@@ -1,5 +1,6 @@
 mod integrated;
 pub(crate) mod runs;
+mod scan;
 
 use chrono::Utc;
 use clap::{Parser, Subcommand};
@@ -16,6 +17,7 @@ use runs::{
         create_runlog_command, create_sample_environment_command,
     },
 };
+use scan::{Scan, run_scan};
 use std::{
     path::PathBuf,
     time::{Duration, SystemTime},
@@ -41,8 +43,10 @@ use tracing::{debug, error, info, warn};
 
 #[derive(Clone, Parser)]
 #[clap(author, version = supermusr_common::version!(), about)]
+#[command(mut_arg("broker", |arg| arg.required(false).default_value("").hide_default_value(true)))]
 struct Cli {
     /// Kafka options common to all tools.
+    /// `--broker` is only required by modes which send messages.
     #[clap(flatten)]
     common_kafka_options: CommonKafkaOpts,
 
@@ -106,6 +110,10 @@ enum Mode {
 
     /// Send a single Alarm command
     Alarm(AlarmData),
+
+    /// Scan a grid of detector parameters over simulated traces, and report the efficiency of each parameter set.
+    /// No messages are sent to the broker.
+    Scan(Scan),
 }
 
 #[derive(Clone, Parser)]
@@ -177,7 +185,15 @@ async fn main() -> miette::Result<()> {
         cli.otel_namespace
     ));
 
+    // Scans run offline, so do not need a broker.
+    if let Mode::Scan(scan) = &cli.mode {
+        return run_scan(scan.clone()).into_diagnostic();
+    }
+
     let kafka_opts = &cli.common_kafka_options;
+    if kafka_opts.broker.is_empty() {
+        return Err(miette::miette!("--broker is required in this mode"));
+    }
 
     let client_config = supermusr_common::generate_kafka_client_config(
         &kafka_opts.broker,
@@ -211,6 +227,7 @@ async fn main() -> miette::Result<()> {
         Mode::Alarm(alarm) => create_alarm_command(tracer.use_otel(), &producer, alarm)
             .await
             .into_diagnostic()?,
+        Mode::Scan(_) => unreachable!("scans are run before the producer is created"),
     }
     Ok(())
 }
@@ -0,0 +1,105 @@
+use super::ScanError;
+use serde_json::{Map, Value};
+use trace_to_events::Mode;
+
+/// A single point of the parameter grid.
+pub(crate) struct ParameterSet {
+    /// Describes the detector mode and parameter values, for display in the report.
+    pub(crate) label: String,
+    pub(crate) mode: Mode,
+}
+
+/// Expands the parameter grid file into every combination of the parameters it specifies.
+///
+/// The file is an array of detector modes, in the same form as the `mode` field of the detector config file,
+/// except that any parameter may be given as an array, in which case each of its values is scanned.
+pub(crate) fn expand_grid(grid: &Value) -> Result<Vec<ParameterSet>, ScanError> {
+    let modes = grid.as_array().ok_or(ScanError::InvalidGrid)?;
+    let mut parameter_sets = Vec::new();
+    for mode in modes {
+        let (name, parameters) = mode
+            .as_object()
+            .filter(|mode| mode.len() == 1)
+            .and_then(|mode| mode.iter().next())
+            .ok_or(ScanError::InvalidGrid)?;
+        let parameters = parameters.as_object().ok_or(ScanError::InvalidGrid)?;
+
+        for combination in expand_parameters(parameters) {
+            let label = combination
+                .iter()
+                .map(|(parameter, value)| format!("{parameter}={value}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mode = serde_json::from_value(Value::Object(Map::from_iter([(
+                name.clone(),
+                Value::Object(combination),
+            )])))?;
+            parameter_sets.push(ParameterSet {
+                label: format!("{name} {label}"),
+                mode,
+            });
+        }
+    }
+    Ok(parameter_sets)
+}
+
+/// Returns the cartesian product of the values of each parameter.
+fn expand_parameters(parameters: &Map<String, Value>) -> Vec<Map<String, Value>> {
+    parameters
+        .iter()
+        .fold(vec![Map::new()], |combinations, (parameter, values)| {
+            let values = match values {
+                Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(|value| {
+                        let mut combination = combination.clone();
+                        combination.insert(parameter.clone(), value.clone());
+                        combination
+                    })
+                })
+                .collect()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_grid_of_two_modes() {
+        let grid: Value = serde_json::from_str(
+            r#"[
+                { "fixed-threshold-discriminator": { "threshold": [10, 20, 30], "duration": [1, 2], "cool-off": 0 } },
+                { "constant-fraction-discriminator": { "threshold": 5 } }
+            ]"#,
+        )
+        .unwrap();
+        let parameter_sets = expand_grid(&grid).unwrap();
+
+        assert_eq!(parameter_sets.len(), 7);
+        assert_eq!(
+            parameter_sets.first().unwrap().label,
+            "fixed-threshold-discriminator cool-off=0 duration=1 threshold=10"
+        );
+        assert!(matches!(
+            parameter_sets.first().unwrap().mode,
+            Mode::FixedThresholdDiscriminator(_)
+        ));
+        assert!(matches!(
+            parameter_sets.last().unwrap().mode,
+            Mode::ConstantFractionDiscriminator(_)
+        ));
+    }
+
+    #[test]
+    fn expand_invalid_grid() {
+        let grid: Value =
+            serde_json::from_str(r#"{ "fixed-threshold-discriminator": { "threshold": 10 } }"#)
+                .unwrap();
+        assert!(matches!(expand_grid(&grid), Err(ScanError::InvalidGrid)));
+    }
+}
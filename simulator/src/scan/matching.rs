@@ -0,0 +1,168 @@
+/// The time (ns) and amplitude of either a simulated pulse, or a detected event.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Pulse {
+    pub(crate) time: f64,
+    pub(crate) amplitude: f64,
+}
+
+/// Accumulates the comparison of detected events with the simulated pulses, over many traces.
+#[derive(Default)]
+pub(crate) struct Efficiency {
+    num_pulses: usize,
+    num_detected: usize,
+    num_matched: usize,
+    /// Sum of the differences between the time of each detected event and its matched pulse.
+    time_difference: f64,
+    /// Sum of the squares of the above differences.
+    time_difference_squared: f64,
+    /// Sum of the differences between the amplitude of each detected event and its matched pulse.
+    amplitude_difference: f64,
+}
+
+impl Efficiency {
+    /// Matches the events detected in a trace with the pulses simulated in it.
+    /// Both are traversed in time order, and each event is matched to the earliest unmatched pulse within `time_tolerance` of it.
+    /// # Parameters
+    /// - pulses: the simulated pulses, sorted by time.
+    /// - detected: the detected events, sorted by time.
+    /// - time_tolerance: the maximum time difference (ns) between a detected event and its matched pulse.
+    pub(crate) fn accumulate(&mut self, pulses: &[Pulse], detected: &[Pulse], time_tolerance: f64) {
+        self.num_pulses += pulses.len();
+        self.num_detected += detected.len();
+
+        let mut pulses = pulses.iter().peekable();
+        let mut detected = detected.iter().peekable();
+        while let (Some(pulse), Some(event)) = (pulses.peek(), detected.peek()) {
+            let time_difference = event.time - pulse.time;
+            if time_difference < -time_tolerance {
+                detected.next();
+            } else if time_difference > time_tolerance {
+                pulses.next();
+            } else {
+                self.num_matched += 1;
+                self.time_difference += time_difference;
+                self.time_difference_squared += time_difference * time_difference;
+                self.amplitude_difference += event.amplitude - pulse.amplitude;
+                pulses.next();
+                detected.next();
+            }
+        }
+    }
+
+    pub(crate) fn num_pulses(&self) -> usize {
+        self.num_pulses
+    }
+
+    pub(crate) fn num_detected(&self) -> usize {
+        self.num_detected
+    }
+
+    /// The proportion of simulated pulses which are matched by a detected event,
+    /// or [None] if there are no simulated pulses.
+    pub(crate) fn efficiency(&self) -> Option<f64> {
+        ratio(self.num_matched as f64, self.num_pulses)
+    }
+
+    /// The proportion of detected events which are not matched to a simulated pulse,
+    /// or [None] if no events are detected.
+    pub(crate) fn false_positive_rate(&self) -> Option<f64> {
+        ratio(
+            (self.num_detected - self.num_matched) as f64,
+            self.num_detected,
+        )
+    }
+
+    /// The mean time (ns) of detected events relative to their matched pulses,
+    /// or [None] if no events are matched.
+    pub(crate) fn time_offset(&self) -> Option<f64> {
+        ratio(self.time_difference, self.num_matched)
+    }
+
+    /// The standard deviation (ns) of the time of detected events relative to their matched pulses,
+    /// or [None] if no events are matched.
+    pub(crate) fn time_resolution(&self) -> Option<f64> {
+        let mean = self.time_offset()?;
+        let mean_squared = ratio(self.time_difference_squared, self.num_matched)?;
+        Some((mean_squared - mean * mean).max(0.0).sqrt())
+    }
+
+    /// The mean amplitude of detected events relative to their matched pulses,
+    /// or [None] if no events are matched.
+    pub(crate) fn amplitude_bias(&self) -> Option<f64> {
+        ratio(self.amplitude_difference, self.num_matched)
+    }
+}
+
+/// Divides `numerator` by `count`, or returns [None] if `count` is zero.
+fn ratio(numerator: f64, count: usize) -> Option<f64> {
+    (count != 0).then(|| numerator / count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn pulses(values: &[(f64, f64)]) -> Vec<Pulse> {
+        values
+            .iter()
+            .map(|&(time, amplitude)| Pulse { time, amplitude })
+            .collect()
+    }
+
+    #[test]
+    fn match_events_to_pulses() {
+        let mut efficiency = Efficiency::default();
+        efficiency.accumulate(
+            &pulses(&[(100.0, 10.0), (200.0, 20.0), (300.0, 30.0), (400.0, 40.0)]),
+            &pulses(&[(50.0, 5.0), (102.0, 12.0), (198.0, 18.0), (303.0, 31.0)]),
+            5.0,
+        );
+
+        assert_eq!(efficiency.num_pulses(), 4);
+        assert_eq!(efficiency.num_detected(), 4);
+        assert_approx_eq!(efficiency.efficiency().unwrap(), 0.75);
+        assert_approx_eq!(efficiency.false_positive_rate().unwrap(), 0.25);
+        assert_approx_eq!(efficiency.time_offset().unwrap(), 1.0);
+        assert_approx_eq!(
+            efficiency.time_resolution().unwrap(),
+            (17.0f64 / 3.0 - 1.0).sqrt()
+        );
+        assert_approx_eq!(efficiency.amplitude_bias().unwrap(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn each_pulse_is_matched_once() {
+        let mut efficiency = Efficiency::default();
+        efficiency.accumulate(
+            &pulses(&[(100.0, 10.0)]),
+            &pulses(&[(99.0, 10.0), (101.0, 10.0)]),
+            5.0,
+        );
+
+        assert_approx_eq!(efficiency.efficiency().unwrap(), 1.0);
+        assert_approx_eq!(efficiency.false_positive_rate().unwrap(), 0.5);
+    }
+
+    #[test]
+    fn empty_scan_has_no_ratios() {
+        let mut efficiency = Efficiency::default();
+        efficiency.accumulate(&[], &[], 5.0);
+
+        assert!(efficiency.efficiency().is_none());
+        assert!(efficiency.false_positive_rate().is_none());
+        assert!(efficiency.time_offset().is_none());
+        assert!(efficiency.time_resolution().is_none());
+        assert!(efficiency.amplitude_bias().is_none());
+    }
+
+    #[test]
+    fn unmatched_events_have_no_time_offset() {
+        let mut efficiency = Efficiency::default();
+        efficiency.accumulate(&pulses(&[(100.0, 10.0)]), &pulses(&[(200.0, 10.0)]), 5.0);
+
+        assert_approx_eq!(efficiency.efficiency().unwrap(), 0.0);
+        assert_approx_eq!(efficiency.false_positive_rate().unwrap(), 1.0);
+        assert!(efficiency.time_offset().is_none());
+    }
+}
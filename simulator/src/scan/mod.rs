@@ -0,0 +1,302 @@
+mod grid;
+mod matching;
+
+use crate::integrated::{
+    simulation::{Simulation, SimulationError},
+    simulation_elements::utils::JsonFloatError,
+};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use grid::{ParameterSet, expand_grid};
+use matching::{Efficiency, Pulse};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use supermusr_common::{Channel, FrameNumber, Intensity};
+use supermusr_streaming_types::{
+    dat2_digitizer_analog_trace_v2_generated::{
+        ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+        DigitizerAnalogTraceMessageArgs, finish_digitizer_analog_trace_message_buffer,
+        root_as_digitizer_analog_trace_message,
+    },
+    dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
+    flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer},
+    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args, GpsTime},
+};
+use thiserror::Error;
+use trace_to_events::{BaselineTrackingOptions, DetectorConfig, EventFormat, Polarity, process};
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub(crate) enum ScanError {
+    #[error("Simulation Error: {0}")]
+    Simulation(#[from] SimulationError),
+    #[error("Json Float error: {0}")]
+    JsonFloat(#[from] JsonFloatError),
+    #[error("Json Error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("File Error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid Flatbuffer: {0}")]
+    InvalidFlatbuffer(#[from] InvalidFlatbuffer),
+    #[error("Parameter grid must be an array of objects, each with a single detector mode")]
+    InvalidGrid,
+}
+
+#[derive(Clone, Parser)]
+pub(crate) struct Scan {
+    /// Path to the json settings file, from which the pulses and noise of the traces are taken
+    file: PathBuf,
+
+    /// Path to the json file defining the grid of detector parameters to scan. See README.md.
+    #[clap(long)]
+    parameter_grid: PathBuf,
+
+    /// Index of the event list in the settings file from which traces are simulated
+    #[clap(long, default_value = "0")]
+    event_list_index: usize,
+
+    /// Number of frames to simulate
+    #[clap(long, default_value = "10")]
+    frames: usize,
+
+    /// Number of channel traces to simulate in each frame
+    #[clap(long, default_value = "8")]
+    channels: usize,
+
+    /// Determines whether events should register as positive or negative intensity
+    #[clap(long, default_value = "positive")]
+    polarity: Polarity,
+
+    /// Value of the intensity baseline
+    #[clap(long, default_value = "0")]
+    baseline: Intensity,
+
+    #[clap(flatten)]
+    baseline_tracking: BaselineTrackingOptions,
+
+    /// A detected event is matched to a simulated pulse if their times differ by no more than this (ns)
+    #[clap(long, default_value = "10")]
+    time_tolerance: f64,
+
+    /// If set, the report is also written to this path as a CSV table
+    #[clap(long)]
+    output: Option<PathBuf>,
+}
+
+/// A simulated trace message, and the pulses simulated in each of its channels.
+struct SimulatedFrame {
+    message: Vec<u8>,
+    pulses: Vec<Vec<Pulse>>,
+}
+
+/// Simulates traces, runs them through each detector in the parameter grid,
+/// and reports how well the detected events match the simulated pulses.
+#[tracing::instrument(skip_all, err(level = "error"))]
+pub(crate) fn run_scan(scan: Scan) -> Result<(), ScanError> {
+    let simulation: Simulation = serde_json::from_reader(File::open(&scan.file)?)?;
+    let parameter_sets = expand_grid(&serde_json::from_reader(File::open(&scan.parameter_grid)?)?)?;
+
+    // The same traces are used for every parameter set, so that they are compared fairly.
+    let frames = (0..scan.frames)
+        .map(|frame_number| simulate_frame(&simulation, &scan, frame_number as FrameNumber))
+        .collect::<Result<Vec<_>, _>>()?;
+    info!(
+        "Simulated {} frames, scanning {} parameter sets",
+        frames.len(),
+        parameter_sets.len()
+    );
+
+    let results = parameter_sets
+        .iter()
+        .map(|parameter_set| {
+            evaluate(&scan, &frames, parameter_set).map(|efficiency| (parameter_set, efficiency))
+        })
+        .collect::<Result<Vec<_>, ScanError>>()?;
+
+    print_report(&results);
+    if let Some(path) = &scan.output {
+        write_report(path, &results)?;
+    }
+    Ok(())
+}
+
+/// Simulates a trace message, with one channel for each event list generated.
+fn simulate_frame(
+    simulation: &Simulation,
+    scan: &Scan,
+    frame_number: FrameNumber,
+) -> Result<SimulatedFrame, ScanError> {
+    let event_lists =
+        simulation.generate_event_lists(scan.event_list_index, frame_number, scan.channels)?;
+    let traces = simulation.generate_traces(&event_lists, frame_number)?;
+
+    // The detected amplitude is relative to the baseline, so only the scaling of the voltage transformation applies.
+    let scale = simulation.voltage_transformation.scale.abs();
+    let pulses = event_lists
+        .iter()
+        .map(|event_list| {
+            let mut pulses = event_list
+                .pulses
+                .iter()
+                .map(|pulse| Pulse {
+                    time: pulse.time() as f64,
+                    amplitude: scale * pulse.intensity() as f64,
+                })
+                .collect::<Vec<_>>();
+            pulses.sort_by(|a, b| a.time.total_cmp(&b.time));
+            pulses
+        })
+        .collect();
+
+    let mut fbb = FlatBufferBuilder::new();
+    let timestamp: GpsTime = DateTime::<Utc>::UNIX_EPOCH.into();
+    let metadata = FrameMetadataV2Args {
+        frame_number,
+        period_number: 0,
+        protons_per_pulse: 0,
+        running: true,
+        timestamp: Some(&timestamp),
+        veto_flags: 0,
+    };
+    let metadata = FrameMetadataV2::create(&mut fbb, &metadata);
+    let channels = traces
+        .iter()
+        .enumerate()
+        .map(|(channel, trace)| {
+            let voltage = Some(fbb.create_vector::<Intensity>(trace.get_intensities()));
+            ChannelTrace::create(
+                &mut fbb,
+                &ChannelTraceArgs {
+                    channel: channel as Channel,
+                    voltage,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    let message = DigitizerAnalogTraceMessageArgs {
+        digitizer_id: 0,
+        metadata: Some(metadata),
+        sample_rate: simulation.sample_rate,
+        channels: Some(fbb.create_vector(&channels)),
+    };
+    let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+    finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+
+    Ok(SimulatedFrame {
+        message: fbb.finished_data().to_vec(),
+        pulses,
+    })
+}
+
+/// Runs every simulated frame through the detector, and matches the events found with the simulated pulses.
+fn evaluate(
+    scan: &Scan,
+    frames: &[SimulatedFrame],
+    parameter_set: &ParameterSet,
+) -> Result<Efficiency, ScanError> {
+    let detector_config = DetectorConfig::new(
+        parameter_set.mode.clone(),
+        scan.polarity,
+        scan.baseline,
+        scan.baseline_tracking.tracking(),
+    );
+
+    let mut efficiency = Efficiency::default();
+    for frame in frames {
+        let trace = root_as_digitizer_analog_trace_message(&frame.message)?;
+        let mut fbb = FlatBufferBuilder::new();
+        process(&mut fbb, &trace, &detector_config, EventFormat::Dev2);
+        let events = root_as_digitizer_event_list_message(fbb.finished_data())?;
+
+        let mut detected = vec![Vec::<Pulse>::new(); frame.pulses.len()];
+        let events = events
+            .channel()
+            .into_iter()
+            .flatten()
+            .zip(events.time().into_iter().flatten())
+            .zip(events.voltage().into_iter().flatten());
+        for ((channel, time), voltage) in events {
+            if let Some(detected) = detected.get_mut(channel as usize) {
+                detected.push(Pulse {
+                    time: time as f64,
+                    amplitude: voltage as f64,
+                });
+            }
+        }
+
+        for (pulses, mut detected) in frame.pulses.iter().zip(detected) {
+            detected.sort_by(|a, b| a.time.total_cmp(&b.time));
+            efficiency.accumulate(pulses, &detected, scan.time_tolerance);
+        }
+    }
+    Ok(efficiency)
+}
+
+const REPORT_COLUMNS: [&str; 7] = [
+    "pulses",
+    "detected",
+    "efficiency",
+    "false positive rate",
+    "time offset (ns)",
+    "time resolution (ns)",
+    "amplitude bias",
+];
+
+fn report_values(efficiency: &Efficiency) -> [String; 7] {
+    [
+        efficiency.num_pulses().to_string(),
+        efficiency.num_detected().to_string(),
+        format_ratio(efficiency.efficiency(), 3),
+        format_ratio(efficiency.false_positive_rate(), 3),
+        format_ratio(efficiency.time_offset(), 2),
+        format_ratio(efficiency.time_resolution(), 2),
+        format_ratio(efficiency.amplitude_bias(), 2),
+    ]
+}
+
+/// Formats a value to the given number of decimal places, or as "n/a" if it is undefined.
+fn format_ratio(value: Option<f64>, precision: usize) -> String {
+    value.map_or_else(|| "n/a".to_owned(), |value| format!("{value:.precision$}"))
+}
+
+/// Prints the report as a table, with one row per parameter set.
+fn print_report(results: &[(&ParameterSet, Efficiency)]) {
+    let label_width = results
+        .iter()
+        .map(|(parameter_set, _)| parameter_set.label.len())
+        .max()
+        .unwrap_or_default()
+        .max("parameters".len());
+
+    let header = REPORT_COLUMNS
+        .iter()
+        .map(|column| format!("{column:>22}"))
+        .collect::<String>();
+    println!("{:<label_width$}{header}", "parameters");
+    for (parameter_set, efficiency) in results {
+        let values = report_values(efficiency)
+            .iter()
+            .map(|value| format!("{value:>22}"))
+            .collect::<String>();
+        println!("{:<label_width$}{values}", parameter_set.label);
+    }
+}
+
+/// Writes the report as a CSV table, with one row per parameter set.
+fn write_report(path: &Path, results: &[(&ParameterSet, Efficiency)]) -> Result<(), ScanError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "parameters,{}", REPORT_COLUMNS.join(","))?;
+    for (parameter_set, efficiency) in results {
+        writeln!(
+            writer,
+            "\"{}\",{}",
+            parameter_set.label.replace('"', "\"\""),
+            report_values(efficiency).join(",")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
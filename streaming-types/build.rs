@@ -10,8 +10,13 @@ fn main() {
 
     let inputs = [
         "aev2_frame_assembled_event_v2.fbs",
+        "aev3_frame_assembled_event_v3.fbs",
+        "aev4_frame_assembled_event_v4.fbs",
+        "ahs1_aggregated_histogram_v1.fbs",
         "dat2_digitizer_analog_trace_v2.fbs",
+        "dat3_digitizer_analog_trace_v3.fbs",
         "dev2_digitizer_event_v2.fbs",
+        "dev3_digitizer_event_v3.fbs",
         "frame_metadata_v2.fbs",
         "ecs_6s4t_run_stop.fbs",
         "ecs_df12_det_spec_map.fbs",
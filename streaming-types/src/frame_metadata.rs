@@ -2,6 +2,7 @@ use crate::{
     frame_metadata_v2_generated::FrameMetadataV2, time_conversions::GpsTimeConversionError,
 };
 use chrono::{DateTime, Utc};
+use std::num::NonZeroU32;
 
 #[derive(Debug, Clone, Eq)]
 pub struct FrameMetadata {
@@ -21,6 +22,13 @@ impl FrameMetadata {
             && self.running == other.running
             && self.frame_number == other.frame_number
     }
+
+    /// Returns the Kafka partition to which messages of this frame are sent when frames are partitioned
+    /// across `num_partitions` partitions. Messages of the same frame from every digitiser share a partition,
+    /// and consecutive frames are spread evenly across the partitions.
+    pub fn partition(&self, num_partitions: NonZeroU32) -> i32 {
+        (self.frame_number % num_partitions.get()) as i32
+    }
 }
 
 /// This is a temporary implementation whilst the issue with veto flags being unequal in different digitisers persists.
@@ -176,4 +184,18 @@ mod tests {
         // This one should be true however
         assert!(m1.equals_ignoring_veto_flags(&m7));
     }
+
+    #[test]
+    fn test_frame_partition() {
+        let metadata = FrameMetadata {
+            period_number: 12,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number: 559,
+            timestamp: DateTime::from_timestamp_nanos(934856374698347),
+            veto_flags: 2,
+        };
+        let partitions = [1, 2, 4].map(|n| metadata.partition(NonZeroU32::new(n).unwrap()));
+        assert_eq!(partitions, [0, 1, 3]);
+    }
 }
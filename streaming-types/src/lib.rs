@@ -1,5 +1,6 @@
 mod frame_metadata;
 pub mod time_conversions;
+pub mod time_deltas;
 
 pub use crate::frame_metadata::FrameMetadata;
 pub use flatbuffers;
@@ -30,8 +31,13 @@ macro_rules! schema {
 
 schema!(frame_metadata_v2_generated);
 schema!(aev2_frame_assembled_event_v2_generated);
+schema!(aev3_frame_assembled_event_v3_generated);
+schema!(aev4_frame_assembled_event_v4_generated);
+schema!(ahs1_aggregated_histogram_v1_generated);
 schema!(dat2_digitizer_analog_trace_v2_generated);
+schema!(dat3_digitizer_analog_trace_v3_generated);
 schema!(dev2_digitizer_event_v2_generated);
+schema!(dev3_digitizer_event_v3_generated);
 
 schema!(ecs_6s4t_run_stop_generated);
 schema!(ecs_al00_alarm_generated);
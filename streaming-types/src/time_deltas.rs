@@ -0,0 +1,161 @@
+//! Encodes event times as variable length differences, as in the `time_delta` field of `aev4` messages.
+//!
+//! Each time is stored as the difference from the time of the previous event, or from zero for the first event
+//! of each group. Differences are zigzag encoded, so that small negative differences are also small, and written
+//! as unsigned LEB128 integers, seven bits per byte with the high bit set on all but the last byte of each.
+use thiserror::Error;
+
+/// The shift of the last byte of a difference. The difference of two `u32` times is zigzag encoded in at most 33 bits,
+/// so takes at most five bytes, and a continuation from the fifth byte is an error.
+const MAX_SHIFT: u32 = 28;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TimeDeltaDecodeError {
+    #[error("Time delta at byte {0} is truncated")]
+    Truncated(usize),
+    #[error("Time delta at byte {0} is too long")]
+    Overlong(usize),
+    #[error("Time of event {0} is out of range")]
+    OutOfRange(usize),
+}
+
+/// Encodes event times as differences.
+/// # Parameters
+/// - times: the time of each event.
+/// - group_starts: the indices of the events at which the differences restart from zero, in increasing order.
+pub fn encode_time_deltas(times: &[u32], group_starts: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(times.len() * 2);
+    let mut group_starts = group_starts.iter().peekable();
+    let mut previous = 0;
+    for (index, &time) in times.iter().enumerate() {
+        if group_starts
+            .next_if(|&&start| start as usize <= index)
+            .is_some()
+        {
+            previous = 0;
+        }
+        let delta = i64::from(time) - i64::from(previous);
+        let mut value = ((delta << 1) ^ (delta >> 63)) as u64;
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                break;
+            }
+            bytes.push(byte | 0x80);
+        }
+        previous = time;
+    }
+    bytes
+}
+
+/// Decodes event times from their differences.
+/// # Parameters
+/// - bytes: the encoded differences.
+/// - group_starts: the indices of the events at which the differences restart from zero, as given when encoding.
+pub fn decode_time_deltas(
+    bytes: &[u8],
+    group_starts: &[u32],
+) -> Result<Vec<u32>, TimeDeltaDecodeError> {
+    let mut times = Vec::with_capacity(bytes.len());
+    let mut group_starts = group_starts.iter().peekable();
+    let mut previous = 0;
+    let mut bytes = bytes.iter().enumerate();
+    while let Some((start, &byte)) = bytes.next() {
+        let mut value = u64::from(byte & 0x7F);
+        let mut last = byte;
+        let mut shift = 7;
+        while last & 0x80 != 0 {
+            if shift > MAX_SHIFT {
+                return Err(TimeDeltaDecodeError::Overlong(start));
+            }
+            let (_, &byte) = bytes.next().ok_or(TimeDeltaDecodeError::Truncated(start))?;
+            value |= u64::from(byte & 0x7F) << shift;
+            last = byte;
+            shift += 7;
+        }
+        let delta = (value >> 1) as i64 ^ -((value & 1) as i64);
+
+        let index = times.len();
+        if group_starts
+            .next_if(|&&start| start as usize <= index)
+            .is_some()
+        {
+            previous = 0;
+        }
+        let time = i64::from(previous)
+            .checked_add(delta)
+            .and_then(|time| u32::try_from(time).ok())
+            .ok_or(TimeDeltaDecodeError::OutOfRange(index))?;
+        times.push(time);
+        previous = time;
+    }
+    Ok(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let times = [5, 300, 299, 20_000_000, 0, 17, 16];
+        let group_starts = [0, 4];
+        let bytes = encode_time_deltas(&times, &group_starts);
+        assert_eq!(
+            decode_time_deltas(&bytes, &group_starts),
+            Ok(times.to_vec())
+        );
+    }
+
+    #[test]
+    fn small_deltas_take_one_byte() {
+        let times = [10, 40, 63, 60];
+        let bytes = encode_time_deltas(&times, &[]);
+        // 10 and 30 are zigzag encoded as 20 and 60, 23 as 46, and -3 as 5.
+        assert_eq!(bytes, [20, 60, 46, 5]);
+        assert_eq!(decode_time_deltas(&bytes, &[]), Ok(times.to_vec()));
+    }
+
+    #[test]
+    fn truncated_delta_is_error() {
+        let bytes = encode_time_deltas(&[1, 1_000_000], &[]);
+        let truncated = bytes.get(..bytes.len() - 1).unwrap();
+        assert_eq!(
+            decode_time_deltas(truncated, &[]),
+            Err(TimeDeltaDecodeError::Truncated(1))
+        );
+    }
+
+    #[test]
+    fn largest_delta_takes_five_bytes() {
+        let times = [u32::MAX, 0];
+        let bytes = encode_time_deltas(&times, &[]);
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(decode_time_deltas(&bytes, &[]), Ok(times.to_vec()));
+    }
+
+    #[test]
+    fn overlong_delta_is_error() {
+        // A zero padded to six bytes, after a valid one byte delta.
+        assert_eq!(
+            decode_time_deltas(&[2, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00], &[]),
+            Err(TimeDeltaDecodeError::Overlong(1))
+        );
+        // A five byte delta is not overlong, though this one is out of range.
+        assert_eq!(
+            decode_time_deltas(&[0x80, 0x80, 0x80, 0x80, 0x7F], &[]),
+            Err(TimeDeltaDecodeError::OutOfRange(0))
+        );
+    }
+
+    #[test]
+    fn negative_time_is_error() {
+        // A delta of -1 from zero.
+        assert_eq!(
+            decode_time_deltas(&[1], &[]),
+            Err(TimeDeltaDecodeError::OutOfRange(0))
+        );
+    }
+}
@@ -0,0 +1,6 @@
+//! Reads PicoScope `.trace` files, and converts their trace events into `dat2` messages.
+mod loader;
+mod processing;
+
+pub use loader::{TraceFile, TraceFileEvent, load_trace_file};
+pub use processing::{create_message, dispatch_trace_file};
@@ -98,7 +98,7 @@ impl TraceFileHeader {
 }
 
 #[derive(Default, Debug)]
-pub(crate) struct TraceFileEvent {
+pub struct TraceFileEvent {
     pub(crate) cur_trace_event: i32,
     pub(crate) trace_event_runtime: f64,
     pub(crate) number_saved_traces: i32,
@@ -155,14 +155,14 @@ impl TraceFileEvent {
 }
 
 #[derive(Debug)]
-pub(crate) struct TraceFile {
+pub struct TraceFile {
     file: File,
     header: TraceFileHeader,
     num_trace_events: usize,
 }
 
 impl TraceFile {
-    pub(crate) fn get_trace_event(&mut self, event: usize) -> Result<TraceFileEvent, Error> {
+    pub fn get_trace_event(&mut self, event: usize) -> Result<TraceFileEvent, Error> {
         if event < self.num_trace_events {
             self.file.seek(SeekFrom::Start(
                 (self.header.get_size() + event * self.header.get_event_size()) as u64,
@@ -176,20 +176,20 @@ impl TraceFile {
         }
     }
 
-    pub(crate) fn get_number_of_trace_events(&self) -> usize {
+    pub fn get_number_of_trace_events(&self) -> usize {
         self.num_trace_events
     }
 
-    pub(crate) fn get_num_channels(&self) -> usize {
+    pub fn get_num_channels(&self) -> usize {
         self.header.number_of_channels as usize
     }
 
-    pub(crate) fn get_sample_time(&self) -> f64 {
+    pub fn get_sample_time(&self) -> f64 {
         self.header.sample_time
     }
 }
 
-pub(crate) fn load_trace_file(name: PathBuf) -> Result<TraceFile, Error> {
+pub fn load_trace_file(name: PathBuf) -> Result<TraceFile, Error> {
     let mut file = File::open(name)?;
     let header: TraceFileHeader = TraceFileHeader::load(&mut file)?;
     let file_size = file
@@ -1,12 +1,9 @@
-mod loader;
-mod processing;
-
-use crate::{loader::load_trace_file, processing::dispatch_trace_file};
 use clap::Parser;
 use rand::seq::IteratorRandom;
 use rdkafka::producer::FutureProducer;
 use std::path::PathBuf;
 use supermusr_common::{CommonKafkaOpts, DigitizerId, FrameNumber};
+use trace_reader::{dispatch_trace_file, load_trace_file};
 
 #[derive(Debug, Parser)]
 #[clap(author, version = supermusr_common::version!(), about)]
@@ -21,7 +21,7 @@ use supermusr_streaming_types::{
 use tracing::{debug, error};
 
 /// Reads the contents of trace_file and dispatches messages to the given Kafka topic.
-pub(crate) async fn dispatch_trace_file(
+pub async fn dispatch_trace_file(
     mut trace_file: TraceFile,
     trace_event_indices: Vec<usize>,
     frame_number: FrameNumber,
@@ -73,7 +73,7 @@ pub(crate) fn create_channel<'a>(
 ///
 /// #Returns
 /// A string result, or an error.
-pub(crate) fn create_message(
+pub fn create_message(
     fbb: &mut FlatBufferBuilder<'_>,
     time: GpsTime,
     frame_number: u32,
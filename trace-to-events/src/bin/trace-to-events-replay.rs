@@ -0,0 +1,307 @@
+//! # Trace To Events Replay
+//!
+//! Runs recorded trace messages through the event formation of `trace-to-events`, without a Kafka broker,
+//! so that detector parameters can be compared reproducibly.
+//!
+//! Traces are read either from a file of `dat2` messages, or from a PicoScope `.trace` file.
+//! The resulting events are written either as event list messages, or as a CSV table.
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use miette::IntoDiagnostic;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+use supermusr_common::{
+    DigitizerId, FrameNumber, Intensity, init_tracer,
+    tracer::{TracerEngine, TracerOptions},
+};
+use supermusr_streaming_types::{
+    dat2_digitizer_analog_trace_v2_generated::{
+        DigitizerAnalogTraceMessage, root_as_digitizer_analog_trace_message,
+    },
+    dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
+    dev3_digitizer_event_v3_generated as dev3,
+    flatbuffers::FlatBufferBuilder,
+};
+use trace_reader::{create_message, load_trace_file};
+use trace_to_events::{
+    BaselineTrackingOptions, DetectorConfig, DetectorConfigOverrides, EventFormat, Mode, Polarity,
+    process,
+};
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum InputFormat {
+    /// A sequence of `dat2` trace messages, each preceded by its size as a 4-byte little-endian integer.
+    Dat2,
+    /// A PicoScope `.trace` file, each trace event of which becomes a single trace message.
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    /// A sequence of event list messages, each preceded by its size as a 4-byte little-endian integer.
+    Messages,
+    /// A CSV table with one row per event.
+    Csv,
+}
+
+#[derive(Debug, Parser)]
+#[clap(author, version = supermusr_common::version!(), about)]
+struct Cli {
+    /// Path of the file containing the recorded traces
+    #[clap(long)]
+    input: PathBuf,
+
+    /// Format of the input file
+    #[clap(long, default_value = "dat2")]
+    input_format: InputFormat,
+
+    /// The digitiser ID assigned to trace messages read from a `.trace` file
+    #[clap(long, default_value = "0")]
+    digitizer_id: DigitizerId,
+
+    /// Path of the file to which events are written
+    #[clap(long)]
+    output: PathBuf,
+
+    /// Format of the output file
+    #[clap(long, default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// If set, detector configuration overrides are loaded from this JSON file. See README.md.
+    #[clap(long)]
+    detector_config_file: Option<PathBuf>,
+
+    /// Determines whether events should register as positive or negative intensity
+    #[clap(long)]
+    polarity: Polarity,
+
+    /// Value of the intensity baseline
+    #[clap(long, default_value = "0")]
+    baseline: Intensity,
+
+    #[clap(flatten)]
+    baseline_tracking: BaselineTrackingOptions,
+
+    /// The schema of the event list messages to produce
+    #[clap(long, default_value = "dev2")]
+    event_format: EventFormat,
+
+    #[command(subcommand)]
+    mode: Mode,
+}
+
+fn main() -> miette::Result<()> {
+    let args = Cli::parse();
+
+    let _tracer = init_tracer!(TracerOptions::new(None, String::new()));
+
+    args.mode.validate().into_diagnostic()?;
+    let mut detector_config = DetectorConfig::new(
+        args.mode.clone(),
+        args.polarity,
+        args.baseline,
+        args.baseline_tracking.tracking(),
+    );
+    if let Some(path) = &args.detector_config_file {
+        detector_config.set_overrides(DetectorConfigOverrides::from_file(path).into_diagnostic()?);
+    }
+
+    let mut writer = EventWriter::new(&args.output, args.output_format, args.event_format)?;
+
+    match args.input_format {
+        InputFormat::Dat2 => {
+            let mut reader = BufReader::new(File::open(&args.input).into_diagnostic()?);
+            while let Some(payload) = read_message(&mut reader).into_diagnostic()? {
+                let trace = root_as_digitizer_analog_trace_message(&payload).into_diagnostic()?;
+                writer.write(&trace, &detector_config)?;
+            }
+        }
+        InputFormat::Trace => {
+            let mut trace_file = load_trace_file(args.input.clone()).into_diagnostic()?;
+            let mut fbb = FlatBufferBuilder::new();
+            for index in 0..trace_file.get_number_of_trace_events() {
+                let event = trace_file.get_trace_event(index).into_diagnostic()?;
+                // A fixed timestamp is used so that the output is reproducible.
+                create_message(
+                    &mut fbb,
+                    DateTime::<Utc>::UNIX_EPOCH.into(),
+                    index as FrameNumber,
+                    args.digitizer_id,
+                    trace_file.get_num_channels(),
+                    (1.0 / trace_file.get_sample_time()) as u64,
+                    &event,
+                )?;
+                let trace = root_as_digitizer_analog_trace_message(fbb.finished_data())
+                    .into_diagnostic()?;
+                writer.write(&trace, &detector_config)?;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// Reads the next size-prefixed message from the reader.
+/// # Return
+/// The message, or [None] if the end of the file has been reached.
+fn read_message(reader: &mut impl Read) -> std::io::Result<Option<Vec<u8>>> {
+    let mut size = [0u8; 4];
+    match reader.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut message = vec![0; u32::from_le_bytes(size) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Writes a message, preceded by its size, in the format read by [read_message].
+fn write_message(writer: &mut impl Write, message: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(message.len() as u32).to_le_bytes())?;
+    writer.write_all(message)
+}
+
+/// Writes the events found in each trace message to the output file.
+struct EventWriter {
+    writer: BufWriter<File>,
+    output_format: OutputFormat,
+    event_format: EventFormat,
+    num_messages: usize,
+    num_events: usize,
+}
+
+impl EventWriter {
+    fn new(
+        path: &Path,
+        output_format: OutputFormat,
+        event_format: EventFormat,
+    ) -> miette::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path).into_diagnostic()?);
+        if let OutputFormat::Csv = output_format {
+            write!(writer, "digitizer_id,frame_number,channel,time,intensity").into_diagnostic()?;
+            if let EventFormat::Dev3 = event_format {
+                write!(writer, ",width,area,rise_time").into_diagnostic()?;
+            }
+            writeln!(writer).into_diagnostic()?;
+        }
+        Ok(Self {
+            writer,
+            output_format,
+            event_format,
+            num_messages: 0,
+            num_events: 0,
+        })
+    }
+
+    /// Finds the events in the trace message, and writes them to the output file.
+    fn write(
+        &mut self,
+        trace: &DigitizerAnalogTraceMessage<'_>,
+        detector_config: &DetectorConfig,
+    ) -> miette::Result<()> {
+        let mut fbb = FlatBufferBuilder::new();
+        process(&mut fbb, trace, detector_config, self.event_format);
+        let payload = fbb.finished_data();
+
+        let message = root_as_digitizer_event_list_message(payload).into_diagnostic()?;
+        let channels = message
+            .channel()
+            .map(|v| v.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        self.num_messages += 1;
+        self.num_events += channels.len();
+
+        match self.output_format {
+            OutputFormat::Messages => write_message(&mut self.writer, payload).into_diagnostic()?,
+            OutputFormat::Csv => {
+                // Pulse shape fields are absent from `dev2` messages, and omitted from `dev3`
+                // messages if the detector does not measure them.
+                let shapes = match self.event_format {
+                    EventFormat::Dev2 => None,
+                    EventFormat::Dev3 => {
+                        let message = dev3::root_as_digitizer_event_list_message(payload)
+                            .into_diagnostic()?;
+                        Some(
+                            [message.width(), message.area(), message.rise_time()].map(|values| {
+                                values
+                                    .map(|v| v.iter().collect::<Vec<_>>())
+                                    .unwrap_or_default()
+                            }),
+                        )
+                    }
+                };
+
+                let events = channels
+                    .iter()
+                    .zip(message.time().into_iter().flatten())
+                    .zip(message.voltage().into_iter().flatten());
+                for (index, ((channel, time), voltage)) in events.enumerate() {
+                    write!(
+                        self.writer,
+                        "{},{},{channel},{time},{voltage}",
+                        message.digitizer_id(),
+                        message.metadata().frame_number()
+                    )
+                    .into_diagnostic()?;
+                    for values in shapes.iter().flatten() {
+                        match values.get(index) {
+                            Some(value) => write!(self.writer, ",{value}"),
+                            None => write!(self.writer, ","),
+                        }
+                        .into_diagnostic()?;
+                    }
+                    writeln!(self.writer).into_diagnostic()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the output file and reports the number of messages and events processed.
+    fn finish(mut self) -> miette::Result<()> {
+        self.writer.flush().into_diagnostic()?;
+        if self.num_messages == 0 {
+            warn!("No trace messages were found in the input file");
+        }
+        info!(
+            "Processed {} trace messages, finding {} events",
+            self.num_messages, self.num_events
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages: [&[u8]; 3] = [&[1, 2, 3], &[], &[4; 300]];
+        let mut buffer = Vec::new();
+        for message in messages {
+            write_message(&mut buffer, message).unwrap();
+        }
+
+        let mut reader = Cursor::new(buffer);
+        for message in messages {
+            assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some(message));
+        }
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_message_is_an_error() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &[1, 2, 3, 4]).unwrap();
+        buffer.truncate(buffer.len() - 1);
+
+        assert!(read_message(&mut Cursor::new(buffer)).is_err());
+    }
+}
@@ -1,61 +1,126 @@
 use crate::{
     parameters::{
-        AdvancedMuonDetectorParameters, DetectorSettings,
-        DifferentialThresholdDiscriminatorParameters, FixedThresholdDiscriminatorParameters, Mode,
-        Polarity,
+        AdvancedMuonDetectorParameters, BaselineTracking, ConstantFractionDiscriminatorParameters,
+        DetectorSettings, DifferentialThresholdDiscriminatorParameters,
+        FixedThresholdDiscriminatorParameters, Mode, Polarity, PulseFitDetectorParameters,
     },
     pulse_detection::{
-        AssembleFilter, EventFilter, Real,
+        AssembleFilter, EventFilter, Pulse, Real,
         advanced_muon_detector::{AdvancedMuonAssembler, AdvancedMuonDetector},
+        detectors::constant_fraction_detector::{
+            ConstantFractionAssembler, ConstantFractionDetector,
+        },
         detectors::differential_threshold_detector::DifferentialThresholdDetector,
+        detectors::pulse_fit_detector::{PulseFitAssembler, PulseFitDetector},
         threshold_detector::{ThresholdDetector, ThresholdDuration},
-        window::{Baseline, FiniteDifferences, SmoothingWindow, WindowFilter},
+        window::{
+            Baseline, FiniteDifferences, GatedExponentialBaseline, MovingMedianBaseline,
+            SmoothingWindow, WindowFilter,
+        },
     },
 };
+use metrics::histogram;
 use supermusr_common::{Intensity, Time};
 use supermusr_streaming_types::dat2_digitizer_analog_trace_v2_generated::ChannelTrace;
 
+/// Optional measurements of the shape of an event's pulse.
+/// Each field is [None] if the detector does not measure it.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PulseShape {
+    /// Time from the start to the end of the pulse (ns).
+    pub(crate) width: Option<f32>,
+    /// Sum of the trace values from the start of the pulse up to, but excluding, its end, multiplied by the sample time.
+    /// This is the same span as [Self::width].
+    pub(crate) area: Option<f32>,
+    /// Time from the start to the peak of the pulse (ns).
+    pub(crate) rise_time: Option<f32>,
+}
+
+impl PulseShape {
+    /// Measures the shape of a pulse.
+    /// # Parameters
+    /// - pulse: the pulse, whose start, end and peak times are used if present.
+    /// - trace: the trace in which the pulse was found, which is integrated to find the area.
+    /// - sample_time: the time between samples of the trace (ns).
+    fn new(pulse: &Pulse, trace: &[(Real, Real)], sample_time: Real) -> Self {
+        let start_end = Option::zip(pulse.start.time, pulse.end.time);
+        Self {
+            width: start_end.map(|(start, end)| (end - start) as f32),
+            area: start_end.map(|(start, end)| {
+                let first_sample = (start / sample_time).ceil().max(0.0) as usize;
+                let sum: Real = trace
+                    .iter()
+                    .skip(first_sample)
+                    .take_while(|(time, _)| *time < end)
+                    .map(|(_, value)| value)
+                    .sum();
+                (sum * sample_time) as f32
+            }),
+            rise_time: Option::zip(pulse.start.time, pulse.peak.time)
+                .map(|(start, peak)| (peak - start) as f32),
+        }
+    }
+}
+
+/// The events found in a single channel trace.
+#[derive(Default, Debug)]
+pub(crate) struct ChannelEvents {
+    pub(crate) time: Vec<Time>,
+    pub(crate) voltage: Vec<Intensity>,
+    pub(crate) shape: Vec<PulseShape>,
+}
+
+impl ChannelEvents {
+    fn push(&mut self, time: Time, voltage: Intensity, shape: PulseShape) {
+        self.time.push(time);
+        self.voltage.push(voltage);
+        self.shape.push(shape);
+    }
+}
+
 #[tracing::instrument(skip_all, fields(channel = trace.channel(), num_pulses))]
 pub(crate) fn find_channel_events(
     trace: &ChannelTrace,
     sample_time: Real,
     detector_settings: &DetectorSettings,
-) -> (Vec<Time>, Vec<Intensity>) {
+) -> ChannelEvents {
+    let raw = baseline_corrected_trace(
+        trace,
+        sample_time,
+        detector_settings.polarity,
+        detector_settings.baseline as Real,
+        detector_settings.baseline_tracking,
+    );
     let result = match &detector_settings.mode {
-        Mode::FixedThresholdDiscriminator(parameters) => find_fixed_threshold_events(
-            trace,
-            sample_time,
-            detector_settings.polarity,
-            detector_settings.baseline as Real,
-            parameters,
-        ),
-        Mode::DifferentialThresholdDiscriminator(parameters) => find_differential_threshold_events(
-            trace,
-            sample_time,
-            detector_settings.polarity,
-            detector_settings.baseline as Real,
-            parameters,
-        ),
-        Mode::AdvancedMuonDetector(parameters) => find_advanced_events(
-            trace,
-            sample_time,
-            detector_settings.polarity,
-            detector_settings.baseline as Real,
-            parameters,
-        ),
+        Mode::FixedThresholdDiscriminator(parameters) => {
+            find_fixed_threshold_events(&raw, parameters)
+        }
+        Mode::DifferentialThresholdDiscriminator(parameters) => {
+            find_differential_threshold_events(&raw, parameters)
+        }
+        Mode::AdvancedMuonDetector(parameters) => {
+            find_advanced_events(&raw, sample_time, parameters)
+        }
+        Mode::ConstantFractionDiscriminator(parameters) => {
+            find_constant_fraction_events(&raw, sample_time, parameters)
+        }
+        Mode::PulseFitDetector(parameters) => find_pulse_fit_events(&raw, sample_time, parameters),
     };
-    tracing::Span::current().record("num_pulses", result.0.len());
+    let result = filter_by_amplitude(result, detector_settings);
+    tracing::Span::current().record("num_pulses", result.time.len());
     result
 }
 
-#[tracing::instrument(skip_all, level = "trace")]
-fn find_fixed_threshold_events(
+/// Converts the trace to a sequence of times and values, in which pulses are positive and the baseline is zero.
+///
+/// The static baseline is subtracted first, after which the baseline tracking algorithm, if enabled, removes any drift.
+fn baseline_corrected_trace(
     trace: &ChannelTrace,
     sample_time: Real,
     polarity: &Polarity,
     baseline: Real,
-    parameters: &FixedThresholdDiscriminatorParameters,
-) -> (Vec<Time>, Vec<Intensity>) {
+    baseline_tracking: &BaselineTracking,
+) -> Vec<(Real, Real)> {
     let sign = match polarity {
         Polarity::Positive => 1.0,
         Polarity::Negative => -1.0,
@@ -67,83 +132,117 @@ fn find_fixed_threshold_events(
         .enumerate()
         .map(|(i, v)| (i as Real * sample_time, sign * (v as Real - baseline)));
 
+    match baseline_tracking {
+        BaselineTracking::Disabled => raw.collect(),
+        BaselineTracking::MovingMedian { window } => {
+            raw.window(MovingMedianBaseline::new(*window)).collect()
+        }
+        BaselineTracking::GatedExponential {
+            smoothing_factor,
+            gate,
+            hold_off,
+        } => raw
+            .window(GatedExponentialBaseline::new(
+                *smoothing_factor,
+                *gate,
+                *hold_off,
+            ))
+            .collect(),
+    }
+}
+
+/// Discards any events whose amplitude lies outside the limits given in the detector settings.
+fn filter_by_amplitude(
+    events: ChannelEvents,
+    detector_settings: &DetectorSettings,
+) -> ChannelEvents {
+    if detector_settings.min_amplitude.is_none() && detector_settings.max_amplitude.is_none() {
+        return events;
+    }
+    let mut result = ChannelEvents::default();
+    for ((time, voltage), shape) in events
+        .time
+        .into_iter()
+        .zip(events.voltage)
+        .zip(events.shape)
+    {
+        let amplitude = voltage as Real;
+        if detector_settings
+            .min_amplitude
+            .is_none_or(|min| min <= amplitude)
+            && detector_settings
+                .max_amplitude
+                .is_none_or(|max| max >= amplitude)
+        {
+            result.push(time, voltage, shape);
+        }
+    }
+    result
+}
+
+#[tracing::instrument(skip_all, level = "trace")]
+fn find_fixed_threshold_events(
+    raw: &[(Real, Real)],
+    parameters: &FixedThresholdDiscriminatorParameters,
+) -> ChannelEvents {
     let pulses = raw
-        .clone()
+        .iter()
+        .copied()
         .events(ThresholdDetector::new(&ThresholdDuration {
             threshold: parameters.threshold,
             duration: parameters.duration,
             cool_off: parameters.cool_off,
         }));
 
-    let mut time = Vec::<Time>::new();
-    let mut voltage = Vec::<Intensity>::new();
+    let mut events = ChannelEvents::default();
     for pulse in pulses {
-        time.push(pulse.0 as Time);
-        voltage.push(pulse.1.pulse_height as Intensity);
+        events.push(
+            pulse.0 as Time,
+            pulse.1.pulse_height as Intensity,
+            PulseShape::default(),
+        );
     }
-    (time, voltage)
+    events
 }
 
 #[tracing::instrument(skip_all, level = "trace")]
 fn find_differential_threshold_events(
-    trace: &ChannelTrace,
-    sample_time: Real,
-    polarity: &Polarity,
-    baseline: Real,
+    raw: &[(Real, Real)],
     parameters: &DifferentialThresholdDiscriminatorParameters,
-) -> (Vec<Time>, Vec<Intensity>) {
-    let sign = match polarity {
-        Polarity::Positive => 1.0,
-        Polarity::Negative => -1.0,
-    };
-    let raw = trace
-        .voltage()
-        .unwrap()
-        .into_iter()
-        .enumerate()
-        .map(|(i, v)| (i as Real * sample_time, sign * (v as Real - baseline)));
-
-    let pulses = raw.clone().window(FiniteDifferences::<2>::new()).events(
-        DifferentialThresholdDetector::new(
+) -> ChannelEvents {
+    let pulses = raw
+        .iter()
+        .copied()
+        .window(FiniteDifferences::<2>::new())
+        .events(DifferentialThresholdDetector::new(
             &ThresholdDuration {
                 threshold: parameters.threshold,
                 duration: parameters.duration,
                 cool_off: parameters.cool_off,
             },
             parameters.constant_multiple,
-        ),
-    );
+        ));
 
-    let mut time = Vec::<Time>::new();
-    let mut voltage = Vec::<Intensity>::new();
+    let mut events = ChannelEvents::default();
     for pulse in pulses {
-        time.push(pulse.0 as Time);
-        voltage.push(pulse.1.pulse_height as Intensity);
+        events.push(
+            pulse.0 as Time,
+            pulse.1.pulse_height as Intensity,
+            PulseShape::default(),
+        );
     }
-    (time, voltage)
+    events
 }
 
 #[tracing::instrument(skip_all, level = "trace")]
 fn find_advanced_events(
-    trace: &ChannelTrace,
+    raw: &[(Real, Real)],
     sample_time: Real,
-    polarity: &Polarity,
-    baseline: Real,
     parameters: &AdvancedMuonDetectorParameters,
-) -> (Vec<Time>, Vec<Intensity>) {
-    let sign = match polarity {
-        Polarity::Positive => 1.0,
-        Polarity::Negative => -1.0,
-    };
-    let raw = trace
-        .voltage()
-        .unwrap()
-        .into_iter()
-        .enumerate()
-        .map(|(i, v)| (i as Real * sample_time, sign * (v as Real - baseline)));
-
+) -> ChannelEvents {
     let smoothed = raw
-        .clone()
+        .iter()
+        .copied()
         .window(Baseline::new(parameters.baseline_length.unwrap_or(0), 0.1))
         .window(SmoothingWindow::new(
             parameters.smoothing_window_size.unwrap_or(1),
@@ -174,11 +273,75 @@ fn find_advanced_events(
                 .unwrap_or(true)
         });
 
-    let mut time = Vec::<Time>::new();
-    let mut voltage = Vec::<Intensity>::new();
+    let mut events = ChannelEvents::default();
+    for pulse in pulses {
+        events.push(
+            pulse.steepest_rise.time.unwrap_or_default() as Time,
+            pulse.peak.value.unwrap_or_default() as Intensity,
+            PulseShape::new(&pulse, raw, sample_time),
+        );
+    }
+    events
+}
+
+#[tracing::instrument(skip_all, level = "trace")]
+fn find_constant_fraction_events(
+    raw: &[(Real, Real)],
+    sample_time: Real,
+    parameters: &ConstantFractionDiscriminatorParameters,
+) -> ChannelEvents {
+    let pulses = raw
+        .iter()
+        .copied()
+        .events(ConstantFractionDetector::new(
+            parameters.threshold,
+            parameters.delay,
+            parameters.fraction,
+        ))
+        .assemble(ConstantFractionAssembler::default());
+
+    let mut events = ChannelEvents::default();
+    for pulse in pulses {
+        events.push(
+            pulse.zero_crossing.time.unwrap_or_default().round() as Time,
+            pulse.peak.value.unwrap_or_default() as Intensity,
+            PulseShape::new(&pulse, raw, sample_time),
+        );
+    }
+    events
+}
+
+#[tracing::instrument(skip_all, level = "trace")]
+fn find_pulse_fit_events(
+    raw: &[(Real, Real)],
+    sample_time: Real,
+    parameters: &PulseFitDetectorParameters,
+) -> ChannelEvents {
+    // The template is validated when the mode is parsed, so this never fails.
+    let Ok(template) = parameters.template() else {
+        return Default::default();
+    };
+
+    let pulses = raw
+        .iter()
+        .copied()
+        .events(PulseFitDetector::new(
+            template,
+            parameters.threshold,
+            parameters.max_pulses,
+        ))
+        .assemble(PulseFitAssembler::default());
+
+    let mut events = ChannelEvents::default();
     for pulse in pulses {
-        time.push(pulse.steepest_rise.time.unwrap_or_default() as Time);
-        voltage.push(pulse.peak.value.unwrap_or_default() as Intensity);
+        if let Some(residual) = pulse.fit_residual {
+            histogram!(crate::PULSE_FIT_RESIDUAL_METRIC).record(residual);
+        }
+        events.push(
+            pulse.peak.time.unwrap_or_default().round() as Time,
+            pulse.peak.value.unwrap_or_default() as Intensity,
+            PulseShape::new(&pulse, raw, sample_time),
+        );
     }
-    (time, voltage)
+    events
 }
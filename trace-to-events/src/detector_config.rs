@@ -0,0 +1,363 @@
+//! Defines the detector configuration, which can be replaced whilst the component is running.
+//!
+//! The command line arguments specify the default [DetectorSettings] for every channel.
+//! These can be overridden, per digitiser and per channel, by [DetectorConfigOverrides]
+//! loaded from a JSON file at startup, or received as JSON messages on the detector configuration topic.
+use crate::{
+    parameters::{BaselineTracking, DetectorSettings, Mode, Polarity, deserialize_mode},
+    pulse_detection::Real,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, fs::File, io, path::Path};
+use supermusr_common::{Channel, DigitizerId, Intensity};
+use thiserror::Error;
+
+/// The name given to the configuration before any overrides are received.
+const COMMAND_LINE_CONFIG_NAME: &str = "command-line";
+
+/// Optional replacements for each of the fields of [DetectorSettings].
+/// Any field which is [None] falls back to the settings at the next level up.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SettingsOverride {
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub(crate) mode: Option<Mode>,
+    pub(crate) polarity: Option<Polarity>,
+    pub(crate) baseline: Option<Intensity>,
+    pub(crate) baseline_tracking: Option<BaselineTracking>,
+    pub(crate) min_amplitude: Option<Real>,
+    pub(crate) max_amplitude: Option<Real>,
+}
+
+/// Overrides applying to a single digitiser.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DigitiserOverrides {
+    /// Overrides applying to every channel of the digitiser.
+    #[serde(default)]
+    pub(crate) default: SettingsOverride,
+    /// Overrides applying to individual channels, keyed by channel number.
+    #[serde(default)]
+    pub(crate) channels: HashMap<Channel, SettingsOverride>,
+}
+
+/// The contents of a message on the detector configuration topic.
+///
+/// For example:
+/// ```json
+/// {
+///     "name": "sample-42",
+///     "default": { "baseline": 10 },
+///     "digitisers": {
+///         "3": {
+///             "default": { "polarity": "negative" },
+///             "channels": {
+///                 "5": { "mode": { "fixed-threshold-discriminator": { "threshold": 12.0 } } }
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DetectorConfigOverrides {
+    /// Identifies the configuration in logs, metrics and event list message headers.
+    pub(crate) name: String,
+    /// Overrides applying to every digitiser.
+    #[serde(default)]
+    pub(crate) default: SettingsOverride,
+    /// Overrides applying to individual digitisers, keyed by digitiser id.
+    #[serde(default)]
+    pub(crate) digitisers: HashMap<DigitizerId, DigitiserOverrides>,
+}
+
+impl Default for DetectorConfigOverrides {
+    fn default() -> Self {
+        Self {
+            name: COMMAND_LINE_CONFIG_NAME.to_owned(),
+            default: Default::default(),
+            digitisers: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DetectorConfigFileError {
+    #[error("IO Error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Json Error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl DetectorConfigOverrides {
+    /// Loads overrides from a JSON file, in the same format as the messages on the detector configuration topic.
+    pub fn from_file(path: &Path) -> Result<Self, DetectorConfigFileError> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Returns the number of digitisers and channels which have their own overrides.
+    fn count_overrides(&self) -> (usize, usize) {
+        (
+            self.digitisers.len(),
+            self.digitisers.values().map(|d| d.channels.len()).sum(),
+        )
+    }
+}
+
+/// The detector settings currently in force.
+#[derive(Debug)]
+pub struct DetectorConfig {
+    mode: Mode,
+    polarity: Polarity,
+    baseline: Intensity,
+    baseline_tracking: BaselineTracking,
+    overrides: DetectorConfigOverrides,
+}
+
+impl DetectorConfig {
+    /// Creates a configuration with no overrides.
+    /// # Parameters
+    /// - mode: the default detector mode.
+    /// - polarity: the default polarity.
+    /// - baseline: the default baseline.
+    /// - baseline_tracking: the default baseline tracking algorithm.
+    pub fn new(
+        mode: Mode,
+        polarity: Polarity,
+        baseline: Intensity,
+        baseline_tracking: BaselineTracking,
+    ) -> Self {
+        Self {
+            mode,
+            polarity,
+            baseline,
+            baseline_tracking,
+            overrides: Default::default(),
+        }
+    }
+
+    /// Returns the name of the current overrides.
+    pub fn name(&self) -> &str {
+        &self.overrides.name
+    }
+
+    /// Replaces all existing overrides with the given ones.
+    pub fn set_overrides(&mut self, overrides: DetectorConfigOverrides) {
+        let (num_digitisers, num_channels) = overrides.count_overrides();
+        tracing::info!(
+            "Detector configuration changed from \"{}\" to \"{}\" ({num_digitisers} digitiser and {num_channels} channel overrides)",
+            self.overrides.name,
+            overrides.name
+        );
+        self.overrides = overrides;
+    }
+
+    /// Returns the settings to use for the given channel of the given digitiser.
+    ///
+    /// Each field is taken from the most specific override which defines it,
+    /// falling back to the command line value if no override does.
+    pub(crate) fn get_settings(
+        &self,
+        digitiser_id: DigitizerId,
+        channel: Channel,
+    ) -> DetectorSettings<'_> {
+        let digitiser = self.overrides.digitisers.get(&digitiser_id);
+        let layers = [
+            digitiser.and_then(|digitiser| digitiser.channels.get(&channel)),
+            digitiser.map(|digitiser| &digitiser.default),
+            Some(&self.overrides.default),
+        ]
+        .into_iter()
+        .flatten();
+
+        DetectorSettings {
+            mode: layers
+                .clone()
+                .find_map(|layer| layer.mode.as_ref())
+                .unwrap_or(&self.mode),
+            polarity: layers
+                .clone()
+                .find_map(|layer| layer.polarity.as_ref())
+                .unwrap_or(&self.polarity),
+            baseline: layers
+                .clone()
+                .find_map(|layer| layer.baseline)
+                .unwrap_or(self.baseline),
+            baseline_tracking: layers
+                .clone()
+                .find_map(|layer| layer.baseline_tracking.as_ref())
+                .unwrap_or(&self.baseline_tracking),
+            min_amplitude: layers.clone().find_map(|layer| layer.min_amplitude),
+            max_amplitude: layers.clone().find_map(|layer| layer.max_amplitude),
+        }
+    }
+
+    /// Returns `false` if the overrides list digitisers, and the given channel is not covered by them.
+    ///
+    /// A channel is covered if its digitiser is listed, and either the digitiser lists the channel,
+    /// or it lists no channels at all. If the overrides list no digitisers, every channel is covered.
+    pub(crate) fn is_known_channel(&self, digitiser_id: DigitizerId, channel: Channel) -> bool {
+        if self.overrides.digitisers.is_empty() {
+            return true;
+        }
+        self.overrides
+            .digitisers
+            .get(&digitiser_id)
+            .is_some_and(|digitiser| {
+                digitiser.channels.is_empty() || digitiser.channels.contains_key(&channel)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::FixedThresholdDiscriminatorParameters;
+
+    fn create_config() -> DetectorConfig {
+        DetectorConfig::new(
+            Mode::FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters {
+                threshold: 5.0,
+                duration: 1,
+                cool_off: 0,
+            }),
+            Polarity::Positive,
+            0,
+            BaselineTracking::Disabled,
+        )
+    }
+
+    #[test]
+    fn no_overrides() {
+        let config = create_config();
+        assert_eq!(config.name(), COMMAND_LINE_CONFIG_NAME);
+
+        let settings = config.get_settings(3, 5);
+        assert!(matches!(settings.polarity, Polarity::Positive));
+        assert_eq!(settings.baseline, 0);
+    }
+
+    #[test]
+    fn overrides_from_json() {
+        let mut config = create_config();
+        let overrides: DetectorConfigOverrides = serde_json::from_str(
+            r#"{
+                "name": "test",
+                "default": { "baseline": 10 },
+                "digitisers": {
+                    "3": {
+                        "default": { "polarity": "negative" },
+                        "channels": {
+                            "5": {
+                                "baseline": 20,
+                                "mode": { "fixed-threshold-discriminator": { "threshold": 12.0 } }
+                            }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.set_overrides(overrides);
+        assert_eq!(config.name(), "test");
+
+        let settings = config.get_settings(0, 5);
+        assert!(matches!(settings.polarity, Polarity::Positive));
+        assert_eq!(settings.baseline, 10);
+
+        let settings = config.get_settings(3, 0);
+        assert!(matches!(settings.polarity, Polarity::Negative));
+        assert_eq!(settings.baseline, 10);
+
+        let settings = config.get_settings(3, 5);
+        assert!(matches!(settings.polarity, Polarity::Negative));
+        assert_eq!(settings.baseline, 20);
+        match settings.mode {
+            Mode::FixedThresholdDiscriminator(parameters) => {
+                assert_eq!(parameters.threshold, 12.0);
+                assert_eq!(parameters.duration, 1);
+                assert_eq!(parameters.cool_off, 0);
+            }
+            _ => panic!("Unexpected mode"),
+        }
+    }
+
+    #[test]
+    fn baseline_tracking_overrides() {
+        let mut config = create_config();
+        let overrides: DetectorConfigOverrides = serde_json::from_str(
+            r#"{
+                "name": "test",
+                "default": { "baseline-tracking": { "moving-median": { "window": 32 } } },
+                "digitisers": {
+                    "3": {
+                        "channels": {
+                            "5": {
+                                "baseline-tracking": {
+                                    "gated-exponential": { "smoothing-factor": 0.05, "gate": 8.0 }
+                                }
+                            },
+                            "6": { "baseline-tracking": "disabled" }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.set_overrides(overrides);
+
+        assert_eq!(
+            config.get_settings(0, 0).baseline_tracking,
+            &BaselineTracking::MovingMedian { window: 32 }
+        );
+        assert_eq!(
+            config.get_settings(3, 5).baseline_tracking,
+            &BaselineTracking::GatedExponential {
+                smoothing_factor: 0.05,
+                gate: 8.0,
+                hold_off: 0
+            }
+        );
+        assert_eq!(
+            config.get_settings(3, 6).baseline_tracking,
+            &BaselineTracking::Disabled
+        );
+    }
+
+    #[test]
+    fn known_channels() {
+        let mut config = create_config();
+        assert!(config.is_known_channel(0, 0));
+
+        let overrides: DetectorConfigOverrides = serde_json::from_str(
+            r#"{
+                "name": "test",
+                "digitisers": {
+                    "1": { "default": { "min-amplitude": 2.0 } },
+                    "3": { "channels": { "5": { "max-amplitude": 100.0 } } }
+                }
+            }"#,
+        )
+        .unwrap();
+        config.set_overrides(overrides);
+
+        assert!(!config.is_known_channel(0, 0));
+        assert!(config.is_known_channel(1, 0));
+        assert!(config.is_known_channel(1, 7));
+        assert!(!config.is_known_channel(3, 0));
+        assert!(config.is_known_channel(3, 5));
+
+        let settings = config.get_settings(1, 7);
+        assert_eq!(settings.min_amplitude, Some(2.0));
+        assert_eq!(settings.max_amplitude, None);
+
+        let settings = config.get_settings(3, 5);
+        assert_eq!(settings.min_amplitude, None);
+        assert_eq!(settings.max_amplitude, Some(100.0));
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert!(serde_json::from_str::<DetectorConfigOverrides>(r#"{ "default": {} }"#).is_err());
+    }
+}
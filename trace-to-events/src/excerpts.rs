@@ -0,0 +1,289 @@
+use crate::{parameters::TraceExcerptOptions, pulse_detection::Real};
+use std::{collections::HashMap, ops::Range};
+use supermusr_common::Channel;
+use supermusr_streaming_types::{
+    dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
+    dat3_digitizer_analog_trace_v3_generated::{
+        ChannelTrace, ChannelTraceArgs,
+        DigitizerAnalogTraceMessage as DigitizerTraceExcerptMessage,
+        DigitizerAnalogTraceMessageArgs, TraceSegment, TraceSegmentArgs,
+        finish_digitizer_analog_trace_message_buffer,
+    },
+    dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
+    flatbuffers::FlatBufferBuilder,
+    frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
+};
+
+/// Creates a `dat3` message containing the segments of each channel trace around the events found in it.
+/// If the frame is selected by the full trace prescale, the message contains the full traces instead.
+/// # Parameters
+/// - fbb: the builder in which the message is created.
+/// - trace: the trace message from which the events were found.
+/// - events: the event list message produced from `trace`.
+/// - options: the size of segments, and the full trace prescale.
+/// # Return
+/// `false` if there are neither events nor full traces to publish, in which case no message is created.
+pub fn create_trace_excerpts(
+    fbb: &mut FlatBufferBuilder<'_>,
+    trace: &DigitizerAnalogTraceMessage<'_>,
+    events: &DigitizerEventListMessage<'_>,
+    options: &TraceExcerptOptions,
+) -> bool {
+    let full_trace = options
+        .full_trace_prescale
+        .is_some_and(|prescale| trace.metadata().frame_number() % prescale.get() == 0);
+
+    let sample_time_in_ns: Real = 1_000_000_000.0 / trace.sample_rate() as Real;
+    let mut event_samples = HashMap::<Channel, Vec<usize>>::new();
+    for (channel, time) in events
+        .channel()
+        .into_iter()
+        .flatten()
+        .zip(events.time().into_iter().flatten())
+    {
+        event_samples
+            .entry(channel)
+            .or_default()
+            .push((time as Real / sample_time_in_ns).round() as usize);
+    }
+
+    if !full_trace && event_samples.is_empty() {
+        return false;
+    }
+
+    let channels = trace
+        .channels()
+        .into_iter()
+        .flatten()
+        .filter_map(|channel_trace| {
+            let voltage = channel_trace.voltage()?;
+            let ranges = if full_trace {
+                vec![0..voltage.len()]
+            } else {
+                excerpt_ranges(
+                    event_samples.remove(&channel_trace.channel())?,
+                    voltage.len(),
+                    options,
+                )
+            };
+            let segments = ranges
+                .into_iter()
+                .map(|range| {
+                    let values: Vec<_> =
+                        voltage.iter().skip(range.start).take(range.len()).collect();
+                    let voltage = Some(fbb.create_vector(&values));
+                    TraceSegment::create(
+                        fbb,
+                        &TraceSegmentArgs {
+                            start: range.start as u32,
+                            voltage,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            let segments = Some(fbb.create_vector(&segments));
+            Some(ChannelTrace::create(
+                fbb,
+                &ChannelTraceArgs {
+                    channel: channel_trace.channel(),
+                    num_samples: voltage.len() as u32,
+                    segments,
+                },
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let metadata = FrameMetadataV2Args {
+        frame_number: trace.metadata().frame_number(),
+        period_number: trace.metadata().period_number(),
+        running: trace.metadata().running(),
+        protons_per_pulse: trace.metadata().protons_per_pulse(),
+        timestamp: trace.metadata().timestamp(),
+        veto_flags: trace.metadata().veto_flags(),
+    };
+    let metadata = FrameMetadataV2::create(fbb, &metadata);
+
+    let message = DigitizerAnalogTraceMessageArgs {
+        digitizer_id: trace.digitizer_id(),
+        metadata: Some(metadata),
+        sample_rate: trace.sample_rate(),
+        channels: Some(fbb.create_vector(&channels)),
+    };
+    let message = DigitizerTraceExcerptMessage::create(fbb, &message);
+    finish_digitizer_analog_trace_message_buffer(fbb, message);
+    true
+}
+
+/// Returns the ranges of samples which lie within the excerpt of any event, merging those which overlap.
+/// # Parameters
+/// - event_samples: the sample index of each event.
+/// - num_samples: the length of the trace, to which the ranges are clamped.
+/// - options: the numbers of samples before and after each event to include.
+fn excerpt_ranges(
+    mut event_samples: Vec<usize>,
+    num_samples: usize,
+    options: &TraceExcerptOptions,
+) -> Vec<Range<usize>> {
+    event_samples.sort_unstable();
+    let mut ranges = Vec::<Range<usize>>::new();
+    for sample in event_samples {
+        let start = sample.saturating_sub(options.excerpt_pre_samples);
+        let end = sample
+            .saturating_add(options.excerpt_post_samples)
+            .saturating_add(1)
+            .min(num_samples);
+        if start >= end {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if start <= last.end => last.end = last.end.max(end),
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::num::NonZeroU32;
+    use supermusr_streaming_types::{
+        dat2_digitizer_analog_trace_v2_generated::{
+            self as dat2, finish_digitizer_analog_trace_message_buffer as finish_dat2,
+            root_as_digitizer_analog_trace_message,
+        },
+        dat3_digitizer_analog_trace_v3_generated::root_as_digitizer_analog_trace_message as root_as_dat3,
+        dev2_digitizer_event_v2_generated::{
+            DigitizerEventListMessageArgs, finish_digitizer_event_list_message_buffer,
+            root_as_digitizer_event_list_message,
+        },
+        frame_metadata_v2_generated::GpsTime,
+    };
+
+    const OPTIONS: TraceExcerptOptions = TraceExcerptOptions {
+        excerpt_pre_samples: 2,
+        excerpt_post_samples: 3,
+        full_trace_prescale: None,
+    };
+
+    #[test]
+    fn overlapping_ranges_are_merged() {
+        assert_eq!(
+            excerpt_ranges(vec![20, 1, 6, 40], 42, &OPTIONS),
+            vec![0..10, 18..24, 38..42]
+        );
+    }
+
+    fn create_messages(
+        trace_fbb: &mut FlatBufferBuilder<'_>,
+        events_fbb: &mut FlatBufferBuilder<'_>,
+        frame_number: u32,
+    ) {
+        let time: GpsTime = Utc::now().into();
+        let metadata = FrameMetadataV2Args {
+            frame_number,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            timestamp: Some(&time),
+            veto_flags: 0,
+        };
+
+        let metadata_offset = FrameMetadataV2::create(trace_fbb, &metadata);
+        let channels = [0, 1]
+            .map(|channel| {
+                let voltage = Some(trace_fbb.create_vector(&(0..20).collect::<Vec<u16>>()));
+                dat2::ChannelTrace::create(trace_fbb, &dat2::ChannelTraceArgs { channel, voltage })
+            })
+            .to_vec();
+        let message = dat2::DigitizerAnalogTraceMessageArgs {
+            digitizer_id: 3,
+            metadata: Some(metadata_offset),
+            sample_rate: 1_000_000_000,
+            channels: Some(trace_fbb.create_vector(&channels)),
+        };
+        let message = dat2::DigitizerAnalogTraceMessage::create(trace_fbb, &message);
+        finish_dat2(trace_fbb, message);
+
+        let metadata_offset = FrameMetadataV2::create(events_fbb, &metadata);
+        let message = DigitizerEventListMessageArgs {
+            digitizer_id: 3,
+            metadata: Some(metadata_offset),
+            time: Some(events_fbb.create_vector(&[5, 15])),
+            voltage: Some(events_fbb.create_vector(&[10, 10])),
+            channel: Some(events_fbb.create_vector(&[1, 1])),
+        };
+        let message = DigitizerEventListMessage::create(events_fbb, &message);
+        finish_digitizer_event_list_message_buffer(events_fbb, message);
+    }
+
+    #[test]
+    fn excerpts_around_events() {
+        let mut trace_fbb = FlatBufferBuilder::new();
+        let mut events_fbb = FlatBufferBuilder::new();
+        create_messages(&mut trace_fbb, &mut events_fbb, 1);
+        let trace = root_as_digitizer_analog_trace_message(trace_fbb.finished_data()).unwrap();
+        let events = root_as_digitizer_event_list_message(events_fbb.finished_data()).unwrap();
+
+        let mut fbb = FlatBufferBuilder::new();
+        assert!(create_trace_excerpts(&mut fbb, &trace, &events, &OPTIONS));
+        let excerpts = root_as_dat3(fbb.finished_data()).unwrap();
+
+        assert_eq!(excerpts.digitizer_id(), 3);
+        assert_eq!(excerpts.metadata().frame_number(), 1);
+        let channels = excerpts.channels().unwrap();
+        assert_eq!(channels.len(), 1);
+        let channel = channels.get(0);
+        assert_eq!(channel.channel(), 1);
+        assert_eq!(channel.num_samples(), 20);
+        let segments = channel.segments().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments.get(0).start(), 3);
+        assert_eq!(
+            segments
+                .get(0)
+                .voltage()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(segments.get(1).start(), 13);
+        assert_eq!(
+            segments
+                .get(1)
+                .voltage()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![13, 14, 15, 16, 17, 18]
+        );
+    }
+
+    #[test]
+    fn prescaled_full_traces() {
+        let mut trace_fbb = FlatBufferBuilder::new();
+        let mut events_fbb = FlatBufferBuilder::new();
+        create_messages(&mut trace_fbb, &mut events_fbb, 4);
+        let trace = root_as_digitizer_analog_trace_message(trace_fbb.finished_data()).unwrap();
+        let events = root_as_digitizer_event_list_message(events_fbb.finished_data()).unwrap();
+
+        let options = TraceExcerptOptions {
+            full_trace_prescale: NonZeroU32::new(2),
+            ..OPTIONS
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        assert!(create_trace_excerpts(&mut fbb, &trace, &events, &options));
+        let excerpts = root_as_dat3(fbb.finished_data()).unwrap();
+
+        let channels = excerpts.channels().unwrap();
+        assert_eq!(channels.len(), 2);
+        for channel in channels {
+            let segments = channel.segments().unwrap();
+            assert_eq!(segments.len(), 1);
+            assert_eq!(segments.get(0).start(), 0);
+            assert_eq!(segments.get(0).voltage().unwrap().len(), 20);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! Detects muon events in digitiser traces, and produces digitiser event list messages.
+//!
+//! This is shared by the `trace-to-events` component, which processes trace messages received from the broker,
+//! and the `trace-to-events-replay` tool, which processes trace messages recorded in files.
+mod channels;
+mod detector_config;
+mod excerpts;
+mod parameters;
+mod processing;
+mod pulse_detection;
+
+use const_format::concatcp;
+use supermusr_common::metrics::names::METRIC_NAME_PREFIX;
+
+pub use detector_config::{DetectorConfig, DetectorConfigFileError, DetectorConfigOverrides};
+pub use excerpts::create_trace_excerpts;
+pub use parameters::{
+    BaselineTracking, BaselineTrackingOptions, EventFormat, InvalidModeError, Mode, Polarity,
+    TraceExcerptOptions,
+};
+pub use processing::process;
+
+pub const EVENTS_FOUND_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "events_found");
+pub const UNKNOWN_CHANNELS_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "unknown_channels");
+pub const PULSE_FIT_RESIDUAL_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "pulse_fit_residual");
@@ -1,34 +1,30 @@
-mod channels;
-mod parameters;
-mod processing;
-mod pulse_detection;
-
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use const_format::concatcp;
-use metrics::{counter, describe_counter, describe_gauge, gauge};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use miette::IntoDiagnostic;
-use parameters::{DetectorSettings, Mode, Polarity};
 use rdkafka::{
     Message,
     consumer::{CommitMode, Consumer},
-    message::{BorrowedHeaders, BorrowedMessage},
+    message::{BorrowedMessage, Header, OwnedHeaders},
     producer::{DeliveryFuture, FutureProducer, FutureRecord},
 };
-use std::net::SocketAddr;
+use std::{collections::VecDeque, net::SocketAddr, num::NonZeroU32, path::PathBuf};
 use supermusr_common::{
-    CommonKafkaOpts, Intensity, init_tracer,
+    CommonKafkaOpts, Intensity,
+    backpressure::{Backpressure, BackpressureOpts, SendBufferFullPolicy},
+    create_latest_message_consumer, init_tracer, load_latest_message,
     metrics::{
         component_info_metric,
         failures::{self, FailureKind},
         messages_received::{self, MessageKind},
         names::{
-            FAILURES, LAST_MESSAGE_FRAME_NUMBER, LAST_MESSAGE_TIMESTAMP, MESSAGES_PROCESSED,
-            MESSAGES_RECEIVED, METRIC_NAME_PREFIX,
+            CONSUMER_PAUSED, FAILURES, LAST_MESSAGE_FRAME_NUMBER, LAST_MESSAGE_TIMESTAMP,
+            MESSAGES_PROCESSED, MESSAGES_RECEIVED, METRIC_NAME_PREFIX, SEND_BUFFER_DEPTH,
         },
     },
-    record_metadata_fields_to_span,
+    record_metadata_fields_to_span, recv_if_present,
     tracer::{FutureRecordTracerExt, OptionalHeaderTracerExt, TracerEngine, TracerOptions},
 };
 use supermusr_streaming_types::{
@@ -37,6 +33,7 @@ use supermusr_streaming_types::{
         DigitizerAnalogTraceMessage, digitizer_analog_trace_message_buffer_has_identifier,
         root_as_digitizer_analog_trace_message,
     },
+    dev2_digitizer_event_v2_generated::root_as_digitizer_event_list_message,
     flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer},
 };
 use tokio::{
@@ -45,12 +42,21 @@ use tokio::{
     sync::mpsc::{Receiver, Sender, error::TrySendError},
     task::JoinHandle,
 };
+use trace_to_events::{
+    BaselineTrackingOptions, DetectorConfig, DetectorConfigOverrides, EVENTS_FOUND_METRIC,
+    EventFormat, Mode, PULSE_FIT_RESIDUAL_METRIC, Polarity, TraceExcerptOptions,
+    UNKNOWN_CHANNELS_METRIC, create_trace_excerpts, process,
+};
 use tracing::{debug, error, info, instrument, trace, warn};
 
 type DigitiserEventListToBufferSender = Sender<DeliveryFuture>;
 type TrySendDigitiserEventListError = TrySendError<DeliveryFuture>;
 
-const EVENTS_FOUND_METRIC: &str = concatcp!(METRIC_NAME_PREFIX, "events_found");
+const DETECTOR_CONFIG_UPDATES_METRIC: &str =
+    concatcp!(METRIC_NAME_PREFIX, "detector_config_updates");
+
+/// Key of the Kafka header identifying the detector configuration used to produce an event list.
+const DETECTOR_CONFIG_HEADER: &str = "detector_config";
 
 #[derive(Debug, Parser)]
 #[clap(author, version = supermusr_common::version!(), about)]
@@ -70,6 +76,28 @@ struct Cli {
     #[clap(long)]
     event_topic: String,
 
+    /// If set, digitiser event messages are partitioned by frame across this many partitions of the event topic,
+    /// so that every digitiser's message for a frame is in the same partition. See README.md.
+    #[clap(long)]
+    frame_partitions: Option<NonZeroU32>,
+
+    /// If set, the segments of each trace around the events found in it are published to this topic as `dat3` messages.
+    #[clap(long)]
+    trace_excerpt_topic: Option<String>,
+
+    #[clap(flatten)]
+    trace_excerpt_options: TraceExcerptOptions,
+
+    /// If set, detector configuration overrides are consumed from this Kafka topic as JSON messages.
+    /// Each message replaces all previous overrides. See README.md.
+    #[clap(long)]
+    detector_config_topic: Option<String>,
+
+    /// If set, initial detector configuration overrides are loaded from this JSON file,
+    /// in the same format as messages on the detector configuration topic. See README.md.
+    #[clap(long)]
+    detector_config_file: Option<PathBuf>,
+
     /// Determines whether events should register as positive or negative intensity
     #[clap(long)]
     polarity: Polarity,
@@ -78,11 +106,21 @@ struct Cli {
     #[clap(long, default_value = "0")]
     baseline: Intensity,
 
+    #[clap(flatten)]
+    baseline_tracking: BaselineTrackingOptions,
+
+    /// The schema of the event list messages to produce
+    #[clap(long, default_value = "dev2")]
+    event_format: EventFormat,
+
     /// Size of the send eventlist buffer.
-    /// If this limit is exceeded, the component will exit.
+    /// If this limit is reached, the behaviour is determined by `--send-buffer-full-policy`.
     #[clap(long, default_value = "1024")]
     send_eventlist_buffer_size: usize,
 
+    #[clap(flatten)]
+    backpressure_options: BackpressureOpts,
+
     /// Endpoint on which OpenMetrics flavour metrics are available
     #[clap(long, env, default_value = "127.0.0.1:9090")]
     observability_address: SocketAddr,
@@ -127,6 +165,40 @@ async fn main() -> miette::Result<()> {
     )
     .into_diagnostic()?;
 
+    let detector_config_consumer = args
+        .detector_config_topic
+        .as_deref()
+        .map(|topic| {
+            create_latest_message_consumer(
+                kafka_opts,
+                &format!("{}-detector-config", args.consumer_group),
+                topic,
+            )
+        })
+        .transpose()
+        .into_diagnostic()?;
+
+    args.mode.validate().into_diagnostic()?;
+    let mut detector_config = DetectorConfig::new(
+        args.mode.clone(),
+        args.polarity,
+        args.baseline,
+        args.baseline_tracking.tracking(),
+    );
+    if let Some(path) = &args.detector_config_file {
+        detector_config.set_overrides(DetectorConfigOverrides::from_file(path).into_diagnostic()?);
+    }
+    // The most recent detector configuration is applied before any traces are consumed,
+    // so they are never processed against stale overrides.
+    if let Some((consumer, num_last_messages)) = &detector_config_consumer {
+        if let Some(m) = load_latest_message(consumer, *num_last_messages)
+            .await
+            .into_diagnostic()?
+        {
+            process_detector_config_message(&mut detector_config, &m);
+        }
+    }
+
     // Install exporter and register metrics
     let builder = PrometheusBuilder::new();
     builder
@@ -162,10 +234,41 @@ async fn main() -> miette::Result<()> {
         metrics::Unit::Count,
         "Number of events found per channel"
     );
+    describe_counter!(
+        DETECTOR_CONFIG_UPDATES_METRIC,
+        metrics::Unit::Count,
+        "Number of detector configuration overrides applied"
+    );
+    describe_counter!(
+        UNKNOWN_CHANNELS_METRIC,
+        metrics::Unit::Count,
+        "Number of channel traces not covered by the detector configuration overrides"
+    );
+    describe_histogram!(
+        PULSE_FIT_RESIDUAL_METRIC,
+        "Relative residual of the template fit of each event found by the pulse fit detector"
+    );
+    describe_gauge!(
+        SEND_BUFFER_DEPTH,
+        "Number of event lists awaiting delivery to the broker"
+    );
+    describe_gauge!(
+        CONSUMER_PAUSED,
+        "Whether consumption of traces is paused until the send buffer drains"
+    );
 
     let (sender, producer_task_handle) =
         create_producer_task(args.send_eventlist_buffer_size).into_diagnostic()?;
 
+    let mut backpressure = Backpressure::new(
+        args.backpressure_options.send_buffer_full_policy,
+        sender.max_capacity(),
+    );
+    let mut delivery_buffer =
+        DeliveryBuffer::new(sender, args.backpressure_options.send_buffer_full_policy);
+    let mut backpressure_poll_interval =
+        tokio::time::interval(args.backpressure_options.poll_interval());
+
     // Is used to await any sigint signals
     let mut sigint = signal(SignalKind::interrupt()).into_diagnostic()?;
 
@@ -178,24 +281,59 @@ async fn main() -> miette::Result<()> {
                     process_kafka_message(
                         &tracer,
                         &args,
-                        &sender,
+                        &detector_config,
+                        &mut delivery_buffer,
                         &producer,
                         &m,
-                    ).into_diagnostic()?;
+                    ).await.into_diagnostic()?;
                     consumer.commit_message(&m, CommitMode::Async).unwrap();
+                    backpressure.update(&consumer, delivery_buffer.depth());
                 }
                 Err(e) => warn!("Kafka error: {}", e)
             },
+            _ = backpressure_poll_interval.tick(), if backpressure.is_paused() => {
+                delivery_buffer.drain_overflow();
+                backpressure.update(&consumer, delivery_buffer.depth());
+            },
+            msg = recv_if_present(detector_config_consumer.as_ref().map(|(consumer, _)| consumer)) => match msg {
+                Ok(m) => process_detector_config_message(&mut detector_config, &m),
+                Err(e) => warn!("Kafka error: {}", e)
+            },
             _ = sigint.recv() => {
                 //  Wait for the channel to close and
                 //  all pending production tasks to finish
                 producer_task_handle.await.into_diagnostic()?;
+                for future in delivery_buffer.overflow {
+                    flush_eventlist(future).await;
+                }
                 return Ok(());
             }
         }
     }
 }
 
+/// Parses a detector configuration message and, if successful, replaces the current overrides.
+#[instrument(skip_all, level = "info")]
+fn process_detector_config_message(detector_config: &mut DetectorConfig, m: &impl Message) {
+    let Some(payload) = m.payload() else {
+        return;
+    };
+    match serde_json::from_slice::<DetectorConfigOverrides>(payload) {
+        Ok(overrides) => {
+            detector_config.set_overrides(overrides);
+            counter!(DETECTOR_CONFIG_UPDATES_METRIC).increment(1);
+        }
+        Err(e) => {
+            warn!("Failed to parse detector configuration: {}", e);
+            counter!(
+                FAILURES,
+                &[failures::get_label(FailureKind::UnableToDecodeMessage)]
+            )
+            .increment(1);
+        }
+    }
+}
+
 #[instrument(skip_all, level = "trace", err(level = "warn"))]
 fn spanned_root_as_digitizer_analog_trace_message(
     payload: &[u8],
@@ -204,10 +342,11 @@ fn spanned_root_as_digitizer_analog_trace_message(
 }
 
 #[instrument(skip_all, level = "debug", err(level = "warn"))]
-fn process_kafka_message(
+async fn process_kafka_message(
     tracer: &TracerEngine,
     args: &Cli,
-    sender: &DigitiserEventListToBufferSender,
+    detector_config: &DetectorConfig,
+    delivery_buffer: &mut DeliveryBuffer,
     producer: &FutureProducer,
     m: &BorrowedMessage,
 ) -> Result<(), TrySendDigitiserEventListError> {
@@ -224,16 +363,16 @@ fn process_kafka_message(
         if digitizer_analog_trace_message_buffer_has_identifier(payload) {
             match spanned_root_as_digitizer_analog_trace_message(payload) {
                 Ok(data) => {
-                    let kafka_timestamp_ms = m.timestamp().to_millis().unwrap_or(-1);
                     process_digitiser_trace_message(
                         tracer,
-                        m.headers(),
+                        m,
                         args,
-                        sender,
+                        detector_config,
+                        delivery_buffer,
                         producer,
-                        kafka_timestamp_ms,
                         data,
-                    )?
+                    )
+                    .await?
                 }
                 Err(e) => {
                     warn!("Failed to parse message: {}", e);
@@ -260,22 +399,23 @@ fn process_kafka_message(
     skip_all,
     fields(
         digitiser_id = message.digitizer_id(),
-        kafka_message_timestamp_ms = kafka_timestamp_ms,
+        kafka_message_timestamp_ms = m.timestamp().to_millis().unwrap_or(-1),
         metadata_timestamp,
         metadata_frame_number,
         metadata_period_number,
         metadata_veto_flags,
         metadata_protons_per_pulse,
         metadata_running,
+        detector_config = detector_config.name(),
     )
 )]
-fn process_digitiser_trace_message(
+async fn process_digitiser_trace_message(
     tracer: &TracerEngine,
-    headers: Option<&BorrowedHeaders>,
+    m: &BorrowedMessage,
     args: &Cli,
-    sender: &DigitiserEventListToBufferSender,
+    detector_config: &DetectorConfig,
+    delivery_buffer: &mut DeliveryBuffer,
     producer: &FutureProducer,
-    kafka_timestamp_ms: i64,
     message: DigitizerAnalogTraceMessage,
 ) -> Result<(), TrySendDigitiserEventListError> {
     let did = format!("{}", message.digitizer_id());
@@ -320,7 +460,7 @@ fn process_digitiser_trace_message(
     )
     .set(message.metadata().frame_number() as f64);
 
-    message
+    let metadata: Option<FrameMetadata> = message
         .metadata()
         .try_into()
         .inspect(|metadata: &FrameMetadata| {
@@ -328,37 +468,112 @@ fn process_digitiser_trace_message(
         })
         .ok();
 
-    headers.conditional_extract_to_current_span(tracer.use_otel());
+    m.headers()
+        .conditional_extract_to_current_span(tracer.use_otel());
     let mut fbb = FlatBufferBuilder::new();
-    processing::process(
-        &mut fbb,
-        &message,
-        &DetectorSettings {
-            polarity: &args.polarity,
-            baseline: args.baseline,
-            mode: &args.mode,
-        },
-    );
+    process(&mut fbb, &message, detector_config, args.event_format);
 
-    let future_record = FutureRecord::to(&args.event_topic)
+    let mut future_record = FutureRecord::to(&args.event_topic)
         .payload(fbb.finished_data())
+        .headers(OwnedHeaders::new().insert(Header {
+            key: DETECTOR_CONFIG_HEADER,
+            value: Some(detector_config.name()),
+        }))
         .conditional_inject_current_span_into_headers(tracer.use_otel())
         .key("Digitiser Events List");
+    if let Some((metadata, num_partitions)) = metadata.as_ref().zip(args.frame_partitions) {
+        future_record = future_record.partition(metadata.partition(num_partitions));
+    }
 
     let future = producer.send_result(future_record).expect("Producer sends");
+    delivery_buffer.push(future)?;
+
+    if let Some(topic) = &args.trace_excerpt_topic {
+        // The event list was created above, so is always valid.
+        let events =
+            root_as_digitizer_event_list_message(fbb.finished_data()).expect("Event list is valid");
+        let mut excerpt_fbb = FlatBufferBuilder::new();
+        if create_trace_excerpts(
+            &mut excerpt_fbb,
+            &message,
+            &events,
+            &args.trace_excerpt_options,
+        ) {
+            let future_record = FutureRecord::to(topic)
+                .payload(excerpt_fbb.finished_data())
+                .conditional_inject_current_span_into_headers(tracer.use_otel())
+                .key("Digitiser Trace Excerpts");
+
+            let future = producer.send_result(future_record).expect("Producer sends");
+            delivery_buffer.push(future)?;
+        }
+    }
+    Ok(())
+}
+
+/// Passes the delivery futures of published messages to the producer task, without waiting for space in its buffer.
+struct DeliveryBuffer {
+    sender: DigitiserEventListToBufferSender,
+    policy: SendBufferFullPolicy,
+    /// Under [SendBufferFullPolicy::Backpressure], futures which do not fit in the send buffer
+    /// are held here, in order, until it has space for them.
+    overflow: VecDeque<DeliveryFuture>,
+}
 
-    if let Err(e) = sender.try_send(future) {
-        match &e {
-            TrySendError::Closed(_) => {
+impl DeliveryBuffer {
+    fn new(sender: DigitiserEventListToBufferSender, policy: SendBufferFullPolicy) -> Self {
+        Self {
+            sender,
+            policy,
+            overflow: Default::default(),
+        }
+    }
+
+    /// Passes a delivery future to the producer task.
+    ///
+    /// If the send buffer is full, then under [SendBufferFullPolicy::Backpressure] the future is held
+    /// until [Self::drain_overflow] finds space for it, otherwise an error is returned.
+    fn push(&mut self, future: DeliveryFuture) -> Result<(), TrySendDigitiserEventListError> {
+        if self.policy == SendBufferFullPolicy::Backpressure {
+            self.overflow.push_back(future);
+            self.drain_overflow();
+            return if self.sender.is_closed() {
                 error!("Send-Frame Channel Closed");
+                Err(TrySendError::Closed(
+                    self.overflow.pop_back().expect("Future was pushed"),
+                ))
+            } else {
+                Ok(())
+            };
+        }
+        if let Err(e) = self.sender.try_send(future) {
+            match &e {
+                TrySendError::Closed(_) => {
+                    error!("Send-Frame Channel Closed");
+                }
+                TrySendError::Full(_) => {
+                    error!("Send-Frame Buffer Full");
+                }
             }
-            TrySendError::Full(_) => {
-                error!("Send-Frame Buffer Full");
-            }
+            Err(e)
+        } else {
+            Ok(())
         }
-        Err(e)
-    } else {
-        Ok(())
+    }
+
+    /// Moves held delivery futures into the send buffer, for as long as it has space.
+    fn drain_overflow(&mut self) {
+        while !self.overflow.is_empty() {
+            let Ok(permit) = self.sender.try_reserve() else {
+                return;
+            };
+            permit.send(self.overflow.pop_front().expect("Overflow is not empty"));
+        }
+    }
+
+    /// The number of delivery futures in the send buffer, or held until it has space.
+    fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity() + self.overflow.len()
     }
 }
 
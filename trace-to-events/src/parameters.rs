@@ -1,47 +1,159 @@
-use crate::pulse_detection::Real;
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::pulse_detection::{Real, detectors::pulse_fit_detector::BiexpTemplate};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Deserializer, de::Error};
+use std::num::{NonZeroU32, ParseFloatError, ParseIntError};
 use supermusr_common::Intensity;
+use thiserror::Error;
 
 #[derive(Debug)]
 pub(crate) struct DetectorSettings<'a> {
     pub(crate) mode: &'a Mode,
     pub(crate) polarity: &'a Polarity,
     pub(crate) baseline: Intensity,
+    /// The algorithm which tracks the baseline across the trace, after the static baseline is subtracted.
+    pub(crate) baseline_tracking: &'a BaselineTracking,
+    /// If set, events whose amplitude is less than this value are discarded.
+    pub(crate) min_amplitude: Option<Real>,
+    /// If set, events whose amplitude is greater than this value are discarded.
+    pub(crate) max_amplitude: Option<Real>,
 }
 
-#[derive(Clone, Copy, Debug, ValueEnum)]
-pub(crate) enum Polarity {
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Polarity {
     Positive,
     Negative,
 }
 
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct FixedThresholdDiscriminatorParameters {
+/// Algorithms which track the baseline continuously across the trace.
+/// These are applied after the static baseline is subtracted, and before detection, in every mode.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", rename_all_fields = "kebab-case")]
+pub enum BaselineTracking {
+    /// Only the static baseline is subtracted.
+    #[default]
+    Disabled,
+    /// The baseline is the median of the most recent `window` samples.
+    MovingMedian { window: usize },
+    /// The baseline is an exponential moving average, which is frozen whilst the trace
+    /// lies more than `gate` from the baseline, and for `hold_off` samples afterwards.
+    GatedExponential {
+        smoothing_factor: Real,
+        gate: Real,
+        #[serde(default)]
+        hold_off: usize,
+    },
+}
+
+/// The [BaselineTracking] algorithms, as selected on the command line.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum BaselineTrackingAlgorithm {
+    /// Only the static baseline is subtracted.
+    Disabled,
+    /// The baseline is the median of a moving window.
+    MovingMedian,
+    /// The baseline is an exponential moving average which is frozen during events.
+    GatedExponential,
+}
+
+/// Command line arguments which specify the default [BaselineTracking].
+#[derive(Clone, Debug, Args)]
+pub struct BaselineTrackingOptions {
+    /// Algorithm which tracks the baseline across the trace, after the static baseline is subtracted. See README.md.
+    #[clap(long, default_value = "disabled")]
+    baseline_tracking: BaselineTrackingAlgorithm,
+
+    /// The number of samples over which the moving median baseline is taken.
+    #[clap(long, default_value = "64")]
+    baseline_window: usize,
+
+    /// The smoothing factor of the gated exponential baseline.
+    #[clap(long, default_value = "0.01")]
+    baseline_smoothing_factor: Real,
+
+    /// The gated exponential baseline is frozen whilst the trace lies further than this from it.
+    #[clap(long, default_value = "10")]
+    baseline_gate: Real,
+
+    /// The number of samples, after the trace returns within the gate, for which the gated exponential baseline remains frozen.
+    #[clap(long, default_value = "0")]
+    baseline_hold_off: usize,
+}
+
+impl BaselineTrackingOptions {
+    pub fn tracking(&self) -> BaselineTracking {
+        match self.baseline_tracking {
+            BaselineTrackingAlgorithm::Disabled => BaselineTracking::Disabled,
+            BaselineTrackingAlgorithm::MovingMedian => BaselineTracking::MovingMedian {
+                window: self.baseline_window,
+            },
+            BaselineTrackingAlgorithm::GatedExponential => BaselineTracking::GatedExponential {
+                smoothing_factor: self.baseline_smoothing_factor,
+                gate: self.baseline_gate,
+                hold_off: self.baseline_hold_off,
+            },
+        }
+    }
+}
+
+/// Command line arguments which specify the trace segments published alongside each event list.
+#[derive(Clone, Debug, Args)]
+pub struct TraceExcerptOptions {
+    /// The number of samples before each event which are included in its trace segment.
+    #[clap(long, default_value = "16", requires = "trace_excerpt_topic")]
+    pub excerpt_pre_samples: usize,
+
+    /// The number of samples after each event which are included in its trace segment.
+    #[clap(long, default_value = "64", requires = "trace_excerpt_topic")]
+    pub excerpt_post_samples: usize,
+
+    /// If set, the full traces of one in every this many frames, by frame number, are published in place of excerpts.
+    #[clap(long, requires = "trace_excerpt_topic")]
+    pub full_trace_prescale: Option<NonZeroU32>,
+}
+
+/// The schema of the event list messages which are produced.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum EventFormat {
+    /// `dev2` messages, consisting of the time, voltage and channel of each event.
+    Dev2,
+    /// `dev3` messages, which also include the width, area and rise time of each event, where the detector measures them.
+    Dev3,
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Parser)]
+#[serde(rename_all = "kebab-case")]
+pub struct FixedThresholdDiscriminatorParameters {
     /// If the detector is armed, an event is registered when the trace passes this value for the given duration.
     #[clap(long)]
     pub(crate) threshold: Real,
 
     /// The duration, in samples, that the trace must exceed the threshold for.
     #[clap(long, default_value = "1")]
+    #[serde(default = "default_duration")]
     pub(crate) duration: i32,
 
     /// After an event is registered, the detector disarms for this many samples.
     #[clap(long, default_value = "0")]
+    #[serde(default)]
     pub(crate) cool_off: i32,
 }
 
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct DifferentialThresholdDiscriminatorParameters {
+#[derive(Default, Debug, Clone, Deserialize, Parser)]
+#[serde(rename_all = "kebab-case")]
+pub struct DifferentialThresholdDiscriminatorParameters {
     /// If the detector is armed, an event is registered when the trace passes this value for the given duration.
     #[clap(long)]
     pub(crate) threshold: Real,
 
     /// The duration, in samples, that the trace must exceed the threshold for.
     #[clap(long, default_value = "1")]
+    #[serde(default = "default_duration")]
     pub(crate) duration: i32,
 
     /// After an event is registered, the detector disarms for this many samples.
     #[clap(long, default_value = "0")]
+    #[serde(default)]
     pub(crate) cool_off: i32,
 
     /// If set, the pulse height is the value of the rising edge, scaled by this factor,
@@ -49,8 +161,9 @@ pub(crate) struct DifferentialThresholdDiscriminatorParameters {
     pub(crate) constant_multiple: Option<Real>,
 }
 
-#[derive(Default, Debug, Clone, Parser)]
-pub(crate) struct AdvancedMuonDetectorParameters {
+#[derive(Default, Debug, Clone, Deserialize, Parser)]
+#[serde(rename_all = "kebab-case")]
+pub struct AdvancedMuonDetectorParameters {
     /// Differential threshold for detecting muon onset. See README.md.
     #[clap(long)]
     pub(crate) muon_onset: Real,
@@ -84,12 +197,209 @@ pub(crate) struct AdvancedMuonDetectorParameters {
     pub(crate) min_amplitude: Option<Real>,
 }
 
-#[derive(Subcommand, Debug)]
-pub(crate) enum Mode {
+#[derive(Default, Debug, Clone, Deserialize, Parser)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConstantFractionDiscriminatorParameters {
+    /// The detector is armed whilst the trace exceeds this value.
+    #[clap(long)]
+    pub(crate) threshold: Real,
+
+    /// The number of samples by which the trace is delayed before the attenuated trace is subtracted from it. Must be at least 1.
+    #[clap(long, default_value = "1", value_parser = parse_delay)]
+    #[serde(default = "default_delay", deserialize_with = "deserialize_delay")]
+    pub(crate) delay: usize,
+
+    /// The factor by which the trace is attenuated before being subtracted from the delayed trace. Must lie strictly between 0 and 1.
+    #[clap(long, default_value = "0.5", value_parser = parse_fraction)]
+    #[serde(
+        default = "default_fraction",
+        deserialize_with = "deserialize_fraction"
+    )]
+    pub(crate) fraction: Real,
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Parser)]
+#[serde(rename_all = "kebab-case")]
+pub struct PulseFitDetectorParameters {
+    /// Regions in which the trace exceeds this value are fitted. Pulses are added to the fit whilst the residual exceeds it.
+    #[clap(long)]
+    pub(crate) threshold: Real,
+
+    /// The rise time constant of the biexponential pulse template, in ns.
+    #[clap(long)]
+    pub(crate) rise: Real,
+
+    /// The decay time constant of the biexponential pulse template, in ns. Must be greater than the rise time constant.
+    #[clap(long)]
+    pub(crate) decay: Real,
+
+    /// The maximum number of pulses fitted to a single region.
+    #[clap(long, default_value = "4")]
+    #[serde(default = "default_max_pulses")]
+    pub(crate) max_pulses: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Subcommand)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
     /// Detects events using a fixed threshold discriminator. Event lists consist of time and voltage values.
     FixedThresholdDiscriminator(FixedThresholdDiscriminatorParameters),
     /// Detects events using a differential threshold discriminator. Event lists consist of time and voltage values.
     DifferentialThresholdDiscriminator(DifferentialThresholdDiscriminatorParameters),
     /// Detects events using differential discriminators. Event lists consist of time and voltage values.
     AdvancedMuonDetector(AdvancedMuonDetectorParameters),
+    /// Detects events using a constant fraction discriminator. Event lists consist of time and voltage values.
+    ConstantFractionDiscriminator(ConstantFractionDiscriminatorParameters),
+    /// Detects events by fitting a pulse template, separating overlapping pulses. Event lists consist of time and voltage values.
+    PulseFitDetector(PulseFitDetectorParameters),
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidModeError {
+    #[error("Invalid pulse template: rise {rise} must be positive and less than decay {decay}")]
+    PulseTemplate { rise: Real, decay: Real },
+}
+
+impl PulseFitDetectorParameters {
+    /// Returns the pulse template given by [Self::rise] and [Self::decay].
+    pub(crate) fn template(&self) -> Result<BiexpTemplate, InvalidModeError> {
+        BiexpTemplate::new(self.rise, self.decay).ok_or(InvalidModeError::PulseTemplate {
+            rise: self.rise,
+            decay: self.decay,
+        })
+    }
+}
+
+impl Mode {
+    /// Checks the parameters of the mode which cannot be validated individually.
+    /// This is done when the command line or a detector configuration is parsed,
+    /// so that an invalid mode is rejected once, rather than for every trace.
+    pub fn validate(&self) -> Result<(), InvalidModeError> {
+        match self {
+            Mode::PulseFitDetector(parameters) => parameters.template().map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Deserialises an optional [Mode], rejecting it if it is invalid.
+pub(crate) fn deserialize_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Mode>, D::Error> {
+    let mode = Option::<Mode>::deserialize(deserializer)?;
+    if let Some(mode) = &mode {
+        mode.validate().map_err(D::Error::custom)?;
+    }
+    Ok(mode)
+}
+
+/// Matches the default value of the `--duration` command line argument.
+fn default_duration() -> i32 {
+    1
+}
+
+/// Matches the default value of the `--delay` command line argument.
+fn default_delay() -> usize {
+    1
+}
+
+/// Matches the default value of the `--fraction` command line argument.
+fn default_fraction() -> Real {
+    0.5
+}
+
+/// Checks the constant fraction discriminator's delay, without which the bipolar signal
+/// is the attenuated trace itself, and never crosses zero.
+fn validate_delay(delay: usize) -> Result<usize, String> {
+    if delay >= 1 {
+        Ok(delay)
+    } else {
+        Err("delay must be at least 1".to_owned())
+    }
+}
+
+/// Checks the constant fraction discriminator's fraction, outside of which the bipolar signal
+/// of a unipolar pulse does not cross zero.
+fn validate_fraction(fraction: Real) -> Result<Real, String> {
+    if fraction > 0.0 && fraction < 1.0 {
+        Ok(fraction)
+    } else {
+        Err("fraction must lie strictly between 0 and 1".to_owned())
+    }
+}
+
+/// Parses the `--delay` command line argument.
+fn parse_delay(s: &str) -> Result<usize, String> {
+    validate_delay(s.parse().map_err(|e: ParseIntError| e.to_string())?)
+}
+
+/// Parses the `--fraction` command line argument.
+fn parse_fraction(s: &str) -> Result<Real, String> {
+    validate_fraction(s.parse().map_err(|e: ParseFloatError| e.to_string())?)
+}
+
+/// Deserialises the `delay` field of a detector configuration.
+fn deserialize_delay<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    validate_delay(usize::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Deserialises the `fraction` field of a detector configuration.
+fn deserialize_fraction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Real, D::Error> {
+    validate_fraction(Real::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Matches the default value of the `--max-pulses` command line argument.
+fn default_max_pulses() -> usize {
+    4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_pulse_template_is_rejected() {
+        let mode = |rise, decay| {
+            Mode::PulseFitDetector(PulseFitDetectorParameters {
+                threshold: 10.0,
+                rise,
+                decay,
+                max_pulses: 4,
+            })
+        };
+        assert!(mode(2.0, 10.0).validate().is_ok());
+        assert!(mode(10.0, 2.0).validate().is_err());
+        assert!(
+            serde_json::from_str::<crate::detector_config::SettingsOverride>(
+                r#"{ "mode": { "pulse-fit-detector": { "threshold": 10.0, "rise": 0.0, "decay": 5.0 } } }"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn constant_fraction_delay_must_be_positive() {
+        assert!(parse_delay("0").is_err());
+        assert_eq!(parse_delay("3"), Ok(3));
+        assert!(
+            serde_json::from_str::<ConstantFractionDiscriminatorParameters>(
+                r#"{ "threshold": 10.0, "delay": 0 }"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn constant_fraction_fraction_must_lie_in_unit_interval() {
+        assert!(parse_fraction("0").is_err());
+        assert!(parse_fraction("1").is_err());
+        assert!(parse_fraction("-0.5").is_err());
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert!(
+            serde_json::from_str::<ConstantFractionDiscriminatorParameters>(
+                r#"{ "threshold": 10.0, "fraction": 1.5 }"#
+            )
+            .is_err()
+        );
+    }
 }
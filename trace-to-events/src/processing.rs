@@ -1,4 +1,9 @@
-use crate::{channels::find_channel_events, parameters::DetectorSettings, pulse_detection::Real};
+use crate::{
+    channels::{PulseShape, find_channel_events},
+    detector_config::DetectorConfig,
+    parameters::EventFormat,
+    pulse_detection::Real,
+};
 use metrics::counter;
 use rayon::prelude::*;
 use supermusr_common::{
@@ -11,16 +16,18 @@ use supermusr_streaming_types::{
         DigitizerEventListMessage, DigitizerEventListMessageArgs,
         finish_digitizer_event_list_message_buffer,
     },
-    flatbuffers::FlatBufferBuilder,
+    dev3_digitizer_event_v3_generated as dev3,
+    flatbuffers::{FlatBufferBuilder, Vector, WIPOffset},
     frame_metadata_v2_generated::{FrameMetadataV2, FrameMetadataV2Args},
 };
 use tracing::debug;
 
 #[tracing::instrument(skip_all, fields(num_total_pulses = tracing::field::Empty))]
-pub(crate) fn process<'a>(
+pub fn process<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     trace: &'a DigitizerAnalogTraceMessage,
-    detector_settings: &DetectorSettings,
+    detector_config: &DetectorConfig,
+    event_format: EventFormat,
 ) {
     debug!(
         "Dig ID: {}, Metadata: {:?}",
@@ -45,10 +52,20 @@ pub(crate) fn process<'a>(
 
             channel_span.in_scope(|| {
                 let channel = spanned_channel_trace.channel();
+                if !detector_config.is_known_channel(trace.digitizer_id(), channel) {
+                    counter!(
+                        crate::UNKNOWN_CHANNELS_METRIC,
+                        &[
+                            ("digitizer_id", format!("{}", trace.digitizer_id())),
+                            ("channel", format!("{channel}"))
+                        ]
+                    )
+                    .increment(1);
+                }
                 let events = find_channel_events(
                     spanned_channel_trace,
                     sample_time_in_ns,
-                    detector_settings,
+                    &detector_config.get_settings(trace.digitizer_id(), channel),
                 );
                 (channel, events)
             })
@@ -56,8 +73,9 @@ pub(crate) fn process<'a>(
         .collect();
 
     let mut events = EventData::default();
-    for (channel, (time, voltage)) in vec {
-        let num_events = voltage.len();
+    let mut shapes = Vec::<PulseShape>::new();
+    for (channel, channel_events) in vec {
+        let num_events = channel_events.time.len();
         counter!(
             crate::EVENTS_FOUND_METRIC,
             &[
@@ -67,9 +85,10 @@ pub(crate) fn process<'a>(
         )
         .increment(num_events as u64);
 
-        events.channel.extend_from_slice(&vec![channel; time.len()]);
-        events.time.extend_from_slice(&time);
-        events.voltage.extend_from_slice(&voltage);
+        events.channel.extend_from_slice(&vec![channel; num_events]);
+        events.time.extend_from_slice(&channel_events.time);
+        events.voltage.extend_from_slice(&channel_events.voltage);
+        shapes.extend_from_slice(&channel_events.shape);
     }
 
     let metadata = FrameMetadataV2Args {
@@ -86,24 +105,62 @@ pub(crate) fn process<'a>(
     let voltage = Some(fbb.create_vector(&events.voltage));
     let channel = Some(fbb.create_vector(&events.channel));
 
-    let message = DigitizerEventListMessageArgs {
-        digitizer_id: trace.digitizer_id(),
-        metadata: Some(metadata),
-        time,
-        voltage,
-        channel,
-    };
-    let message = DigitizerEventListMessage::create(fbb, &message);
-    finish_digitizer_event_list_message_buffer(fbb, message);
+    match event_format {
+        EventFormat::Dev2 => {
+            let message = DigitizerEventListMessageArgs {
+                digitizer_id: trace.digitizer_id(),
+                metadata: Some(metadata),
+                time,
+                voltage,
+                channel,
+            };
+            let message = DigitizerEventListMessage::create(fbb, &message);
+            finish_digitizer_event_list_message_buffer(fbb, message);
+        }
+        EventFormat::Dev3 => {
+            let message = dev3::DigitizerEventListMessageArgs {
+                digitizer_id: trace.digitizer_id(),
+                metadata: Some(metadata),
+                time,
+                voltage,
+                channel,
+                width: create_shape_vector(fbb, &shapes, |shape| shape.width),
+                area: create_shape_vector(fbb, &shapes, |shape| shape.area),
+                rise_time: create_shape_vector(fbb, &shapes, |shape| shape.rise_time),
+            };
+            let message = dev3::DigitizerEventListMessage::create(fbb, &message);
+            dev3::finish_digitizer_event_list_message_buffer(fbb, message);
+        }
+    }
 
     tracing::Span::current().record("num_total_pulses", events.channel.len());
 }
 
+/// Creates a vector of one of the pulse shape fields, with NaN for events which lack the field.
+/// Returns [None] if no event has the field, in which case it is omitted from the message.
+fn create_shape_vector<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    shapes: &[PulseShape],
+    field: impl Fn(&PulseShape) -> Option<f32>,
+) -> Option<WIPOffset<Vector<'a, f32>>> {
+    shapes.iter().any(|shape| field(shape).is_some()).then(|| {
+        let values: Vec<f32> = shapes
+            .iter()
+            .map(|shape| field(shape).unwrap_or(f32::NAN))
+            .collect();
+        fbb.create_vector(&values)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         Mode, Polarity,
-        parameters::{AdvancedMuonDetectorParameters, FixedThresholdDiscriminatorParameters},
+        parameters::{
+            AdvancedMuonDetectorParameters, BaselineTracking,
+            ConstantFractionDiscriminatorParameters, FixedThresholdDiscriminatorParameters,
+            PulseFitDetectorParameters,
+        },
     };
 
     use super::*;
@@ -185,11 +242,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::FixedThresholdDiscriminator(test_parameters),
-                polarity: &Polarity::Positive,
-                baseline: Intensity::default(),
-            },
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -235,11 +294,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::FixedThresholdDiscriminator(test_parameters),
-                polarity: &Polarity::Positive,
-                baseline: Intensity::default(),
-            },
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -263,6 +324,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fixed_threshold_discriminator_per_channel_overrides() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+        let channels: Vec<&[Intensity]> = vec![
+            [0, 1, 2, 1, 0, 1, 2, 1, 9, 0, 2, 8, 3, 1, 2].as_slice(),
+            [3, 4, 5, 4, 3, 4, 5, 4, 11, 3, 5, 12, 6, 4, 5].as_slice(),
+        ];
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+        };
+        let mut detector_config = DetectorConfig::new(
+            Mode::FixedThresholdDiscriminator(test_parameters),
+            Polarity::Positive,
+            Intensity::default(),
+            BaselineTracking::default(),
+        );
+        detector_config.set_overrides(
+            serde_json::from_str(
+                r#"{
+                    "name": "test",
+                    "digitisers": {
+                        "0": { "channels": { "1": { "baseline": 3, "min-amplitude": 9.0 } } }
+                    }
+                }"#,
+            )
+            .unwrap(),
+        );
+        let mut fbb = FlatBufferBuilder::new();
+        process(&mut fbb, &message, &detector_config, EventFormat::Dev2);
+
+        assert!(digitizer_event_list_message_buffer_has_identifier(
+            fbb.finished_data()
+        ));
+        let event_message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+
+        assert_eq!(
+            vec![0, 0, 1],
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![8, 11, 11],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![9, 8, 9],
+            event_message.voltage().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn advanced_positive_zero_baseline() {
         let mut fbb = FlatBufferBuilder::new();
@@ -286,11 +406,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::AdvancedMuonDetector(test_parameters),
-                polarity: &Polarity::Positive,
-                baseline: Intensity::default(),
-            },
+            &DetectorConfig::new(
+                Mode::AdvancedMuonDetector(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -314,6 +436,220 @@ mod tests {
         );
     }
 
+    #[test]
+    fn constant_fraction_discriminator_positive_zero_baseline() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+        let channels: Vec<&[Intensity]> =
+            vec![[0, 1, 2, 1, 0, 1, 2, 1, 8, 0, 2, 8, 3, 1, 2].as_slice()];
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = ConstantFractionDiscriminatorParameters {
+            threshold: 5.0,
+            delay: 1,
+            fraction: 0.5,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        process(
+            &mut fbb,
+            &message,
+            &DetectorConfig::new(
+                Mode::ConstantFractionDiscriminator(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
+        );
+
+        assert!(digitizer_event_list_message_buffer_has_identifier(
+            fbb.finished_data()
+        ));
+        let event_message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+
+        assert_eq!(
+            vec![0, 0],
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        // The zero-crossings occur at 8 + 3/11 and 11 + 2/8.5, which are rounded to the nearest sample.
+        assert_eq!(
+            vec![8, 11],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![8, 8],
+            event_message.voltage().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn constant_fraction_discriminator_dev3() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+        let channels: Vec<&[Intensity]> =
+            vec![[0, 1, 2, 1, 0, 1, 2, 1, 8, 0, 2, 8, 3, 1, 2].as_slice()];
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = ConstantFractionDiscriminatorParameters {
+            threshold: 5.0,
+            delay: 1,
+            fraction: 0.5,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        process(
+            &mut fbb,
+            &message,
+            &DetectorConfig::new(
+                Mode::ConstantFractionDiscriminator(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev3,
+        );
+
+        assert!(dev3::digitizer_event_list_message_buffer_has_identifier(
+            fbb.finished_data()
+        ));
+        let event_message =
+            dev3::root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+
+        assert_eq!(
+            vec![8, 11],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![8, 8],
+            event_message.voltage().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        // Each pulse starts and peaks on the first sample above the threshold,
+        // and ends on the next sample, which is excluded from the area.
+        assert_eq!(
+            vec![1.0, 1.0],
+            event_message.width().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![8.0, 8.0],
+            event_message.area().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0.0, 0.0],
+            event_message
+                .rise_time()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn fixed_threshold_discriminator_dev3_omits_pulse_shape() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+        let channels: Vec<&[Intensity]> =
+            vec![[0, 1, 2, 1, 0, 1, 2, 1, 8, 0, 2, 8, 3, 1, 2].as_slice()];
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        process(
+            &mut fbb,
+            &message,
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev3,
+        );
+
+        let event_message =
+            dev3::root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+        assert_eq!(
+            vec![8, 11],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert!(event_message.width().is_none());
+        assert!(event_message.area().is_none());
+        assert!(event_message.rise_time().is_none());
+    }
+
+    #[test]
+    fn pulse_fit_detector_pile_up() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        // Biexponential pulses (rise 2, decay 10) starting at 10.0 with height 100,
+        // and at 16.2 with height 60, rounded to the nearest integer.
+        let time: GpsTime = Utc::now().into();
+        let channels: Vec<&[Intensity]> = vec![
+            [
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 56, 84, 97, 100, 98, 93, 116, 129, 131, 127, 121,
+                112, 104, 95, 87, 79, 72, 65, 59, 53, 48, 44, 40, 36, 32, 29, 27, 24, 22,
+            ]
+            .as_slice(),
+        ];
+        create_message(&mut fbb, &channels, &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = PulseFitDetectorParameters {
+            threshold: 5.0,
+            rise: 2.0,
+            decay: 10.0,
+            max_pulses: 4,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        process(
+            &mut fbb,
+            &message,
+            &DetectorConfig::new(
+                Mode::PulseFitDetector(test_parameters),
+                Polarity::Positive,
+                Intensity::default(),
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
+        );
+
+        assert!(digitizer_event_list_message_buffer_has_identifier(
+            fbb.finished_data()
+        ));
+        let event_message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+
+        assert_eq!(
+            vec![0, 0],
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        // The template peaks 4.02 after the start of each pulse.
+        assert_eq!(
+            vec![14, 20],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![99, 60],
+            event_message.voltage().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn fixed_threshold_discriminator_positive_nonzero_baseline() {
         let mut fbb = FlatBufferBuilder::new();
@@ -333,11 +669,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::FixedThresholdDiscriminator(test_parameters),
-                polarity: &Polarity::Positive,
-                baseline: 3,
-            },
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Positive,
+                3,
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -361,6 +699,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fixed_threshold_discriminator_moving_median_baseline() {
+        let mut fbb = FlatBufferBuilder::new();
+
+        // A baseline drifting upwards from 3, with a single pulse at index 10.
+        let time: GpsTime = Utc::now().into();
+        let channel0: Vec<u16> = vec![3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 22, 14, 15, 16, 17];
+        create_message(&mut fbb, &[channel0.as_slice()], &time);
+        let message = fbb.finished_data().to_vec();
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = FixedThresholdDiscriminatorParameters {
+            threshold: 5.0,
+            duration: 1,
+            cool_off: 0,
+        };
+        let mut fbb = FlatBufferBuilder::new();
+        process(
+            &mut fbb,
+            &message,
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Positive,
+                3,
+                BaselineTracking::MovingMedian { window: 5 },
+            ),
+            EventFormat::Dev2,
+        );
+
+        let event_message = root_as_digitizer_event_list_message(fbb.finished_data()).unwrap();
+
+        assert_eq!(
+            vec![10],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![11],
+            event_message.voltage().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn advanced_positive_nonzero_baseline() {
         let mut fbb = FlatBufferBuilder::new();
@@ -384,11 +764,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::AdvancedMuonDetector(test_parameters),
-                polarity: &Polarity::Positive,
-                baseline: 3,
-            },
+            &DetectorConfig::new(
+                Mode::AdvancedMuonDetector(test_parameters),
+                Polarity::Positive,
+                3,
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -431,11 +813,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::FixedThresholdDiscriminator(test_parameters),
-                polarity: &Polarity::Negative,
-                baseline: 10,
-            },
+            &DetectorConfig::new(
+                Mode::FixedThresholdDiscriminator(test_parameters),
+                Polarity::Negative,
+                10,
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
@@ -482,11 +866,13 @@ mod tests {
         process(
             &mut fbb,
             &message,
-            &DetectorSettings {
-                mode: &Mode::AdvancedMuonDetector(test_parameters),
-                polarity: &Polarity::Negative,
-                baseline: 10,
-            },
+            &DetectorConfig::new(
+                Mode::AdvancedMuonDetector(test_parameters),
+                Polarity::Negative,
+                10,
+                BaselineTracking::default(),
+            ),
+            EventFormat::Dev2,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(
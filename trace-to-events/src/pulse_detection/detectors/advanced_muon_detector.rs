@@ -303,6 +303,8 @@ impl Assembler for AdvancedMuonAssembler {
                     end: end.into(),
                     steepest_rise: steepest_rise.into(),
                     sharpest_fall: sharpest_fall.into(),
+                    zero_crossing: Default::default(),
+                    fit_residual: None,
                 }
             }),
         }
@@ -0,0 +1,284 @@
+use super::{Assembler, Detector, EventData, EventPoint, Pulse, Real, TimeValue};
+use std::{collections::VecDeque, fmt::Display};
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) enum Class {
+    #[default]
+    Crossing,
+    End,
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Crossing => "0",
+            Self::End => "-1",
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    class: Class,
+    value: Real,
+    /// For events of class [Class::End], the time and value at which the detector was armed.
+    start: Option<TimeValue<Real>>,
+    /// For events of class [Class::End], the time and value of the highest point of the pulse.
+    peak: Option<TimeValue<Real>>,
+}
+
+impl Data {
+    pub(crate) fn get_class(&self) -> Class {
+        self.class.clone()
+    }
+
+    pub(crate) fn get_value(&self) -> Real {
+        self.value
+    }
+}
+
+impl EventData for Data {}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{0},{1}", self.class, self.value))
+    }
+}
+
+type ConstantFractionEvent = (Real, Data);
+
+/// Detects pulses using a constant fraction discriminator.
+///
+/// The trace is delayed by `delay` samples, and the undelayed trace, attenuated by `fraction`,
+/// is subtracted from it. For each pulse this bipolar signal passes from negative to positive
+/// at a time which is independent of the pulse amplitude.
+/// The detector is armed when the trace exceeds `threshold`, and disarmed when it falls back below it.
+/// Whilst armed, the first zero-crossing of the bipolar signal is registered, its time
+/// being linearly interpolated between samples.
+#[derive(Default, Clone)]
+pub(crate) struct ConstantFractionDetector {
+    threshold: Real,
+    delay: usize,
+    fraction: Real,
+
+    /// The most recent `delay + 1` trace values.
+    history: VecDeque<Real>,
+    /// The time and value of the bipolar signal at the previous sample.
+    previous: Option<TimeValue<Real>>,
+    /// If armed, the time and value at which the detector was armed.
+    start: Option<TimeValue<Real>>,
+    peak: TimeValue<Real>,
+    crossed: bool,
+    /// Events found but not yet returned, as more than one event can occur in a single sample.
+    pending: VecDeque<ConstantFractionEvent>,
+}
+
+impl ConstantFractionDetector {
+    pub(crate) fn new(threshold: Real, delay: usize, fraction: Real) -> Self {
+        Self {
+            threshold,
+            delay,
+            fraction,
+            history: VecDeque::with_capacity(delay + 1),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the value of the bipolar signal, or [None] if too few samples have been seen.
+    fn bipolar_value(&mut self, value: Real) -> Option<Real> {
+        self.history.push_back(value);
+        if self.history.len() > self.delay {
+            let delayed = self.history.pop_front()?;
+            Some(delayed - self.fraction * value)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the time at which the bipolar signal passes through zero,
+    /// if it does so between the previous sample and the current one.
+    fn interpolated_crossing(&self, time: Real, bipolar: Real) -> Option<Real> {
+        let previous = self.previous.as_ref()?;
+        (previous.value < 0.0 && bipolar >= 0.0).then(|| {
+            previous.time + (time - previous.time) * previous.value / (previous.value - bipolar)
+        })
+    }
+}
+
+impl Detector for ConstantFractionDetector {
+    type TracePointType = (Real, Real);
+    type EventPointType = (Real, Data);
+
+    fn signal(&mut self, time: Real, value: Real) -> Option<ConstantFractionEvent> {
+        let bipolar = self.bipolar_value(value);
+
+        if self.start.is_none() && value > self.threshold {
+            self.start = Some(TimeValue { time, value });
+            self.peak = TimeValue { time, value };
+            self.crossed = false;
+        }
+
+        if let Some(start) = &self.start {
+            if value > self.peak.value {
+                self.peak = TimeValue { time, value };
+            }
+
+            if let Some(crossing) = bipolar
+                .filter(|_| !self.crossed)
+                .and_then(|bipolar| self.interpolated_crossing(time, bipolar))
+            {
+                self.crossed = true;
+                self.pending.push_back((
+                    crossing,
+                    Data {
+                        class: Class::Crossing,
+                        value,
+                        ..Default::default()
+                    },
+                ));
+            }
+
+            if value <= self.threshold {
+                self.pending.push_back((
+                    time,
+                    Data {
+                        class: Class::End,
+                        value,
+                        start: Some(start.clone()),
+                        peak: Some(self.peak.clone()),
+                    },
+                ));
+                self.start = None;
+            }
+        }
+
+        self.previous = bipolar.map(|value| TimeValue { time, value });
+        self.pending.pop_front()
+    }
+
+    fn finish(&mut self) -> Option<Self::EventPointType> {
+        self.pending.pop_front()
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct ConstantFractionAssembler {
+    crossing: Option<TimeValue<Real>>,
+}
+
+impl Assembler for ConstantFractionAssembler {
+    type DetectorType = ConstantFractionDetector;
+
+    fn assemble_pulses(&mut self, source: (Real, Data)) -> Option<Pulse> {
+        match source.get_data().get_class() {
+            Class::Crossing => {
+                self.crossing = Some(TimeValue {
+                    time: source.get_time(),
+                    value: source.get_data().get_value(),
+                });
+                None
+            }
+            Class::End => {
+                // Pulses which end without a zero-crossing are discarded.
+                let crossing = self.crossing.take()?;
+                Some(Pulse {
+                    start: source.get_data().start.clone().unwrap_or_default().into(),
+                    end: TimeValue {
+                        time: source.get_time(),
+                        value: source.get_data().get_value(),
+                    }
+                    .into(),
+                    peak: source.get_data().peak.clone().unwrap_or_default().into(),
+                    zero_crossing: crossing.into(),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{AssembleFilter, EventFilter};
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn zero_data() {
+        let data: [Real; 0] = [];
+        let detector = ConstantFractionDetector::new(2.0, 1, 0.5);
+        let mut iter = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .events(detector);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_single_pulse() {
+        // The bipolar signal is:
+        //           -, 0, -2, -1, 6, 6, 3.5, 1
+        // so the zero-crossing lies 1/7 of a sample after index 3.
+        let data = [0, 0, 4, 10, 8, 4, 1, 0];
+        let detector = ConstantFractionDetector::new(2.0, 1, 0.5);
+        let mut iter = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .events(detector);
+
+        let (time, data) = iter.next().unwrap();
+        assert_approx_eq!(time, 3.0 + 1.0 / 7.0);
+        assert_eq!(data.get_class(), Class::Crossing);
+
+        let (time, data) = iter.next().unwrap();
+        assert_eq!(time, 6.0);
+        assert_eq!(data.get_class(), Class::End);
+        assert_eq!(
+            data.peak,
+            Some(TimeValue {
+                time: 3.0,
+                value: 10.0
+            })
+        );
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_amplitude_independence() {
+        let shape = [0.0, 0.0, 1.0, 3.0, 5.0, 4.0, 2.0, 1.0, 0.0];
+        let crossing_times = [1.0, 3.0, 10.0].map(|scale| {
+            let detector = ConstantFractionDetector::new(0.5, 2, 0.4);
+            shape
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| (i as Real, scale * v))
+                .events(detector)
+                .assemble(ConstantFractionAssembler::default())
+                .map(|pulse| pulse.zero_crossing.time.unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(crossing_times[0].len(), 1);
+        assert_approx_eq!(crossing_times[0][0], crossing_times[1][0]);
+        assert_approx_eq!(crossing_times[0][0], crossing_times[2][0]);
+    }
+
+    #[test]
+    fn test_two_pulses() {
+        let data = [0, 5, 10, 3, 0, 0, 8, 16, 6, 0];
+        let detector = ConstantFractionDetector::new(2.0, 1, 0.5);
+        let pulses = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .events(detector)
+            .assemble(ConstantFractionAssembler::default())
+            .collect::<Vec<_>>();
+
+        assert_eq!(pulses.len(), 2);
+        assert_eq!(pulses[0].peak.value, Some(10.0));
+        assert_eq!(pulses[1].peak.value, Some(16.0));
+        assert!(pulses[0].zero_crossing.time < pulses[1].zero_crossing.time);
+    }
+}
@@ -1,5 +1,7 @@
 pub mod advanced_muon_detector;
+pub mod constant_fraction_detector;
 pub mod differential_threshold_detector;
+pub mod pulse_fit_detector;
 pub mod threshold_detector;
 
 use super::{EventData, EventPoint, Pulse, Real, RealArray, TracePoint, pulse::TimeValue};
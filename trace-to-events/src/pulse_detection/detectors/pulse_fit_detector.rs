@@ -0,0 +1,492 @@
+use super::{Assembler, Detector, EventData, EventPoint, Pulse, Real, TimeValue};
+use std::{collections::VecDeque, fmt::Display};
+
+/// Start times are refined until they are known to within this fraction of the sample interval.
+const REFINEMENT_PRECISION: Real = 1e-4;
+/// The maximum number of refinement iterations made each time a pulse is added to a fit.
+const MAX_REFINEMENT_ITERATIONS: usize = 200;
+
+/// A biexponential pulse shape, with the same form as the simulator's `PulseTemplate::Biexp`,
+/// normalised to have a peak height of one.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct BiexpTemplate {
+    rise: Real,
+    decay: Real,
+    /// The time from the start of the pulse to its peak.
+    peak_time: Real,
+    /// The reciprocal of the unnormalised peak height.
+    coef: Real,
+}
+
+impl BiexpTemplate {
+    /// Returns [None] unless `0 < rise < decay`.
+    pub(crate) fn new(rise: Real, decay: Real) -> Option<Self> {
+        (0.0 < rise && rise < decay).then(|| {
+            let peak_time = Real::ln(decay / rise) * rise * decay / (decay - rise);
+            let peak_height = Real::exp(-peak_time / decay) - Real::exp(-peak_time / rise);
+            Self {
+                rise,
+                decay,
+                peak_time,
+                coef: 1.0 / peak_height,
+            }
+        })
+    }
+
+    /// Returns the value of a unit height pulse, starting at `start`, at the given time.
+    pub(crate) fn value_at(&self, start: Real, time: Real) -> Real {
+        if time < start {
+            Real::default()
+        } else {
+            let time = time - start;
+            self.coef * (Real::exp(-time / self.decay) - Real::exp(-time / self.rise))
+        }
+    }
+
+    pub(crate) fn peak_time(&self) -> Real {
+        self.peak_time
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    amplitude: Real,
+    /// The fitted start time of the pulse.
+    start: Real,
+    /// The residual of the fit of the region containing the pulse, relative to the size of the region's signal.
+    /// Zero indicates a perfect fit.
+    residual: Real,
+}
+
+impl Data {
+    pub(crate) fn get_amplitude(&self) -> Real {
+        self.amplitude
+    }
+
+    pub(crate) fn get_residual(&self) -> Real {
+        self.residual
+    }
+}
+
+impl EventData for Data {}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{0},{1},{2}",
+            self.amplitude, self.start, self.residual
+        ))
+    }
+}
+
+type PulseFitEvent = (Real, Data);
+
+/// The result of fitting a fixed set of start times to a region.
+#[derive(Default, Debug, Clone)]
+struct Fit {
+    starts: Vec<Real>,
+    amplitudes: Vec<Real>,
+    /// The sum of the squared differences between the region and the fitted pulses.
+    chi_squared: Real,
+}
+
+/// Detects pulses by fitting a pulse template to each region in which the trace exceeds `threshold`.
+///
+/// Pulses are added to the fit of a region one at a time, starting at the largest remaining residual,
+/// until either the residual no longer exceeds `threshold`, adding a pulse fails to improve the fit,
+/// or `max_pulses` is reached. In this way overlapping pulses are separated into individual events.
+/// Each time a pulse is added, the start times of every pulse are refined to sub-sample precision,
+/// and their amplitudes found by linear least squares.
+/// Events are registered at the fitted peak time of each pulse.
+#[derive(Default, Clone)]
+pub(crate) struct PulseFitDetector {
+    template: BiexpTemplate,
+    threshold: Real,
+    max_pulses: usize,
+
+    /// The last sample seen, which is included at the start of the next region to constrain the pulse onset.
+    previous: Option<(Real, Real)>,
+    /// The samples of the current region, if the trace exceeds the threshold.
+    region: Vec<(Real, Real)>,
+    /// Events found but not yet returned, as a single region can contain several pulses.
+    pending: VecDeque<PulseFitEvent>,
+}
+
+impl PulseFitDetector {
+    pub(crate) fn new(template: BiexpTemplate, threshold: Real, max_pulses: usize) -> Self {
+        Self {
+            template,
+            threshold,
+            max_pulses,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the amplitudes which best fit pulses at the given start times to the region,
+    /// or [None] if they cannot be determined.
+    fn fit_amplitudes(&self, starts: Vec<Real>) -> Option<Fit> {
+        let basis: Vec<Vec<Real>> = starts
+            .iter()
+            .map(|&start| {
+                self.region
+                    .iter()
+                    .map(|&(time, _)| self.template.value_at(start, time))
+                    .collect()
+            })
+            .collect();
+
+        let normal_matrix = basis
+            .iter()
+            .map(|row| basis.iter().map(|column| dot(row, column)).collect())
+            .collect();
+        let values: Vec<Real> = self.region.iter().map(|&(_, value)| value).collect();
+        let normal_rhs = basis.iter().map(|row| dot(row, &values)).collect();
+        let amplitudes = solve_linear_system(normal_matrix, normal_rhs)?;
+
+        let chi_squared = self
+            .region
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, value))| {
+                let model: Real = basis
+                    .iter()
+                    .zip(&amplitudes)
+                    .map(|(row, amplitude)| amplitude * row.get(i).copied().unwrap_or_default())
+                    .sum();
+                (value - model).powi(2)
+            })
+            .sum();
+        Some(Fit {
+            starts,
+            amplitudes,
+            chi_squared,
+        })
+    }
+
+    /// Refines the start times by moving each in turn, keeping any move which improves the fit.
+    /// The size of the moves starts at half a sample, and is halved whenever no move improves the fit.
+    fn refine(&self, mut fit: Fit, sample_interval: Real) -> Fit {
+        let mut step = sample_interval / 2.0;
+        for _ in 0..MAX_REFINEMENT_ITERATIONS {
+            if step < sample_interval * REFINEMENT_PRECISION {
+                break;
+            }
+            let mut improved = false;
+            for index in 0..fit.starts.len() {
+                for offset in [-step, step] {
+                    let mut starts = fit.starts.clone();
+                    if let Some(start) = starts.get_mut(index) {
+                        *start += offset;
+                    }
+                    if let Some(candidate) = self.fit_amplitudes(starts) {
+                        if candidate.chi_squared < fit.chi_squared {
+                            fit = candidate;
+                            improved = true;
+                        }
+                    }
+                }
+            }
+            if !improved {
+                step /= 2.0;
+            }
+        }
+        fit
+    }
+
+    /// Returns the time and value of the largest remaining residual of the region, given the fit.
+    fn largest_residual(&self, fit: &Fit) -> Option<(Real, Real)> {
+        self.region
+            .iter()
+            .map(|&(time, value)| {
+                let model: Real = fit
+                    .starts
+                    .iter()
+                    .zip(&fit.amplitudes)
+                    .map(|(&start, amplitude)| amplitude * self.template.value_at(start, time))
+                    .sum();
+                (time, value - model)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// Fits the current region, and queues an event for each fitted pulse.
+    fn fit_region(&mut self) {
+        let sample_interval = match (self.region.first(), self.region.last()) {
+            (Some(first), Some(last)) if self.region.len() > 1 => {
+                (last.0 - first.0) / (self.region.len() - 1) as Real
+            }
+            _ => 1.0,
+        };
+        let total_squared: Real = self.region.iter().map(|(_, value)| value.powi(2)).sum();
+
+        let mut fit = Fit {
+            chi_squared: total_squared,
+            ..Default::default()
+        };
+        while fit.starts.len() < self.max_pulses {
+            let Some((time, residual)) = self.largest_residual(&fit) else {
+                break;
+            };
+            if residual <= self.threshold {
+                break;
+            }
+            let mut starts = fit.starts.clone();
+            starts.push(time - self.template.peak_time());
+            let Some(candidate) = self.fit_amplitudes(starts) else {
+                break;
+            };
+            let candidate = self.refine(candidate, sample_interval);
+            if candidate.chi_squared >= fit.chi_squared
+                || candidate
+                    .amplitudes
+                    .iter()
+                    .any(|&amplitude| amplitude <= 0.0)
+            {
+                break;
+            }
+            fit = candidate;
+        }
+
+        let residual = if total_squared > 0.0 {
+            Real::sqrt(fit.chi_squared / total_squared)
+        } else {
+            Real::default()
+        };
+        let mut pulses: Vec<_> = fit.starts.into_iter().zip(fit.amplitudes).collect();
+        pulses.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        self.pending
+            .extend(pulses.into_iter().map(|(start, amplitude)| {
+                (
+                    start + self.template.peak_time(),
+                    Data {
+                        amplitude,
+                        start,
+                        residual,
+                    },
+                )
+            }));
+        self.region.clear();
+    }
+}
+
+impl Detector for PulseFitDetector {
+    type TracePointType = (Real, Real);
+    type EventPointType = (Real, Data);
+
+    fn signal(&mut self, time: Real, value: Real) -> Option<PulseFitEvent> {
+        if value > self.threshold {
+            if self.region.is_empty() {
+                self.region.extend(self.previous);
+            }
+            self.region.push((time, value));
+        } else if !self.region.is_empty() {
+            self.region.push((time, value));
+            self.fit_region();
+        }
+        self.previous = Some((time, value));
+        self.pending.pop_front()
+    }
+
+    fn finish(&mut self) -> Option<Self::EventPointType> {
+        if !self.region.is_empty() {
+            self.fit_region();
+        }
+        self.pending.pop_front()
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct PulseFitAssembler {}
+
+impl Assembler for PulseFitAssembler {
+    type DetectorType = PulseFitDetector;
+
+    fn assemble_pulses(&mut self, source: (Real, Data)) -> Option<Pulse> {
+        let data = source.get_data();
+        Some(Pulse {
+            start: TimeValue {
+                time: data.start,
+                value: Real::default(),
+            }
+            .into(),
+            peak: TimeValue {
+                time: source.get_time(),
+                value: data.get_amplitude(),
+            }
+            .into(),
+            fit_residual: Some(data.get_residual()),
+            ..Default::default()
+        })
+    }
+}
+
+fn dot(a: &[Real], b: &[Real]) -> Real {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Solves `matrix * x = rhs` by Gaussian elimination with partial pivoting.
+/// Returns [None] if the matrix is singular.
+fn solve_linear_system(matrix: Vec<Vec<Real>>, rhs: Vec<Real>) -> Option<Vec<Real>> {
+    // Each row is augmented with its right hand side value.
+    let mut rows: Vec<Vec<Real>> = matrix
+        .into_iter()
+        .zip(rhs)
+        .map(|(mut row, rhs)| {
+            row.push(rhs);
+            row
+        })
+        .collect();
+
+    for column in 0..rows.len() {
+        let magnitude = |index: usize| {
+            rows.get(index)
+                .and_then(|row| row.get(column))
+                .map_or(Real::default(), |value| value.abs())
+        };
+        let pivot = (column..rows.len()).max_by(|&a, &b| magnitude(a).total_cmp(&magnitude(b)))?;
+        rows.swap(column, pivot);
+
+        let (upper, lower) = rows.split_at_mut(column + 1);
+        let pivot_row = upper.last()?;
+        let pivot_value = pivot_row.get(column).copied()?;
+        if pivot_value.abs() <= Real::EPSILON {
+            return None;
+        }
+        for row in lower {
+            let factor = row.get(column).copied().unwrap_or_default() / pivot_value;
+            row.iter_mut()
+                .zip(pivot_row)
+                .for_each(|(value, pivot)| *value -= factor * pivot);
+        }
+    }
+
+    let mut solution = VecDeque::with_capacity(rows.len());
+    for (index, row) in rows.iter().enumerate().rev() {
+        let (rhs, coefficients) = row.split_last()?;
+        let sum: Real = coefficients
+            .iter()
+            .skip(index + 1)
+            .zip(&solution)
+            .map(|(coefficient, value)| coefficient * value)
+            .sum();
+        solution.push_front((rhs - sum) / coefficients.get(index)?);
+    }
+    Some(solution.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{AssembleFilter, EventFilter};
+    use assert_approx_eq::assert_approx_eq;
+
+    fn create_trace(template: &BiexpTemplate, pulses: &[(Real, Real)], len: usize) -> Vec<Real> {
+        (0..len)
+            .map(|i| {
+                pulses
+                    .iter()
+                    .map(|&(start, amplitude)| amplitude * template.value_at(start, i as Real))
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn invalid_template() {
+        assert!(BiexpTemplate::new(0.0, 5.0).is_none());
+        assert!(BiexpTemplate::new(5.0, 5.0).is_none());
+        assert!(BiexpTemplate::new(6.0, 5.0).is_none());
+    }
+
+    #[test]
+    fn template_peak() {
+        let template = BiexpTemplate::new(2.0, 10.0).unwrap();
+        assert_approx_eq!(template.value_at(3.0, 3.0 + template.peak_time()), 1.0);
+        assert_eq!(template.value_at(3.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn zero_data() {
+        let template = BiexpTemplate::new(2.0, 10.0).unwrap();
+        let data: [Real; 0] = [];
+        let detector = PulseFitDetector::new(template, 5.0, 4);
+        let mut iter = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .events(detector);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_single_pulse() {
+        let template = BiexpTemplate::new(2.0, 10.0).unwrap();
+        let data = create_trace(&template, &[(10.25, 100.0)], 80);
+        let detector = PulseFitDetector::new(template.clone(), 5.0, 4);
+        let pulses = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .events(detector)
+            .assemble(PulseFitAssembler::default())
+            .collect::<Vec<_>>();
+
+        assert_eq!(pulses.len(), 1);
+        assert_approx_eq!(pulses[0].start.time.unwrap(), 10.25, 1e-3);
+        assert_approx_eq!(
+            pulses[0].peak.time.unwrap(),
+            10.25 + template.peak_time(),
+            1e-3
+        );
+        assert_approx_eq!(pulses[0].peak.value.unwrap(), 100.0, 1e-2);
+        assert!(pulses[0].fit_residual.unwrap() < 1e-3);
+    }
+
+    #[test]
+    fn test_pile_up() {
+        let template = BiexpTemplate::new(2.0, 10.0).unwrap();
+        let data = create_trace(&template, &[(10.0, 100.0), (16.5, 60.0)], 100);
+        let detector = PulseFitDetector::new(template, 5.0, 4);
+        let pulses = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .events(detector)
+            .assemble(PulseFitAssembler::default())
+            .collect::<Vec<_>>();
+
+        assert_eq!(pulses.len(), 2);
+        assert_approx_eq!(pulses[0].start.time.unwrap(), 10.0, 1e-3);
+        assert_approx_eq!(pulses[0].peak.value.unwrap(), 100.0, 1e-2);
+        assert_approx_eq!(pulses[1].start.time.unwrap(), 16.5, 1e-3);
+        assert_approx_eq!(pulses[1].peak.value.unwrap(), 60.0, 1e-2);
+    }
+
+    #[test]
+    fn test_max_pulses() {
+        let template = BiexpTemplate::new(2.0, 10.0).unwrap();
+        let data = create_trace(&template, &[(10.0, 100.0), (16.5, 60.0)], 100);
+        let detector = PulseFitDetector::new(template, 5.0, 1);
+        let pulses = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .events(detector)
+            .assemble(PulseFitAssembler::default())
+            .collect::<Vec<_>>();
+
+        assert_eq!(pulses.len(), 1);
+        assert!(pulses[0].fit_residual.unwrap() > 0.01);
+    }
+
+    #[test]
+    fn test_solve_linear_system() {
+        let solution =
+            solve_linear_system(vec![vec![2.0, 1.0], vec![1.0, 3.0]], vec![3.0, 5.0]).unwrap();
+        assert_approx_eq!(solution[0], 0.8);
+        assert_approx_eq!(solution[1], 1.4);
+        assert!(
+            solve_linear_system(vec![vec![1.0, 2.0], vec![2.0, 4.0]], vec![1.0, 2.0]).is_none()
+        );
+    }
+}
@@ -62,13 +62,23 @@ pub(crate) struct Pulse {
     pub(crate) peak: TimeValueOptional<Real>,
     pub(crate) steepest_rise: TimeValueOptional<RealArray<2>>,
     pub(crate) sharpest_fall: TimeValueOptional<RealArray<2>>,
+    /// The interpolated time at which a constant fraction discriminator's bipolar signal crosses zero.
+    pub(crate) zero_crossing: TimeValueOptional<Real>,
+    /// The relative residual of the template fit from which the pulse was found.
+    pub(crate) fit_residual: Option<Real>,
 }
 
 impl Display for Pulse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
-            "{0},{1},{2},{3},{4}",
-            self.start, self.end, self.peak, self.steepest_rise, self.sharpest_fall
+            "{0},{1},{2},{3},{4},{5},{6}",
+            self.start,
+            self.end,
+            self.peak,
+            self.steepest_rise,
+            self.sharpest_fall,
+            self.zero_crossing,
+            self.fit_residual.unwrap_or_default()
         ))
     }
 }
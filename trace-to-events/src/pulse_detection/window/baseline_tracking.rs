@@ -0,0 +1,231 @@
+use super::{Real, Window};
+use std::collections::VecDeque;
+
+/// Tracks the baseline continuously by taking the median of the most recent `window_size` values,
+/// and outputs each value with this baseline subtracted.
+///
+/// The median is insensitive to pulses, provided they occupy less than half of the window.
+#[derive(Default, Clone)]
+pub(crate) struct MovingMedianBaseline {
+    window_size: usize,
+    /// The values in the window, in the order they were pushed.
+    values: VecDeque<Real>,
+    /// The values in the window, in ascending order, maintained by binary search so the median need not be re-sorted for each value.
+    sorted: Vec<Real>,
+    value: Real,
+}
+
+impl MovingMedianBaseline {
+    pub(crate) fn new(window_size: usize) -> Self {
+        MovingMedianBaseline {
+            window_size: window_size.max(1),
+            values: VecDeque::with_capacity(window_size.max(1)),
+            sorted: Vec::with_capacity(window_size.max(1)),
+            ..Default::default()
+        }
+    }
+
+    fn median(&self) -> Real {
+        let sorted = &self.sorted;
+        let mid = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            match (
+                mid.checked_sub(1).and_then(|i| sorted.get(i)),
+                sorted.get(mid),
+            ) {
+                (Some(lower), Some(upper)) => (lower + upper) / 2.0,
+                _ => Real::default(),
+            }
+        } else {
+            sorted.get(mid).copied().unwrap_or_default()
+        }
+    }
+}
+
+impl Window for MovingMedianBaseline {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        if self.values.len() == self.window_size {
+            if let Some(oldest) = self.values.pop_front() {
+                if let Ok(index) = self
+                    .sorted
+                    .binary_search_by(|probe| probe.total_cmp(&oldest))
+                {
+                    self.sorted.remove(index);
+                }
+            }
+        }
+        self.values.push_back(value);
+        let index = self
+            .sorted
+            .partition_point(|probe| probe.total_cmp(&value).is_lt());
+        self.sorted.insert(index, value);
+        self.value = value - self.median();
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        Some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time
+    }
+}
+
+/// Tracks the baseline continuously with an exponential moving average, and outputs each value with this baseline subtracted.
+///
+/// The average is only updated whilst the output lies within `gate` of zero, and for `hold_off` samples
+/// after it leaves this range the average remains frozen, so that pulses and their tails do not pull the baseline.
+#[derive(Default, Clone)]
+pub(crate) struct GatedExponentialBaseline {
+    smoothing_factor: Real,
+    gate: Real,
+    hold_off: usize,
+    baseline: Option<Real>,
+    samples_since_event: Option<usize>,
+    value: Real,
+}
+
+impl GatedExponentialBaseline {
+    pub(crate) fn new(smoothing_factor: Real, gate: Real, hold_off: usize) -> Self {
+        GatedExponentialBaseline {
+            smoothing_factor,
+            gate,
+            hold_off,
+            ..Default::default()
+        }
+    }
+}
+
+impl Window for GatedExponentialBaseline {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        let baseline = self.baseline.unwrap_or(value);
+        self.value = value - baseline;
+
+        if self.value.abs() > self.gate {
+            self.samples_since_event = Some(0);
+        } else if let Some(samples) = self.samples_since_event.as_mut() {
+            *samples += 1;
+        }
+
+        let gated = self
+            .samples_since_event
+            .is_some_and(|samples| samples <= self.hold_off);
+        self.baseline = Some(if gated {
+            baseline
+        } else {
+            baseline + self.smoothing_factor * (value - baseline)
+        });
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        Some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::WindowFilter;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn moving_median_removes_step() {
+        let input: Vec<Real> = vec![2.0, 2.0, 2.0, 5.0, 5.0, 5.0, 5.0, 5.0];
+        let output: Vec<_> = input
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .window(MovingMedianBaseline::new(3))
+            .map(|(_, v)| v)
+            .collect();
+
+        assert_eq!(output, [0.0, 0.0, 0.0, 3.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn moving_median_ignores_short_pulse() {
+        let input: Vec<Real> = vec![1.0, 1.0, 1.0, 1.0, 9.0, 1.0, 1.0];
+        let output: Vec<_> = input
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .window(MovingMedianBaseline::new(4))
+            .map(|(_, v)| v)
+            .collect();
+
+        assert_eq!(output, [0.0, 0.0, 0.0, 0.0, 8.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn moving_median_matches_sorted_window() {
+        let input: Vec<Real> = (0..50).map(|i| ((i * 7) % 11) as Real).collect();
+        let window_size = 6;
+        let output: Vec<_> = input
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .window(MovingMedianBaseline::new(window_size))
+            .map(|(_, v)| v)
+            .collect();
+
+        for (i, value) in output.into_iter().enumerate() {
+            let mut window = input[i.saturating_sub(window_size - 1)..=i].to_vec();
+            window.sort_by(Real::total_cmp);
+            let mid = window.len() / 2;
+            let median = if window.len() % 2 == 0 {
+                (window[mid - 1] + window[mid]) / 2.0
+            } else {
+                window[mid]
+            };
+            assert_approx_eq!(value, input[i] - median);
+        }
+    }
+
+    #[test]
+    fn gated_exponential_follows_drift() {
+        let input: Vec<Real> = vec![0.0, 1.0, 2.0, 3.0];
+        let output: Vec<_> = input
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .window(GatedExponentialBaseline::new(0.5, 10.0, 0))
+            .map(|(_, v)| v)
+            .collect();
+
+        // The baseline is 0, 0, 0.5, 1.25 before each sample.
+        assert_approx_eq!(output[0], 0.0);
+        assert_approx_eq!(output[1], 1.0);
+        assert_approx_eq!(output[2], 1.5);
+        assert_approx_eq!(output[3], 1.75);
+    }
+
+    #[test]
+    fn gated_exponential_freezes_during_pulse() {
+        let input: Vec<Real> = vec![4.0, 4.0, 20.0, 30.0, 6.0, 4.0, 4.0];
+        let output: Vec<_> = input
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .window(GatedExponentialBaseline::new(0.5, 5.0, 1))
+            .map(|(_, v)| v)
+            .collect();
+
+        // Updates are paused from the first sample outside the gate until one sample after the last.
+        assert_eq!(output, [0.0, 0.0, 16.0, 26.0, 2.0, 0.0, 0.0]);
+    }
+}
@@ -1,9 +1,11 @@
 pub(crate) mod baseline;
+pub(crate) mod baseline_tracking;
 pub(crate) mod finite_differences;
 pub(crate) mod smoothing_window;
 
 use super::{Real, RealArray, Stats, Temporal, TracePoint};
 pub(crate) use baseline::Baseline;
+pub(crate) use baseline_tracking::{GatedExponentialBaseline, MovingMedianBaseline};
 pub(crate) use finite_differences::FiniteDifferences;
 pub(crate) use smoothing_window::SmoothingWindow;
 
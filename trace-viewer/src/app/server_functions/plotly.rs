@@ -36,7 +36,7 @@ cfg_if! {
     if #[cfg(feature = "ssr")] {
         use crate::{
             app::SessionError,
-            structs::{DigitiserMetadata, Trace as MuonTrace, EventList, ServerSideData},
+            structs::{DigitiserMetadata, TraceSegments, EventList, ServerSideData},
             Channel
         };
         use plotly::{
@@ -48,7 +48,7 @@ cfg_if! {
         };
         use tracing::info;
 
-        fn create_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a MuonTrace, eventlist: Option<&'a EventList>) -> Result<TracePlotly, ServerFnError> {
+        fn create_plotly<'a>(metadata: &DigitiserMetadata, channel: Channel, trace: &'a TraceSegments, eventlist: Option<&'a EventList>) -> Result<TracePlotly, ServerFnError> {
             info!("create_plotly_on_server");
 
             let date = metadata.timestamp.date_naive().to_string();
@@ -61,10 +61,21 @@ cfg_if! {
                 .x_axis(Axis::new().title("Time (ns)"))
                 .y_axis(Axis::new().title("Intensity"));
 
-            let trace = Scatter::new(
-                (0..trace.len()).collect::<Vec<_>>(),
-                trace.clone(),
-            )
+            // Segments of a trace excerpt are separated by a null value, so they are not joined by the line.
+            let (times, intensities): (Vec<_>, Vec<_>) = trace
+                .iter()
+                .enumerate()
+                .flat_map(|(index, segment)| {
+                    let gap = (index > 0).then_some((None, None));
+                    let values = segment
+                        .trace
+                        .iter()
+                        .enumerate()
+                        .map(|(time, &intensity)| (Some(segment.start + time), Some(intensity)));
+                    gap.into_iter().chain(values)
+                })
+                .unzip();
+            let trace = Scatter::new(times, intensities)
             .mode(Mode::Lines)
             .name("Trace")
             .line(Line::new().color(NamedColor::CadetBlue));
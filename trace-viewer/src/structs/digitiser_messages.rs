@@ -28,11 +28,25 @@ pub(crate) struct DigitiserMetadata {
     pub(crate) veto_flags: u16,
 }
 
+/// A contiguous excerpt of a [Trace].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TraceSegment {
+    /// Index, within the full trace, of the first value of the segment.
+    pub(crate) start: usize,
+    /// The values of the segment.
+    pub(crate) trace: Trace,
+}
+
+/// The segments of a trace which are available, ordered by start and not overlapping.
+///
+/// A full trace consists of a single segment starting at zero.
+pub(crate) type TraceSegments = Vec<TraceSegment>;
+
 /// Encapsulates all traces of a digitiser trace message.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct DigitiserTrace {
     /// Maps channels to traces.
-    pub(crate) traces: HashMap<Channel, Trace>,
+    pub(crate) traces: HashMap<Channel, TraceSegments>,
     /// If present, maps channels to [EventList]s.
     pub(crate) events: Option<DigitiserEventList>,
 }
@@ -57,8 +71,10 @@ pub(crate) type DigitiserEventList = HashMap<Channel, EventList>;
 
 cfg_if! {
     if #[cfg(feature = "ssr")] {
+        use crate::structs::UnpackedTraceMessage;
         use supermusr_streaming_types::{
             dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
+            dat3_digitizer_analog_trace_v3_generated::DigitizerAnalogTraceMessage as DigitizerTraceExcerptMessage,
             dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
         };
 
@@ -74,13 +90,46 @@ cfg_if! {
 
         impl FromMessage<&DigitizerAnalogTraceMessage<'_>> for DigitiserTrace {
             fn from_message(msg: &DigitizerAnalogTraceMessage) -> Self {
-                let pairs: Vec<(Channel, Trace)> = msg
+                let pairs: Vec<(Channel, TraceSegments)> = msg
+                    .channels()
+                    .unwrap()
+                    .iter()
+                    .map(|x| {
+                        let segment = TraceSegment {
+                            start: 0,
+                            trace: x.voltage().unwrap().iter().collect(),
+                        };
+                        (x.channel(), vec![segment])
+                    })
+                    .collect();
+                let traces: HashMap<Channel, TraceSegments> = HashMap::from_iter(pairs);
+                DigitiserTrace {
+                    traces,
+                    events: None,
+                }
+            }
+        }
+
+        impl FromMessage<&DigitizerTraceExcerptMessage<'_>> for DigitiserTrace {
+            fn from_message(msg: &DigitizerTraceExcerptMessage) -> Self {
+                let pairs: Vec<(Channel, TraceSegments)> = msg
                     .channels()
                     .unwrap()
                     .iter()
-                    .map(|x| (x.channel(), x.voltage().unwrap().iter().collect()))
+                    .map(|x| {
+                        let segments = x
+                            .segments()
+                            .unwrap()
+                            .iter()
+                            .map(|segment| TraceSegment {
+                                start: segment.start() as usize,
+                                trace: segment.voltage().unwrap().iter().collect(),
+                            })
+                            .collect();
+                        (x.channel(), segments)
+                    })
                     .collect();
-                let traces: HashMap<Channel, Trace> = HashMap::from_iter(pairs);
+                let traces: HashMap<Channel, TraceSegments> = HashMap::from_iter(pairs);
                 DigitiserTrace {
                     traces,
                     events: None,
@@ -88,6 +137,15 @@ cfg_if! {
             }
         }
 
+        impl FromMessage<&UnpackedTraceMessage<'_>> for DigitiserTrace {
+            fn from_message(msg: &UnpackedTraceMessage) -> Self {
+                match msg {
+                    UnpackedTraceMessage::Full(msg) => DigitiserTrace::from_message(msg),
+                    UnpackedTraceMessage::Excerpt(msg) => DigitiserTrace::from_message(msg),
+                }
+            }
+        }
+
         impl FromMessage<&DigitizerEventListMessage<'_>> for DigitiserEventList {
             fn from_message(msg: &DigitizerEventListMessage) -> Self {
                 let mut events = HashMap::<Channel, EventList>::new();
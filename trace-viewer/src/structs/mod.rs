@@ -23,8 +23,8 @@ cfg_if! {
 
         use clap::Args; // This should be imported only for server-side use.
 
-        pub(crate) use digitiser_messages::{DigitiserMetadata, DigitiserTrace, EventList, Trace};
-        pub(crate) use server_only::{Cache, BorrowedMessageError, SearchResults, EventListMessage, FBMessage, TraceMessage};
+        pub(crate) use digitiser_messages::{DigitiserMetadata, DigitiserTrace, EventList, TraceSegments};
+        pub(crate) use server_only::{Cache, BorrowedMessageError, SearchResults, EventListMessage, FBMessage, TraceMessage, UnpackedTraceMessage};
 
         pub use server_only::ServerSideData;
     }
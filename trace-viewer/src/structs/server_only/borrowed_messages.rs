@@ -8,11 +8,13 @@ use supermusr_streaming_types::{
         DigitizerAnalogTraceMessage, digitizer_analog_trace_message_buffer_has_identifier,
         root_as_digitizer_analog_trace_message,
     },
+    dat3_digitizer_analog_trace_v3_generated as dat3,
     dev2_digitizer_event_v2_generated::{
         DigitizerEventListMessage, digitizer_event_list_message_buffer_has_identifier,
         root_as_digitizer_event_list_message,
     },
     flatbuffers::InvalidFlatbuffer,
+    frame_metadata_v2_generated::FrameMetadataV2,
     time_conversions::GpsTimeConversionError,
 };
 use thiserror::Error;
@@ -47,6 +49,41 @@ pub(crate) trait FBMessage<'a>:
     fn digitiser_id(&self) -> DigitizerId;
 }
 
+/// A trace message, which is either a full trace or, as published by `trace-to-events`, a trace excerpt.
+pub(crate) enum UnpackedTraceMessage<'a> {
+    /// A `dat2` message.
+    Full(DigitizerAnalogTraceMessage<'a>),
+    /// A `dat3` message.
+    Excerpt(dat3::DigitizerAnalogTraceMessage<'a>),
+}
+
+impl<'a> UnpackedTraceMessage<'a> {
+    pub(crate) fn digitizer_id(&self) -> DigitizerId {
+        match self {
+            Self::Full(trace) => trace.digitizer_id(),
+            Self::Excerpt(trace) => trace.digitizer_id(),
+        }
+    }
+
+    pub(crate) fn metadata(&self) -> FrameMetadataV2<'a> {
+        match self {
+            Self::Full(trace) => trace.metadata(),
+            Self::Excerpt(trace) => trace.metadata(),
+        }
+    }
+
+    pub(crate) fn has_channel(&self, channel: Channel) -> bool {
+        match self {
+            Self::Full(trace) => trace
+                .channels()
+                .is_some_and(|c| c.iter().any(|c| c.channel() == channel)),
+            Self::Excerpt(trace) => trace
+                .channels()
+                .is_some_and(|c| c.iter().any(|c| c.channel() == channel)),
+        }
+    }
+}
+
 pub(crate) struct TraceMessage<'a> {
     message: BorrowedMessage<'a>,
     timestamp: Timestamp,
@@ -56,10 +93,7 @@ pub(crate) struct TraceMessage<'a> {
 impl<'a> TraceMessage<'a> {
     pub(crate) fn has_channel(&self, channel: Channel) -> bool {
         self.try_unpacked_message()
-            .ok()
-            .and_then(|d| d.channels())
-            .and_then(|c| c.iter().find(|c| c.channel() == channel))
-            .is_some()
+            .is_ok_and(|d| d.has_channel(channel))
     }
 
     pub(crate) fn filter_by(&self, by: &SearchTargetBy) -> bool {
@@ -106,7 +140,7 @@ impl<'a> TryFrom<BorrowedMessage<'a>> for TraceMessage<'a> {
 }
 
 impl<'a> FBMessage<'a> for TraceMessage<'a> {
-    type UnpackedMessage = DigitizerAnalogTraceMessage<'a>;
+    type UnpackedMessage = UnpackedTraceMessage<'a>;
 
     fn try_unpacked_message(&'a self) -> Result<Self::UnpackedMessage, BorrowedMessageError> {
         self.message.unpack_trace_message()
@@ -181,9 +215,7 @@ impl<'a> FBMessage<'a> for EventListMessage<'a> {
 }
 
 pub(crate) trait UnpackMessage<'a> {
-    fn unpack_trace_message(
-        &'a self,
-    ) -> Result<DigitizerAnalogTraceMessage<'a>, BorrowedMessageError>;
+    fn unpack_trace_message(&'a self) -> Result<UnpackedTraceMessage<'a>, BorrowedMessageError>;
 
     fn unpack_event_list_message(
         &'a self,
@@ -191,15 +223,21 @@ pub(crate) trait UnpackMessage<'a> {
 }
 
 impl<'a> UnpackMessage<'a> for BorrowedMessage<'a> {
-    fn unpack_trace_message(
-        &'a self,
-    ) -> Result<DigitizerAnalogTraceMessage<'a>, BorrowedMessageError> {
-        root_as_digitizer_analog_trace_message(
-            self.payload()
-                .filter(|payload| digitizer_analog_trace_message_buffer_has_identifier(payload))
-                .ok_or(BorrowedMessageError::InvalidIdentifier)?,
-        )
-        .map_err(Into::into)
+    fn unpack_trace_message(&'a self) -> Result<UnpackedTraceMessage<'a>, BorrowedMessageError> {
+        let payload = self
+            .payload()
+            .ok_or(BorrowedMessageError::InvalidIdentifier)?;
+        if digitizer_analog_trace_message_buffer_has_identifier(payload) {
+            Ok(UnpackedTraceMessage::Full(
+                root_as_digitizer_analog_trace_message(payload)?,
+            ))
+        } else if dat3::digitizer_analog_trace_message_buffer_has_identifier(payload) {
+            Ok(UnpackedTraceMessage::Excerpt(
+                dat3::root_as_digitizer_analog_trace_message(payload)?,
+            ))
+        } else {
+            Err(BorrowedMessageError::InvalidIdentifier)
+        }
     }
 
     fn unpack_event_list_message(
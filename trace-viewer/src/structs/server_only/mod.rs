@@ -6,7 +6,7 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 
 pub(crate) use borrowed_messages::{
-    BorrowedMessageError, EventListMessage, FBMessage, TraceMessage,
+    BorrowedMessageError, EventListMessage, FBMessage, TraceMessage, UnpackedTraceMessage,
 };
 pub(crate) use search_results::{Cache, SearchResults};
 
@@ -1,7 +1,8 @@
 use crate::{
     app::SessionError,
-    structs::digitiser_messages::{
-        DigitiserEventList, DigitiserMetadata, DigitiserTrace, FromMessage,
+    structs::{
+        UnpackedTraceMessage,
+        digitiser_messages::{DigitiserEventList, DigitiserMetadata, DigitiserTrace, FromMessage},
     },
 };
 use std::collections::{
@@ -9,7 +10,6 @@ use std::collections::{
     btree_map::{self, Entry},
 };
 use supermusr_streaming_types::{
-    dat2_digitizer_analog_trace_v2_generated::DigitizerAnalogTraceMessage,
     dev2_digitizer_event_v2_generated::DigitizerEventListMessage,
     time_conversions::GpsTimeConversionError,
 };
@@ -40,7 +40,7 @@ impl Cache {
     #[tracing::instrument(skip_all)]
     pub(crate) fn push_trace(
         &mut self,
-        msg: &DigitizerAnalogTraceMessage<'_>,
+        msg: &UnpackedTraceMessage<'_>,
     ) -> Result<(), GpsTimeConversionError> {
         let metadata = DigitiserMetadata {
             id: msg.digitizer_id(),